@@ -104,6 +104,7 @@ class TestCase(object):
     def set_up(self, client_params):
         '''Subclasses should implement both set_up and get_client so the runner
         can get access to the client.'''
+        self.client_params = client_params
         self.client = client_params.create_client()
 
     def get_client(self):
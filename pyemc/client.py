@@ -110,6 +110,22 @@ class MemcacheClient(object):
             noreply=noreply,
         )
 
+    def cache_memlimit(self, megabytes, noreply=False):
+        # prepare command
+        command = 'cache_memlimit %(megabytes)d %(noreply)s\r\n' % {
+            'megabytes': megabytes,
+            'noreply': 'noreply' if noreply else '',
+        }
+
+        # execute command
+        self.maybe_write_now(command, noreply=noreply)
+
+        # parse the response
+        if not noreply:
+            resp = self.stream.read_line()
+            if not resp == 'OK\r\n':
+                raise create_exc(resp, 'Could not set cache_memlimit')
+
     def cas(self, key, value, flags=0, exptime=0, cas_unique=None, noreply=False):
         return self._set_family(
             'cas',
@@ -142,8 +158,10 @@ class MemcacheClient(object):
 
     def flush_all(self, exptime=None, noreply=False):
         # prepare command
-        # TODO no support for parameters yet
-        command = 'flush_all\r\n'
+        command = 'flush_all %(exptime)s %(noreply)s\r\n' % {
+            'exptime': '%d' % exptime if exptime is not None else '',
+            'noreply': 'noreply' if noreply else '',
+        }
 
         # execute command
         self.maybe_write_now(command, noreply=noreply)
@@ -222,15 +240,20 @@ class MemcacheClient(object):
         except KeyError:
             raise NotFoundError('The item with key %r was not found' % key)
 
-    def get_stats(self):
-        dct = OrderedDict()
-
+    def get_stats(self, group=None):
         # prepare command
         command = 'stats\r\n'
+        if group:
+            command = 'stats %s\r\n' % group
 
         # execute command
         self.stream.write(command)
 
+        return self._read_stats()
+
+    def _read_stats(self):
+        dct = OrderedDict()
+
         # read response line by line
         stream_terminator = 'END\r\n'
 
@@ -267,6 +290,29 @@ class MemcacheClient(object):
             except IndexError:
                 raise create_exc(resp, 'Could not %s key %r' % (instr, key))
 
+    def invalidate_prefix(self, prefix, noreply=False):
+        return self._invalidate_family('prefix', prefix, noreply)
+
+    def invalidate_tag(self, tag, noreply=False):
+        return self._invalidate_family('tag', tag, noreply)
+
+    def _invalidate_family(self, instr, target, noreply=False):
+        # prepare command
+        command = 'invalidate %(instr)s %(target)s %(noreply)s\r\n' % {
+            'instr': instr,
+            'target': target,
+            'noreply': 'noreply' if noreply else '',
+        }
+
+        # execute command
+        self.maybe_write_now(command, noreply=noreply)
+
+        # parse the response
+        if not noreply:
+            resp = self.stream.read_line()
+            if not resp == 'OK\r\n':
+                raise create_exc(resp, 'Could not invalidate %s %r' % (instr, target))
+
     def quit(self):
         '''Tells the server to drop the connection.'''
 
@@ -337,6 +383,43 @@ class MemcacheClient(object):
         buf = self.stream.read(4096)
         return buf.strip()
 
+    def slowlog_get(self, count=None):
+        # prepare command
+        command = 'slowlog get %(count)s\r\n' % {
+            'count': '%d' % count if count is not None else '',
+        }
+
+        # execute command
+        self.stream.write(command)
+
+        return self._read_stats()
+
+    def slowlog_reset(self):
+        # execute command
+        self.stream.write('slowlog reset\r\n')
+
+        # parse the response
+        resp = self.stream.read_line()
+        if not resp == 'OK\r\n':
+            raise create_exc(resp, 'Could not reset the slowlog')
+
+    def tag(self, key, tag, noreply=False):
+        # prepare command
+        command = 'tag %(key)s %(tag)s %(noreply)s\r\n' % {
+            'key': key,
+            'tag': tag,
+            'noreply': 'noreply' if noreply else '',
+        }
+
+        # execute command
+        self.maybe_write_now(command, noreply=noreply)
+
+        # check for success
+        if not noreply:
+            resp = self.stream.read_line()
+            if not resp == 'TAGGED\r\n':
+                raise create_exc(resp, 'Could not tag key %r' % key)
+
     def touch(self, key, exptime=0, noreply=False):
         # prepare command
         command = 'touch %(key)s %(exptime)d %(noreply)s\r\n' % {
@@ -366,6 +449,23 @@ class MemcacheClient(object):
         kw, version = resp.split(' ', 1)
         return version.strip()
 
+    def watch(self, *kinds):
+        # prepare command
+        command = ' '.join(('watch',) + kinds) + '\r\n'
+
+        # execute command
+        self.stream.write(command)
+
+        # check for success, after which the connection only streams events
+        resp = self.stream.read_line()
+        if not resp == 'OK\r\n':
+            raise create_exc(resp, 'Could not watch %r' % (kinds,))
+
+    def read_event(self):
+        # an event is a line of name=value pairs
+        line = self.stream.read_line()
+        return dict(pair.split('=', 1) for pair in line.split())
+
     def maybe_write_now(self, command, noreply=False):
         if noreply and self.pipeline_mode:
             self.stream.write_pipelined(command)
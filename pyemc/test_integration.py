@@ -64,6 +64,16 @@ class TestApi(TestCase):
         item = self.client.get(key)
         assert val + val2 == item.value
 
+    # CacheMemlimit
+
+    def test_cache_memlimit(self):
+        # set the limit to what it is already so as not to disturb other tests
+        limit = int(self.client.get_stats()['limit_maxbytes'])
+        self.client.cache_memlimit(limit >> 20)
+
+        limit2 = int(self.client.get_stats()['limit_maxbytes'])
+        assert limit == limit2
+
     # Cas
 
     def test_cas_ok(self):
@@ -194,6 +204,37 @@ class TestApi(TestCase):
         item = self.client.get(key2)
         assert item.value == val2
 
+    def test_flush_all_zero_delay(self):
+        key = generate_random_key(4)
+        val = generate_random_data(5, 8)
+
+        # a delay of 0 flushes right away
+        self.client.set(key, val)
+        self.client.flush_all(exptime=0)
+        with self.assert_raises(NotFoundError):
+            self.client.get(key)
+
+    def test_flush_all_delayed(self):
+        key = generate_random_key(4)
+        val = generate_random_data(5, 8)
+
+        self.client.set(key, val)
+        self.client.flush_all(exptime=1)
+
+        # key set before the flush kicks in is still there for now
+        key2 = generate_random_key(4)
+        val2 = generate_random_data(5, 8)
+        self.client.set(key2, val2)
+        item = self.client.get(key2)
+        assert item.value == val2
+
+        # both keys are expired once it does
+        time.sleep(1.5)
+        with self.assert_raises(NotFoundError):
+            self.client.get(key)
+        with self.assert_raises(NotFoundError):
+            self.client.get(key2)
+
     # Get and Set
 
     def test_set_and_get_small_key(self):
@@ -218,6 +259,17 @@ class TestApi(TestCase):
 
         assert val == val2
 
+    def test_set_and_get_chunked_value(self):
+        key = generate_random_key(10)
+        val = generate_random_data((1 << 20) - 10)  # spans two chunks
+        val2 = generate_random_data(10)
+
+        self.client.set(key, val)
+        self.client.append(key, val2)
+
+        item = self.client.get(key)
+        assert val + val2 == item.value
+
     def test_get_multiple(self):
         key1 = generate_random_key(10)
         val1 = generate_random_data(10)
@@ -275,6 +327,17 @@ class TestApi(TestCase):
         assert val == item.value
         assert flags == item.flags
 
+    def test_set_flags_32bit(self):
+        key = generate_random_key(10)
+        val = generate_random_data(10)
+
+        for flags in [1 << 16, 1 << 31, (1 << 32) - 1]:
+            self.client.set(key, val, flags=flags)
+            item = self.client.get(key)
+
+            assert val == item.value
+            assert flags == item.flags
+
     def test_set_noreply(self):
         key = generate_random_key(10)
         val = generate_random_data(10)
@@ -340,6 +403,36 @@ class TestApi(TestCase):
         item = self.client.get(key)
         assert int(val) + 1 == int(item.value)
 
+    # Invalidate
+
+    def test_invalidate_prefix(self):
+        prefix = generate_random_key(8)
+        key1 = prefix + generate_random_key(4)
+        key2 = prefix + generate_random_key(4)
+        key3 = generate_random_key(12)
+        val = generate_random_data(10)
+
+        for key in [key1, key2, key3]:
+            self.client.set(key, val)
+        self.client.invalidate_prefix(prefix)
+
+        dct = self.client.get_multi([key1, key2, key3])
+        assert dct.keys() == [key3]
+
+    def test_invalidate_tag(self):
+        key1 = generate_random_key(10)
+        key2 = generate_random_key(10)
+        tag = generate_random_key(10)
+        val = generate_random_data(10)
+
+        self.client.set(key1, val)
+        self.client.set(key2, val)
+        self.client.tag(key1, tag)
+        self.client.invalidate_tag(tag, noreply=True)
+
+        dct = self.client.get_multi([key1, key2])
+        assert dct.keys() == [key2]
+
     # Quit
 
     def test_quit(self):
@@ -399,12 +492,68 @@ class TestApi(TestCase):
 
     # Stats
 
+    def test_get_stats_commands(self):
+        key = generate_random_key(10)
+        self.client.set(key, generate_random_data(10))
+
+        dct = self.client.get_stats('commands')
+        assert int(dct['cmd:set:count']) > 0
+        assert int(dct['cmd:set:stored']) > 0
+        assert float(dct['cmd:set:max_us']) >= 0
+
+    def test_get_stats_hotkeys(self):
+        # hot keys are only tracked if the server is started with --hotkeys
+        dct = self.client.get_stats('hotkeys')
+        for (key, count) in dct.items():
+            assert int(count) > 0
+
+    def test_get_stats_namespaces(self):
+        # namespaces are off unless the server is started with a delimiter
+        dct = self.client.get_stats('namespaces')
+        assert dct == {}
+
+    def test_get_stats_sizes(self):
+        key = generate_random_key(10)
+        val = generate_random_data(10)
+        self.client.set(key, val)
+
+        # sizes are counted in buckets of 32 bytes
+        dct = self.client.get_stats('sizes')
+        assert len(dct) > 0
+        for (size, count) in dct.items():
+            assert int(size) % 32 == 0
+            assert int(count) > 0
+
     # name mangle to get it to run last so we see stats from other tests
     def test_z_get_stats(self):
         dct = self.client.get_stats()
         for (key, value) in dct.items():
             self.write('%s: %s' % (key, value))
 
+    # Slowlog
+
+    def test_slowlog(self):
+        # commands are only logged if the server is started with a threshold
+        dct = self.client.slowlog_get(5)
+        assert len(dct) <= 5
+        for (id, entry) in dct.items():
+            assert 'cmd=' in entry
+
+        # only the reset itself can have been slow since
+        self.client.slowlog_reset()
+        dct = self.client.slowlog_get()
+        assert len(dct) <= 1
+        for (id, entry) in dct.items():
+            assert 'cmd=slowlog' in entry
+
+    # Tag
+
+    def test_tag_missing_key(self):
+        key = generate_random_key(10)
+
+        with self.assert_raises(NotFoundError):
+            self.client.tag(key, 'sometag')
+
     # Touch
 
     def test_touch(self):
@@ -453,6 +602,32 @@ class TestApi(TestCase):
         self.write(version)
 
 
+    # Watch
+
+    def test_watch(self):
+        key = generate_random_key(10)
+        val = generate_random_data(10)
+
+        watcher = self.client_params.create_client()
+        watcher.watch('fetchers', 'mutations')
+
+        self.client.set(key, val, exptime=60)
+        self.client.get(key)
+        self.client.delete(key)
+
+        # other clients may be busy too, so only look at our key
+        events = []
+        while len(events) < 3:
+            event = watcher.read_event()
+            if event['key'] == key:
+                events.append(event)
+
+        assert [e['event'] for e in events] == ['set', 'hit', 'delete']
+        assert events[0]['size'] == '10'
+        assert 0 < int(events[0]['ttl']) <= 60
+        assert 'conn' in events[0] and 'ts' in events[0]
+
+
     ## Failure cases
 
     def test_get_invalid_key(self):
@@ -0,0 +1,92 @@
+extern crate docopt;
+extern crate emcache;
+extern crate rustc_serialize;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::process;
+
+use docopt::Docopt;
+
+use emcache::protocol::Driver;
+use emcache::storage::Cache;
+use emcache::trace::Client;
+use emcache::trace::TraceReader;
+use emcache::trace::replay;
+
+
+const USAGE: &'static str = "
+Replays a trace recorded with emcache --trace-path, and reports the hit rate
+and latency of the commands.
+
+Usage:
+    emcache-replay [options] <trace>
+
+Options:
+    --server ADDR       Send the commands to a server at ADDR (eg.
+                        127.0.0.1:11311) instead of a cache in this process.
+    -m --mem MEMSIZE    Max memory of the cache in this process (in
+                        megabytes).
+    --speed FACTOR      Replay this many times faster than recorded, 0 for as
+                        fast as possible.
+    -h --help           Show this screen.
+";
+
+
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    arg_trace: String,
+    flag_server: Option<String>,
+    flag_mem: Option<u64>,
+    flag_speed: Option<f64>,
+}
+
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+                         .and_then(|d| d.decode())
+                         .unwrap_or_else(|e| e.exit());
+
+    let speed = args.flag_speed.unwrap_or(1.0);
+    if speed < 0.0 {
+        println!("Speed must not be negative");
+        process::exit(1);
+    }
+
+    let file = File::open(&args.arg_trace).unwrap_or_else(|e| {
+        println!("Failed to open {}: {}", args.arg_trace, e);
+        process::exit(1);
+    });
+    let reader = TraceReader::new(BufReader::new(file)).unwrap_or_else(|e| {
+        println!("Failed to read {}: {}", args.arg_trace, e);
+        process::exit(1);
+    });
+    if !reader.has_values() {
+        println!("The trace has no values, sets store zeroes instead");
+    }
+
+    let rv = match args.flag_server {
+        Some(ref addr) => {
+            let stream = TcpStream::connect(&addr[..]).unwrap_or_else(|e| {
+                println!("Failed to connect to {}: {}", addr, e);
+                process::exit(1);
+            });
+            let mut client = Client::new(stream);
+            replay(reader, speed, |cmd| client.run(&cmd))
+        }
+        None => {
+            let mem = args.flag_mem.unwrap_or(64);
+            let mut driver = Driver::new(Cache::new(mem << 20));
+            replay(reader, speed, |cmd| Ok(driver.run(cmd)))
+        }
+    };
+
+    match rv {
+        Ok(stats) => println!("{}", stats.report()),
+        Err(e) => {
+            println!("Replay failed: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+extern crate docopt;
+extern crate emcache;
+extern crate rustc_serialize;
+
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::process;
+use std::thread;
+
+use docopt::Docopt;
+
+use emcache::storage::EvictionPolicy;
+use emcache::trace::ReplayStats;
+use emcache::trace::TraceReader;
+use emcache::trace::simulate;
+
+
+const USAGE: &'static str = "
+Runs a trace recorded with emcache --trace-path through caches of several
+sizes and eviction policies, and prints the miss ratio of each.
+
+Usage:
+    emcache-sim [options] <trace>
+
+Options:
+    -m --mem SIZES      Cache sizes to simulate (in megabytes), eg.
+                        1024,2048,4096.
+    --policies POLICIES
+                        Eviction policies to simulate, any of lru and slru
+                        (eg. lru,slru).
+    --sample RATE       Simulate only this share of the keys, in caches
+                        scaled down to match (eg. 0.01). Needs less memory
+                        and time, at some cost in accuracy.
+    -j --jobs N         Simulate at most this many caches at a time
+                        (default: number of cpus). Every cache holds up to
+                        its size in memory.
+    -h --help           Show this screen.
+";
+
+
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    arg_trace: String,
+    flag_mem: Option<String>,
+    flag_policies: Option<String>,
+    flag_sample: Option<f64>,
+    flag_jobs: Option<usize>,
+}
+
+
+// Parses a list of sizes like 1024,2048 (in megabytes), none of which may be
+// too large to count in bytes
+fn parse_sizes(sizes: &str) -> Option<Vec<u64>> {
+    let mut parsed = vec![];
+
+    for size in sizes.split(',') {
+        match size.parse::<u64>() {
+            Ok(size) if size > 0 && size.checked_mul(1 << 20).is_some() => {
+                parsed.push(size)
+            }
+            _ => return None,
+        }
+    }
+
+    Some(parsed)
+}
+
+// Parses a list of eviction policies like lru,slru
+fn parse_policies(policies: &str) -> Option<Vec<EvictionPolicy>> {
+    let mut parsed = vec![];
+
+    for name in policies.split(',') {
+        match EvictionPolicy::from_name(name) {
+            Some(policy) => parsed.push(policy),
+            None => return None,
+        }
+    }
+
+    Some(parsed)
+}
+
+fn open_trace(path: &str) -> io::Result<TraceReader<BufReader<File>>> {
+    let file = try!(File::open(path));
+    TraceReader::new(BufReader::new(file))
+}
+
+fn miss_ratio(stats: &ReplayStats) -> String {
+    match stats.hit_rate() {
+        Some(hit_rate) => format!("{:.4}", 1.0 - hit_rate),
+        None => "-".to_string(),
+    }
+}
+
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+                         .and_then(|d| d.decode())
+                         .unwrap_or_else(|e| e.exit());
+
+    let sizes = args.flag_mem
+                    .as_ref()
+                    .map_or("64,128,256,512,1024", |sizes| &sizes[..]);
+    let sizes = parse_sizes(sizes).unwrap_or_else(|| {
+        println!("Sizes must be a list of megabytes, eg. 1024,2048");
+        process::exit(1);
+    });
+    let policies = args.flag_policies
+                       .as_ref()
+                       .map_or("lru,slru", |policies| &policies[..]);
+    let policies = parse_policies(policies).unwrap_or_else(|| {
+        println!("Policies must be among lru and slru");
+        process::exit(1);
+    });
+    let rate = args.flag_sample.unwrap_or(1.0);
+    if rate <= 0.0 || rate > 1.0 {
+        println!("Sample rate must be above 0 and at most 1");
+        process::exit(1);
+    }
+    let jobs = args.flag_jobs.unwrap_or_else(|| {
+        thread::available_parallelism().map_or(1, |cpus| cpus.get())
+    });
+    if jobs == 0 {
+        println!("Jobs must be at least 1");
+        process::exit(1);
+    }
+
+    // Make sure the trace can be read before we start
+    if let Err(e) = open_trace(&args.arg_trace) {
+        println!("Failed to read {}: {}", args.arg_trace, e);
+        process::exit(1);
+    }
+
+    let mut caches = vec![];
+    for size in &sizes {
+        for policy in &policies {
+            caches.push((*size, *policy));
+        }
+    }
+
+    // Every cache reads the trace by itself, so they can run in parallel,
+    // a batch of as many as there are jobs at a time
+    let mut results = vec![];
+    for batch in caches.chunks(jobs) {
+        let mut handles = vec![];
+        for &(size, policy) in batch {
+            let path = args.arg_trace.clone();
+
+            handles.push(thread::spawn(move || {
+                let reader = try!(open_trace(&path));
+                simulate(reader, policy, size << 20, rate)
+            }));
+        }
+
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(stats)) => results.push(stats),
+                Ok(Err(e)) => {
+                    println!("Simulation failed: {}", e);
+                    process::exit(1);
+                }
+                Err(_) => {
+                    println!("Simulation failed: thread panicked");
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Every cache saw the same commands
+    println!("{} commands, {} key lookups per cache",
+             results[0].cmds,
+             results[0].get_hits + results[0].get_misses);
+    println!();
+
+    // A row of miss ratios per size, a column per policy
+    let mut header = format!("{:<12}", "mem (mb)");
+    for policy in &policies {
+        header.push_str(&format!("{:<12}", policy.name()));
+    }
+    println!("{}", header.trim_end());
+
+    for (i, size) in sizes.iter().enumerate() {
+        let mut row = format!("{:<12}", size);
+        for stats in &results[i * policies.len()..(i + 1) * policies.len()] {
+            row.push_str(&format!("{:<12}", miss_ratio(stats)));
+        }
+        println!("{}", row.trim_end());
+    }
+}
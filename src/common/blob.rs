@@ -0,0 +1,356 @@
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::slice;
+use std::sync::Arc;
+
+
+// Values bigger than this are stored as a chain of chunks of this size, so
+// that large items don't require huge contiguous allocations.
+pub const CHUNK_SIZE: usize = 512 * 1024; // 512kb
+
+
+// The data block of an item. Small blobs are kept in a single buffer, large
+// ones as a chain of chunks where every chunk but the last is exactly
+// CHUNK_SIZE long. How a blob is chunked is not visible to the client:
+// two blobs with the same bytes are equal regardless of their layout.
+//
+// Chunks are reference counted, so cloning a blob (eg. to hand an item out
+// in a response) doesn't copy any bytes. A chunk that is shared is copied
+// only when one of the blobs holding it is modified.
+#[derive(Debug, Clone)]
+pub struct Blob {
+    head: Arc<Vec<u8>>, // The first chunk (or the whole blob if it's small)
+    tail: Vec<Arc<Vec<u8>>>, // The rest of the chain, empty if not chunked
+}
+
+// Iterates over the chunks of a blob in order
+pub struct Chunks<'a> {
+    head: Option<&'a Arc<Vec<u8>>>,
+    tail: slice::Iter<'a, Arc<Vec<u8>>>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a Vec<u8>;
+
+    fn next(&mut self) -> Option<&'a Vec<u8>> {
+        match self.head.take() {
+            Some(head) => Some(&**head),
+            None => self.tail.next().map(|chunk| &**chunk),
+        }
+    }
+}
+
+impl Blob {
+    // Takes over the buffer if it fits into a single chunk, a larger one is
+    // copied into a chain of chunks
+    pub fn new(bytes: Vec<u8>) -> Blob {
+        if bytes.len() > CHUNK_SIZE {
+            return Blob::from_slice(&bytes);
+        }
+
+        Blob {
+            head: Arc::new(bytes),
+            tail: vec![],
+        }
+    }
+
+    pub fn empty() -> Blob {
+        Blob::new(vec![])
+    }
+
+    // Assemble a blob from chunks that are already CHUNK_SIZE long (except
+    // for the last one)
+    pub fn from_chunks(chunks: Vec<Vec<u8>>) -> Blob {
+        let mut chunks = chunks.into_iter();
+
+        match chunks.next() {
+            Some(head) => {
+                Blob {
+                    head: Arc::new(head),
+                    tail: chunks.map(Arc::new).collect(),
+                }
+            }
+            None => Blob::empty(),
+        }
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Blob {
+        let mut blob = Blob::empty();
+        blob.extend_from_slice(bytes);
+        blob
+    }
+
+
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            head: Some(&self.head),
+            tail: self.tail.iter(),
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        !self.tail.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks().fold(0, |acc, chunk| acc + chunk.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The number of bytes allocated to hold the data, including the chain.
+    // Every chunk is allocated together with its reference counts.
+    pub fn mem_size(&self) -> usize {
+        let chunk_overhead = mem::size_of::<Vec<u8>>() +
+                             2 * mem::size_of::<usize>();
+        let data = self.chunks().fold(0, |acc, chunk| {
+            acc + chunk.capacity() + chunk_overhead
+        });
+        data + self.tail.capacity() * mem::size_of::<Arc<Vec<u8>>>()
+    }
+
+    // Release any memory the chunks have reserved but don't use. Chunks that
+    // are shared are left alone, we would have to copy them.
+    pub fn shrink_to_fit(&mut self) {
+        if let Some(head) = Arc::get_mut(&mut self.head) {
+            head.shrink_to_fit();
+        }
+        for chunk in self.tail.iter_mut() {
+            if let Some(chunk) = Arc::get_mut(chunk) {
+                chunk.shrink_to_fit();
+            }
+        }
+        self.tail.shrink_to_fit();
+    }
+
+    pub fn reader(&self) -> BlobReader<'_> {
+        BlobReader {
+            chunks: self.chunks(),
+            current: &[],
+        }
+    }
+
+    // Copies the whole blob into one contiguous buffer
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        for chunk in self.chunks() {
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+
+
+    pub fn extend_from_slice(&mut self, mut bytes: &[u8]) {
+        // Does it still fit into a flat buffer?
+        if !self.is_chunked() && self.head.len() + bytes.len() <= CHUNK_SIZE {
+            Arc::make_mut(&mut self.head).extend_from_slice(bytes);
+            return;
+        }
+
+        while !bytes.is_empty() {
+            // Top up the last chunk before starting a new one
+            let need_chunk = match self.tail.last() {
+                Some(last) => last.len() == CHUNK_SIZE,
+                None => self.head.len() == CHUNK_SIZE,
+            };
+            if need_chunk {
+                self.tail.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+            }
+
+            let last = Arc::make_mut(match self.tail.last_mut() {
+                Some(last) => last,
+                None => &mut self.head,
+            });
+            let room = CHUNK_SIZE - last.len();
+            let (front, back) = bytes.split_at(room.min(bytes.len()));
+            last.extend_from_slice(front);
+            bytes = back;
+        }
+    }
+
+    pub fn append(&mut self, other: &Blob) {
+        for chunk in other.chunks() {
+            self.extend_from_slice(chunk);
+        }
+    }
+
+    pub fn prepend(&mut self, other: &Blob) {
+        let mut blob = other.clone();
+        blob.append(self);
+        *self = blob;
+    }
+}
+
+// Reads the bytes of a blob in order, across chunk boundaries
+pub struct BlobReader<'a> {
+    chunks: Chunks<'a>,
+    current: &'a [u8], // What's left of the chunk we're reading from
+}
+
+impl<'a> Read for BlobReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        self.current.read(buf)
+    }
+}
+
+
+impl PartialEq for Blob {
+    fn eq(&self, other: &Blob) -> bool {
+        let mine = self.chunks().flat_map(|chunk| chunk.iter());
+        let theirs = other.chunks().flat_map(|chunk| chunk.iter());
+
+        self.len() == other.len() && mine.eq(theirs)
+    }
+}
+
+impl PartialEq<Vec<u8>> for Blob {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        let theirs = other.iter();
+        let mine = self.chunks().flat_map(|chunk| chunk.iter());
+
+        self.len() == other.len() && mine.eq(theirs)
+    }
+}
+
+impl PartialEq<Blob> for Vec<u8> {
+    fn eq(&self, other: &Blob) -> bool {
+        *other == *self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::Blob;
+    use super::CHUNK_SIZE;
+
+
+    #[test]
+    fn test_blob_small_stays_flat() {
+        let mut blob = Blob::new(vec![1, 2]);
+        blob.extend_from_slice(&[3]);
+
+        assert!(!blob.is_chunked());
+        assert_eq!(blob, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_blob_large_is_chunked() {
+        let bytes = vec![7; CHUNK_SIZE * 2 + 5];
+        let blob = Blob::from_slice(&bytes);
+
+        assert!(blob.is_chunked());
+        assert_eq!(blob.len(), bytes.len());
+        assert_eq!(blob.chunks().count(), 3);
+        let lens: Vec<usize> = blob.chunks().map(|c| c.len()).collect();
+        assert_eq!(lens, vec![CHUNK_SIZE, CHUNK_SIZE, 5]);
+        assert_eq!(blob.to_vec(), bytes);
+    }
+
+    #[test]
+    fn test_blob_new_large_is_chunked() {
+        let mut blob = Blob::new(vec![1; 1 << 20]);
+        assert_eq!(blob.chunks().count(), 2);
+
+        // Appending to it starts a new chunk
+        blob.extend_from_slice(&[2, 3]);
+
+        let mut expected = vec![1; 1 << 20];
+        expected.extend_from_slice(&[2, 3]);
+
+        let lens: Vec<usize> = blob.chunks().map(|c| c.len()).collect();
+        assert_eq!(lens, vec![CHUNK_SIZE, CHUNK_SIZE, 2]);
+        assert_eq!(blob, expected);
+    }
+
+    #[test]
+    fn test_blob_append_across_chunks() {
+        let mut blob = Blob::new(vec![1; CHUNK_SIZE - 1]);
+        blob.append(&Blob::new(vec![2, 3, 4]));
+
+        let mut expected = vec![1; CHUNK_SIZE - 1];
+        expected.extend_from_slice(&[2, 3, 4]);
+
+        assert!(blob.is_chunked());
+        assert_eq!(blob.chunks().next().unwrap().len(), CHUNK_SIZE);
+        assert_eq!(*blob.chunks().nth(1).unwrap(), vec![3, 4]);
+        assert_eq!(blob, expected);
+    }
+
+    #[test]
+    fn test_blob_prepend_across_chunks() {
+        let mut blob = Blob::from_slice(&vec![1; CHUNK_SIZE + 1]);
+        blob.prepend(&Blob::new(vec![2, 3]));
+
+        let mut expected = vec![2, 3];
+        expected.extend_from_slice(&vec![1; CHUNK_SIZE + 1]);
+
+        assert_eq!(blob.chunks().count(), 2);
+        assert_eq!(blob.chunks().next().unwrap().len(), CHUNK_SIZE);
+        assert_eq!(blob, expected);
+    }
+
+    #[test]
+    fn test_blob_reader_across_chunks() {
+        let bytes: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let blob = Blob::from_slice(&bytes);
+        let mut reader = blob.reader();
+
+        // A read that straddles the chunk boundary
+        let mut buf = vec![0; CHUNK_SIZE - 5];
+        reader.read_exact(&mut buf).unwrap();
+        let mut buf2 = vec![0; 10];
+        reader.read_exact(&mut buf2).unwrap();
+        assert_eq!(buf2, bytes[CHUNK_SIZE - 5..CHUNK_SIZE + 5].to_vec());
+
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, bytes[CHUNK_SIZE + 5..].to_vec());
+    }
+
+    #[test]
+    fn test_blob_clone_shares_chunks() {
+        let blob = Blob::from_slice(&vec![1; CHUNK_SIZE + 1]);
+        let mut copy = blob.clone();
+
+        // No bytes were copied
+        for (mine, theirs) in blob.chunks().zip(copy.chunks()) {
+            assert_eq!(mine.as_ptr(), theirs.as_ptr());
+        }
+
+        // Changing the copy leaves the original as it was
+        copy.extend_from_slice(&[2]);
+        assert_eq!(blob, vec![1; CHUNK_SIZE + 1]);
+        assert_eq!(copy.len(), CHUNK_SIZE + 2);
+
+        // Only the chunk that changed was copied
+        assert_eq!(blob.chunks().next().unwrap().as_ptr(),
+                   copy.chunks().next().unwrap().as_ptr());
+        assert!(blob.chunks().nth(1).unwrap().as_ptr() !=
+                copy.chunks().nth(1).unwrap().as_ptr());
+    }
+
+    #[test]
+    fn test_blob_eq_ignores_layout() {
+        let bytes = vec![9; CHUNK_SIZE + 3];
+        let chunked = Blob::from_slice(&bytes);
+        let uneven = Blob::from_chunks(vec![vec![9; 3], vec![9; CHUNK_SIZE]]);
+
+        assert_eq!(chunked.chunks().next().unwrap().len(), CHUNK_SIZE);
+        assert_eq!(uneven.chunks().next().unwrap().len(), 3);
+        assert_eq!(chunked, uneven);
+        assert!(chunked != Blob::new(vec![9; CHUNK_SIZE + 2]));
+    }
+}
@@ -1,6 +1,9 @@
 pub const APP_NAME: &'static str = "emcache";
 pub const APP_VERSION: &'static str = "0.1.0-dev";
 
+// The longest key memcached accepts
+pub const KEY_MAXLEN: usize = 250;
+
 
 pub fn get_version_string() -> String {
     format!("{} {}", APP_NAME, APP_VERSION)
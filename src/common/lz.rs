@@ -0,0 +1,244 @@
+// A small LZ77 codec in the spirit of the lz4 block format. It favors speed
+// over ratio, which suits values like json that are full of repetition.
+//
+// The output is a sequence of:
+//
+//   token (1 byte): high nibble literal length, low nibble match length - 4
+//   [literal length extension bytes] (if the nibble is 15)
+//   literals
+//   offset (2 bytes, little endian) \
+//   [match length extension bytes]   } omitted in the last sequence
+//
+// Extension bytes are summed until one of them is not 255.
+
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = 65535;
+const HASH_LOG: usize = 12;
+
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    (bytes[pos] as u32) | (bytes[pos + 1] as u32) << 8 |
+    (bytes[pos + 2] as u32) << 16 | (bytes[pos + 3] as u32) << 24
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+// Writes the literals followed by the match (offset, length), if any
+fn write_sequence(out: &mut Vec<u8>,
+                  literals: &[u8],
+                  mtch: Option<(usize, usize)>) {
+    let lit_len = literals.len();
+    let match_len = match mtch {
+        Some((_, len)) => len - MIN_MATCH,
+        None => 0,
+    };
+
+    let token = (lit_len.min(15) << 4) | match_len.min(15);
+    out.push(token as u8);
+    if lit_len >= 15 {
+        write_len(out, lit_len - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, _)) = mtch {
+        out.push(offset as u8);
+        out.push((offset >> 8) as u8);
+        if match_len >= 15 {
+            write_len(out, match_len - 15);
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+
+    // Maps the hash of 4 bytes to the last position they were seen at (+1, so
+    // that 0 means never seen)
+    let mut table = vec![0usize; 1 << HASH_LOG];
+
+    let mut anchor = 0; // Start of the literals not yet written
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let seq = read_u32(input, pos);
+        let slot = hash(seq);
+        let candidate = table[slot];
+        table[slot] = pos + 1;
+
+        if candidate > 0 {
+            let cand = candidate - 1;
+
+            if pos - cand <= MAX_OFFSET && read_u32(input, cand) == seq {
+                // We have a match, see how far it goes
+                let mut len = MIN_MATCH;
+                while pos + len < input.len() &&
+                      input[cand + len] == input[pos + len] {
+                    len += 1;
+                }
+
+                write_sequence(&mut out,
+                               &input[anchor..pos],
+                               Some((pos - cand, len)));
+
+                pos += len;
+                anchor = pos;
+                continue;
+            }
+        }
+
+        pos += 1;
+    }
+
+    // Whatever is left over goes out as literals
+    write_sequence(&mut out, &input[anchor..], None);
+
+    out
+}
+
+
+fn read_len(input: &[u8], ip: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble < 15 {
+        return Some(len);
+    }
+
+    loop {
+        let byte = match input.get(*ip) {
+            Some(byte) => *byte as usize,
+            None => return None,
+        };
+        *ip += 1;
+        len += byte;
+
+        if byte != 255 {
+            return Some(len);
+        }
+    }
+}
+
+// Returns None if the input is corrupt or doesn't decompress to raw_len bytes
+pub fn decompress(input: &[u8], raw_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(raw_len);
+    let mut ip = 0;
+
+    loop {
+        let token = match input.get(ip) {
+            Some(token) => *token as usize,
+            None => return None,
+        };
+        ip += 1;
+
+        // Copy the literals
+        let lit_len = match read_len(input, &mut ip, token >> 4) {
+            Some(len) => len,
+            None => return None,
+        };
+        if ip + lit_len > input.len() {
+            return None;
+        }
+        out.extend_from_slice(&input[ip..ip + lit_len]);
+        ip += lit_len;
+
+        // The last sequence has no match
+        if ip == input.len() {
+            break;
+        }
+
+        // Copy the match, which may overlap with what it produces
+        if ip + 2 > input.len() {
+            return None;
+        }
+        let offset = input[ip] as usize | (input[ip + 1] as usize) << 8;
+        ip += 2;
+
+        let match_len = match read_len(input, &mut ip, token & 15) {
+            Some(len) => len + MIN_MATCH,
+            None => return None,
+        };
+        if offset == 0 || offset > out.len() ||
+           out.len() + match_len > raw_len {
+            return None;
+        }
+
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+
+    match out.len() == raw_len {
+        true => Some(out),
+        false => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::compress;
+    use super::decompress;
+
+
+    fn roundtrip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        decompress(&compressed, input.len()).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip_empty() {
+        assert_eq!(roundtrip(b""), b"".to_vec());
+    }
+
+    #[test]
+    fn test_roundtrip_short() {
+        assert_eq!(roundtrip(b"abc"), b"abc".to_vec());
+    }
+
+    #[test]
+    fn test_roundtrip_repetitive() {
+        let mut input = vec![];
+        for _ in 0..100 {
+            input.extend_from_slice(b"{\"id\": 1, \"name\": \"x\"}");
+        }
+        let compressed = compress(&input);
+
+        assert!(compressed.len() < input.len() / 5);
+        assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_roundtrip_long_runs() {
+        // Literal and match lengths that need extension bytes
+        let mut input: Vec<u8> = (0..1000).map(|i| (i * 7 % 256) as u8)
+                                          .collect();
+        input.extend_from_slice(&vec![0; 70000]);
+        assert_eq!(roundtrip(&input), input);
+    }
+
+    #[test]
+    fn test_decompress_corrupt() {
+        let input = b"abcdabcdabcdabcd".to_vec();
+        let compressed = compress(&input);
+
+        // Wrong length
+        assert_eq!(None, decompress(&compressed, input.len() + 1));
+        // Truncated
+        assert_eq!(None,
+                   decompress(&compressed[..compressed.len() - 1],
+                              input.len()));
+        // Empty
+        assert_eq!(None, decompress(b"", 0));
+    }
+}
@@ -1,3 +1,5 @@
 // Declare sub modules
+pub mod blob;
 pub mod consts;
 pub mod conversions;
+pub mod lz;
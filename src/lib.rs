@@ -0,0 +1,25 @@
+// Benchmark testing primitives
+#![feature(test)]
+extern crate test;
+
+#[cfg_attr(test, macro_use)]
+extern crate maplit;
+extern crate bufstream;
+extern crate docopt;
+extern crate linked_hash_map;
+extern crate libc;
+extern crate net2;
+extern crate rand;
+extern crate rustc_serialize;
+extern crate time;
+
+pub mod common;
+pub mod metrics;
+pub mod options;
+pub mod orchestrator;
+pub mod platform;
+pub mod protocol;
+pub mod storage;
+pub mod tcp_transport;
+pub mod testlib;
+pub mod trace;
@@ -1,31 +1,8 @@
-// Benchmark testing primitives
-#![feature(test)]
-extern crate test;
+extern crate emcache;
 
-#[macro_use]
-extern crate maplit;
-extern crate bufstream;
-extern crate docopt;
-extern crate linked_hash_map;
-extern crate libc;
-extern crate net2;
-extern crate rand;
-extern crate rustc_serialize;
-extern crate time;
-
-mod common;
-mod metrics;
-mod options;
-mod orchestrator;
-mod platform;
-mod protocol;
-mod storage;
-mod tcp_transport;
-mod testlib;
-
-use common::consts;
-use options::parse_args;
-use orchestrator::ListenerTask;
+use emcache::common::consts;
+use emcache::options::parse_args;
+use emcache::orchestrator::ListenerTask;
 
 
 fn print_version() {
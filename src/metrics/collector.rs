@@ -0,0 +1,64 @@
+use super::Distribution;
+use super::MetricId;
+use super::TopKeys;
+
+
+// Where the tasks record their metrics. Tasks are generic over it, so with
+// the NullCollector all the recording compiles away.
+pub trait Collector {
+    fn is_enabled(&self) -> bool;
+
+    fn start_timer(&mut self, id: MetricId);
+    fn stop_timer(&mut self, id: MetricId);
+
+    fn incr_counter(&mut self, id: MetricId, count: u64);
+    fn set_gauge(&mut self, id: MetricId, value: f64);
+    fn record_sample(&mut self, id: MetricId, value: f64);
+
+    fn record_distribution(&mut self, distribution: Distribution);
+    fn record_top_keys(&mut self, top_keys: TopKeys);
+
+    // Send off what was recorded so far
+    fn flush_metrics(&mut self);
+}
+
+
+// Records nothing, for when metrics are off
+pub struct NullCollector;
+
+impl NullCollector {
+    pub fn new() -> NullCollector {
+        NullCollector
+    }
+}
+
+impl Collector for NullCollector {
+    #[inline(always)]
+    fn is_enabled(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    fn start_timer(&mut self, _id: MetricId) {}
+
+    #[inline(always)]
+    fn stop_timer(&mut self, _id: MetricId) {}
+
+    #[inline(always)]
+    fn incr_counter(&mut self, _id: MetricId, _count: u64) {}
+
+    #[inline(always)]
+    fn set_gauge(&mut self, _id: MetricId, _value: f64) {}
+
+    #[inline(always)]
+    fn record_sample(&mut self, _id: MetricId, _value: f64) {}
+
+    #[inline(always)]
+    fn record_distribution(&mut self, _distribution: Distribution) {}
+
+    #[inline(always)]
+    fn record_top_keys(&mut self, _top_keys: TopKeys) {}
+
+    #[inline(always)]
+    fn flush_metrics(&mut self) {}
+}
@@ -0,0 +1,20 @@
+use super::StartTime;
+
+
+// Something happened a number of times, eg. commands were processed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counter {
+    pub name: String,
+    pub time: StartTime, // When it happened
+    pub count: u64,
+}
+
+impl Counter {
+    pub fn new(name: &str, time: StartTime, count: u64) -> Counter {
+        Counter {
+            name: name.to_string(),
+            time: time,
+            count: count,
+        }
+    }
+}
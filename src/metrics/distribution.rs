@@ -0,0 +1,25 @@
+// How the values of something (eg. item sizes) are spread out at a point in
+// time. Unlike a Timing this is computed where the values live and sent
+// along as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub name: String,
+    pub n: u64,
+    pub p50: Option<f64>,
+    pub p90: Option<f64>,
+    pub p99: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Distribution {
+    pub fn new(name: &str, n: u64) -> Distribution {
+        Distribution {
+            name: name.to_string(),
+            n: n,
+            p50: None,
+            p90: None,
+            p99: None,
+            max: None,
+        }
+    }
+}
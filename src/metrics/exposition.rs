@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use protocol::cmd::Stat;
+
+use super::statistics::Histogram;
+
+
+// What we prefix every metric name with
+const NAMESPACE: &'static str = "emcache";
+
+// Stats that can go down as well as up, the rest only ever go up
+const GAUGE_STATS: [&'static str; 12] = ["bytes",
+                                         "compress_ratio",
+                                         "curr_items",
+                                         "extstore_bytes",
+                                         "extstore_index_bytes",
+                                         "extstore_items",
+                                         "hash_bytes",
+                                         "limit_maxbytes",
+                                         "pid",
+                                         "rss",
+                                         "time",
+                                         "uptime"];
+
+// The upper bounds (in seconds) of the latency buckets we expose. Our own
+// histograms are much finer than that, but scrapers don't need that much.
+const LATENCY_BUCKETS: [f64; 16] = [0.00001, 0.000025, 0.00005, 0.0001,
+                                    0.00025, 0.0005, 0.001, 0.0025, 0.005,
+                                    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+pub const CONTENT_TYPE: &'static str = "application/openmetrics-text; \
+                                        version=1.0.0; charset=utf-8";
+
+
+// Label values are quoted, so quotes and backslashes in them are escaped
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\")
+         .replace('"', "\\\"")
+         .replace('\n', "\\n")
+}
+
+fn write_stats(out: &mut String, stats: &[Stat]) {
+    for stat in stats {
+        let name = format!("{}_{}", NAMESPACE, stat.key);
+
+        // The version is the only stat that isn't a number
+        if stat.key == "version" {
+            writeln!(out, "# TYPE {} info", name).unwrap();
+            writeln!(out,
+                     "{}_info{{version=\"{}\"}} 1",
+                     name,
+                     escape_label(&stat.value))
+                .unwrap();
+            continue;
+        }
+
+        let value = match stat.value.parse::<f64>() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+
+        if GAUGE_STATS.contains(&&stat.key[..]) {
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        } else {
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{}_total {}", name, value).unwrap();
+        }
+    }
+}
+
+fn write_latencies(out: &mut String, timers: &HashMap<String, Histogram>) {
+    if timers.is_empty() {
+        return;
+    }
+
+    let name = format!("{}_latency_seconds", NAMESPACE);
+    writeln!(out, "# TYPE {} histogram", name).unwrap();
+    writeln!(out, "# UNIT {} seconds", name).unwrap();
+
+    let mut timer_names: Vec<&String> = timers.keys().collect();
+    timer_names.sort();
+
+    for timer_name in timer_names {
+        let histogram = timers.get(timer_name).unwrap();
+        let timer = escape_label(timer_name);
+
+        for le in LATENCY_BUCKETS.iter() {
+            writeln!(out,
+                     "{}_bucket{{timer=\"{}\",le=\"{}\"}} {}",
+                     name,
+                     timer,
+                     le,
+                     histogram.count_at_or_below(*le))
+                .unwrap();
+        }
+        writeln!(out,
+                 "{}_bucket{{timer=\"{}\",le=\"+Inf\"}} {}",
+                 name,
+                 timer,
+                 histogram.len())
+            .unwrap();
+        writeln!(out,
+                 "{}_count{{timer=\"{}\"}} {}",
+                 name,
+                 timer,
+                 histogram.len())
+            .unwrap();
+        writeln!(out,
+                 "{}_sum{{timer=\"{}\"}} {}",
+                 name,
+                 timer,
+                 histogram.sum())
+            .unwrap();
+    }
+}
+
+// Render the stats (as returned by the stats command) and latencies in the
+// OpenMetrics text format
+pub fn render_openmetrics(stats: &[Stat],
+                          timers: &HashMap<String, Histogram>)
+                          -> String {
+    let mut out = String::new();
+
+    write_stats(&mut out, stats);
+    write_latencies(&mut out, timers);
+    out.push_str("# EOF\n");
+
+    out
+}
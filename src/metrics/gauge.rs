@@ -0,0 +1,21 @@
+use super::StartTime;
+
+
+// The level of something at a point in time, eg. the length of a queue. Only
+// the latest level is of interest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gauge {
+    pub name: String,
+    pub time: StartTime, // When it was at that level
+    pub value: f64,
+}
+
+impl Gauge {
+    pub fn new(name: &str, time: StartTime, value: f64) -> Gauge {
+        Gauge {
+            name: name.to_string(),
+            time: time,
+            value: value,
+        }
+    }
+}
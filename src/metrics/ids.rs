@@ -0,0 +1,31 @@
+use super::MetricId;
+
+
+// The metrics we record all the time have fixed ids, so there is nothing to
+// look up when recording them. Their names are in KNOWN, in the same order.
+
+pub const DRIVER_TASK_BACKLOG: MetricId = MetricId(0);
+pub const DRIVER_TASK_CMDS: MetricId = MetricId(1);
+pub const DRIVER_TASK_EXEC_CMD: MetricId = MetricId(2);
+pub const DRIVER_TASK_LOOP: MetricId = MetricId(3);
+pub const DRIVER_TASK_RECV_CMD: MetricId = MetricId(4);
+pub const DRIVER_TASK_SEND_RESP: MetricId = MetricId(5);
+pub const DRIVER_TASK_WATCH_DROPS: MetricId = MetricId(6);
+pub const TRANSPORT_TASK_LOOP: MetricId = MetricId(7);
+pub const TRANSPORT_TASK_READ_CMD: MetricId = MetricId(8);
+pub const TRANSPORT_TASK_RECV_RESP: MetricId = MetricId(9);
+pub const TRANSPORT_TASK_SEND_CMD: MetricId = MetricId(10);
+pub const TRANSPORT_TASK_WRITE_RESP: MetricId = MetricId(11);
+
+pub const KNOWN: [&'static str; 12] = ["DriverTask:backlog",
+                                       "DriverTask:cmds",
+                                       "DriverTask:exec_cmd",
+                                       "DriverTask:loop",
+                                       "DriverTask:recv_cmd",
+                                       "DriverTask:send_resp",
+                                       "DriverTask:watch_drops",
+                                       "TransportTask:loop",
+                                       "TransportTask:read_cmd",
+                                       "TransportTask:recv_resp",
+                                       "TransportTask:send_cmd",
+                                       "TransportTask:write_resp"];
@@ -1,43 +1,55 @@
-use std::collections::HashMap;
-
 use platform::time::time_now;
 
+use super::MetricId;
 use super::StartTime;
 use super::Timing;
 
 
+// The timers that are running, indexed by metric id
 #[derive(Debug, Clone, PartialEq)]
 pub struct LiveTimers {
-    timers: HashMap<String, StartTime>,
+    timers: Vec<Option<StartTime>>,
 }
 
 impl LiveTimers {
     pub fn new() -> LiveTimers {
-        LiveTimers { timers: HashMap::new() }
+        LiveTimers { timers: vec![] }
     }
 
-    pub fn get_timers(&self) -> &HashMap<String, StartTime> {
-        &self.timers
+    pub fn get_timer(&self, id: MetricId) -> Option<StartTime> {
+        match self.timers.get(id.index()) {
+            Some(start_time) => *start_time,
+            None => None,
+        }
     }
 
 
-    pub fn start(&mut self, name: &str) -> StartTime {
+    pub fn start(&mut self, id: MetricId) -> StartTime {
         let start_time = time_now();
-        self.timers.insert(name.to_string(), start_time.clone());
+
+        let index = id.index();
+        if index >= self.timers.len() {
+            self.timers.resize(index + 1, None);
+        }
+        self.timers[index] = Some(start_time);
+
         start_time
     }
 
-    pub fn stop(&mut self, name: &str) -> Timing {
+    pub fn stop(&mut self, id: MetricId) -> Timing {
         let stop_time = time_now();
 
-        let opt = self.timers.remove(name);
+        let opt = match self.timers.get_mut(id.index()) {
+            Some(start_time) => start_time.take(),
+            None => None,
+        };
         if opt.is_none() {
-            panic!("Tried to stop non-live timer: {:?}", name);
+            panic!("Tried to stop non-live timer: {:?}", id.name());
         }
 
         let start_time = opt.unwrap();
         let duration = stop_time - start_time;
 
-        Timing::new(name, start_time, duration)
+        Timing::new(id.name(), start_time, duration)
     }
 }
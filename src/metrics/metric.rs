@@ -1,15 +1,26 @@
+use super::Counter;
+use super::Distribution;
+use super::Gauge;
+use super::Sample;
 use super::Timing;
+use super::TopKeys;
 
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Metric {
+    Counter(Counter),
+    Distribution(Distribution),
+    Gauge(Gauge),
+    Sample(Sample),
     Timing(Timing),
+    TopKeys(TopKeys),
 }
 
 impl Metric {
-    pub fn get_timing(&self) -> &Timing {
+    pub fn get_timing(&self) -> Option<&Timing> {
         match self {
-            &Metric::Timing(ref timing) => &timing,
+            &Metric::Timing(ref timing) => Some(&timing),
+            _ => None,
         }
     }
 }
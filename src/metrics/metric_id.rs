@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+use super::ids::KNOWN;
+
+
+// Names of metrics that were interned as the server went along (eg. one per
+// kind of command), after the known ones. There are only ever a handful of
+// them, so they live as long as the server.
+struct Registry {
+    ids: HashMap<&'static str, MetricId>,
+    names: Vec<&'static str>, // Id -> name, past the known ones
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+    REGISTRY.get_or_init(|| {
+        let ids = KNOWN.iter()
+                       .enumerate()
+                       .map(|(id, name)| (*name, MetricId(id as u32)))
+                       .collect();
+
+        Mutex::new(Registry {
+            ids: ids,
+            names: vec![],
+        })
+    })
+}
+
+
+// Stands for the name of a metric, so that recording one doesn't involve
+// strings. The name is only looked up once the metric is sent off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MetricId(pub u32);
+
+impl MetricId {
+    // The id of the name, the same one every time
+    pub fn intern(name: &str) -> MetricId {
+        let mut registry = registry().lock().unwrap();
+
+        if let Some(id) = registry.ids.get(name) {
+            return *id;
+        }
+
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = MetricId((KNOWN.len() + registry.names.len()) as u32);
+        registry.names.push(name);
+        registry.ids.insert(name, id);
+
+        id
+    }
+
+    pub fn index(&self) -> usize {
+        let MetricId(id) = *self;
+        id as usize
+    }
+
+    pub fn name(&self) -> &'static str {
+        let index = self.index();
+        if index < KNOWN.len() {
+            return KNOWN[index];
+        }
+
+        let registry = registry().lock().unwrap();
+        registry.names[index - KNOWN.len()]
+    }
+}
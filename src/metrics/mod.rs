@@ -1,26 +1,53 @@
 // Declare sub modules
+pub mod collector;
+pub mod counter;
+pub mod distribution;
+pub mod exposition;
+pub mod gauge;
+pub mod ids;
 pub mod live_timers;
 pub mod metric;
+pub mod metric_id;
 pub mod metrics;
 pub mod recorder;
+pub mod sample;
+pub mod sinks;
 pub mod statistics;
+pub mod summary;
 pub mod time_series;
 pub mod timer;
 pub mod timing;
+pub mod top_keys;
 pub mod typedefs;
 
 // internal stuff
+#[cfg(test)]
 mod tests;  // needed to be part of the compilation unit in test mode
 
 
 // Export our public api
+pub use self::collector::Collector;
+pub use self::collector::NullCollector;
+pub use self::counter::Counter;
+pub use self::distribution::Distribution;
+pub use self::exposition::render_openmetrics;
+pub use self::gauge::Gauge;
 pub use self::live_timers::LiveTimers;
 pub use self::metric::Metric;
+pub use self::metric_id::MetricId;
 pub use self::metrics::Metrics;
 pub use self::recorder::MetricsRecorder;
+pub use self::sample::Sample;
+pub use self::sinks::JsonLinesSink;
+pub use self::sinks::MetricsSink;
+pub use self::sinks::StatsdSink;
+pub use self::sinks::StdoutSink;
+pub use self::statistics::Histogram;
+pub use self::summary::Summary;
 pub use self::time_series::TimeSeries;
 pub use self::timer::Timer;
 pub use self::timing::Timing;
+pub use self::top_keys::TopKeys;
 pub use self::typedefs::Duration;
 pub use self::typedefs::Second;
 pub use self::typedefs::StartTime;
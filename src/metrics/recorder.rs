@@ -1,17 +1,20 @@
 use orchestrator::MetricsSender;
+use platform::time::time_now;
 
+use super::Collector;
+use super::Counter;
+use super::Distribution;
+use super::Gauge;
 use super::LiveTimers;
 use super::Metric;
+use super::MetricId;
 use super::Metrics;
+use super::Sample;
+use super::TopKeys;
 
 
+// Collects metrics and sends them off to the metrics task over a channel
 pub struct MetricsRecorder {
-    // If not enabled the recorder is just a shim - records nothing, transmits
-    // nothing (no performance overhead)
-    // TODO: Consider replacing this flag with a Collector trait - can implement
-    // a real collector and a NullCollector
-    enabled: bool,
-
     live_timers: LiveTimers,
     metrics: Metrics,
 
@@ -19,37 +22,53 @@ pub struct MetricsRecorder {
 }
 
 impl MetricsRecorder {
-    pub fn new(met_tx: MetricsSender, enabled: bool) -> MetricsRecorder {
+    pub fn new(met_tx: MetricsSender) -> MetricsRecorder {
         MetricsRecorder {
-            enabled: enabled,
             live_timers: LiveTimers::new(),
             metrics: Metrics::new(),
             met_tx: met_tx,
         }
     }
+}
 
-    pub fn start_timer(&mut self, name: &str) {
-        if !self.enabled {
-            return;
-        }
-
-        self.live_timers.start(name);
+impl Collector for MetricsRecorder {
+    fn is_enabled(&self) -> bool {
+        true
     }
 
-    pub fn stop_timer(&mut self, name: &str) {
-        if !self.enabled {
-            return;
-        }
+    fn start_timer(&mut self, id: MetricId) {
+        self.live_timers.start(id);
+    }
 
-        let timing = self.live_timers.stop(name);
+    fn stop_timer(&mut self, id: MetricId) {
+        let timing = self.live_timers.stop(id);
         self.metrics.push(Metric::Timing(timing));
     }
 
-    pub fn flush_metrics(&mut self) {
-        if !self.enabled {
-            return;
-        }
+    fn incr_counter(&mut self, id: MetricId, count: u64) {
+        let counter = Counter::new(id.name(), time_now(), count);
+        self.metrics.push(Metric::Counter(counter));
+    }
+
+    fn set_gauge(&mut self, id: MetricId, value: f64) {
+        let gauge = Gauge::new(id.name(), time_now(), value);
+        self.metrics.push(Metric::Gauge(gauge));
+    }
+
+    fn record_sample(&mut self, id: MetricId, value: f64) {
+        let sample = Sample::new(id.name(), time_now(), value);
+        self.metrics.push(Metric::Sample(sample));
+    }
+
+    fn record_distribution(&mut self, distribution: Distribution) {
+        self.metrics.push(Metric::Distribution(distribution));
+    }
+
+    fn record_top_keys(&mut self, top_keys: TopKeys) {
+        self.metrics.push(Metric::TopKeys(top_keys));
+    }
 
+    fn flush_metrics(&mut self) {
         // package up all our data into a metrics object
         let metrics = self.metrics.clone();
 
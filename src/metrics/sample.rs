@@ -0,0 +1,22 @@
+use super::StartTime;
+
+
+// One value of something we want to know the spread of over time, eg. the
+// length of a queue every time we look at it. Like a Timing, but for values
+// that aren't durations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub time: StartTime, // When it was taken
+    pub value: f64,
+}
+
+impl Sample {
+    pub fn new(name: &str, time: StartTime, value: f64) -> Sample {
+        Sample {
+            name: name.to_string(),
+            time: time,
+            value: value,
+        }
+    }
+}
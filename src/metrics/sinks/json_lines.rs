@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+
+use rustc_serialize::json::Json;
+
+use metrics::Summary;
+use metrics::statistics::AggregatedMetric;
+
+use super::MetricsSink;
+
+
+// How many rotated files we keep around (as path.1, path.2, ...)
+const ROTATED_FILES: u32 = 3;
+
+
+fn number(value: Option<f64>) -> Json {
+    match value {
+        Some(value) => Json::F64(value),
+        None => Json::Null,
+    }
+}
+
+fn aggregated(agg: &AggregatedMetric) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("n".to_string(), Json::U64(agg.n));
+    obj.insert("avg".to_string(), number(agg.avg));
+    obj.insert("p0".to_string(), number(agg.p0));
+    obj.insert("p50".to_string(), number(agg.p50));
+    obj.insert("p90".to_string(), number(agg.p90));
+    obj.insert("p99".to_string(), number(agg.p99));
+    obj.insert("p999".to_string(), number(agg.p999));
+    obj.insert("max".to_string(), number(agg.max));
+    Json::Object(obj)
+}
+
+
+// Appends a summary per line, as a JSON object, to a file that is rotated
+// once it gets too big
+pub struct JsonLinesSink {
+    path: String,
+    max_bytes: u64, // Rotate the file when it would get bigger than this
+    prefix: String, // Which server the metrics are from
+    tags: Vec<(String, String)>,
+
+    file: Option<File>, // Opened on the first write
+    written: u64, // Size of the file in bytes
+}
+
+impl JsonLinesSink {
+    pub fn new(path: &str,
+               max_bytes: u64,
+               prefix: &str,
+               tags: Vec<(String, String)>)
+               -> JsonLinesSink {
+        JsonLinesSink {
+            path: path.to_string(),
+            max_bytes: max_bytes,
+            prefix: prefix.to_string(),
+            tags: tags,
+            file: None,
+            written: 0,
+        }
+    }
+
+    pub fn render(&self, summary: &Summary) -> String {
+        let mut obj = BTreeMap::new();
+        obj.insert("time".to_string(), Json::F64(summary.time));
+        obj.insert("interval".to_string(), Json::F64(summary.interval));
+        obj.insert("prefix".to_string(), Json::String(self.prefix.clone()));
+
+        let tags = self.tags
+                       .iter()
+                       .map(|&(ref key, ref value)| {
+                           (key.clone(), Json::String(value.clone()))
+                       })
+                       .collect();
+        obj.insert("tags".to_string(), Json::Object(tags));
+
+        let timers = summary.timers
+                            .iter()
+                            .map(|(name, agg)| (name.clone(), aggregated(agg)))
+                            .collect();
+        obj.insert("timers".to_string(), Json::Object(timers));
+
+        let counters = summary.counters
+                              .iter()
+                              .map(|(name, count)| {
+                                  (name.clone(), Json::U64(*count))
+                              })
+                              .collect();
+        obj.insert("counters".to_string(), Json::Object(counters));
+
+        let gauges = summary.gauges
+                            .iter()
+                            .map(|(name, value)| {
+                                (name.clone(), Json::F64(*value))
+                            })
+                            .collect();
+        obj.insert("gauges".to_string(), Json::Object(gauges));
+
+        let samples = summary.samples
+                             .iter()
+                             .map(|(name, agg)| {
+                                 (name.clone(), aggregated(agg))
+                             })
+                             .collect();
+        obj.insert("samples".to_string(), Json::Object(samples));
+
+        let distributions =
+            summary.distributions
+                   .iter()
+                   .map(|(name, dist)| {
+                       let mut dist_obj = BTreeMap::new();
+                       dist_obj.insert("n".to_string(), Json::U64(dist.n));
+                       dist_obj.insert("p50".to_string(), number(dist.p50));
+                       dist_obj.insert("p90".to_string(), number(dist.p90));
+                       dist_obj.insert("p99".to_string(), number(dist.p99));
+                       dist_obj.insert("max".to_string(), number(dist.max));
+                       (name.clone(), Json::Object(dist_obj))
+                   })
+                   .collect();
+        obj.insert("distributions".to_string(), Json::Object(distributions));
+
+        let top_keys =
+            summary.top_keys
+                   .iter()
+                   .map(|(name, keys)| {
+                       let keys = keys.keys
+                                      .iter()
+                                      .map(|&(ref key, count)| {
+                                          Json::Array(vec![
+                                              Json::String(key.clone()),
+                                              Json::U64(count),
+                                          ])
+                                      })
+                                      .collect();
+                       (name.clone(), Json::Array(keys))
+                   })
+                   .collect();
+        obj.insert("top_keys".to_string(), Json::Object(top_keys));
+
+        Json::Object(obj).to_string()
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let file = try!(OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(&self.path));
+        self.written = try!(file.metadata()).len();
+        self.file = Some(file);
+
+        Ok(())
+    }
+
+    // Shift path to path.1, path.1 to path.2 and so on, dropping the oldest
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        for i in (1..ROTATED_FILES).rev() {
+            let from = format!("{}.{}", self.path, i);
+            if fs::metadata(&from).is_ok() {
+                try!(fs::rename(&from, format!("{}.{}", self.path, i + 1)));
+            }
+        }
+        try!(fs::rename(&self.path, format!("{}.1", self.path)));
+
+        self.open()
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+
+        if self.file.is_none() {
+            try!(self.open());
+        }
+
+        // An empty file takes the line however long it is
+        if self.written > 0 && self.written + len > self.max_bytes {
+            try!(self.rotate());
+        }
+
+        try!(writeln!(self.file.as_mut().unwrap(), "{}", line));
+        self.written += len;
+
+        Ok(())
+    }
+}
+
+impl MetricsSink for JsonLinesSink {
+    fn write(&mut self, summary: &Summary) {
+        let line = self.render(summary);
+
+        if let Err(err) = self.write_line(&line) {
+            println!("Failed to write metrics to {}: {}", self.path, err);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+// Declare sub modules
+pub mod json_lines;
+pub mod sink;
+pub mod statsd;
+pub mod stdout;
+
+// internal stuff
+#[cfg(test)]
+mod tests;  // needed to be part of the compilation unit in test mode
+
+
+// Export our public api
+pub use self::json_lines::JsonLinesSink;
+pub use self::sink::MetricsSink;
+pub use self::statsd::StatsdSink;
+pub use self::stdout::StdoutSink;
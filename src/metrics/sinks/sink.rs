@@ -0,0 +1,7 @@
+use metrics::Summary;
+
+
+// Somewhere to report metrics to, once every interval
+pub trait MetricsSink {
+    fn write(&mut self, summary: &Summary);
+}
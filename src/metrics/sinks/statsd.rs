@@ -0,0 +1,204 @@
+use std::io;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+
+use metrics::Summary;
+use metrics::statistics::AggregatedMetric;
+use metrics::summary::sorted_names;
+
+use super::MetricsSink;
+
+
+// Lines are batched into packets no bigger than this, so that they make it
+// across a network with the usual MTU in one piece
+const MAX_PACKET: usize = 1432;
+
+
+// StatsD splits lines on ':' and '|', so names are made of letters, digits and
+// a few punctuation characters, with our ':' separators turned into dots
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            match c {
+                ':' => '.',
+                '_' | '-' | '.' => c,
+                c if c.is_ascii_alphanumeric() => c,
+                _ => '_',
+            }
+        })
+        .collect()
+}
+
+
+// Sends the summary to a StatsD server over udp, as counters and gauges since
+// the aggregation already happened. With tags the lines are in the DogStatsD
+// dialect.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    addr: SocketAddr, // The StatsD server
+    prefix: String, // Put in front of every name
+    tags: Vec<(String, String)>,
+}
+
+impl StatsdSink {
+    pub fn new(addr: &str,
+               prefix: &str,
+               tags: Vec<(String, String)>)
+               -> io::Result<StatsdSink> {
+        let addr = match try!(addr.to_socket_addrs()).next() {
+            Some(addr) => addr,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "no address to send metrics to"))
+            }
+        };
+
+        let local = match addr {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = try!(UdpSocket::bind(local));
+
+        Ok(StatsdSink {
+            socket: socket,
+            addr: addr,
+            prefix: prefix.to_string(),
+            tags: tags,
+        })
+    }
+
+    fn line(&self,
+            name: &str,
+            suffix: &str,
+            value: f64,
+            kind: &str)
+            -> String {
+        let mut line = String::new();
+
+        if !self.prefix.is_empty() {
+            line.push_str(&sanitize(&self.prefix));
+            line.push('.');
+        }
+        line.push_str(&sanitize(name));
+        if !suffix.is_empty() {
+            line.push('.');
+            line.push_str(suffix);
+        }
+        line.push_str(&format!(":{}|{}", value, kind));
+
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags
+                                        .iter()
+                                        .map(|&(ref key, ref value)| {
+                                            format!("{}:{}",
+                                                    sanitize(key),
+                                                    sanitize(value))
+                                        })
+                                        .collect();
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+
+        line
+    }
+
+    // The count as a counter, the rest as gauges (scaled, eg. to ms)
+    fn aggregated_lines(&self,
+                        lines: &mut Vec<String>,
+                        name: &str,
+                        agg: &AggregatedMetric,
+                        scale: f64) {
+        lines.push(self.line(name, "count", agg.n as f64, "c"));
+
+        let stats = [("avg", agg.avg),
+                     ("p0", agg.p0),
+                     ("p50", agg.p50),
+                     ("p90", agg.p90),
+                     ("p99", agg.p99),
+                     ("p999", agg.p999),
+                     ("max", agg.max)];
+        for &(suffix, value) in stats.iter() {
+            if let Some(value) = value {
+                lines.push(self.line(name, suffix, value * scale, "g"));
+            }
+        }
+    }
+
+    pub fn render(&self, summary: &Summary) -> Vec<String> {
+        let mut lines = vec![];
+
+        // Timings are reported in ms, as StatsD does
+        for name in sorted_names(&summary.timers) {
+            let agg = summary.timers.get(name).unwrap();
+            self.aggregated_lines(&mut lines, name, agg, 1000.0);
+        }
+
+        for name in sorted_names(&summary.counters) {
+            let count = *summary.counters.get(name).unwrap();
+            lines.push(self.line(name, "", count as f64, "c"));
+        }
+
+        for name in sorted_names(&summary.gauges) {
+            let value = *summary.gauges.get(name).unwrap();
+            lines.push(self.line(name, "", value, "g"));
+        }
+
+        for name in sorted_names(&summary.samples) {
+            let agg = summary.samples.get(name).unwrap();
+            self.aggregated_lines(&mut lines, name, agg, 1.0);
+        }
+
+        for name in sorted_names(&summary.distributions) {
+            let dist = summary.distributions.get(name).unwrap();
+
+            let stats = [("p50", dist.p50),
+                         ("p90", dist.p90),
+                         ("p99", dist.p99),
+                         ("max", dist.max)];
+            for &(suffix, value) in stats.iter() {
+                if let Some(value) = value {
+                    lines.push(self.line(name, suffix, value, "g"));
+                }
+            }
+        }
+
+        // Top keys are left out, every key would make a metric of its own
+
+        lines
+    }
+
+    // Packs the lines into as few packets as we can
+    pub fn packets(lines: &[String]) -> Vec<String> {
+        let mut packets = vec![];
+        let mut packet = String::new();
+
+        for line in lines {
+            if !packet.is_empty() {
+                if packet.len() + 1 + line.len() > MAX_PACKET {
+                    packets.push(packet);
+                    packet = String::new();
+                } else {
+                    packet.push('\n');
+                }
+            }
+            packet.push_str(line);
+        }
+        if !packet.is_empty() {
+            packets.push(packet);
+        }
+
+        packets
+    }
+}
+
+impl MetricsSink for StatsdSink {
+    fn write(&mut self, summary: &Summary) {
+        let lines = self.render(summary);
+
+        // If the server isn't there the metrics are lost, that's how udp goes
+        for packet in StatsdSink::packets(&lines) {
+            let _ = self.socket.send_to(packet.as_bytes(), self.addr);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt::Write;
+
+use metrics::Summary;
+use metrics::summary::sorted_names;
+
+use super::MetricsSink;
+
+
+// Prints a human readable summary
+pub struct StdoutSink;
+
+impl StdoutSink {
+    pub fn new() -> StdoutSink {
+        StdoutSink
+    }
+
+    pub fn render(&self, summary: &Summary) -> String {
+        let mut out = String::new();
+
+        writeln!(out,
+                 "== Metrics {}s snapshot at {} ==",
+                 summary.interval,
+                 summary.time as u64)
+            .unwrap();
+
+        for name in sorted_names(&summary.timers) {
+            let agg = summary.timers.get(name).unwrap();
+
+            let avg = agg.avg.unwrap_or(-1.0) * 1000.0;
+            let p0 = agg.p0.unwrap_or(-1.0) * 1000.0;
+            let p50 = agg.p50.unwrap_or(-1.0) * 1000.0;
+            let p90 = agg.p90.unwrap_or(-1.0) * 1000.0;
+            let p99 = agg.p99.unwrap_or(-1.0) * 1000.0;
+            let p999 = agg.p999.unwrap_or(-1.0) * 1000.0;
+            let max = agg.max.unwrap_or(-1.0) * 1000.0;
+
+            writeln!(out,
+                     "{:30}  n: {:5}  p0: {:.3}ms  avg: {:.3}ms  \
+                      p50: {:.3}ms  p90: {:.3}ms  p99: {:.3}ms  \
+                      p999: {:.3}ms  max: {:.3}ms",
+                     name,
+                     agg.n,
+                     p0,
+                     avg,
+                     p50,
+                     p90,
+                     p99,
+                     p999,
+                     max)
+                .unwrap();
+        }
+
+        for name in sorted_names(&summary.counters) {
+            let count = *summary.counters.get(name).unwrap();
+            let rate = count as f64 / summary.interval;
+
+            writeln!(out, "{:30}  n: {:5}  rate: {:.1}/s", name, count, rate)
+                .unwrap();
+        }
+
+        for name in sorted_names(&summary.gauges) {
+            writeln!(out,
+                     "{:30}  value: {}",
+                     name,
+                     summary.gauges.get(name).unwrap())
+                .unwrap();
+        }
+
+        for name in sorted_names(&summary.samples) {
+            let agg = summary.samples.get(name).unwrap();
+
+            let avg = agg.avg.unwrap_or(-1.0);
+            let p0 = agg.p0.unwrap_or(-1.0);
+            let p50 = agg.p50.unwrap_or(-1.0);
+            let p90 = agg.p90.unwrap_or(-1.0);
+            let p99 = agg.p99.unwrap_or(-1.0);
+            let max = agg.max.unwrap_or(-1.0);
+
+            writeln!(out,
+                     "{:30}  n: {:5}  p0: {}  avg: {:.1}  p50: {}  p90: {}  \
+                      p99: {}  max: {}",
+                     name,
+                     agg.n,
+                     p0,
+                     avg,
+                     p50,
+                     p90,
+                     p99,
+                     max)
+                .unwrap();
+        }
+
+        for name in sorted_names(&summary.distributions) {
+            let dist = summary.distributions.get(name).unwrap();
+
+            let p50 = dist.p50.unwrap_or(-1.0);
+            let p90 = dist.p90.unwrap_or(-1.0);
+            let p99 = dist.p99.unwrap_or(-1.0);
+            let max = dist.max.unwrap_or(-1.0);
+
+            writeln!(out,
+                     "{:30}  n: {:5}  p50: {}b  p90: {}b  p99: {}b  max: {}b",
+                     name,
+                     dist.n,
+                     p50,
+                     p90,
+                     p99,
+                     max)
+                .unwrap();
+        }
+
+        for name in sorted_names(&summary.top_keys) {
+            let keys = summary.top_keys.get(name).unwrap();
+
+            for (rank, &(ref key, count)) in keys.keys.iter().enumerate() {
+                writeln!(out,
+                         "{:30}  #{}: {}  n: {}",
+                         name,
+                         rank + 1,
+                         key,
+                         count)
+                    .unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+impl MetricsSink for StdoutSink {
+    fn write(&mut self, summary: &Summary) {
+        print!("{}", self.render(summary));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use metrics::Distribution;
+use metrics::Summary;
+use metrics::TopKeys;
+use metrics::statistics::AggregatedMetric;
+use testlib::tempfile::TempPath;
+
+use super::JsonLinesSink;
+use super::MetricsSink;
+use super::StatsdSink;
+use super::StdoutSink;
+
+
+fn summary() -> Summary {
+    let mut timers = HashMap::new();
+    timers.insert("Cmd:get".to_string(),
+                  AggregatedMetric {
+                      name: "Cmd:get".to_string(),
+                      n: 4,
+                      avg: Some(0.002),
+                      p0: Some(0.001),
+                      p50: Some(0.002),
+                      p90: Some(0.003),
+                      p99: Some(0.003),
+                      p999: Some(0.003),
+                      max: Some(0.003),
+                  });
+
+    let mut distribution = Distribution::new("Cache:item_sizes", 10);
+    distribution.p50 = Some(64.0);
+
+    Summary {
+        time: 1000.0,
+        interval: 2.0,
+        timers: timers,
+        counters: hashmap!{ "Cmd:get:hit".to_string() => 3 },
+        gauges: hashmap!{ "DriverTask:backlog".to_string() => 5.0 },
+        samples: HashMap::new(),
+        distributions: hashmap!{
+            "Cache:item_sizes".to_string() => distribution,
+        },
+        top_keys: hashmap!{
+            "Driver:hotkeys".to_string() =>
+                TopKeys::new("Driver:hotkeys", vec![("a".to_string(), 7)]),
+        },
+    }
+}
+
+fn tags() -> Vec<(String, String)> {
+    vec![("env".to_string(), "prod".to_string())]
+}
+
+
+// Stdout
+
+#[test]
+fn test_stdout_render() {
+    let out = StdoutSink::new().render(&summary());
+
+    assert!(out.starts_with("== Metrics 2s snapshot at 1000 ==\n"));
+    assert!(out.contains("p50: 2.000ms"));
+    assert!(out.contains("rate: 1.5/s"));
+    assert!(out.contains("value: 5"));
+    assert!(out.contains("p50: 64b"));
+    assert!(out.contains("#1: a  n: 7"));
+}
+
+
+// JSON lines
+
+#[test]
+fn test_json_lines_render() {
+    let sink = JsonLinesSink::new("unused", 1 << 20, "emcache", tags());
+    let json = Json::from_str(&sink.render(&summary())).unwrap();
+
+    assert_eq!(Some(1000.0), json["time"].as_f64());
+    assert_eq!(Some("emcache"), json["prefix"].as_string());
+    assert_eq!(Some("prod"), json["tags"]["env"].as_string());
+    assert_eq!(Some(4), json["timers"]["Cmd:get"]["n"].as_u64());
+    assert_eq!(Some(0.002), json["timers"]["Cmd:get"]["p50"].as_f64());
+    assert_eq!(Some(3), json["counters"]["Cmd:get:hit"].as_u64());
+    assert_eq!(Some(5.0), json["gauges"]["DriverTask:backlog"].as_f64());
+    assert!(json["distributions"]["Cache:item_sizes"]["p90"].is_null());
+    let top_key = &json["top_keys"]["Driver:hotkeys"][0];
+    assert_eq!(Some("a"), top_key[0].as_string());
+    assert_eq!(Some(7), top_key[1].as_u64());
+}
+
+#[test]
+fn test_json_lines_rotate() {
+    let temp = TempPath::new("emcache-test-metrics");
+    let path = temp.as_path().to_str().unwrap().to_string();
+    let rotated = format!("{}.1", path);
+
+    // Room for one line per file
+    let line_len = JsonLinesSink::new(&path, 0, "emcache", vec![])
+                       .render(&summary())
+                       .len() as u64 + 1;
+    let mut sink = JsonLinesSink::new(&path, line_len, "emcache", vec![]);
+
+    sink.write(&summary());
+    assert_eq!(line_len, fs::metadata(&path).unwrap().len());
+    assert!(fs::metadata(&rotated).is_err());
+
+    sink.write(&summary());
+    assert_eq!(line_len, fs::metadata(&path).unwrap().len());
+    assert_eq!(line_len, fs::metadata(&rotated).unwrap().len());
+
+    let mut contents = String::new();
+    File::open(&rotated).unwrap().read_to_string(&mut contents).unwrap();
+    assert!(Json::from_str(contents.trim()).is_ok());
+
+    fs::remove_file(&rotated).unwrap();
+}
+
+
+// StatsD
+
+#[test]
+fn test_statsd_render() {
+    let sink = StatsdSink::new("127.0.0.1:8125", "emcache", vec![]).unwrap();
+    let lines = sink.render(&summary());
+
+    assert!(lines.contains(&"emcache.Cmd.get.count:4|c".to_string()));
+    assert!(lines.contains(&"emcache.Cmd.get.p50:2|g".to_string()));
+    assert!(lines.contains(&"emcache.Cmd.get.hit:3|c".to_string()));
+    assert!(lines.contains(&"emcache.DriverTask.backlog:5|g".to_string()));
+    assert!(lines.contains(&"emcache.Cache.item_sizes.p50:64|g".to_string()));
+
+    // Nothing we don't know
+    assert!(!lines.iter().any(|line| line.contains("item_sizes.p90")));
+    assert!(!lines.iter().any(|line| line.contains("hotkeys")));
+}
+
+#[test]
+fn test_statsd_packets() {
+    let lines: Vec<String> = (0..100)
+                                 .map(|i| format!("emcache.metric{}:1|c", i))
+                                 .collect();
+    let packets = StatsdSink::packets(&lines);
+
+    assert!(packets.len() > 1);
+    assert!(packets.iter().all(|packet| packet.len() <= 1432));
+    assert_eq!(lines.join("\n"), packets.join("\n"));
+}
+
+#[test]
+fn test_statsd_send() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+
+    let mut sink = StatsdSink::new(&addr, "emcache", tags()).unwrap();
+    sink.write(&summary());
+
+    let mut buf = [0; 2048];
+    let len = server.recv(&mut buf).unwrap();
+    let packet = String::from_utf8(buf[..len].to_vec()).unwrap();
+
+    let lines: Vec<&str> = packet.lines().collect();
+    assert!(lines.contains(&"emcache.Cmd.get.hit:3|c|#env:prod"));
+    assert!(lines.contains(&"emcache.DriverTask.backlog:5|g|#env:prod"));
+}
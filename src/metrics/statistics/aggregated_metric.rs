@@ -4,7 +4,9 @@ pub struct AggregatedMetric {
     pub n: u64,
     pub avg: Option<f64>,
     pub p0: Option<f64>,
+    pub p50: Option<f64>,
     pub p90: Option<f64>,
     pub p99: Option<f64>,
     pub p999: Option<f64>,
+    pub max: Option<f64>,
 }
@@ -0,0 +1,170 @@
+use std::f64;
+
+
+// Durations are counted in nanoseconds (given a duration in seconds)
+const NANOS_PER_SECOND: f64 = 1_000_000_000.0;
+
+// Every power of two is split into this many (2^bits) linear buckets, which
+// bounds the error of a value to 1/128th of it (<0.8%)
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+// Values up to 2^44 units (about 5 hours in ns) are counted precisely, larger
+// values end up in the last bucket
+const MAX_VALUE_BITS: u32 = 44;
+const BUCKETS: usize = SUB_BUCKETS * (MAX_VALUE_BITS - SUB_BUCKET_BITS + 1)
+                           as usize;
+
+
+// Counts values in buckets laid out log-linearly, like a HDR histogram: small
+// values get a bucket each, after that every power of two is split into the
+// same number of buckets. The layout is fixed, so memory use doesn't grow with
+// the number of values and histograms can be merged by adding up counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    units: f64, // What a value of 1.0 is counted as, eg. 1e9 ns per second
+    counts: Vec<u64>, // Count per bucket
+    n: u64, // Total over all buckets
+    sum: f64, // Of all values, for the average
+    min: Option<f64>, // Smallest value seen, exactly
+    max: Option<f64>, // Largest value seen, exactly
+}
+
+impl Histogram {
+    // A histogram of durations in seconds
+    pub fn new() -> Histogram {
+        Histogram::with_units(NANOS_PER_SECOND)
+    }
+
+    // A histogram of values that are counted in the given units, eg. 1.0 for
+    // whole numbers
+    pub fn with_units(units: f64) -> Histogram {
+        Histogram {
+            units: units,
+            counts: vec![0; BUCKETS],
+            n: 0,
+            sum: 0.0,
+            min: None,
+            max: None,
+        }
+    }
+
+    fn bucket_of(&self, value: f64) -> usize {
+        let units = (value * self.units).max(0.0) as u64;
+        if units < SUB_BUCKETS as u64 {
+            return units as usize;
+        }
+
+        // The power of two the value falls into, and the linear bucket within
+        let bits = 63 - units.leading_zeros();
+        let shift = bits - SUB_BUCKET_BITS;
+        let sub = (units >> shift) as usize - SUB_BUCKETS;
+
+        (SUB_BUCKETS + shift as usize * SUB_BUCKETS + sub).min(BUCKETS - 1)
+    }
+
+    // The largest value that falls into the bucket
+    fn bucket_max(&self, bucket: usize) -> f64 {
+        if bucket == BUCKETS - 1 {
+            return f64::INFINITY;
+        }
+        if bucket < SUB_BUCKETS {
+            return bucket as f64 / self.units;
+        }
+
+        let shift = (bucket / SUB_BUCKETS - 1) as u32;
+        let sub = (bucket % SUB_BUCKETS + SUB_BUCKETS) as u64;
+        let units = ((sub + 1) << shift) - 1;
+
+        units as f64 / self.units
+    }
+
+
+    pub fn add(&mut self, value: f64) {
+        let bucket = self.bucket_of(value);
+        self.counts[bucket] += 1;
+        self.n += 1;
+        self.sum += value;
+
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+
+    // Add in all the values of another histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        assert_eq!(self.units, other.units);
+
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += *other_count;
+        }
+        self.n += other.n;
+        self.sum += other.sum;
+
+        self.min = match (self.min, other.min) {
+            (Some(min), Some(other_min)) => Some(min.min(other_min)),
+            (min, other_min) => min.or(other_min),
+        };
+        self.max = match (self.max, other.max) {
+            (Some(max), Some(other_max)) => Some(max.max(other_max)),
+            (max, other_max) => max.or(other_max),
+        };
+    }
+
+
+    pub fn len(&self) -> u64 {
+        self.n
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        match self.n {
+            0 => None,
+            n => Some(self.sum / n as f64),
+        }
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    // How many values are no larger than the given value, as far as we can
+    // tell from the buckets
+    pub fn count_at_or_below(&self, value: f64) -> u64 {
+        let bucket = self.bucket_of(value);
+        let mut count = self.counts[..bucket].iter().sum();
+
+        // The value's own bucket may also hold larger values
+        if self.bucket_max(bucket) <= value {
+            count += self.counts[bucket];
+        }
+
+        count
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.max
+    }
+
+    // The value that the given fraction of values are at or below, eg. 0.99
+    // for the p99. It's the largest value of the bucket it falls into, but
+    // never beyond the values actually seen.
+    pub fn percentile(&self, pct: f64) -> Option<f64> {
+        let rank = ((self.n as f64) * pct).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += *count;
+            if seen >= rank {
+                let value = self.bucket_max(bucket);
+                let value = value.max(self.min.unwrap())
+                                 .min(self.max.unwrap());
+                return Some(value);
+            }
+        }
+
+        None
+    }
+}
@@ -1,13 +1,42 @@
+use testlib::cmp::eq_f64;
 use testlib::datagen::get_rand_f64_vec;
 
 use super::AggregatedMetric;
+use super::Histogram;
 use super::aggregate_metric;
 use super::compute_average;
+use super::compute_max;
 use super::compute_p0;
+use super::compute_p50;
 use super::compute_p90;
 use super::compute_p999;
 use super::compute_p99;
-use super::sort_f64;
+
+
+fn histogram_of(vals: &Vec<f64>) -> Histogram {
+    let mut histogram = Histogram::new();
+    for val in vals {
+        histogram.add(*val);
+    }
+    histogram
+}
+
+fn histogram_of_units(vals: &Vec<f64>, units: f64) -> Histogram {
+    let mut histogram = Histogram::with_units(units);
+    for val in vals {
+        histogram.add(*val);
+    }
+    histogram
+}
+
+// Values come back from a histogram within 1/128th of what they were
+fn assert_near(expected: f64, actual: Option<f64>) {
+    let actual = actual.unwrap();
+    assert!(eq_f64(expected, actual, expected / 128.0),
+            "{} is not near {}",
+            actual,
+            expected);
+}
 
 
 #[test]
@@ -19,104 +48,200 @@ fn test_get_rand_f64_vec() {
 
 
 #[test]
-fn test_compute_average_empty() {
-    assert_eq!(None, compute_average(&vec![]));
+fn test_histogram_empty() {
+    let histogram = Histogram::new();
+    assert_eq!(0, histogram.len());
+    assert_eq!(None, histogram.percentile(0.5));
+    assert_eq!(None, histogram.min());
+    assert_eq!(None, histogram.max());
 }
 
 #[test]
-fn test_compute_average_ok() {
-    assert_eq!(1.3, compute_average(&vec![1.1, 1.3, 1.5]).unwrap());
+fn test_histogram_small_values_exact() {
+    // Anything below 128ns has a bucket of its own
+    let histogram = histogram_of(&vec![0.000000001, 0.000000002, 0.00000005]);
+    assert_eq!(Some(0.000000001), histogram.percentile(0.0));
+    assert_eq!(Some(0.000000002), histogram.percentile(0.5));
+    assert_eq!(Some(0.00000005), histogram.percentile(1.0));
+}
+
+#[test]
+fn test_histogram_precision() {
+    // Every value in a wide range comes back within the bounded error
+    let mut val = 0.000001;
+    while val < 1000.0 {
+        let histogram = histogram_of(&vec![0.0, val, 2000.0]);
+        assert_near(val, histogram.percentile(0.5));
+        val *= 1.7;
+    }
+}
+
+#[test]
+fn test_histogram_huge_values() {
+    // Beyond the largest bucket values are still counted
+    let histogram = histogram_of(&vec![1.0, 50000.0, 90000.0]);
+    assert_eq!(3, histogram.len());
+    assert_near(1.0, histogram.percentile(0.1));
+    assert_eq!(Some(90000.0), histogram.percentile(1.0));
+}
+
+#[test]
+fn test_histogram_merge() {
+    let mut histogram = histogram_of(&get_rand_f64_vec(1, 500));
+    histogram.merge(&histogram_of(&get_rand_f64_vec(501, 1000)));
+    histogram.merge(&Histogram::new());
+
+    // Same as if we'd added all values to one histogram
+    assert_eq!(histogram_of(&get_rand_f64_vec(1, 1000)), histogram);
+}
+
+#[test]
+fn test_histogram_with_units() {
+    // Counting whole numbers, small ones are exact
+    let mut histogram = Histogram::with_units(1.0);
+    for val in &[3.0, 7.0, 100.0] {
+        histogram.add(*val);
+    }
+    assert_eq!(Some(7.0), histogram.percentile(0.5));
+
+    // Large ones are within the bounded error
+    let mut val = 1.0;
+    while val < 1e12 {
+        let histogram = histogram_of_units(&vec![0.0, val, 1e13], 1.0);
+        assert_near(val, histogram.percentile(0.5));
+        val *= 3.0;
+    }
 }
 
 
 #[test]
-fn test_sort_f64() {
-    assert_eq!(&vec![1.2, 3.1, 9.1], sort_f64(&mut vec![9.1, 1.2, 3.1]));
+fn test_compute_average_empty() {
+    assert_eq!(None, compute_average(&Histogram::new()));
+}
+
+#[test]
+fn test_compute_average_ok() {
+    let histogram = histogram_of(&vec![1.1, 1.3, 1.5]);
+    assert!(eq_f64(1.3, compute_average(&histogram).unwrap(), 0.000001));
 }
 
 
 #[test]
 fn test_compute_p0_too_short() {
-    let vals = vec![];
-    assert_eq!(None, compute_p0(&vals));
+    assert_eq!(None, compute_p0(&Histogram::new()));
 }
 
 #[test]
 fn test_compute_p0_ok() {
-    let vals = get_rand_f64_vec(1, 17);
-    assert_eq!(1.0, compute_p0(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 17));
+    assert_eq!(1.0, compute_p0(&histogram).unwrap());
+}
+
+#[test]
+fn test_compute_p50_too_short() {
+    let histogram = histogram_of(&get_rand_f64_vec(1, 1));
+    assert_eq!(None, compute_p50(&histogram));
+}
+
+#[test]
+fn test_compute_p50_ok() {
+    let histogram = histogram_of(&get_rand_f64_vec(1, 100));
+    assert_near(50.0, compute_p50(&histogram));
 }
 
 #[test]
 fn test_compute_p90_too_short() {
-    let vals = get_rand_f64_vec(1, 9);
-    assert_eq!(None, compute_p90(&vals));
+    let histogram = histogram_of(&get_rand_f64_vec(1, 9));
+    assert_eq!(None, compute_p90(&histogram));
 }
 
 #[test]
 fn test_compute_p90_small() {
-    let vals = get_rand_f64_vec(1, 10);
-    assert_eq!(10.0, compute_p90(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 10));
+    assert_near(9.0, compute_p90(&histogram));
 }
 
 #[test]
 fn test_compute_p90_large() {
-    let vals = get_rand_f64_vec(1, 100);
-    assert_eq!(91.0, compute_p90(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 100));
+    assert_near(90.0, compute_p90(&histogram));
 }
 
 
 #[test]
 fn test_compute_p99_too_short() {
-    let vals = get_rand_f64_vec(1, 99);
-    assert_eq!(None, compute_p99(&vals));
+    let histogram = histogram_of(&get_rand_f64_vec(1, 99));
+    assert_eq!(None, compute_p99(&histogram));
 }
 
 #[test]
 fn test_compute_p99_small() {
-    let vals = get_rand_f64_vec(1, 100);
-    assert_eq!(100.0, compute_p99(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 100));
+    assert_near(99.0, compute_p99(&histogram));
 }
 
 #[test]
 fn test_compute_p99_large() {
-    let vals = get_rand_f64_vec(1, 1000);
-    assert_eq!(991.0, compute_p99(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 1000));
+    assert_near(990.0, compute_p99(&histogram));
 }
 
 
 #[test]
 fn test_compute_p999_too_short() {
-    let vals = get_rand_f64_vec(1, 999);
-    assert_eq!(None, compute_p999(&vals));
+    let histogram = histogram_of(&get_rand_f64_vec(1, 999));
+    assert_eq!(None, compute_p999(&histogram));
 }
 
 #[test]
 fn test_compute_p999_small() {
-    let vals = get_rand_f64_vec(1, 1000);
-    assert_eq!(1000.0, compute_p999(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 1000));
+    assert_near(999.0, compute_p999(&histogram));
 }
 
 #[test]
 fn test_compute_p999_large() {
-    let vals = get_rand_f64_vec(1, 10000);
-    assert_eq!(9991.0, compute_p999(&vals).unwrap());
+    let histogram = histogram_of(&get_rand_f64_vec(1, 10000));
+    assert_near(9990.0, compute_p999(&histogram));
+}
+
+
+#[test]
+fn test_compute_max() {
+    assert_eq!(None, compute_max(&Histogram::new()));
+
+    let histogram = histogram_of(&get_rand_f64_vec(1, 17));
+    assert_eq!(17.0, compute_max(&histogram).unwrap());
 }
 
 
 #[test]
 fn test_compute_metric() {
     let vals = get_rand_f64_vec(1, 1000);
-    let metric = aggregate_metric("latency", &vals);
-
+    let metric = aggregate_metric("latency", &histogram_of(&vals));
+
+    assert_eq!("latency", metric.name);
+    assert_eq!(1000, metric.n);
+    assert!(eq_f64(500.5, metric.avg.unwrap(), 0.000001));
+    assert_eq!(Some(1.0), metric.p0);
+    assert_near(500.0, metric.p50);
+    assert_near(900.0, metric.p90);
+    assert_near(990.0, metric.p99);
+    assert_near(999.0, metric.p999);
+    assert_eq!(Some(1000.0), metric.max);
+
+    // Nothing to report about nothing
+    let metric = aggregate_metric("latency", &Histogram::new());
     let expected = AggregatedMetric {
         name: "latency".to_string(),
-        n: 1000,
-        avg: Some(500.5),
-        p0: Some(1.0),
-        p90: Some(901.0),
-        p99: Some(991.0),
-        p999: Some(1000.0),
+        n: 0,
+        avg: None,
+        p0: None,
+        p50: None,
+        p90: None,
+        p99: None,
+        p999: None,
+        max: None,
     };
     assert_eq!(expected, metric);
 }
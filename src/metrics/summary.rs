@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use super::Distribution;
+use super::TimeSeries;
+use super::TopKeys;
+use super::statistics::AggregatedMetric;
+
+
+// Everything we know about one interval, worked out once and handed to every
+// sink so they all report the same numbers
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub time: f64, // unixtime the interval ended
+    pub interval: f64, // in seconds
+    pub timers: HashMap<String, AggregatedMetric>, // in seconds
+    pub counters: HashMap<String, u64>, // Counted during the interval
+    pub gauges: HashMap<String, f64>, // Latest level
+    pub samples: HashMap<String, AggregatedMetric>,
+    pub distributions: HashMap<String, Distribution>, // Latest one
+    pub top_keys: HashMap<String, TopKeys>, // Latest one
+}
+
+impl Summary {
+    pub fn new(time: f64,
+               interval: f64,
+               ts: &TimeSeries,
+               distributions: &HashMap<String, Distribution>,
+               top_keys: &HashMap<String, TopKeys>)
+               -> Summary {
+        Summary {
+            time: time,
+            interval: interval,
+            timers: ts.aggregate_metrics(),
+            counters: ts.total_counters(),
+            gauges: ts.latest_gauges(),
+            samples: ts.aggregate_samples(),
+            distributions: distributions.clone(),
+            top_keys: top_keys.clone(),
+        }
+    }
+}
+
+
+// The keys of a map in order, so that output is stable
+pub fn sorted_names<V>(map: &HashMap<String, V>) -> Vec<&String> {
+    let mut names: Vec<&String> = map.keys().collect();
+    names.sort();
+    names
+}
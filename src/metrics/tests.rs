@@ -2,13 +2,33 @@ use std::sync::mpsc;
 
 use platform::time::sleep_secs;
 use platform::time::time_now;
+use protocol::cmd::Stat;
 use testlib::cmp::eq_f64;
 
+use super::Collector;
+use super::Counter;
+use super::Gauge;
 use super::LiveTimers;
+use super::Metric;
+use super::MetricId;
 use super::MetricsRecorder;
+use super::NullCollector;
+use super::Sample;
 use super::TimeSeries;
 use super::Timer;
 use super::Timing;
+use super::ids;
+use super::render_openmetrics;
+use super::statistics::Histogram;
+
+
+fn histogram(values: &[f64]) -> Histogram {
+    let mut histogram = Histogram::new();
+    for value in values {
+        histogram.add(*value);
+    }
+    histogram
+}
 
 
 #[test]
@@ -16,8 +36,8 @@ use super::Timing;
 fn test_live_timers_name_mismatch() {
     let mut lt = LiveTimers::new();
 
-    lt.start("cmd");
-    lt.stop("resp"); // panic expected
+    lt.start(MetricId::intern("cmd"));
+    lt.stop(MetricId::intern("resp")); // panic expected
 }
 
 // this is a slow test that relies on sleeps
@@ -25,23 +45,26 @@ fn test_live_timers_name_mismatch() {
 #[test]
 fn test_live_timers_ok() {
     let mut lt = LiveTimers::new();
+    let cmd = MetricId::intern("cmd");
 
     let t1 = time_now();
-    let start_time = lt.start("cmd");
+    let start_time = lt.start(cmd);
     // start_time is very close to *now*
     assert!(eq_f64(t1, start_time, 0.01));
-    // "cmd" -> start_time was added to the map
-    assert_eq!(&start_time, lt.get_timers().get("cmd").unwrap());
+    // "cmd" -> start_time was added to the timers
+    assert_eq!(Some(start_time), lt.get_timer(cmd));
 
     sleep_secs(0.25);
 
-    let timing = lt.stop("cmd");
+    let timing = lt.stop(cmd);
+    // the timing carries the name
+    assert_eq!("cmd", timing.name);
     // the returned start_time matches what we saw before
     assert_eq!(start_time, timing.start_time);
     // the duration is almost exactly the time we slept
     assert!(eq_f64(0.25, timing.duration, 0.01));
-    // "cmd" was removed from the map
-    assert!(!lt.get_timers().contains_key("cmd"));
+    // "cmd" was removed from the timers
+    assert_eq!(None, lt.get_timer(cmd));
 }
 
 #[test]
@@ -53,7 +76,7 @@ fn test_time_series_updates() {
     // construct the expected value for comparison
     let expected = hashmap!{
         "cmd".to_string() => hashmap!{
-            1 => vec![0.25],
+            1 => histogram(&[0.25]),
         },
     };
     // compare
@@ -64,7 +87,7 @@ fn test_time_series_updates() {
     // construct the expected value for comparison
     let expected = hashmap!{
         "cmd".to_string() => hashmap!{
-            1 => vec![0.25, 0.51],
+            1 => histogram(&[0.25, 0.51]),
         },
     };
     // compare
@@ -75,8 +98,8 @@ fn test_time_series_updates() {
     // construct the expected value for comparison
     let expected = hashmap!{
         "cmd".to_string() => hashmap!{
-            1 => vec![0.25, 0.51],
-            2 => vec![8.8],
+            1 => histogram(&[0.25, 0.51]),
+            2 => histogram(&[8.8]),
         },
     };
     // compare
@@ -87,11 +110,11 @@ fn test_time_series_updates() {
     // construct the expected value for comparison
     let expected = hashmap!{
         "cmd".to_string() => hashmap!{
-            1 => vec![0.25, 0.51],
-            2 => vec![8.8],
+            1 => histogram(&[0.25, 0.51]),
+            2 => histogram(&[8.8]),
         },
         "resp".to_string() => hashmap!{
-            4 => vec![1.0],
+            4 => histogram(&[1.0]),
         },
     };
     // compare
@@ -105,17 +128,182 @@ fn test_time_series_updates() {
     assert_eq!(&expected, ts.get_timers());
 }
 
+#[test]
+fn test_time_series_aggregate_all_seconds() {
+    let mut ts = TimeSeries::new();
+
+    ts.add_timing(&Timing::new("cmd", 1.1, 0.25));
+    ts.add_timing(&Timing::new("cmd", 2.3, 0.5));
+    ts.add_timing(&Timing::new("cmd", 3.7, 8.0));
+
+    // every second counts, not just the last one
+    let agg = ts.aggregate_metrics().remove("cmd").unwrap();
+    assert_eq!(3, agg.n);
+    assert_eq!(Some(0.25), agg.p0);
+    assert_eq!(Some(8.0), agg.max);
+
+    // or just the seconds we ask for
+    let agg = ts.aggregate_metrics_between(1, 3).remove("cmd").unwrap();
+    assert_eq!(2, agg.n);
+    assert_eq!(Some(0.5), agg.max);
+
+    // no timings means no aggregate
+    assert!(ts.aggregate_metrics_between(5, 10).is_empty());
+}
+
+#[test]
+fn test_time_series_merge() {
+    let mut ts = TimeSeries::new();
+    ts.add_timing(&Timing::new("cmd", 1.1, 0.25));
+
+    let mut other = TimeSeries::new();
+    other.add_timing(&Timing::new("cmd", 1.5, 0.5));
+    other.add_timing(&Timing::new("resp", 2.5, 1.0));
+
+    ts.merge(&other);
+
+    let expected = hashmap!{
+        "cmd".to_string() => hashmap!{
+            1 => histogram(&[0.25, 0.5]),
+        },
+        "resp".to_string() => hashmap!{
+            2 => histogram(&[1.0]),
+        },
+    };
+    assert_eq!(&expected, ts.get_timers());
+}
+
+#[test]
+fn test_time_series_counters() {
+    let mut ts = TimeSeries::new();
+
+    ts.add_counter(&Counter::new("cmds", 1.1, 1));
+    ts.add_counter(&Counter::new("cmds", 1.9, 2));
+    ts.add_counter(&Counter::new("cmds", 2.3, 4));
+    let expected = hashmap!{
+        "cmds".to_string() => hashmap!{
+            1 => 3,
+            2 => 4,
+        },
+    };
+    assert_eq!(&expected, ts.get_counters());
+
+    let expected = hashmap!{
+        "cmds".to_string() => 7,
+    };
+    assert_eq!(expected, ts.total_counters());
+
+    ts.clear();
+    assert!(ts.total_counters().is_empty());
+}
+
+#[test]
+fn test_time_series_gauges() {
+    let mut ts = TimeSeries::new();
+
+    ts.add_gauge(&Gauge::new("backlog", 1.1, 3.0));
+    ts.add_gauge(&Gauge::new("backlog", 2.3, 5.0));
+    // an earlier level arriving late doesn't count
+    ts.add_gauge(&Gauge::new("backlog", 1.9, 4.0));
+    let expected = hashmap!{
+        "backlog".to_string() => 5.0,
+    };
+    assert_eq!(expected, ts.latest_gauges());
+
+    // the level stays after a clear
+    ts.clear();
+    assert_eq!(expected, ts.latest_gauges());
+}
+
+#[test]
+fn test_time_series_samples() {
+    let mut ts = TimeSeries::new();
+
+    ts.add_sample(&Sample::new("backlog", 1.1, 3.0));
+    ts.add_sample(&Sample::new("backlog", 2.3, 12.0));
+    ts.add_sample(&Sample::new("backlog", 2.9, 7.0));
+
+    // samples are whole numbers, not durations
+    let mut expected = Histogram::with_units(1.0);
+    expected.add(12.0);
+    expected.add(7.0);
+    assert_eq!(&expected,
+               ts.get_samples().get("backlog").unwrap().get(&2).unwrap());
+
+    let agg = ts.aggregate_samples().remove("backlog").unwrap();
+    assert_eq!(3, agg.n);
+    assert_eq!(Some(3.0), agg.p0);
+    assert_eq!(Some(7.0), agg.p50);
+    assert_eq!(Some(12.0), agg.max);
+}
+
+fn kind_and_value(metric: &Metric) -> (&str, f64) {
+    match *metric {
+        Metric::Counter(ref counter) => ("counter", counter.count as f64),
+        Metric::Gauge(ref gauge) => ("gauge", gauge.value),
+        Metric::Sample(ref sample) => ("sample", sample.value),
+        _ => ("other", 0.0),
+    }
+}
+
+#[test]
+fn test_recorder_counter_gauge_sample() {
+    let (met_tx, met_rx) = mpsc::channel();
+    let mut rec = MetricsRecorder::new(met_tx);
+
+    rec.incr_counter(MetricId::intern("cmds"), 2);
+    rec.set_gauge(ids::DRIVER_TASK_BACKLOG, 3.0);
+    rec.record_sample(ids::DRIVER_TASK_BACKLOG, 4.0);
+    rec.flush_metrics();
+
+    let metrics = met_rx.recv().unwrap();
+    let kinds: Vec<(&str, f64)> = metrics.metrics
+                                         .iter()
+                                         .map(kind_and_value)
+                                         .collect();
+    assert_eq!(vec![("counter", 2.0), ("gauge", 3.0), ("sample", 4.0)],
+               kinds);
+}
+
+#[test]
+fn test_null_collector() {
+    let mut rec = NullCollector::new();
+    assert!(!rec.is_enabled());
+
+    // a timer on a null collector is fine, it just doesn't record anything
+    {
+        let _t = Timer::new(&mut rec, ids::DRIVER_TASK_LOOP);
+    }
+    rec.incr_counter(ids::DRIVER_TASK_CMDS, 1);
+    rec.flush_metrics();
+}
+
+#[test]
+fn test_metric_id_intern() {
+    // known metrics have fixed ids
+    assert_eq!(ids::DRIVER_TASK_LOOP, MetricId::intern("DriverTask:loop"));
+    assert_eq!("TransportTask:write_resp",
+               ids::TRANSPORT_TASK_WRITE_RESP.name());
+
+    // the rest get an id when first seen, and keep it
+    let id = MetricId::intern("Cmd:test_intern");
+    assert!(id.index() >= ids::KNOWN.len());
+    assert_eq!(id, MetricId::intern("Cmd:test_intern"));
+    assert_eq!("Cmd:test_intern", id.name());
+    assert!(id != MetricId::intern("Cmd:test_intern:hit"));
+}
+
 
 // this is a slow test that relies on sleeps
 #[ignore]
 #[test]
 fn test_timer_correct() {
     let (met_tx, met_rx) = mpsc::channel();
-    let mut rec = MetricsRecorder::new(met_tx, true);
+    let mut rec = MetricsRecorder::new(met_tx);
 
     // use Timer to make one timing
     let _rv = {
-        let _t = Timer::new(&mut rec, "cmd");
+        let _t = Timer::new(&mut rec, MetricId::intern("cmd"));
         sleep_secs(0.25);
         ()
     };
@@ -126,7 +314,7 @@ fn test_timer_correct() {
     // receive the metrics
     let metrics = met_rx.recv().unwrap();
     // verify that the timing is correct
-    let dur = metrics.first().get_timing().duration;
+    let dur = metrics.first().get_timing().unwrap().duration;
     assert!(eq_f64(0.25, dur, 0.03));
 }
 
@@ -135,12 +323,12 @@ fn test_timer_correct() {
 #[test]
 fn test_timer_wrong_binding() {
     let (met_tx, met_rx) = mpsc::channel();
-    let mut rec = MetricsRecorder::new(met_tx, true);
+    let mut rec = MetricsRecorder::new(met_tx);
 
     // use Timer to make one timing
     let _rv = {
         // this binding discards the value right away!
-        let _ = Timer::new(&mut rec, "cmd");
+        let _ = Timer::new(&mut rec, MetricId::intern("cmd"));
         sleep_secs(0.25);
         ()
     };
@@ -151,7 +339,7 @@ fn test_timer_wrong_binding() {
     // receive the metrics
     let metrics = met_rx.recv().unwrap();
     // verify that the timing is correct
-    let dur = metrics.first().get_timing().duration;
+    let dur = metrics.first().get_timing().unwrap().duration;
     assert!(eq_f64(0.0, dur, 0.01));
 }
 
@@ -160,12 +348,12 @@ fn test_timer_wrong_binding() {
 #[test]
 fn test_timer_no_binding() {
     let (met_tx, met_rx) = mpsc::channel();
-    let mut rec = MetricsRecorder::new(met_tx, true);
+    let mut rec = MetricsRecorder::new(met_tx);
 
     // use Timer to make one timing
     let _rv = {
         // no binding means Timer does not live past the first line
-        Timer::new(&mut rec, "cmd");
+        Timer::new(&mut rec, MetricId::intern("cmd"));
         sleep_secs(0.25);
         ()
     };
@@ -176,6 +364,53 @@ fn test_timer_no_binding() {
     // receive the metrics
     let metrics = met_rx.recv().unwrap();
     // verify that the timing is correct
-    let dur = metrics.first().get_timing().duration;
+    let dur = metrics.first().get_timing().unwrap().duration;
     assert!(eq_f64(0.0, dur, 0.01));
 }
+
+
+#[test]
+fn test_render_openmetrics_stats() {
+    let stats = vec![Stat::new("version", "0.1.0".to_string()),
+                     Stat::new("get_hits", "5".to_string()),
+                     Stat::new("bytes", "1024".to_string()),
+                     Stat::new("rusage_user", "0.250000".to_string())];
+
+    let expected = "# TYPE emcache_version info\n\
+                    emcache_version_info{version=\"0.1.0\"} 1\n\
+                    # TYPE emcache_get_hits counter\n\
+                    emcache_get_hits_total 5\n\
+                    # TYPE emcache_bytes gauge\n\
+                    emcache_bytes 1024\n\
+                    # TYPE emcache_rusage_user counter\n\
+                    emcache_rusage_user_total 0.25\n\
+                    # EOF\n";
+    assert_eq!(expected, render_openmetrics(&stats, &hashmap!{}));
+}
+
+#[test]
+fn test_render_openmetrics_latencies() {
+    let timers = hashmap!{
+        "cmd".to_string() => histogram(&[0.00003, 0.0002, 0.0002, 2.0]),
+    };
+
+    let text = render_openmetrics(&vec![], &timers);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!("# TYPE emcache_latency_seconds histogram", lines[0]);
+    assert_eq!("# UNIT emcache_latency_seconds seconds", lines[1]);
+    // the buckets are cumulative
+    assert!(lines.contains(&"emcache_latency_seconds_bucket\
+                             {timer=\"cmd\",le=\"0.000025\"} 0"));
+    assert!(lines.contains(&"emcache_latency_seconds_bucket\
+                             {timer=\"cmd\",le=\"0.00005\"} 1"));
+    assert!(lines.contains(&"emcache_latency_seconds_bucket\
+                             {timer=\"cmd\",le=\"0.00025\"} 3"));
+    assert!(lines.contains(&"emcache_latency_seconds_bucket\
+                             {timer=\"cmd\",le=\"1\"} 3"));
+    assert!(lines.contains(&"emcache_latency_seconds_bucket\
+                             {timer=\"cmd\",le=\"+Inf\"} 4"));
+    assert!(lines.contains(&"emcache_latency_seconds_count\
+                             {timer=\"cmd\"} 4"));
+    assert_eq!(Some(&"# EOF"), lines.last());
+}
@@ -1,63 +1,197 @@
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 
-use super::Duration;
+use super::Counter;
+use super::Gauge;
+use super::Sample;
 use super::Second;
 use super::Timing;
 use super::statistics::AggregatedMetric;
+use super::statistics::Histogram;
 use super::statistics::aggregate_metric;
 
 
+// Aggregate the histograms of the seconds from (inclusive) up to (exclusive)
+fn aggregate_between(series: &HashMap<String, HashMap<Second, Histogram>>,
+                     from: Second,
+                     to: Second)
+                     -> HashMap<String, AggregatedMetric> {
+    let mut agg_mets = HashMap::new();
+
+    for (name, seconds) in series.iter() {
+        let mut total: Option<Histogram> = None;
+        for (_, histogram) in seconds.iter()
+                                     .filter(|&(sec, _)| {
+                                         *sec >= from && *sec < to
+                                     }) {
+            match total {
+                Some(ref mut total) => total.merge(histogram),
+                None => total = Some(histogram.clone()),
+            }
+        }
+
+        if let Some(total) = total {
+            agg_mets.insert(name.to_string(), aggregate_metric(name, &total));
+        }
+    }
+
+    agg_mets
+}
+
+fn merge_histograms(ours: &mut HashMap<String, HashMap<Second, Histogram>>,
+                    theirs: &HashMap<String, HashMap<Second, Histogram>>) {
+    for (name, seconds) in theirs.iter() {
+        let ours = ours.entry(name.to_string())
+                       .or_insert_with(HashMap::new);
+
+        for (sec, histogram) in seconds.iter() {
+            match ours.entry(*sec) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(histogram),
+                Entry::Vacant(entry) => {
+                    entry.insert(histogram.clone());
+                }
+            }
+        }
+    }
+}
+
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TimeSeries {
-    // name -> { 1 -> [0.13, 0.41], 2 -> [0.42, 0.6] }
-    timers: HashMap<String, HashMap<Second, Vec<Duration>>>,
+    // name -> { 1 -> Histogram[0.13, 0.41], 2 -> Histogram[0.42, 0.6] }
+    timers: HashMap<String, HashMap<Second, Histogram>>,
+    // name -> { 1 -> 14, 2 -> 3 }
+    counters: HashMap<String, HashMap<Second, u64>>,
+    // name -> (time, value) of the latest level
+    gauges: HashMap<String, (f64, f64)>,
+    // name -> { 1 -> Histogram[4, 12], 2 -> Histogram[7] }
+    samples: HashMap<String, HashMap<Second, Histogram>>,
 }
 
 impl TimeSeries {
     pub fn new() -> TimeSeries {
-        TimeSeries { timers: HashMap::new() }
+        TimeSeries {
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+            samples: HashMap::new(),
+            timers: HashMap::new(),
+        }
     }
 
-    pub fn get_timers(&self) -> &HashMap<String, HashMap<Second, Vec<Duration>>> {
+    pub fn get_timers(&self) -> &HashMap<String, HashMap<Second, Histogram>> {
         &self.timers
     }
 
+    pub fn get_counters(&self) -> &HashMap<String, HashMap<Second, u64>> {
+        &self.counters
+    }
+
+    pub fn get_samples(&self) -> &HashMap<String, HashMap<Second, Histogram>> {
+        &self.samples
+    }
 
-    pub fn add_timing(&mut self, timing: &Timing) {
-        // does the name series exist?
-        if !self.timers.contains_key(&timing.name) {
-            self.timers.insert(timing.name.to_string(), HashMap::new());
-        }
 
-        // does the second series exist?
+    pub fn add_timing(&mut self, timing: &Timing) {
         let sec = timing.start_time as Second;
-        if !self.timers.get(&timing.name).unwrap().contains_key(&sec) {
-            self.timers.get_mut(&timing.name).unwrap().insert(sec, vec![]);
-        }
 
-        // insert the value
         self.timers
-            .get_mut(&timing.name)
-            .unwrap()
-            .get_mut(&sec)
-            .unwrap()
-            .push(timing.duration);
+            .entry(timing.name.to_string())
+            .or_insert_with(HashMap::new)
+            .entry(sec)
+            .or_insert_with(Histogram::new)
+            .add(timing.duration);
     }
 
-    pub fn aggregate_metrics(&self) -> HashMap<String, AggregatedMetric> {
-        let mut agg_mets = HashMap::new();
+    pub fn add_counter(&mut self, counter: &Counter) {
+        let sec = counter.time as Second;
 
-        for (name, seconds) in self.timers.iter() {
-            for (_, samples) in seconds.iter() {
-                let agg = aggregate_metric(name, samples);
-                agg_mets.insert(name.to_string(), agg);
+        *self.counters
+             .entry(counter.name.to_string())
+             .or_insert_with(HashMap::new)
+             .entry(sec)
+             .or_insert(0) += counter.count;
+    }
+
+    pub fn add_gauge(&mut self, gauge: &Gauge) {
+        let latest = self.gauges
+                         .entry(gauge.name.to_string())
+                         .or_insert((gauge.time, gauge.value));
+
+        // Levels may arrive out of order from different threads
+        if gauge.time >= latest.0 {
+            *latest = (gauge.time, gauge.value);
+        }
+    }
+
+    pub fn add_sample(&mut self, sample: &Sample) {
+        let sec = sample.time as Second;
+
+        self.samples
+            .entry(sample.name.to_string())
+            .or_insert_with(HashMap::new)
+            .entry(sec)
+            .or_insert_with(|| Histogram::with_units(1.0))
+            .add(sample.value);
+    }
+
+    // Add in all the metrics of another series, eg. one kept by another
+    // thread
+    pub fn merge(&mut self, other: &TimeSeries) {
+        merge_histograms(&mut self.timers, &other.timers);
+        merge_histograms(&mut self.samples, &other.samples);
+
+        for (name, seconds) in other.counters.iter() {
+            let ours = self.counters
+                           .entry(name.to_string())
+                           .or_insert_with(HashMap::new);
+
+            for (sec, count) in seconds.iter() {
+                *ours.entry(*sec).or_insert(0) += *count;
             }
         }
 
-        agg_mets
+        for (name, &(time, value)) in other.gauges.iter() {
+            self.add_gauge(&Gauge::new(name, time, value));
+        }
+    }
+
+    // Aggregate the timings of the seconds from (inclusive) up to (exclusive)
+    pub fn aggregate_metrics_between(&self,
+                                     from: Second,
+                                     to: Second)
+                                     -> HashMap<String, AggregatedMetric> {
+        aggregate_between(&self.timers, from, to)
+    }
+
+    pub fn aggregate_metrics(&self) -> HashMap<String, AggregatedMetric> {
+        self.aggregate_metrics_between(0, Second::max_value())
+    }
+
+    pub fn aggregate_samples(&self) -> HashMap<String, AggregatedMetric> {
+        aggregate_between(&self.samples, 0, Second::max_value())
+    }
+
+    // How many times each counter was counted in all
+    pub fn total_counters(&self) -> HashMap<String, u64> {
+        self.counters
+            .iter()
+            .map(|(name, seconds)| (name.to_string(), seconds.values().sum()))
+            .collect()
+    }
+
+    // The latest level of each gauge
+    pub fn latest_gauges(&self) -> HashMap<String, f64> {
+        self.gauges
+            .iter()
+            .map(|(name, &(_, value))| (name.to_string(), value))
+            .collect()
     }
 
     pub fn clear(&mut self) {
+        self.counters.clear();
+        self.samples.clear();
         self.timers.clear();
+
+        // A gauge keeps its level until it's told otherwise
     }
 }
@@ -1,24 +1,25 @@
-use super::MetricsRecorder;
+use super::Collector;
+use super::MetricId;
 
 
-pub struct Timer<'a> {
-    recorder: &'a mut MetricsRecorder,
-    name: &'a str,
+pub struct Timer<'a, C: Collector + 'a> {
+    collector: &'a mut C,
+    id: MetricId,
 }
 
-impl<'a> Timer<'a> {
-    pub fn new(recorder: &'a mut MetricsRecorder, name: &'a str) -> Timer<'a> {
-        recorder.start_timer(name);
+impl<'a, C: Collector> Timer<'a, C> {
+    pub fn new(collector: &'a mut C, id: MetricId) -> Timer<'a, C> {
+        collector.start_timer(id);
 
         Timer {
-            name: name,
-            recorder: recorder,
+            collector: collector,
+            id: id,
         }
     }
 }
 
-impl<'a> Drop for Timer<'a> {
+impl<'a, C: Collector> Drop for Timer<'a, C> {
     fn drop(&mut self) {
-        self.recorder.stop_timer(self.name);
+        self.collector.stop_timer(self.id);
     }
 }
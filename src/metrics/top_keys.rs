@@ -0,0 +1,16 @@
+// The most requested keys at a point in time and how often they were
+// requested, most requested first
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopKeys {
+    pub name: String,
+    pub keys: Vec<(String, u64)>,
+}
+
+impl TopKeys {
+    pub fn new(name: &str, keys: Vec<(String, u64)>) -> TopKeys {
+        TopKeys {
+            name: name.to_string(),
+            keys: keys,
+        }
+    }
+}
@@ -1,5 +1,14 @@
+use std::process;
+
 use docopt::Docopt;
 
+use storage::EvictionPolicy;
+
+
+// Bounds for the max item size
+const MIN_ITEM_SIZE: u64 = 1 << 10; // 1kb
+pub const MAX_ITEM_SIZE: u64 = 128 << 20; // 128mb
+
 
 // Write the Docopt usage string.
 const USAGE: &'static str = "
@@ -10,7 +19,54 @@ Options:
     --host HOST         Interface to listen on (ie. ip hostname/ip).
     -p --port PORT      Port to bind to.
     -m --mem MEMSIZE    Max memory to use (in megabytes).
+    -I --max-item-size SIZE
+                        Max size of an item (eg. 512k, 1m, 128m).
+    --compress-threshold BYTES
+                        Compress values larger than this.
+    --ext-path PATH     File to keep items evicted from memory in.
+    --ext-size MEMSIZE  Max size of that file (in megabytes).
+    --ext-item-min BYTES
+                        Smallest item worth keeping on disk.
+    --hotkeys K         Track the K most requested keys.
+    --hotkeys-decay SECS
+                        Halve the request counts of hot keys every SECS.
+    --lru-bump-interval SECS
+                        Move items read more often than this to the head of
+                        the LRU only once per interval.
+    --eviction-policy POLICY
+                        How to pick items to evict, lru or slru (segmented
+                        LRU, which favors items that are read again).
+    --ns-delimiter CHAR
+                        Keys are namespaced by the prefix before CHAR.
+    --ns-quotas QUOTAS  Memory quotas of namespaces (in megabytes),
+                        eg. teama=64,teamb=128.
     --metrics           Collect server performance metrics.
+    --metrics-listen ADDR
+                        Serve stats and metrics over http at ADDR/metrics
+                        (eg. 127.0.0.1:9150).
+    --metrics-sinks SINKS
+                        Where to report metrics to, any of stdout, json and
+                        statsd (eg. stdout,statsd).
+    --metrics-interval SECS
+                        How often to report metrics.
+    --metrics-prefix PREFIX
+                        Put PREFIX in front of reported metric names.
+    --metrics-tags TAGS
+                        Tags to report metrics with, eg. env=prod,dc=ams.
+    --metrics-json-path PATH
+                        File to append metrics to as JSON lines.
+    --metrics-json-size MEMSIZE
+                        Rotate that file when it gets this big (in megabytes).
+    --metrics-statsd ADDR
+                        StatsD server to send metrics to (eg. 127.0.0.1:8125).
+    --slowlog-threshold MS
+                        Log commands that take longer than MS milliseconds.
+    --slowlog-size N    Max number of slow commands to keep.
+    --slowlog-print     Also print slow commands as they happen.
+    --watch-sample N    Stream only every Nth event to watchers.
+    --watch-rate N      Max events per second to stream to a watcher.
+    --trace-path PATH   Record every command to a trace file at PATH.
+    --trace-no-values   Record only the sizes of values, not the data.
     -V --version        Print version info and exit
     -h --help           Show this screen.
 ";
@@ -21,7 +77,33 @@ pub struct MemcacheOptions {
     pub flag_host: Option<String>,
     pub flag_port: Option<u16>,
     pub flag_mem: Option<u64>,
+    pub flag_max_item_size: Option<String>,
+    pub flag_compress_threshold: Option<u64>,
+    pub flag_ext_path: Option<String>,
+    pub flag_ext_size: Option<u64>,
+    pub flag_ext_item_min: Option<u64>,
+    pub flag_hotkeys: Option<u64>,
+    pub flag_hotkeys_decay: Option<u64>,
+    pub flag_lru_bump_interval: Option<u64>,
+    pub flag_eviction_policy: Option<String>,
+    pub flag_ns_delimiter: Option<String>,
+    pub flag_ns_quotas: Option<String>,
     pub flag_metrics: bool,
+    pub flag_metrics_listen: Option<String>,
+    pub flag_metrics_sinks: Option<String>,
+    pub flag_metrics_interval: Option<f64>,
+    pub flag_metrics_prefix: Option<String>,
+    pub flag_metrics_tags: Option<String>,
+    pub flag_metrics_json_path: Option<String>,
+    pub flag_metrics_json_size: Option<u64>,
+    pub flag_metrics_statsd: Option<String>,
+    pub flag_slowlog_threshold: Option<f64>,
+    pub flag_slowlog_size: Option<u64>,
+    pub flag_slowlog_print: bool,
+    pub flag_watch_sample: Option<u64>,
+    pub flag_watch_rate: Option<u64>,
+    pub flag_trace_path: Option<String>,
+    pub flag_trace_no_values: bool,
     pub flag_version: bool,
 }
 
@@ -45,9 +127,198 @@ impl MemcacheOptions {
         self.flag_mem.unwrap() << 20
     }
 
+    pub fn get_compress_threshold(&self) -> Option<u64> {
+        self.flag_compress_threshold
+    }
+
+    pub fn get_ext_path(&self) -> Option<String> {
+        self.flag_ext_path.clone()
+    }
+
+    pub fn get_hotkeys(&self) -> Option<u64> {
+        self.flag_hotkeys
+    }
+
+    pub fn get_hotkeys_decay(&self) -> u64 {
+        self.flag_hotkeys_decay.unwrap()
+    }
+
+    pub fn get_lru_bump_interval(&self) -> Option<u64> {
+        self.flag_lru_bump_interval
+    }
+
+    pub fn get_eviction_policy(&self) -> EvictionPolicy {
+        let name = self.flag_eviction_policy.clone().unwrap();
+        EvictionPolicy::from_name(&name).unwrap()
+    }
+
+    pub fn get_ext_size_bytes(&self) -> u64 {
+        self.flag_ext_size.unwrap() << 20
+    }
+
+    pub fn get_ext_item_min(&self) -> u64 {
+        self.flag_ext_item_min.unwrap()
+    }
+
+    pub fn get_ns_delimiter(&self) -> Option<u8> {
+        self.flag_ns_delimiter
+            .as_ref()
+            .map(|delimiter| delimiter.as_bytes()[0])
+    }
+
+    pub fn get_ns_quotas_bytes(&self) -> Vec<(String, u64)> {
+        match self.flag_ns_quotas {
+            Some(ref quotas) => {
+                parse_ns_quotas(quotas)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(name, quota)| (name, quota << 20))
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    pub fn get_max_item_size_bytes(&self) -> u64 {
+        parse_size(&self.flag_max_item_size.clone().unwrap()).unwrap()
+    }
+
     pub fn get_metrics_enabled(&self) -> bool {
         self.flag_metrics
     }
+
+    pub fn get_metrics_listen(&self) -> Option<String> {
+        self.flag_metrics_listen.clone()
+    }
+
+    pub fn get_metrics_sinks(&self) -> Vec<String> {
+        parse_sinks(&self.flag_metrics_sinks.clone().unwrap()).unwrap()
+    }
+
+    pub fn get_metrics_interval(&self) -> f64 {
+        self.flag_metrics_interval.unwrap()
+    }
+
+    pub fn get_metrics_prefix(&self) -> String {
+        self.flag_metrics_prefix.clone().unwrap()
+    }
+
+    pub fn get_metrics_tags(&self) -> Vec<(String, String)> {
+        match self.flag_metrics_tags {
+            Some(ref tags) => parse_tags(tags).unwrap(),
+            None => vec![],
+        }
+    }
+
+    pub fn get_metrics_json_path(&self) -> Option<String> {
+        self.flag_metrics_json_path.clone()
+    }
+
+    pub fn get_metrics_json_size_bytes(&self) -> u64 {
+        self.flag_metrics_json_size.unwrap() << 20
+    }
+
+    pub fn get_metrics_statsd(&self) -> String {
+        self.flag_metrics_statsd.clone().unwrap()
+    }
+
+    // The threshold in seconds, None when there is no slow log
+    pub fn get_slowlog_threshold(&self) -> Option<f64> {
+        self.flag_slowlog_threshold.map(|ms| ms / 1000.0)
+    }
+
+    pub fn get_slowlog_size(&self) -> u64 {
+        self.flag_slowlog_size.unwrap()
+    }
+
+    pub fn get_slowlog_print(&self) -> bool {
+        self.flag_slowlog_print
+    }
+
+    pub fn get_watch_sample(&self) -> u64 {
+        self.flag_watch_sample.unwrap()
+    }
+
+    pub fn get_watch_rate(&self) -> u64 {
+        self.flag_watch_rate.unwrap()
+    }
+
+    pub fn get_trace_path(&self) -> Option<String> {
+        self.flag_trace_path.clone()
+    }
+
+    pub fn get_trace_values(&self) -> bool {
+        !self.flag_trace_no_values
+    }
+}
+
+
+// Parses a size like 1024, 512k or 2m into bytes
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.to_lowercase();
+
+    let (num, shift) = match size.chars().last() {
+        Some('k') => (&size[..size.len() - 1], 10),
+        Some('m') => (&size[..size.len() - 1], 20),
+        _ => (&size[..], 0),
+    };
+
+    match num.parse::<u64>() {
+        Ok(num) => num.checked_mul(1 << shift),
+        Err(_) => None,
+    }
+}
+
+// Parses quotas like teama=64,teamb=128 into (namespace, megabytes) pairs
+fn parse_ns_quotas(quotas: &str) -> Option<Vec<(String, u64)>> {
+    let mut parsed = vec![];
+
+    for quota in quotas.split(',') {
+        let mut parts = quota.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let size = parts.next().unwrap_or("");
+
+        match size.parse::<u64>() {
+            Ok(size) if !name.is_empty() => {
+                parsed.push((name.to_string(), size));
+            }
+            _ => return None,
+        }
+    }
+
+    Some(parsed)
+}
+
+// Parses a list of metrics sinks like stdout,statsd
+fn parse_sinks(sinks: &str) -> Option<Vec<String>> {
+    let mut parsed = vec![];
+
+    for sink in sinks.split(',') {
+        match sink {
+            "stdout" | "json" | "statsd" => parsed.push(sink.to_string()),
+            _ => return None,
+        }
+    }
+
+    Some(parsed)
+}
+
+// Parses tags like env=prod,dc=ams into (name, value) pairs
+fn parse_tags(tags: &str) -> Option<Vec<(String, String)>> {
+    let mut parsed = vec![];
+
+    for tag in tags.split(',') {
+        let mut parts = tag.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+
+        if name.is_empty() || value.is_empty() {
+            return None;
+        }
+        parsed.push((name.to_string(), value.to_string()));
+    }
+
+    Some(parsed)
 }
 
 
@@ -67,5 +338,178 @@ pub fn parse_args() -> MemcacheOptions {
         opts.flag_mem = Some(64);
     }
 
+    if opts.flag_ext_size.is_none() {
+        opts.flag_ext_size = Some(1024);
+    }
+    if opts.flag_ext_item_min.is_none() {
+        opts.flag_ext_item_min = Some(512);
+    }
+
+    if opts.flag_hotkeys_decay.is_none() {
+        opts.flag_hotkeys_decay = Some(60);
+    }
+
+    if opts.flag_eviction_policy.is_none() {
+        opts.flag_eviction_policy = Some("lru".to_string());
+    }
+    if opts.flag_eviction_policy
+           .as_ref()
+           .and_then(|name| EvictionPolicy::from_name(name))
+           .is_none() {
+        println!("Eviction policy must be lru or slru");
+        process::exit(1);
+    }
+
+    if opts.flag_slowlog_size.is_none() {
+        opts.flag_slowlog_size = Some(128);
+    }
+
+    if opts.flag_watch_sample.is_none() {
+        opts.flag_watch_sample = Some(1);
+    }
+    if opts.flag_watch_rate.is_none() {
+        opts.flag_watch_rate = Some(1000);
+    }
+    if opts.flag_watch_sample == Some(0) || opts.flag_watch_rate == Some(0) {
+        println!("Watch sample and rate must be positive");
+        process::exit(1);
+    }
+
+    if opts.flag_max_item_size.is_none() {
+        opts.flag_max_item_size = Some("1m".to_string());
+    }
+    match parse_size(&opts.flag_max_item_size.clone().unwrap()) {
+        Some(size) if size >= MIN_ITEM_SIZE && size <= MAX_ITEM_SIZE => (),
+        _ => {
+            println!("Max item size must be between 1k and 128m");
+            process::exit(1);
+        }
+    }
+
+    // A namespace delimiter has to be a single character that can be part
+    // of a key
+    if let Some(ref delimiter) = opts.flag_ns_delimiter {
+        if delimiter.len() != 1 || delimiter.as_bytes()[0] <= b' ' {
+            println!("Namespace delimiter must be a single character");
+            process::exit(1);
+        }
+    }
+    if let Some(ref quotas) = opts.flag_ns_quotas {
+        if opts.flag_ns_delimiter.is_none() {
+            println!("Namespace quotas need a namespace delimiter");
+            process::exit(1);
+        }
+        if parse_ns_quotas(quotas).is_none() {
+            println!("Namespace quotas must look like teama=64,teamb=128");
+            process::exit(1);
+        }
+    }
+
+    if opts.flag_metrics_sinks.is_none() {
+        opts.flag_metrics_sinks = Some("stdout".to_string());
+    }
+    if opts.flag_metrics_interval.is_none() {
+        opts.flag_metrics_interval = Some(1.0);
+    }
+    if opts.flag_metrics_prefix.is_none() {
+        opts.flag_metrics_prefix = Some("emcache".to_string());
+    }
+    if opts.flag_metrics_json_size.is_none() {
+        opts.flag_metrics_json_size = Some(64);
+    }
+    if opts.flag_metrics_statsd.is_none() {
+        opts.flag_metrics_statsd = Some("127.0.0.1:8125".to_string());
+    }
+    match parse_sinks(&opts.flag_metrics_sinks.clone().unwrap()) {
+        Some(ref sinks) if sinks.contains(&"json".to_string()) => {
+            if opts.flag_metrics_json_path.is_none() {
+                println!("The json metrics sink needs a path to write to");
+                process::exit(1);
+            }
+        }
+        Some(_) => (),
+        None => {
+            println!("Metrics sinks must be among stdout, json and statsd");
+            process::exit(1);
+        }
+    }
+    if opts.flag_metrics_interval.unwrap() <= 0.0 {
+        println!("Metrics interval must be positive");
+        process::exit(1);
+    }
+    if let Some(ref tags) = opts.flag_metrics_tags {
+        if parse_tags(tags).is_none() {
+            println!("Metrics tags must look like env=prod,dc=ams");
+            process::exit(1);
+        }
+    }
+
     opts
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_ns_quotas;
+    use super::parse_sinks;
+    use super::parse_size;
+    use super::parse_tags;
+
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Some(1024), parse_size("1024"));
+        assert_eq!(Some(512 << 10), parse_size("512k"));
+        assert_eq!(Some(2 << 20), parse_size("2M"));
+    }
+
+    #[test]
+    fn test_parse_size_malformed() {
+        assert_eq!(None, parse_size(""));
+        assert_eq!(None, parse_size("m"));
+        assert_eq!(None, parse_size("1g"));
+        assert_eq!(None, parse_size("-1k"));
+    }
+
+    #[test]
+    fn test_parse_ns_quotas() {
+        let quotas = parse_ns_quotas("teama=64,teamb=128").unwrap();
+        let expected = vec![("teama".to_string(), 64),
+                            ("teamb".to_string(), 128)];
+        assert_eq!(quotas, expected);
+    }
+
+    #[test]
+    fn test_parse_ns_quotas_malformed() {
+        assert_eq!(None, parse_ns_quotas(""));
+        assert_eq!(None, parse_ns_quotas("teama"));
+        assert_eq!(None, parse_ns_quotas("teama=x"));
+        assert_eq!(None, parse_ns_quotas("=64"));
+        assert_eq!(None, parse_ns_quotas("teama=64,"));
+    }
+    #[test]
+    fn test_parse_sinks() {
+        let sinks = parse_sinks("stdout,statsd").unwrap();
+        assert_eq!(sinks, vec!["stdout".to_string(), "statsd".to_string()]);
+
+        assert_eq!(None, parse_sinks(""));
+        assert_eq!(None, parse_sinks("stdout,graphite"));
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let tags = parse_tags("env=prod,dc=ams").unwrap();
+        let expected = vec![("env".to_string(), "prod".to_string()),
+                            ("dc".to_string(), "ams".to_string())];
+        assert_eq!(tags, expected);
+    }
+
+    #[test]
+    fn test_parse_tags_malformed() {
+        assert_eq!(None, parse_tags(""));
+        assert_eq!(None, parse_tags("env"));
+        assert_eq!(None, parse_tags("env="));
+        assert_eq!(None, parse_tags("=prod"));
+        assert_eq!(None, parse_tags("env=prod,"));
+    }
+}
@@ -1,15 +1,39 @@
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 
+use metrics::Collector;
+use metrics::Distribution;
+use metrics::MetricId;
 use metrics::MetricsRecorder;
+use metrics::NullCollector;
 use metrics::Timer;
+use metrics::TopKeys;
+use metrics::ids;
 use options::MemcacheOptions;
+use platform::time::time_now;
 use protocol::Driver;
+use protocol::HotKeys;
+use protocol::cmd::Cmd;
 use storage::Cache;
+use storage::ExtStore;
+use storage::Namespaces;
+use storage::SizeHistogram;
 use tcp_transport::stats::TransportStats;
 
+use super::CmdBacklog;
 use super::CmdReceiver;
+use super::INTERNAL_TRANSPORT_ID;
 use super::MetricsSender;
 use super::TransportId;
+use super::Watchers;
+
+
+// How often to send the distribution of sizes and the hot keys (in seconds),
+// they're too costly to work out on every command
+const SNAPSHOT_INTERVAL: f64 = 1.0;
+
+// How many of the hot keys to send
+const TOP_KEYS: usize = 10;
 
 
 type StatsMap = HashMap<TransportId, TransportStats>;
@@ -25,67 +49,226 @@ fn compute_stats_sums(map: &StatsMap) -> TransportStats {
     total_stats
 }
 
+fn size_distribution(name: &str, sizes: &SizeHistogram) -> Distribution {
+    let mut distribution = Distribution::new(name, sizes.len());
+
+    distribution.p50 = sizes.percentile(0.5).map(|size| size as f64);
+    distribution.p90 = sizes.percentile(0.9).map(|size| size as f64);
+    distribution.p99 = sizes.percentile(0.99).map(|size| size as f64);
+    distribution.max = sizes.max().map(|size| size as f64);
+
+    distribution
+}
+
+fn top_keys(name: &str, hotkeys: &HotKeys) -> TopKeys {
+    let keys = hotkeys.top()
+                      .into_iter()
+                      .take(TOP_KEYS)
+                      .map(|(key, count)| {
+                          (String::from_utf8_lossy(&key).into_owned(), count)
+                      })
+                      .collect();
+
+    TopKeys::new(name, keys)
+}
+
+
+// The ids of the metrics we keep per kind of command, interned the first time
+// we see one
+struct CmdMetricIds {
+    ids: HashMap<(&'static str, &'static str), MetricId>, // (name, outcome)
+}
+
+impl CmdMetricIds {
+    fn new() -> CmdMetricIds {
+        CmdMetricIds { ids: HashMap::new() }
+    }
+
+    // The timer of the kind of command
+    fn timer(&mut self, name: &'static str) -> MetricId {
+        *self.ids
+             .entry((name, ""))
+             .or_insert_with(|| MetricId::intern(&format!("Cmd:{}", name)))
+    }
+
+    // The counter of the kind of command with that outcome
+    fn counter(&mut self,
+               name: &'static str,
+               outcome: &'static str)
+               -> MetricId {
+        *self.ids
+             .entry((name, outcome))
+             .or_insert_with(|| {
+                 MetricId::intern(&format!("Cmd:{}:{}", name, outcome))
+             })
+    }
+}
+
 
 pub struct DriverTask {
     cmd_rx: CmdReceiver,
+    backlog: CmdBacklog,
     met_tx: MetricsSender,
     options: MemcacheOptions,
+    extstore: Option<ExtStore>, // Opened up front, so a bad path stops us
 }
 
 impl DriverTask {
     pub fn new(cmd_rx: CmdReceiver,
+               backlog: CmdBacklog,
                met_tx: MetricsSender,
-               options: MemcacheOptions)
+               options: MemcacheOptions,
+               extstore: Option<ExtStore>)
                -> DriverTask {
         DriverTask {
+            backlog: backlog,
             cmd_rx: cmd_rx,
+            extstore: extstore,
             met_tx: met_tx,
             options: options,
         }
     }
 
-    pub fn run(&self) {
-        let cache = Cache::new(self.options.get_mem_limit_bytes());
+    pub fn run(&mut self) {
+        let mut cache = Cache::new(self.options.get_mem_limit_bytes());
+        cache.with_value_maxlen(self.options.get_max_item_size_bytes());
+        if let Some(threshold) = self.options.get_compress_threshold() {
+            cache.with_compress_threshold(threshold);
+        }
+        cache.with_policy(self.options.get_eviction_policy());
+        if let Some(interval) = self.options.get_lru_bump_interval() {
+            cache.with_bump_interval(interval as f64);
+        }
+        if let Some(mut extstore) = self.extstore.take() {
+            extstore.with_item_min(self.options.get_ext_item_min());
+            cache.with_extstore(extstore);
+        }
+        if let Some(delimiter) = self.options.get_ns_delimiter() {
+            let mut namespaces = Namespaces::new(delimiter);
+            for (name, quota) in self.options.get_ns_quotas_bytes() {
+                namespaces.with_quota(name.as_bytes(), quota);
+            }
+            cache.with_namespaces(namespaces);
+        }
         let mut driver = Driver::new(cache);
+        if let Some(capacity) = self.options.get_hotkeys() {
+            let half_life = self.options.get_hotkeys_decay();
+            driver.with_hotkeys(HotKeys::new(capacity as usize,
+                                             half_life as f64));
+        }
 
+        // Collect server metrics only if asked to, otherwise recording them
+        // compiles to nothing
+        if self.options.get_metrics_enabled() {
+            self.serve(driver, MetricsRecorder::new(self.met_tx.clone()));
+        } else {
+            self.serve(driver, NullCollector::new());
+        }
+    }
+
+    fn serve<C: Collector>(&self, mut driver: Driver, mut rec: C) {
         // Here we store stats per transport
         let mut transport_stats: StatsMap = HashMap::new();
 
-        // For collecting server metrics
-        let mut rec = MetricsRecorder::new(self.met_tx.clone(),
-                                           self.options.get_metrics_enabled());
+        let mut cmd_ids = CmdMetricIds::new();
+        let mut last_snapshot_at = time_now();
+
+        let mut watchers = Watchers::new(self.options.get_watch_sample(),
+                                         self.options.get_watch_rate());
 
         loop {
             // Time the whole loop
-            rec.start_timer("DriverTask:loop");
+            rec.start_timer(ids::DRIVER_TASK_LOOP);
 
             // Receive command
             let (id, resp_tx, cmd, stats) = {
-                let _t = Timer::new(&mut rec, "DriverTask:recv_cmd");
+                let _t = Timer::new(&mut rec, ids::DRIVER_TASK_RECV_CMD);
                 self.cmd_rx.recv().unwrap()
             };
+            rec.incr_counter(ids::DRIVER_TASK_CMDS, 1);
 
-            // Update our stats store
-            transport_stats.insert(id, stats);
+            // See how many commands are still waiting for us
+            if rec.is_enabled() {
+                let backlog = self.backlog.fetch_sub(1, Ordering::SeqCst) - 1;
+                rec.set_gauge(ids::DRIVER_TASK_BACKLOG, backlog as f64);
+                rec.record_sample(ids::DRIVER_TASK_BACKLOG, backlog as f64);
+            }
+
+            // Update our stats store. Commands of our own don't come with
+            // any.
+            if id != INTERNAL_TRANSPORT_ID {
+                transport_stats.insert(id, stats);
+            }
 
             // Update the driver's view of all transport metrics
             let total_stats = compute_stats_sums(&transport_stats);
             driver.update_transport_stats(total_stats);
 
-            // Execute the command
+            // A connection that watches is sent the events on its response
+            // channel from now on
+            if let Cmd::Watch(ref watch) = cmd {
+                watchers.add(resp_tx.clone(), watch.clone(), time_now());
+                driver.set_watched(true);
+            }
+
+            // Execute the command, timing every kind of command on its own
+            // as well
+            let cmd_name = cmd.name();
+            let cmd_timer = match rec.is_enabled() {
+                true => Some(cmd_ids.timer(cmd_name)),
+                false => None,
+            };
+            if let Some(cmd_timer) = cmd_timer {
+                rec.start_timer(cmd_timer);
+            }
             let resp = {
-                let _t = Timer::new(&mut rec, "DriverTask:exec_cmd");
+                let _t = Timer::new(&mut rec, ids::DRIVER_TASK_EXEC_CMD);
                 driver.run(cmd)
             };
+            if let Some(cmd_timer) = cmd_timer {
+                rec.stop_timer(cmd_timer);
+                rec.incr_counter(cmd_ids.counter(cmd_name, resp.outcome()), 1);
+            }
 
             // Send response
             {
-                let _t = Timer::new(&mut rec, "DriverTask:send_resp");
+                let _t = Timer::new(&mut rec, ids::DRIVER_TASK_SEND_RESP);
                 resp_tx.send(resp).unwrap();
             }
 
+            // Pass on what the command did to the keys
+            if !watchers.is_empty() {
+                let dropped = watchers.publish(id,
+                                               driver.take_events(),
+                                               time_now());
+                if dropped > 0 {
+                    rec.incr_counter(ids::DRIVER_TASK_WATCH_DROPS, dropped);
+                }
+                if watchers.is_empty() {
+                    driver.set_watched(false);
+                }
+            }
+
             // Stop timing the loop
-            rec.stop_timer("DriverTask:loop");
+            rec.stop_timer(ids::DRIVER_TASK_LOOP);
+
+            // Now and then see what sizes we store and which keys are hot
+            if rec.is_enabled() &&
+               last_snapshot_at + SNAPSHOT_INTERVAL < time_now() {
+                let cache = driver.get_cache();
+                let keys = cache.get_key_sizes();
+                let items = cache.get_item_sizes();
+                rec.record_distribution(size_distribution("Cache:key_sizes",
+                                                          keys));
+                rec.record_distribution(size_distribution("Cache:item_sizes",
+                                                          items));
+
+                if let Some(hotkeys) = driver.get_hotkeys() {
+                    rec.record_top_keys(top_keys("Driver:hotkeys", hotkeys));
+                }
+
+                last_snapshot_at = time_now();
+            }
 
             // Now flush metrics outside the request path
             rec.flush_metrics();
@@ -0,0 +1,187 @@
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use metrics::exposition::CONTENT_TYPE;
+use metrics::render_openmetrics;
+use options::MemcacheOptions;
+use protocol::cmd::Cmd;
+use protocol::cmd::Resp;
+use protocol::cmd::Stats;
+use tcp_transport::stats::TransportStats;
+
+use super::CmdBacklog;
+use super::CmdSender;
+use super::INTERNAL_TRANSPORT_ID;
+use super::SharedTimers;
+
+
+// How long we wait for a scraper to send its request
+const READ_TIMEOUT_SECS: u64 = 5;
+
+
+// Parses a request line like "GET /metrics HTTP/1.1" into the method and the
+// path (without the query string)
+fn parse_request_line(line: &str) -> Option<(&str, &str)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+
+    match parts.len() {
+        3 if parts[2].starts_with("HTTP/") => {
+            let path = parts[1].splitn(2, '?').next().unwrap();
+            Some((parts[0], path))
+        }
+        _ => None,
+    }
+}
+
+fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: \
+             {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body)
+}
+
+
+// Serves stats and metrics over http for scrapers like Prometheus. This is
+// all the http we need: one request per connection, one connection at a
+// time.
+pub struct ExporterTask {
+    addr: String,
+    cmd_tx: CmdSender,
+    backlog: CmdBacklog,
+    timers: SharedTimers,
+    options: MemcacheOptions,
+}
+
+impl ExporterTask {
+    pub fn new(addr: String,
+               cmd_tx: CmdSender,
+               backlog: CmdBacklog,
+               timers: SharedTimers,
+               options: MemcacheOptions)
+               -> ExporterTask {
+        ExporterTask {
+            addr: addr,
+            backlog: backlog,
+            cmd_tx: cmd_tx,
+            options: options,
+            timers: timers,
+        }
+    }
+
+    fn render_metrics(&self) -> String {
+        // Ask the driver for its stats
+        let (resp_tx, resp_rx) = mpsc::sync_channel(1);
+        let cmd = Cmd::Stats(Stats::new(None));
+        if self.options.get_metrics_enabled() {
+            self.backlog.fetch_add(1, Ordering::SeqCst);
+        }
+        self.cmd_tx
+            .send((INTERNAL_TRANSPORT_ID, resp_tx, cmd, TransportStats::new()))
+            .unwrap();
+
+        let stats = match resp_rx.recv().unwrap() {
+            Resp::Stats(stats) => stats,
+            _ => vec![],
+        };
+
+        let timers = self.timers.lock().unwrap();
+        render_openmetrics(&stats, &timers)
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        let timeout = Duration::from_secs(READ_TIMEOUT_SECS);
+        try!(stream.set_read_timeout(Some(timeout)));
+
+        let mut reader = BufReader::new(try!(stream.try_clone()));
+        let mut request_line = String::new();
+        try!(reader.read_line(&mut request_line));
+
+        // Skip the headers, we have no use for them
+        loop {
+            let mut line = String::new();
+            let len = try!(reader.read_line(&mut line));
+            if len == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let response = match parse_request_line(&request_line) {
+            Some(("GET", "/metrics")) => {
+                http_response("200 OK", CONTENT_TYPE, &self.render_metrics())
+            }
+            Some(("GET", _)) => {
+                http_response("404 Not Found", "text/plain", "Not found\n")
+            }
+            Some(_) => {
+                http_response("405 Method Not Allowed",
+                              "text/plain",
+                              "Method not allowed\n")
+            }
+            None => {
+                http_response("400 Bad Request", "text/plain", "Bad request\n")
+            }
+        };
+
+        stream.write_all(response.as_bytes())
+    }
+
+    pub fn run(&self) {
+        let listener = TcpListener::bind(&self.addr[..]).unwrap();
+        println!("Serving metrics on http://{}/metrics", self.addr);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = self.serve(stream) {
+                        println!("Failed to serve metrics: {:?}", err);
+                    }
+                }
+                Err(_) => {
+                    println!("Connection failed :(");
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::http_response;
+    use super::parse_request_line;
+
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(Some(("GET", "/metrics")),
+                   parse_request_line("GET /metrics HTTP/1.1\r\n"));
+        assert_eq!(Some(("GET", "/metrics")),
+                   parse_request_line("GET /metrics?x=1 HTTP/1.0\r\n"));
+        assert_eq!(Some(("POST", "/")),
+                   parse_request_line("POST / HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_parse_request_line_malformed() {
+        assert_eq!(None, parse_request_line(""));
+        assert_eq!(None, parse_request_line("GET /metrics\r\n"));
+        assert_eq!(None, parse_request_line("GET /metrics SPDY\r\n"));
+    }
+
+    #[test]
+    fn test_http_response() {
+        let resp = http_response("200 OK", "text/plain", "hi\n");
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                    Content-Length: 3\r\nConnection: close\r\n\r\nhi\n",
+                   resp);
+    }
+}
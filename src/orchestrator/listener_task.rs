@@ -1,4 +1,12 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
 use std::net::TcpListener;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc;
 use std::thread;
 
@@ -6,14 +14,23 @@ use net2::TcpStreamExt;
 
 use common::conversions::string_to_str;
 use options::MemcacheOptions;
+use platform::clock::Clock;
+use platform::clock::SystemClock;
+use storage::ExtStore;
+use trace::TraceWriter;
 
 use super::DriverTask;
+use super::ExporterTask;
+use super::INTERNAL_TRANSPORT_ID;
 use super::MetricsTask;
+use super::SlowLog;
 use super::TransportId;
 use super::TransportTask;
 
 
 pub struct ListenerTask {
+    // The last id handed out. We start from the reserved internal id, so
+    // that no transport gets it.
     cur_transport_id: TransportId,
     options: MemcacheOptions,
 }
@@ -21,7 +38,7 @@ pub struct ListenerTask {
 impl ListenerTask {
     pub fn new(options: MemcacheOptions) -> ListenerTask {
         ListenerTask {
-            cur_transport_id: TransportId(0),
+            cur_transport_id: INTERNAL_TRANSPORT_ID,
             options: options,
         }
     }
@@ -34,9 +51,22 @@ impl ListenerTask {
     }
 
     pub fn run(&mut self) {
+        // Open the disk tier before anything else, so that a path we can't
+        // use stops us right away instead of taking the driver down later
+        let extstore = self.options.get_ext_path().map(|path| {
+            let size = self.options.get_ext_size_bytes();
+            ExtStore::new(Path::new(&path), size).unwrap_or_else(|e| {
+                println!("Failed to open {}: {}", path, e);
+                process::exit(1);
+            })
+        });
+
         // Initialize the metrics sink
         let (met_tx, met_rx) = mpsc::channel();
-        let metrics = MetricsTask::new(met_rx);
+        let timers = Arc::new(Mutex::new(HashMap::new()));
+        let mut metrics = MetricsTask::new(met_rx,
+                                           timers.clone(),
+                                           &self.options);
 
         thread::spawn(move || {
             metrics.run();
@@ -44,14 +74,51 @@ impl ListenerTask {
 
         // Initialize the driver
         let (cmd_tx, cmd_rx) = mpsc::channel();
-        let driver = DriverTask::new(cmd_rx,
-                                     met_tx.clone(),
-                                     self.options.clone());
+        let backlog = Arc::new(AtomicUsize::new(0));
+        let mut driver = DriverTask::new(cmd_rx,
+                                         backlog.clone(),
+                                         met_tx.clone(),
+                                         self.options.clone(),
+                                         extstore);
 
         thread::spawn(move || {
             driver.run();
         });
 
+        // Serve metrics over http if asked to
+        if let Some(addr) = self.options.get_metrics_listen() {
+            let exporter = ExporterTask::new(addr,
+                                             cmd_tx.clone(),
+                                             backlog.clone(),
+                                             timers.clone(),
+                                             self.options.clone());
+
+            thread::spawn(move || {
+                exporter.run();
+            });
+        }
+
+        // The slow log is shared by all the transports
+        let slowlog_size = self.options.get_slowlog_size() as usize;
+        let slowlog = Arc::new(Mutex::new(SlowLog::new(slowlog_size)));
+
+        // So is the trace, if we record one, along with the clock that tells
+        // the time of its commands
+        let trace_clock = SystemClock::new();
+        let trace = self.options.get_trace_path().map(|path| {
+            let file = OpenOptions::new()
+                           .write(true)
+                           .create(true)
+                           .truncate(true)
+                           .open(path)
+                           .unwrap();
+            let writer = TraceWriter::new(BufWriter::new(file),
+                                          self.options.get_trace_values(),
+                                          trace_clock.now())
+                             .unwrap();
+            Arc::new(Mutex::new(Some(writer)))
+        });
+
         // Start up a tcp server
         let (host, port) = self.options.get_bind_params();
         let tcp_listener = TcpListener::bind((string_to_str(&host), port))
@@ -65,9 +132,20 @@ impl ListenerTask {
 
                     let id = self.next_transport_id();
                     let cmd_tx = cmd_tx.clone();
+                    let backlog = backlog.clone();
+                    let slowlog = slowlog.clone();
+                    let trace = trace.clone();
+                    let trace_clock = Box::new(trace_clock.clone());
                     let met_tx = met_tx.clone();
                     let opts = self.options.clone();
-                    let task = TransportTask::new(id, cmd_tx, met_tx, opts);
+                    let task = TransportTask::new(id,
+                                                  cmd_tx,
+                                                  backlog,
+                                                  slowlog,
+                                                  trace,
+                                                  trace_clock,
+                                                  met_tx,
+                                                  opts);
 
                     thread::spawn(move || {
                         task.run(stream);
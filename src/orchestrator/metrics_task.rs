@@ -1,72 +1,133 @@
+use std::collections::HashMap;
+
+use metrics::Histogram;
+use metrics::JsonLinesSink;
 use metrics::Metric;
+use metrics::MetricsSink;
+use metrics::StatsdSink;
+use metrics::StdoutSink;
+use metrics::Summary;
 use metrics::TimeSeries;
+use options::MemcacheOptions;
 use platform::time::time_now;
 
 use super::MetricsReceiver;
+use super::SharedTimers;
+
+
+// The sinks picked in the options, leaving out any we can't set up
+fn create_sinks(options: &MemcacheOptions) -> Vec<Box<dyn MetricsSink + Send>> {
+    let mut sinks: Vec<Box<dyn MetricsSink + Send>> = vec![];
+    let prefix = options.get_metrics_prefix();
+    let tags = options.get_metrics_tags();
+
+    for name in options.get_metrics_sinks() {
+        if name == "stdout" {
+            sinks.push(Box::new(StdoutSink::new()));
+        } else if name == "json" {
+            let path = options.get_metrics_json_path().unwrap();
+            let max_bytes = options.get_metrics_json_size_bytes();
+            sinks.push(Box::new(JsonLinesSink::new(&path,
+                                                   max_bytes,
+                                                   &prefix,
+                                                   tags.clone())));
+        } else if name == "statsd" {
+            let addr = options.get_metrics_statsd();
+            match StatsdSink::new(&addr, &prefix, tags.clone()) {
+                Ok(sink) => sinks.push(Box::new(sink)),
+                Err(err) => {
+                    println!("Not sending metrics to {}: {}", addr, err)
+                }
+            }
+        }
+    }
+
+    sinks
+}
 
 
 pub struct MetricsTask {
     met_rx: MetricsReceiver,
+    timers: SharedTimers, // For the exporter to see
+    sinks: Vec<Box<dyn MetricsSink + Send>>, // Where summaries are reported
 
     summary_interval: f64,
 }
 
 impl MetricsTask {
-    pub fn new(met_rx: MetricsReceiver) -> MetricsTask {
+    pub fn new(met_rx: MetricsReceiver,
+               timers: SharedTimers,
+               options: &MemcacheOptions)
+               -> MetricsTask {
         MetricsTask {
             met_rx: met_rx,
+            timers: timers,
+            sinks: create_sinks(options),
 
-            summary_interval: 1.0,
+            summary_interval: options.get_metrics_interval(),
         }
     }
 
-    pub fn run(&self) {
+    pub fn run(&mut self) {
         let mut ts = TimeSeries::new();
+        let mut distributions = HashMap::new();
+        let mut top_keys = HashMap::new();
         let mut last_summary_at = time_now();
 
         loop {
             // Receive metrics
             let metrics = self.met_rx.recv().unwrap();
+            let mut timers = self.timers.lock().unwrap();
             for metric in metrics.metrics {
                 match metric {
+                    Metric::Counter(counter) => {
+                        ts.add_counter(&counter);
+                    }
+                    Metric::Distribution(distribution) => {
+                        // Only the latest one is of interest
+                        distributions.insert(distribution.name.clone(),
+                                             distribution);
+                    }
+                    Metric::Gauge(gauge) => {
+                        ts.add_gauge(&gauge);
+                    }
+                    Metric::Sample(sample) => {
+                        ts.add_sample(&sample);
+                    }
                     Metric::Timing(timing) => {
                         ts.add_timing(&timing);
+
+                        if !timers.contains_key(&timing.name) {
+                            timers.insert(timing.name.clone(),
+                                          Histogram::new());
+                        }
+                        timers.get_mut(&timing.name)
+                              .unwrap()
+                              .add(timing.duration);
+                    }
+                    Metric::TopKeys(keys) => {
+                        // Only the latest one is of interest
+                        top_keys.insert(keys.name.clone(), keys);
                     }
                 }
             }
 
-            // Is is time to print a summary?
+            drop(timers);
+
+            // Is is time to report a summary?
             if last_summary_at + self.summary_interval < time_now() {
-                self.print_summary(&ts);
+                let summary = Summary::new(time_now(),
+                                           self.summary_interval,
+                                           &ts,
+                                           &distributions,
+                                           &top_keys);
+                for sink in self.sinks.iter_mut() {
+                    sink.write(&summary);
+                }
                 ts.clear();
 
                 last_summary_at = time_now();
             }
         }
     }
-
-    pub fn print_summary(&self, ts: &TimeSeries) {
-        let agg_mets = ts.aggregate_metrics();
-        let mut names: Vec<&String> = agg_mets.keys().collect();
-        names.sort();
-
-        println!("== Metrics {}s snapshot at {} ==",
-                 self.summary_interval,
-                 time_now() as u64);
-
-        for name in names {
-            let agg = agg_mets.get(name).unwrap();
-
-            let avg = agg.avg.unwrap_or(-1.0) * 1000.0;
-            let p0 = agg.p0.unwrap_or(-1.0) * 1000.0;
-            let p99 = agg.p99.unwrap_or(-1.0) * 1000.0;
-
-            println!("{:30}  n: {:5}  p0: {:.3}ms  avg: {:.3}ms  p99: {:.3}ms",
-                     name,
-                     agg.n,
-                     p0,
-                     avg,
-                     p99);
-        }
-    }
 }
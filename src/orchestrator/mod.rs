@@ -1,20 +1,32 @@
 // Declare sub modules
 pub mod driver_task;
+pub mod exporter_task;
 pub mod metrics_task;
 pub mod listener_task;
+pub mod slowlog;
 pub mod transport_task;
 pub mod typedefs;
+pub mod watchers;
 
 
 // Export our public api
 pub use self::driver_task::DriverTask;
+pub use self::exporter_task::ExporterTask;
 pub use self::listener_task::ListenerTask;
 pub use self::metrics_task::MetricsTask;
+pub use self::slowlog::SlowLog;
+pub use self::slowlog::SlowLogEntry;
 pub use self::transport_task::TransportTask;
+pub use self::typedefs::CmdBacklog;
 pub use self::typedefs::CmdReceiver;
 pub use self::typedefs::CmdSender;
+pub use self::typedefs::INTERNAL_TRANSPORT_ID;
 pub use self::typedefs::MetricsReceiver;
 pub use self::typedefs::MetricsSender;
 pub use self::typedefs::RespReceiver;
 pub use self::typedefs::RespSender;
+pub use self::typedefs::SharedSlowLog;
+pub use self::typedefs::SharedTimers;
+pub use self::typedefs::SharedTrace;
 pub use self::typedefs::TransportId;
+pub use self::watchers::Watchers;
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use super::TransportId;
+
+
+// Keys beyond this many (eg. in a big multiget) are counted but not kept
+const MAX_KEYS: usize = 10;
+
+
+// A command that took longer than the threshold, from the moment its first
+// byte could be read until its response was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowLogEntry {
+    pub id: u64, // Assigned by the log, increases with every entry
+    pub time: f64, // unixtime when the command started to be read
+    pub transport: TransportId,
+    pub peer: String, // Address of the client
+    pub cmd: &'static str, // Name of the command, eg. get
+    pub keys: Vec<Vec<u8>>, // The first few keys of the command
+    pub key_count: usize, // All the keys of the command
+    pub value_bytes: u64, // Size of the values sent or returned
+    pub read: f64, // secs spent reading the command
+    pub driver: f64, // secs waiting for the driver to respond
+    pub write: f64, // secs spent writing the response
+}
+
+impl SlowLogEntry {
+    pub fn new(time: f64,
+               transport: TransportId,
+               peer: String,
+               cmd: &'static str,
+               keys: &[&[u8]])
+               -> SlowLogEntry {
+        SlowLogEntry {
+            id: 0,
+            time: time,
+            transport: transport,
+            peer: peer,
+            cmd: cmd,
+            keys: keys.iter().take(MAX_KEYS).map(|key| key.to_vec()).collect(),
+            key_count: keys.len(),
+            value_bytes: 0,
+            read: 0.0,
+            driver: 0.0,
+            write: 0.0,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.read + self.driver + self.write
+    }
+
+    // A single line with everything we know about the command
+    pub fn describe(&self) -> String {
+        let TransportId(transport) = self.transport;
+        let mut keys: Vec<String> =
+            self.keys
+                .iter()
+                .map(|key| String::from_utf8_lossy(key).into_owned())
+                .collect();
+        if self.key_count > self.keys.len() {
+            keys.push(format!("+{}", self.key_count - self.keys.len()));
+        }
+
+        format!("time={:.6} transport={} peer={} cmd={} keys={} \
+                 value_bytes={} total_us={:.3} read_us={:.3} \
+                 driver_us={:.3} write_us={:.3}",
+                self.time,
+                transport,
+                self.peer,
+                self.cmd,
+                keys.join(","),
+                self.value_bytes,
+                self.total() * 1_000_000.0,
+                self.read * 1_000_000.0,
+                self.driver * 1_000_000.0,
+                self.write * 1_000_000.0)
+    }
+}
+
+
+// The most recent slow commands, at most capacity of them
+pub struct SlowLog {
+    capacity: usize,
+    entries: VecDeque<SlowLogEntry>, // Oldest first
+    next_id: u64,
+}
+
+impl SlowLog {
+    pub fn new(capacity: usize) -> SlowLog {
+        SlowLog {
+            capacity: capacity,
+            entries: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    // Adds the entry, making room by dropping the oldest one
+    pub fn add(&mut self, mut entry: SlowLogEntry) {
+        entry.id = self.next_id;
+        self.next_id += 1;
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // The count most recent entries (all of them if None), newest first
+    pub fn get(&self, count: Option<u64>) -> Vec<SlowLogEntry> {
+        let count = match count {
+            Some(count) => count as usize,
+            None => self.entries.len(),
+        };
+
+        self.entries.iter().rev().take(count).cloned().collect()
+    }
+
+    // Forget all the entries, the ids carry on from where they were
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::TransportId;
+
+    use super::SlowLog;
+    use super::SlowLogEntry;
+
+
+    fn entry(cmd: &'static str) -> SlowLogEntry {
+        SlowLogEntry::new(1.0, TransportId(1), "peer".to_string(), cmd, &[])
+    }
+
+    #[test]
+    fn test_slowlog_bounded() {
+        let mut log = SlowLog::new(2);
+        log.add(entry("get"));
+        log.add(entry("set"));
+        log.add(entry("delete"));
+
+        // The oldest entry made way
+        let entries = log.get(None);
+        assert_eq!(2, entries.len());
+        assert_eq!("delete", entries[0].cmd);
+        assert_eq!(2, entries[0].id);
+        assert_eq!("set", entries[1].cmd);
+        assert_eq!(1, entries[1].id);
+    }
+
+    #[test]
+    fn test_slowlog_get_count() {
+        let mut log = SlowLog::new(10);
+        log.add(entry("get"));
+        log.add(entry("set"));
+        log.add(entry("delete"));
+
+        let entries = log.get(Some(2));
+        assert_eq!(2, entries.len());
+        assert_eq!("delete", entries[0].cmd);
+        assert_eq!("set", entries[1].cmd);
+
+        assert_eq!(3, log.get(Some(5)).len());
+    }
+
+    #[test]
+    fn test_slowlog_reset() {
+        let mut log = SlowLog::new(10);
+        log.add(entry("get"));
+        log.reset();
+        assert!(log.get(None).is_empty());
+
+        // Ids aren't reused
+        log.add(entry("set"));
+        assert_eq!(1, log.get(None)[0].id);
+    }
+
+    #[test]
+    fn test_slowlog_entry_describe() {
+        let keys: Vec<&[u8]> = vec![b"a", b"b"];
+        let mut entry = SlowLogEntry::new(1.5,
+                                          TransportId(3),
+                                          "127.0.0.1:5000".to_string(),
+                                          "get",
+                                          &keys);
+        entry.value_bytes = 10;
+        entry.read = 0.001;
+        entry.driver = 0.002;
+        entry.write = 0.003;
+
+        assert_eq!("time=1.500000 transport=3 peer=127.0.0.1:5000 cmd=get \
+                    keys=a,b value_bytes=10 total_us=6000.000 \
+                    read_us=1000.000 driver_us=2000.000 write_us=3000.000",
+                   entry.describe());
+    }
+
+    #[test]
+    fn test_slowlog_entry_many_keys() {
+        let keys: Vec<Vec<u8>> = (0..12)
+                                     .map(|i| format!("k{}", i).into_bytes())
+                                     .collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| &key[..]).collect();
+        let entry = SlowLogEntry::new(1.0,
+                                      TransportId(1),
+                                      "peer".to_string(),
+                                      "get",
+                                      &keys);
+
+        assert_eq!(10, entry.keys.len());
+        assert_eq!(12, entry.key_count);
+        assert!(entry.describe().contains("keys=k0,k1,k2,k3,k4,k5,k6,k7,k8,\
+                                           k9,+2 "));
+    }
+}
@@ -1,23 +1,76 @@
+use std::io;
+use std::io::Read;
 use std::net::TcpStream;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 
+use metrics::Collector;
 use metrics::MetricsRecorder;
+use metrics::NullCollector;
 use metrics::Timer;
+use metrics::ids;
 use options::MemcacheOptions;
+use platform::clock::Clock;
+use platform::time::time_now;
 use protocol::cmd::Cmd;
 use protocol::cmd::Resp;
+use protocol::cmd::Slowlog;
+use protocol::cmd::SlowlogInstr;
+use protocol::cmd::Stat;
 use tcp_transport::TcpTransport;
+use tcp_transport::TcpTransportError;
 
+use super::CmdBacklog;
 use super::CmdSender;
 use super::MetricsSender;
 use super::RespReceiver;
 use super::RespSender;
+use super::SharedSlowLog;
+use super::SharedTrace;
+use super::SlowLogEntry;
 use super::TransportId;
 
 
+// A watcher is sent events on a channel of its own, with room for this many
+// batches. The driver drops the ones that don't fit.
+const WATCH_QUEUE_SIZE: usize = 64;
+
+// How long a watcher waits for events before it checks whether the client is
+// still there (in milliseconds)
+const WATCH_POLL_INTERVAL: u64 = 1000;
+
+
+// Has the client closed the connection? A watching client isn't meant to
+// send anything, so whatever it does send is read and thrown away.
+fn client_gone(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+
+    let mut buf = [0; 512];
+    let gone = loop {
+        match (&*stream).read(&mut buf) {
+            Ok(0) => break true,
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break false,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break true,
+        }
+    };
+
+    gone || stream.set_nonblocking(false).is_err()
+}
+
+
 pub struct TransportTask {
     id: TransportId,
     cmd_tx: CmdSender,
+    backlog: CmdBacklog,
+    slowlog: SharedSlowLog,
+    trace: Option<SharedTrace>, // Where to record the commands, if anywhere
+    trace_clock: Box<dyn Clock + Send>, // Tells the time of traced commands
     met_tx: MetricsSender,
     options: MemcacheOptions,
 }
@@ -25,34 +78,198 @@ pub struct TransportTask {
 impl TransportTask {
     pub fn new(id: TransportId,
                cmd_tx: CmdSender,
+               backlog: CmdBacklog,
+               slowlog: SharedSlowLog,
+               trace: Option<SharedTrace>,
+               trace_clock: Box<dyn Clock + Send>,
                met_tx: MetricsSender,
                options: MemcacheOptions)
                -> TransportTask {
         TransportTask {
             id: id,
+            backlog: backlog,
             cmd_tx: cmd_tx,
+            slowlog: slowlog,
+            trace: trace,
+            trace_clock: trace_clock,
             met_tx: met_tx,
             options: options,
         }
     }
 
+    // Only read the clock when there is a slow log to time commands for
+    fn clock(&self) -> f64 {
+        match self.options.get_slowlog_threshold() {
+            Some(_) => time_now(),
+            None => 0.0,
+        }
+    }
+
+    fn do_slowlog(&self, slowlog: Slowlog) -> Resp {
+        let mut log = self.slowlog.lock().unwrap();
+
+        match slowlog.instr {
+            SlowlogInstr::Get => {
+                let stats = log.get(slowlog.count)
+                               .iter()
+                               .map(|entry| {
+                                   Stat::new(&entry.id.to_string(),
+                                             entry.describe())
+                               })
+                               .collect();
+                Resp::Stats(stats)
+            }
+            SlowlogInstr::Reset => {
+                log.reset();
+                Resp::Ok
+            }
+        }
+    }
+
+    fn slowlog_entry(&self,
+                     read_at: f64,
+                     peer: &str,
+                     cmd: &Cmd)
+                     -> SlowLogEntry {
+        let mut entry = SlowLogEntry::new(read_at,
+                                          self.id,
+                                          peer.to_string(),
+                                          cmd.name(),
+                                          &cmd.keys());
+        if let Cmd::Set(ref set) = *cmd {
+            entry.value_bytes += set.data.len() as u64;
+        }
+
+        entry
+    }
+
+    // Keeps the command if it took too long, and prints it if asked to
+    fn log_if_slow(&self, entry: SlowLogEntry) {
+        match self.options.get_slowlog_threshold() {
+            Some(threshold) if entry.total() > threshold => (),
+            _ => return,
+        }
+
+        if self.options.get_slowlog_print() {
+            println!("Slow command: {}", entry.describe());
+        }
+        self.slowlog.lock().unwrap().add(entry);
+    }
+
+    // Records the command in the trace. Losing the trace is no reason to stop
+    // serving, so that only gets a mention. Once a write fails we stop
+    // recording altogether: whatever we wrote after a record that only made
+    // it out in part could not be read back.
+    fn record_trace(&self, cmd: &Cmd) {
+        let trace = match self.trace {
+            Some(ref trace) => trace,
+            None => return,
+        };
+
+        let TransportId(conn) = self.id;
+        let mut trace = trace.lock().unwrap();
+        let rv = match *trace {
+            Some(ref mut writer) => {
+                writer.write(self.trace_clock.now(), conn, cmd)
+            }
+            None => return,
+        };
+        if let Err(err) = rv {
+            println!("Failed to record command in trace, no longer \
+                      recording: {}",
+                     err);
+            *trace = None;
+        }
+    }
+
+    // Writes out the events the driver sends until the client goes away.
+    // That is noticed even when there are no events to write, so that we
+    // don't hang on to the connection (and the driver to the watcher) on a
+    // quiet server.
+    fn stream_events(&self,
+                     transport: &mut TcpTransport<TcpStream>,
+                     watch_rx: &RespReceiver) {
+        let interval = Duration::from_millis(WATCH_POLL_INTERVAL);
+
+        loop {
+            let resp = match watch_rx.recv_timeout(interval) {
+                Ok(resp) => resp,
+                Err(RecvTimeoutError::Timeout) => {
+                    match client_gone(transport.get_stream()) {
+                        true => break,
+                        false => continue,
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            if transport.write_resp(&resp).is_err() {
+                break;
+            }
+        }
+
+        println!("Watcher disconnected");
+    }
+
     pub fn run(&self, stream: TcpStream) {
-        let mut rec = MetricsRecorder::new(self.met_tx.clone(),
-                                           self.options.get_metrics_enabled());
+        // Collect server metrics only if asked to, otherwise recording them
+        // compiles to nothing
+        if self.options.get_metrics_enabled() {
+            self.serve(stream, MetricsRecorder::new(self.met_tx.clone()));
+        } else {
+            self.serve(stream, NullCollector::new());
+        }
+    }
+
+    fn serve<C: Collector>(&self, stream: TcpStream, mut rec: C) {
+        // Who we are talking to, for the slow log
+        let peer = match stream.peer_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => "unknown".to_string(),
+        };
 
         let mut transport = TcpTransport::new(stream);
-        let (resp_tx, resp_rx): (RespSender, RespReceiver) = mpsc::channel();
+        transport.with_value_maxlen(self.options.get_max_item_size_bytes());
+        // We only ever wait on one response at a time
+        let (resp_tx, resp_rx): (RespSender, RespReceiver) =
+            mpsc::sync_channel(1);
 
         loop {
+            // Don't count the time the client takes to send a command
+            if self.options.get_slowlog_threshold().is_some() {
+                let _ = transport.wait_for_cmd();
+            }
+            let read_at = self.clock();
+
             // Time the whole loop
-            rec.start_timer("TransportTask:loop");
+            rec.start_timer(ids::TRANSPORT_TASK_LOOP);
 
             // println!("Ready to read command...");
             let rv = {
-                let _t = Timer::new(&mut rec, "TransportTask:read_cmd");
+                let _t = Timer::new(&mut rec, ids::TRANSPORT_TASK_READ_CMD);
                 transport.read_cmd()
             };
 
+            // A bad key is the client's fault, but the command was read in
+            // full so we can carry on with the next one
+            if let Err(TcpTransportError::InvalidKey) = rv {
+                let resp = Resp::ClientError("bad command line format"
+                                                 .to_string());
+                let _ = transport.write_resp(&resp);
+                rec.stop_timer(ids::TRANSPORT_TASK_LOOP);
+                continue;
+            }
+
+            // The value was too large to store, so it was never read in.
+            // What is left of it is dropped before the next command.
+            if let Err(TcpTransportError::ValueTooLarge) = rv {
+                let resp = Resp::ServerError("object too large for cache"
+                                                 .to_string());
+                let _ = transport.write_resp(&resp);
+                rec.stop_timer(ids::TRANSPORT_TASK_LOOP);
+                continue;
+            }
+
             // If we couldn't parse the command return an error
             if !rv.is_ok() {
                 println!("Failed to read command: {:?}, \
@@ -63,47 +280,100 @@ impl TransportTask {
             }
 
             let cmd = rv.unwrap();
+            self.record_trace(&cmd);
+            let sent_at = self.clock();
+
+            // Note what the command is about before handing it over
+            let entry = match self.options.get_slowlog_threshold() {
+                Some(_) => Some(self.slowlog_entry(read_at, &peer, &cmd)),
+                None => None,
+            };
+
+            // The connection only streams events once it watches, which
+            // the driver sends on a channel of their own
+            let watch_rx = match cmd {
+                Cmd::Watch(_) => Some(mpsc::sync_channel(WATCH_QUEUE_SIZE)),
+                _ => None,
+            };
 
             // Special case commands handled directly by transport
-            match cmd {
+            let resp = match cmd {
                 Cmd::Quit => {
                     println!("Client disconnected");
                     break; // Drop the connection
                 }
-                _ => (),
-            }
+                Cmd::Slowlog(slowlog) => self.do_slowlog(slowlog),
+                cmd => {
+                    // Send the command to the driver
+                    let resp_tx_clone = match watch_rx {
+                        Some((ref watch_tx, _)) => watch_tx.clone(),
+                        None => resp_tx.clone(),
+                    };
+                    let stats = transport.get_stats_clone();
+                    {
+                        let _t = Timer::new(&mut rec,
+                                            ids::TRANSPORT_TASK_SEND_CMD);
 
-            // Send the command to the driver
-            let resp_tx_clone = resp_tx.clone();
-            let stats = transport.get_stats_clone();
-            {
-                let _t = Timer::new(&mut rec, "TransportTask:send_cmd");
-                self.cmd_tx
-                    .send((self.id, resp_tx_clone, cmd, stats))
-                    .unwrap();
-            }
+                        // The driver takes it off the backlog when it
+                        // receives it
+                        if self.options.get_metrics_enabled() {
+                            self.backlog.fetch_add(1, Ordering::SeqCst);
+                        }
+                        self.cmd_tx
+                            .send((self.id, resp_tx_clone, cmd, stats))
+                            .unwrap();
+                    }
 
-            // Obtain a response
-            let resp = {
-                let _t = Timer::new(&mut rec, "TransportTask:recv_resp");
-                resp_rx.recv().unwrap()
+                    // Obtain a response
+                    let _t = Timer::new(&mut rec,
+                                        ids::TRANSPORT_TASK_RECV_RESP);
+                    match watch_rx {
+                        Some((_, ref watch_rx)) => watch_rx.recv().unwrap(),
+                        None => resp_rx.recv().unwrap(),
+                    }
+                }
             };
+            let received_at = self.clock();
 
             // Return a response
             // println!("Returning response: {:?}", &resp);
             let rv = {
-                let _t = Timer::new(&mut rec, "TransportTask:write_resp");
+                let _t = Timer::new(&mut rec, ids::TRANSPORT_TASK_WRITE_RESP);
                 transport.write_resp(&resp)
             };
             if !rv.is_ok() {
                 println!("Failed to write response :(");
             }
+            let written_at = self.clock();
+
+            if let Some(mut entry) = entry {
+                if let Resp::Values(ref values) = resp {
+                    for value in values {
+                        entry.value_bytes += value.data.len() as u64;
+                    }
+                }
+
+                entry.read = sent_at - read_at;
+                entry.driver = received_at - sent_at;
+                entry.write = written_at - received_at;
+                self.log_if_slow(entry);
+            }
 
             // Stop timing the loop
-            rec.stop_timer("TransportTask:loop");
+            rec.stop_timer(ids::TRANSPORT_TASK_LOOP);
 
             // Now flush metrics outside the request path
             rec.flush_metrics();
+
+            // Only the driver holds on to the sending end of a watcher's
+            // channel, so that we hear about it when it lets go
+            if let Some((watch_tx, watch_rx)) = watch_rx {
+                drop(watch_tx);
+                if resp == Resp::Ok {
+                    self.stream_events(&mut transport, &watch_rx);
+                    break; // Drop the connection
+                }
+            }
         }
     }
 }
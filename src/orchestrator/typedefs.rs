@@ -1,10 +1,21 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
 use std::sync::mpsc::Receiver;
 
+use metrics::Histogram;
 use metrics::Metrics;
 use protocol::cmd::Cmd;
 use protocol::cmd::Resp;
 use tcp_transport::stats::TransportStats;
+use trace::TraceWriter;
+
+use super::slowlog::SlowLog;
 
 
 // Cmd/Resp Protocol
@@ -12,7 +23,14 @@ use tcp_transport::stats::TransportStats;
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct TransportId(pub u64);
 
-pub type RespSender = Sender<Resp>;
+// Reserved for commands the server sends itself (eg. the exporter asking for
+// stats), which don't have transport stats of their own. Transports are
+// numbered from 1.
+pub const INTERNAL_TRANSPORT_ID: TransportId = TransportId(0);
+
+// Bounded, so that a connection that can't keep up with what it is sent (a
+// watcher) can't make its queue grow without limit
+pub type RespSender = SyncSender<Resp>;
 pub type RespReceiver = Receiver<Resp>;
 
 pub type CmdSender = Sender<(TransportId, RespSender, Cmd, TransportStats)>;
@@ -21,7 +39,20 @@ pub type CmdReceiver = Receiver<(TransportId,
                                  Cmd,
                                  TransportStats)>;
 
+// The number of commands sent to the driver that it hasn't received yet
+pub type CmdBacklog = Arc<AtomicUsize>;
+
+// The slow commands of all transports
+pub type SharedSlowLog = Arc<Mutex<SlowLog>>;
+
+// The trace all transports record their commands to, None once we failed to
+// write to it
+pub type SharedTrace = Arc<Mutex<Option<TraceWriter<BufWriter<File>>>>>;
+
 // Metrics
 
 pub type MetricsSender = Sender<Metrics>;
 pub type MetricsReceiver = Receiver<Metrics>;
+
+// The timings of every timer since the server started
+pub type SharedTimers = Arc<Mutex<HashMap<String, Histogram>>>;
@@ -0,0 +1,242 @@
+use std::sync::mpsc::TrySendError;
+
+use protocol::cmd::Resp;
+use protocol::cmd::Watch;
+use protocol::cmd::WatchEvent;
+
+use super::RespSender;
+use super::TransportId;
+
+
+// A connection that streams the events it watches
+struct Watcher {
+    resp_tx: RespSender, // The response channel of the connection
+    watch: Watch, // Which kinds of events it wants
+    seen: u64, // Events it wanted so far, sampled or not
+    allowance: f64, // How many events it may still be sent right now
+    refilled_at: f64, // unixtime when the allowance was last topped up
+}
+
+impl Watcher {
+    // The allowance grows back at the rate, up to a second's worth
+    fn refill(&mut self, rate: f64, now: f64) {
+        let elapsed = (now - self.refilled_at).max(0.0);
+        self.allowance = (self.allowance + elapsed * rate).min(rate);
+        self.refilled_at = now;
+    }
+
+    // Returns false if the connection is gone. Events that don't fit in its
+    // queue (because it can't write them out as fast as they come) are
+    // dropped, and counted in dropped.
+    fn publish(&mut self,
+               events: &[WatchEvent],
+               sample: u64,
+               rate: f64,
+               now: f64,
+               dropped: &mut u64)
+               -> bool {
+        self.refill(rate, now);
+
+        let mut batch = vec![];
+        for event in events {
+            if !self.watch.wants(event.kind) {
+                continue;
+            }
+
+            self.seen += 1;
+            if (self.seen - 1) % sample != 0 || self.allowance < 1.0 {
+                continue;
+            }
+
+            self.allowance -= 1.0;
+            batch.push(event.clone());
+        }
+
+        if batch.is_empty() {
+            return true;
+        }
+        let batch_len = batch.len() as u64;
+        match self.resp_tx.try_send(Resp::Events(batch)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                *dropped += batch_len;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+
+// The connections that watch what happens to the keys. Each is sent only
+// every so many events, and no more than so many per second, so that a busy
+// server doesn't spend its time on them.
+pub struct Watchers {
+    watchers: Vec<Watcher>,
+    sample: u64, // Send every this many-th event, 1 for all
+    rate: f64, // Max events per second to send to each watcher
+}
+
+impl Watchers {
+    pub fn new(sample: u64, rate: u64) -> Watchers {
+        Watchers {
+            watchers: vec![],
+            sample: sample,
+            rate: rate as f64,
+        }
+    }
+
+    pub fn add(&mut self, resp_tx: RespSender, watch: Watch, now: f64) {
+        self.watchers.push(Watcher {
+            resp_tx: resp_tx,
+            watch: watch,
+            seen: 0,
+            allowance: self.rate,
+            refilled_at: now,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchers.is_empty()
+    }
+
+    // Sends the events caused by a command of the connection to everyone
+    // watching, and forgets about the watchers that have disconnected.
+    // Returns the number of events dropped because a watcher's queue was
+    // full.
+    pub fn publish(&mut self,
+                   id: TransportId,
+                   mut events: Vec<WatchEvent>,
+                   now: f64)
+                   -> u64 {
+        let TransportId(conn) = id;
+        for event in events.iter_mut() {
+            event.time = now;
+            event.conn = conn;
+        }
+
+        let sample = self.sample;
+        let rate = self.rate;
+        let mut dropped = 0;
+        self.watchers.retain_mut(|watcher| {
+            watcher.publish(&events, sample, rate, now, &mut dropped)
+        });
+
+        dropped
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use protocol::cmd::Resp;
+    use protocol::cmd::Watch;
+    use protocol::cmd::WatchEvent;
+    use protocol::cmd::WatchEventKind;
+
+    use super::super::TransportId;
+
+    use super::Watchers;
+
+
+    fn events(kind: WatchEventKind, count: usize) -> Vec<WatchEvent> {
+        (0..count)
+            .map(|i| {
+                WatchEvent::new(kind, format!("k{}", i).as_bytes(), 1, None)
+            })
+            .collect()
+    }
+
+    fn received(resp: Resp) -> Vec<WatchEvent> {
+        match resp {
+            Resp::Events(events) => events,
+            resp => panic!("not events: {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_watchers_filter_and_stamp() {
+        let (resp_tx, resp_rx) = mpsc::sync_channel(10);
+        let mut watchers = Watchers::new(1, 100);
+        watchers.add(resp_tx, Watch::new(false, true, false), 1.0);
+
+        let mut batch = events(WatchEventKind::Hit, 2);
+        batch.extend(events(WatchEventKind::Set, 1));
+        watchers.publish(TransportId(7), batch, 2.0);
+
+        // Only the mutation it asked for
+        let got = received(resp_rx.try_recv().unwrap());
+        assert_eq!(1, got.len());
+        assert_eq!(WatchEventKind::Set, got[0].kind);
+        assert_eq!(7, got[0].conn);
+        assert_eq!(2.0, got[0].time);
+
+        // Nothing is sent when there is nothing it wants
+        watchers.publish(TransportId(7), events(WatchEventKind::Miss, 3), 2.0);
+        assert!(resp_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_watchers_sample() {
+        let (resp_tx, resp_rx) = mpsc::sync_channel(10);
+        let mut watchers = Watchers::new(3, 100);
+        watchers.add(resp_tx, Watch::new(true, true, true), 1.0);
+
+        watchers.publish(TransportId(1), events(WatchEventKind::Hit, 7), 1.0);
+
+        let got = received(resp_rx.try_recv().unwrap());
+        let keys: Vec<&[u8]> = got.iter()
+                                  .map(|event| &event.key[..])
+                                  .collect();
+        assert_eq!(vec![&b"k0"[..], &b"k3"[..], &b"k6"[..]], keys);
+    }
+
+    #[test]
+    fn test_watchers_rate_limit() {
+        let (resp_tx, resp_rx) = mpsc::sync_channel(10);
+        let mut watchers = Watchers::new(1, 2);
+        watchers.add(resp_tx, Watch::new(true, true, true), 1.0);
+
+        // A second's worth at most
+        watchers.publish(TransportId(1), events(WatchEventKind::Hit, 5), 1.0);
+        assert_eq!(2, received(resp_rx.try_recv().unwrap()).len());
+        watchers.publish(TransportId(1), events(WatchEventKind::Hit, 5), 1.0);
+        assert!(resp_rx.try_recv().is_err());
+
+        // Half a second later there is room for one more
+        watchers.publish(TransportId(1), events(WatchEventKind::Hit, 5), 1.5);
+        assert_eq!(1, received(resp_rx.try_recv().unwrap()).len());
+    }
+
+    #[test]
+    fn test_watchers_drop_when_full() {
+        let (resp_tx, resp_rx) = mpsc::sync_channel(1);
+        let mut watchers = Watchers::new(1, 100);
+        watchers.add(resp_tx, Watch::new(true, true, true), 1.0);
+
+        // The watcher doesn't keep up, so the second batch doesn't fit
+        let batch = events(WatchEventKind::Hit, 2);
+        assert_eq!(0, watchers.publish(TransportId(1), batch.clone(), 1.0));
+        assert_eq!(2, watchers.publish(TransportId(1), batch.clone(), 1.0));
+        assert!(!watchers.is_empty());
+
+        // Once it catches up it is sent events again
+        assert_eq!(2, received(resp_rx.try_recv().unwrap()).len());
+        assert!(resp_rx.try_recv().is_err());
+        assert_eq!(0, watchers.publish(TransportId(1), batch, 1.0));
+        assert_eq!(2, received(resp_rx.try_recv().unwrap()).len());
+    }
+
+    #[test]
+    fn test_watchers_drop_disconnected() {
+        let (resp_tx, resp_rx) = mpsc::sync_channel(10);
+        let mut watchers = Watchers::new(1, 100);
+        watchers.add(resp_tx, Watch::new(true, true, true), 1.0);
+
+        drop(resp_rx);
+        watchers.publish(TransportId(1), events(WatchEventKind::Hit, 1), 1.0);
+        assert!(watchers.is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::Instant;
+
+use super::time::time_now;
+
+
+// Where everything that expires gets the time from. Times are in seconds on a
+// timeline that starts at the wall clock time the clock was created, so that
+// it lines up with the unixtimes clients send us, but from then on it only
+// moves forward. That way adjusting the system clock can't expire or
+// resurrect items.
+pub trait Clock {
+    fn now(&self) -> f64;
+}
+
+
+#[derive(Clone)]
+pub struct SystemClock {
+    started_at: f64, // unixtime the clock was created
+    started: Instant, // the same moment on the monotonic clock
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            started_at: time_now(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        let elapsed = self.started.elapsed();
+        let secs = elapsed.as_secs() as f64 +
+                   elapsed.subsec_nanos() as f64 / 1_000_000_000f64;
+
+        self.started_at + secs
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use platform::time::time_now;
+
+    use super::Clock;
+    use super::SystemClock;
+
+
+    #[test]
+    fn test_system_clock() {
+        let clock = SystemClock::new();
+
+        // starts out at the wall clock time
+        let t1 = clock.now();
+        assert!((t1 - time_now()).abs() < 1.0);
+
+        // never goes backwards
+        let t2 = clock.now();
+        assert!(t2 >= t1);
+    }
+}
@@ -1,3 +1,4 @@
 // Declare sub modules
+pub mod clock;
 pub mod process;
 pub mod time;
@@ -1,6 +1,71 @@
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+
 use libc;
 
 
 pub fn get_pid() -> u32 {
     unsafe { libc::getpid() as u32 }
 }
+
+fn get_page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+fn convert_timeval_to_secs(tv: libc::timeval) -> f64 {
+    tv.tv_sec as f64 + (tv.tv_usec as f64 / 1_000_000f64)
+}
+
+// Returns the cpu time (user, system) consumed by the process, in seconds
+pub fn get_rusage() -> (f64, f64) {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    unsafe {
+        libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+    }
+
+    (convert_timeval_to_secs(usage.ru_utime),
+     convert_timeval_to_secs(usage.ru_stime))
+}
+
+// Parses the contents of /proc/<pid>/statm and returns the resident set size
+// in pages (the second field)
+fn parse_statm_rss_pages(statm: &str) -> Option<u64> {
+    match statm.split_whitespace().nth(1) {
+        Some(field) => field.parse::<u64>().ok(),
+        None => None,
+    }
+}
+
+// Returns the resident set size of the process in bytes, or None if it cannot
+// be determined (eg. no /proc filesystem)
+pub fn get_rss_bytes() -> Option<u64> {
+    let mut statm = String::new();
+
+    let rv = File::open("/proc/self/statm")
+                 .and_then(|mut file| file.read_to_string(&mut statm));
+    if rv.is_err() {
+        return None;
+    }
+
+    parse_statm_rss_pages(&statm).map(|pages| pages * get_page_size())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_statm_rss_pages;
+
+
+    #[test]
+    fn test_parse_statm_rss_pages() {
+        assert_eq!(Some(512), parse_statm_rss_pages("4096 512 128 1 0 64 0\n"));
+    }
+
+    #[test]
+    fn test_parse_statm_rss_pages_malformed() {
+        assert_eq!(None, parse_statm_rss_pages(""));
+        assert_eq!(None, parse_statm_rss_pages("4096"));
+        assert_eq!(None, parse_statm_rss_pages("4096 x 128"));
+    }
+}
@@ -1,18 +1,39 @@
 // ref: https://github.com/memcached/memcached/blob/master/doc/protocol.txt
+//
+// Keys are arbitrary bytes (apart from whitespace and control characters), so
+// they are carried as such rather than as strings.
+
+use common::blob::Blob;
 
 
 // Request structs
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct CacheMemlimit {
+    pub memlimit: u64, // New capacity of the cache in megabytes
+    pub noreply: bool, // Indicates whether the server should reply
+}
+
+impl CacheMemlimit {
+    pub fn new(memlimit: u64, noreply: bool) -> CacheMemlimit {
+        CacheMemlimit {
+            memlimit: memlimit,
+            noreply: noreply,
+        }
+    }
+}
+
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Delete {
-    pub key: String,
+    pub key: Vec<u8>,
     pub noreply: bool, // Indicates whether the server should reply to the delete
 }
 
 impl Delete {
-    pub fn new(key: &str, noreply: bool) -> Delete {
+    pub fn new(key: &[u8], noreply: bool) -> Delete {
         Delete {
-            key: key.to_string(),
+            key: key.to_vec(),
             noreply: noreply,
         }
     }
@@ -45,21 +66,21 @@ pub enum GetInstr {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Get {
     pub instr: GetInstr, // Instruction to perform
-    pub keys: Vec<String>,
+    pub keys: Vec<Vec<u8>>,
 }
 
 impl Get {
-    pub fn new(instr: GetInstr, keys: Vec<String>) -> Get {
+    pub fn new(instr: GetInstr, keys: Vec<Vec<u8>>) -> Get {
         Get {
             instr: instr,
             keys: keys,
         }
     }
 
-    pub fn one(instr: GetInstr, key: &str) -> Get {
+    pub fn one(instr: GetInstr, key: &[u8]) -> Get {
         Get {
             instr: instr,
-            keys: vec![key.to_string()],
+            keys: vec![key.to_vec()],
         }
     }
 }
@@ -75,16 +96,16 @@ pub enum IncInstr {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Inc {
     pub instr: IncInstr, // Instruction to perform
-    pub key: String,
+    pub key: Vec<u8>,
     pub delta: u64,
     pub noreply: bool,
 }
 
 impl Inc {
-    pub fn new(instr: IncInstr, key: &str, delta: u64, noreply: bool) -> Inc {
+    pub fn new(instr: IncInstr, key: &[u8], delta: u64, noreply: bool) -> Inc {
         Inc {
             instr: instr,
-            key: key.to_string(),
+            key: key.to_vec(),
             delta: delta,
             noreply: noreply,
         }
@@ -92,6 +113,34 @@ impl Inc {
 }
 
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum InvalidateInstr {
+    Prefix, // Invalidate all keys starting with a prefix
+    Tag, // Invalidate all items with a tag
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Invalidate {
+    pub instr: InvalidateInstr, // Instruction to perform
+    pub target: Vec<u8>, // The prefix or the tag
+    pub noreply: bool,
+}
+
+impl Invalidate {
+    pub fn new(instr: InvalidateInstr,
+               target: &[u8],
+               noreply: bool)
+               -> Invalidate {
+        Invalidate {
+            instr: instr,
+            target: target.to_vec(),
+            noreply: noreply,
+        }
+    }
+}
+
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum SetInstr {
     Set, // Store an item
@@ -106,28 +155,28 @@ pub enum SetInstr {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Set {
     pub instr: SetInstr, // Instruction to perform
-    pub key: String, // Alphanumeric characters
-    pub flags: u16, // Arbitrary bit pattern chosen by the client
+    pub key: Vec<u8>, // No whitespace or control characters
+    pub flags: u32, // Arbitrary bit pattern chosen by the client
     pub exptime: u32, // Relative (secs) or absolute (unixtime) expiry time
-    pub data: Vec<u8>, // Binary data
+    pub data: Blob, // Binary data
     pub cas_unique: Option<u64>, // Client cookie used for conditional updates
     pub noreply: bool, // Indicates whether the server should reply to the set
 }
 
 impl Set {
     pub fn new(instr: SetInstr,
-               key: &str,
-               flags: u16,
+               key: &[u8],
+               flags: u32,
                exptime: u32,
                data: Vec<u8>,
                noreply: bool)
                -> Set {
         Set {
             instr: instr,
-            key: key.to_string(),
+            key: key.to_vec(),
             flags: flags,
             exptime: exptime,
-            data: data,
+            data: Blob::new(data),
             cas_unique: None,
             noreply: noreply,
         }
@@ -140,17 +189,70 @@ impl Set {
 }
 
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum SlowlogInstr {
+    Get, // Show the slowest recent commands
+    Reset, // Forget them
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Slowlog {
+    pub instr: SlowlogInstr, // Instruction to perform
+    pub count: Option<u64>, // How many entries to show, None for all
+}
+
+impl Slowlog {
+    pub fn new(instr: SlowlogInstr, count: Option<u64>) -> Slowlog {
+        Slowlog {
+            instr: instr,
+            count: count,
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Stats {
+    pub group: Option<String>, // Which stats to report, None for the general
+}
+
+impl Stats {
+    pub fn new(group: Option<&str>) -> Stats {
+        Stats { group: group.map(|group| group.to_string()) }
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Tag {
+    pub key: Vec<u8>,
+    pub tag: Vec<u8>, // Same rules as for keys
+    pub noreply: bool,
+}
+
+impl Tag {
+    pub fn new(key: &[u8], tag: &[u8], noreply: bool) -> Tag {
+        Tag {
+            key: key.to_vec(),
+            tag: tag.to_vec(),
+            noreply: noreply,
+        }
+    }
+}
+
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Touch {
-    pub key: String,
+    pub key: Vec<u8>,
     pub exptime: u32,
     pub noreply: bool,
 }
 
 impl Touch {
-    pub fn new(key: &str, exptime: u32, noreply: bool) -> Touch {
+    pub fn new(key: &[u8], exptime: u32, noreply: bool) -> Touch {
         Touch {
-            key: key.to_string(),
+            key: key.to_vec(),
             exptime: exptime,
             noreply: noreply,
         }
@@ -158,8 +260,88 @@ impl Touch {
 }
 
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Watch {
+    pub fetchers: bool, // Gets, hit or miss
+    pub mutations: bool, // Sets and deletes
+    pub evictions: bool, // Items evicted or expired
+}
+
+impl Watch {
+    pub fn new(fetchers: bool, mutations: bool, evictions: bool) -> Watch {
+        Watch {
+            fetchers: fetchers,
+            mutations: mutations,
+            evictions: evictions,
+        }
+    }
+
+    // Does the watcher want to hear about this kind of event?
+    pub fn wants(&self, kind: WatchEventKind) -> bool {
+        match kind {
+            WatchEventKind::Hit | WatchEventKind::Miss => self.fetchers,
+            WatchEventKind::Set | WatchEventKind::Delete => self.mutations,
+            WatchEventKind::Evict | WatchEventKind::Expire => self.evictions,
+        }
+    }
+}
+
+
 // Response structs
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchEventKind {
+    Hit,
+    Miss,
+    Set,
+    Delete,
+    Evict, // Removed to make space
+    Expire, // Found dead and dropped
+}
+
+impl WatchEventKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WatchEventKind::Hit => "hit",
+            WatchEventKind::Miss => "miss",
+            WatchEventKind::Set => "set",
+            WatchEventKind::Delete => "delete",
+            WatchEventKind::Evict => "evict",
+            WatchEventKind::Expire => "expire",
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WatchEvent {
+    pub time: f64, // unixtime
+    pub conn: u64, // The connection whose command caused it
+    pub kind: WatchEventKind,
+    pub key: Vec<u8>,
+    pub size: u64, // Bytes of the value, 0 if there is none
+    pub ttl: Option<u32>, // Seconds it has left, None if it never expires
+}
+
+impl WatchEvent {
+    pub fn new(kind: WatchEventKind,
+               key: &[u8],
+               size: u64,
+               ttl: Option<u32>)
+               -> WatchEvent {
+        WatchEvent {
+            time: 0.0,
+            conn: 0,
+            kind: kind,
+            key: key.to_vec(),
+            size: size,
+            ttl: ttl,
+        }
+    }
+}
+
+
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Stat {
     pub key: String,
@@ -178,19 +360,19 @@ impl Stat {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Value {
-    pub key: String,
-    pub flags: u16,
+    pub key: Vec<u8>,
+    pub flags: u32,
     pub cas_unique: Option<u64>,
-    pub data: Vec<u8>,
+    pub data: Blob,
 }
 
 impl Value {
-    pub fn new(key: &str, flags: u16, data: Vec<u8>) -> Value {
+    pub fn new(key: &[u8], flags: u32, data: Vec<u8>) -> Value {
         Value {
-            key: key.to_string(),
+            key: key.to_vec(),
             flags: flags,
             cas_unique: None,
-            data: data,
+            data: Blob::new(data),
         }
     }
 
@@ -205,15 +387,74 @@ impl Value {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Cmd {
+    CacheMemlimit(CacheMemlimit),
     Delete(Delete),
     FlushAll(FlushAll),
     Get(Get),
     Inc(Inc),
+    Invalidate(Invalidate),
     Quit,
     Set(Set),
-    Stats,
+    Slowlog(Slowlog),
+    Stats(Stats),
+    Tag(Tag),
     Touch(Touch),
     Version,
+    Watch(Watch),
+}
+
+impl Cmd {
+    // What the command is called in the protocol, to keep stats by
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Cmd::CacheMemlimit(_) => "cache_memlimit",
+            Cmd::Delete(_) => "delete",
+            Cmd::FlushAll(_) => "flush",
+            Cmd::Get(ref get) => {
+                match get.instr {
+                    GetInstr::Get => "get",
+                    GetInstr::Gets => "gets",
+                }
+            }
+            Cmd::Inc(ref inc) => {
+                match inc.instr {
+                    IncInstr::Incr => "incr",
+                    IncInstr::Decr => "decr",
+                }
+            }
+            Cmd::Invalidate(_) => "invalidate",
+            Cmd::Quit => "quit",
+            Cmd::Set(ref set) => {
+                match set.instr {
+                    SetInstr::Set => "set",
+                    SetInstr::Add => "add",
+                    SetInstr::Replace => "replace",
+                    SetInstr::Append => "append",
+                    SetInstr::Prepend => "prepend",
+                    SetInstr::Cas => "cas",
+                }
+            }
+            Cmd::Slowlog(_) => "slowlog",
+            Cmd::Stats(_) => "stats",
+            Cmd::Tag(_) => "tag",
+            Cmd::Touch(_) => "touch",
+            Cmd::Version => "version",
+            Cmd::Watch(_) => "watch",
+        }
+    }
+
+    // The keys the command is about, if any
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match *self {
+            Cmd::Delete(ref delete) => vec![&delete.key[..]],
+            Cmd::Get(ref get) => get.keys.iter().map(|key| &key[..]).collect(),
+            Cmd::Inc(ref inc) => vec![&inc.key[..]],
+            Cmd::Set(ref set) => vec![&set.key[..]],
+            Cmd::Tag(ref tag) => vec![&tag.key[..]],
+            Cmd::Touch(ref touch) => vec![&touch.key[..]],
+            _ => vec![],
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -228,12 +469,14 @@ pub enum Resp {
 
     Deleted, // The item was deleted successfully
     Exists, // The cas item has been modified
-    Ok, // FlushAll succeeded
+    Ok, // FlushAll/CacheMemlimit/Invalidate/Watch succeeded
     NotFound, // The cas item does not exist
     NotStored, // Precondition not met
     Stored, // The item was stored successfully
+    Tagged, // The item was tagged successfully
     Touched, // The item was touched successfully
 
+    Events(Vec<WatchEvent>), // Streamed to a connection that watches
     IntValue(u64), // Result of an incr/decr
     Stats(Vec<Stat>),
     Values(Vec<Value>),
@@ -242,6 +485,30 @@ pub enum Resp {
 }
 
 impl Resp {
+    // How the command went, to keep stats by
+    pub fn outcome(&self) -> &'static str {
+        match *self {
+            Resp::Empty => "noreply",
+            Resp::Error => "error",
+            Resp::ClientError(_) => "error",
+            Resp::ServerError(_) => "error",
+            Resp::Deleted => "hit",
+            Resp::Exists => "exists",
+            Resp::NotFound => "miss",
+            Resp::NotStored => "not_stored",
+            Resp::Stored => "stored",
+            Resp::Tagged => "hit",
+            Resp::Touched => "hit",
+            Resp::IntValue(_) => "hit",
+            Resp::Events(_) => "ok",
+            Resp::Values(ref values) if values.is_empty() => "miss",
+            Resp::Values(_) => "hit",
+            Resp::Ok => "ok",
+            Resp::Stats(_) => "ok",
+            Resp::Version(_) => "ok",
+        }
+    }
+
     pub fn get_stats(&self) -> Option<&Vec<Stat>> {
         match *self {
             Resp::Stats(ref stats) => Some(&stats),
@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+
+use metrics::Histogram;
+
+
+// How often one kind of command ran, how it went and how long it took
+pub struct CommandStat {
+    pub count: u64,
+    pub outcomes: BTreeMap<&'static str, u64>, // Outcome -> count
+    pub latency: Histogram, // in seconds
+}
+
+impl CommandStat {
+    pub fn new() -> CommandStat {
+        CommandStat {
+            count: 0,
+            latency: Histogram::new(),
+            outcomes: BTreeMap::new(),
+        }
+    }
+}
+
+
+// Stats per kind of command (get, set, ...), so that a slow kind doesn't
+// hide among the fast ones
+pub struct CommandStats {
+    commands: BTreeMap<&'static str, CommandStat>, // Name -> stats
+}
+
+impl CommandStats {
+    pub fn new() -> CommandStats {
+        CommandStats { commands: BTreeMap::new() }
+    }
+
+    pub fn record(&mut self,
+                  name: &'static str,
+                  outcome: &'static str,
+                  duration: f64) {
+        let stat = self.commands.entry(name).or_insert_with(CommandStat::new);
+
+        stat.count += 1;
+        *stat.outcomes.entry(outcome).or_insert(0) += 1;
+        stat.latency.add(duration);
+    }
+
+    // The kinds of commands that ran and their stats, by name
+    pub fn iter(&self) -> btree_map::Iter<'_, &'static str, CommandStat> {
+        self.commands.iter()
+    }
+}
@@ -1,12 +1,21 @@
+use std::mem;
+
+use common::blob::Blob;
 use common::consts::get_version_string;
 use platform::process::get_pid;
-use platform::time::time_now;
+use platform::process::get_rss_bytes;
+use platform::process::get_rusage;
 use storage::Cache;
 use storage::CacheError;
+use storage::CacheEventKind;
+use storage::CacheResult;
 use storage::Key;
+use storage::NamespaceStats;
+use storage::SizeHistogram;
 use storage::Value;
 use tcp_transport::stats::TransportStats;
 
+use super::cmd::CacheMemlimit;
 use super::cmd::Cmd;
 use super::cmd::Delete;
 use super::cmd::FlushAll;
@@ -14,12 +23,20 @@ use super::cmd::Get;
 use super::cmd::GetInstr;
 use super::cmd::Inc;
 use super::cmd::IncInstr;
+use super::cmd::Invalidate;
+use super::cmd::InvalidateInstr;
 use super::cmd::Resp;
 use super::cmd::Set;
 use super::cmd::SetInstr;
 use super::cmd::Stat;
+use super::cmd::Stats;
+use super::cmd::Tag;
 use super::cmd::Touch;
 use super::cmd::Value as CmdValue;
+use super::cmd::WatchEvent;
+use super::cmd::WatchEventKind;
+use super::command_stats::CommandStats;
+use super::hotkeys::HotKeys;
 use super::util::bytes_to_u64;
 use super::util::convert_exptime;
 use super::util::from_cache_err;
@@ -131,8 +148,13 @@ impl DriverStats {
 
 pub struct Driver {
     cache: Cache,
+    commands: CommandStats, // Per kind of command
+    hotkeys: Option<HotKeys>, // The most requested keys, if we track them
     time_start: f64,
 
+    watched: bool, // Does anyone watch what happens to the keys?
+    events: Vec<WatchEvent>, // What happened since they were last taken
+
     stats: DriverStats,
     transport_stats: TransportStats, // this is a global snapshot
 }
@@ -140,17 +162,26 @@ pub struct Driver {
 impl Driver {
     pub fn new(cache: Cache) -> Driver {
         Driver {
-            cache: cache,
+            commands: CommandStats::new(),
+            events: vec![],
+            hotkeys: None,
             stats: DriverStats::new(),
-            time_start: time_now(),
+            time_start: cache.get_clock().now(),
+            cache: cache,
             transport_stats: TransportStats::new(),
+            watched: false,
         }
     }
 
+    pub fn with_hotkeys(&mut self, hotkeys: HotKeys) -> &mut Driver {
+        self.hotkeys = Some(hotkeys);
+        self
+    }
+
 
 
     fn set_exptime(&self, value: &mut Value, exptime: u32) {
-        match convert_exptime(exptime) {
+        match convert_exptime(exptime, self.cache.get_clock().now()) {
             Some(tm) => {
                 value.set_exptime(tm);
             }
@@ -159,8 +190,52 @@ impl Driver {
     }
 
 
+    // The seconds an item with this exptime has left, None if it never
+    // expires
+    fn ttl(&self, exptime: f64) -> Option<u32> {
+        if exptime <= 0.0 {
+            return None;
+        }
+
+        let left = exptime - self.cache.get_clock().now();
+        Some(left.max(0.0).ceil() as u32)
+    }
+
+    fn watch_event(&mut self,
+                   kind: WatchEventKind,
+                   key: &[u8],
+                   size: u64,
+                   exptime: f64) {
+        if !self.watched {
+            return;
+        }
+
+        let ttl = self.ttl(exptime);
+        self.events.push(WatchEvent::new(kind, key, size, ttl));
+    }
+
+    // Stores the value on behalf of a client, as opposed to putting back a
+    // value we took out to change its metadata
+    fn store(&mut self, key: Key, value: Value) -> CacheResult<()> {
+        let watched = match self.watched {
+            true => {
+                Some((key.item.clone(), value.len(), *value.get_exptime()))
+            }
+            false => None,
+        };
+
+        let rv = self.cache.set(key, value);
+
+        if let (Ok(_), Some((key, size, exptime))) = (&rv, watched) {
+            self.watch_event(WatchEventKind::Set, &key, size as u64, exptime);
+        }
+
+        rv
+    }
+
+
     fn do_add(&mut self, set: Set) -> Resp {
-        let key = Key::new(set.key.into_bytes());
+        let key = Key::new(set.key);
 
         // Do we store this item already? If so it's an early exit.
         let rv = self.cache.contains_key(&key);
@@ -175,7 +250,7 @@ impl Driver {
         value.set_flags(set.flags);
         self.set_exptime(&mut value, set.exptime);
 
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!set.noreply,
                           match rv {
@@ -185,7 +260,7 @@ impl Driver {
     }
 
     fn do_append(&mut self, set: Set) -> Resp {
-        let key = Key::new(set.key.into_bytes());
+        let key = Key::new(set.key);
 
         // Load the value
         let rv = self.cache.remove(&key);
@@ -206,12 +281,9 @@ impl Driver {
         self.set_exptime(&mut value, set.exptime);
 
         // Append the data we just received to the blob that is there
-        {
-            let mut blob = value.get_item_mut();
-            blob.extend(set.data);
-        }
+        value.get_item_mut().append(&set.data);
 
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!set.noreply,
                           match rv {
@@ -220,8 +292,27 @@ impl Driver {
                           })
     }
 
+    fn do_cache_memlimit(&mut self, cache_memlimit: CacheMemlimit) -> Resp {
+        // A cache that can't store anything is not a cache, and one that
+        // holds more bytes than we can count isn't either
+        let capacity = cache_memlimit.memlimit.checked_mul(1 << 20);
+        let capacity = match capacity {
+            Some(capacity) if capacity > 0 => capacity,
+            _ => {
+                let err = "bad command line format".to_string();
+                return maybe_reply_expr!(!cache_memlimit.noreply,
+                                         Resp::ClientError(err));
+            }
+        };
+
+        // Shrinking evicts gradually, so this returns right away
+        self.cache.set_capacity(capacity);
+
+        maybe_reply_expr!(!cache_memlimit.noreply, Resp::Ok)
+    }
+
     fn do_cas(&mut self, set: Set) -> Resp {
-        let key = Key::new(set.key.into_bytes());
+        let key = Key::new(set.key);
 
         // If the key is not set we bail
         let rv = self.cache.contains_key(&key);
@@ -262,12 +353,14 @@ impl Driver {
         let rv = self.cache.remove(&key);
         let mut value = rv.unwrap();
 
-        // Set all the data the client sent
+        // Set all the data the client sent. Only the cas id carries over,
+        // the tags were for the item we're replacing.
         value.set_item(set.data);
         value.set_flags(set.flags);
+        value.clear_tags();
         self.set_exptime(&mut value, set.exptime);
 
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!set.noreply,
                           match rv {
@@ -277,10 +370,16 @@ impl Driver {
     }
 
     fn do_delete(&mut self, delete: Delete) -> Resp {
-        let key = Key::new(delete.key.clone().into_bytes());
+        let key = Key::new(delete.key);
 
         let rv = self.cache.remove(&key);
 
+        if let Ok(ref value) = rv {
+            let size = value.len() as u64;
+            let exptime = *value.get_exptime();
+            self.watch_event(WatchEventKind::Delete, &key.item, size, exptime);
+        }
+
         maybe_reply_expr!(!delete.noreply,
                           match rv {
                               Ok(_) => Resp::Deleted,
@@ -293,12 +392,16 @@ impl Driver {
         // Update stats
         self.stats.cmd_flush += 1;
 
-        let exptime: f64 = match flush_all.exptime {
-            Some(ref exptime) => convert_exptime(*exptime).unwrap(),
-            None => time_now(),
+        // No delay (or 0) flushes right away, like in memcached
+        let now = self.cache.get_clock().now();
+        let deadline = match flush_all.exptime {
+            Some(exptime) => convert_exptime(exptime, now),
+            None => None,
+        };
+        let rv = match deadline {
+            Some(deadline) => self.cache.flush_all_at(deadline),
+            None => self.cache.flush_all(),
         };
-
-        let rv = self.cache.flush_all(exptime);
 
         maybe_reply_expr!(!flush_all.noreply,
                           match rv {
@@ -314,28 +417,34 @@ impl Driver {
         let mut values = vec![];
 
         for key in get.keys {
-            let key_str = key.clone();
-
-            let key_st = Key::new(key.into_bytes());
+            let key_st = Key::new(key.clone());
             let rv = self.cache.get(&key_st);
 
             match rv {
                 Ok(value) => {
+                    let size = value.len() as u64;
+                    let exptime = *value.get_exptime();
+                    self.watch_event(WatchEventKind::Hit, &key, size, exptime);
+
+                    let cas_id = value.get_cas_id().clone();
+
                     let mut val_st = CmdValue {
-                        key: key_str,
+                        key: key,
                         flags: value.get_flags().clone(),
                         cas_unique: None,
-                        data: value.get_item().clone(),
+                        data: value.into_item(),
                     };
 
                     if get.instr == GetInstr::Gets {
-                        val_st.with_cas_unique(value.get_cas_id().clone());
+                        val_st.with_cas_unique(cas_id);
                     }
 
                     values.push(val_st);
                 }
                 // Keys that were not found are skipped, no error given
-                Err(_) => (),
+                Err(_) => {
+                    self.watch_event(WatchEventKind::Miss, &key, 0, 0.0);
+                }
             }
         }
 
@@ -343,7 +452,7 @@ impl Driver {
     }
 
     fn do_inc(&mut self, inc: Inc) -> Resp {
-        let key = Key::new(inc.key.clone().into_bytes());
+        let key = Key::new(inc.key);
 
         {
             // Check the value first
@@ -382,7 +491,7 @@ impl Driver {
             let value = rv.unwrap();
             // Does it represent a number?
             maybe_reply_stmt!(!inc.noreply,
-                              match bytes_to_u64(value.get_item()) {
+                              match bytes_to_u64(&value.get_item().to_vec()) {
                                   Some(_) => None,
                                   None => {
                                       Some(Resp::ClientError("Not a number"
@@ -396,7 +505,7 @@ impl Driver {
         let mut value = rv.unwrap();
 
         // Apply incr/decr
-        let mut num = bytes_to_u64(value.get_item()).unwrap();
+        let mut num = bytes_to_u64(&value.get_item().to_vec()).unwrap();
         match inc.instr {
             IncInstr::Decr => {
                 // saturates (stays at 0), does not underflow
@@ -407,10 +516,10 @@ impl Driver {
                 num = num.wrapping_add(inc.delta);
             }
         };
-        value.set_item(u64_to_bytes(&num));
+        value.set_item(Blob::new(u64_to_bytes(&num)));
 
         // Set it
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!inc.noreply,
                           match rv {
@@ -419,8 +528,25 @@ impl Driver {
                           })
     }
 
+    fn do_invalidate(&mut self, invalidate: Invalidate) -> Resp {
+        let rv = match invalidate.instr {
+            InvalidateInstr::Prefix => {
+                self.cache.invalidate_prefix(&invalidate.target)
+            }
+            InvalidateInstr::Tag => {
+                self.cache.invalidate_tag(&invalidate.target)
+            }
+        };
+
+        maybe_reply_expr!(!invalidate.noreply,
+                          match rv {
+                              Ok(_) => Resp::Ok,
+                              Err(ref err) => from_cache_err(err),
+                          })
+    }
+
     fn do_prepend(&mut self, set: Set) -> Resp {
-        let key = Key::new(set.key.into_bytes());
+        let key = Key::new(set.key);
 
         // Load the value
         let rv = self.cache.remove(&key);
@@ -441,13 +567,9 @@ impl Driver {
         self.set_exptime(&mut value, set.exptime);
 
         // Prepend the data we just received to the blob that is there
-        let mut new_item = Vec::with_capacity(set.data.len() +
-                                              value.get_item().len());
-        new_item.extend(set.data);
-        new_item.extend(value.get_item());
-        value.set_item(new_item);
+        value.get_item_mut().prepend(&set.data);
 
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!set.noreply,
                           match rv {
@@ -457,7 +579,7 @@ impl Driver {
     }
 
     fn do_replace(&mut self, set: Set) -> Resp {
-        let key = Key::new(set.key.into_bytes());
+        let key = Key::new(set.key);
 
         // Do we store this item already? If not it's an early exit.
         let rv = self.cache.contains_key(&key);
@@ -472,7 +594,7 @@ impl Driver {
         value.set_flags(set.flags);
         self.set_exptime(&mut value, set.exptime);
 
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!set.noreply,
                           match rv {
@@ -485,7 +607,7 @@ impl Driver {
         // Update stats
         self.stats.cmd_set += 1;
 
-        let key = Key::new(set.key.into_bytes());
+        let key = Key::new(set.key);
 
         // Obtain either the existing value or a fresh one
         let mut value = {
@@ -496,12 +618,14 @@ impl Driver {
             }
         };
 
-        // Set all the data the client sent
+        // Set all the data the client sent. Only the cas id carries over,
+        // the tags were for the item we're replacing.
         value.set_item(set.data);
         value.set_flags(set.flags);
+        value.clear_tags();
         self.set_exptime(&mut value, set.exptime);
 
-        let rv = self.cache.set(key, value);
+        let rv = self.store(key, value);
 
         maybe_reply_expr!(!set.noreply,
                           match rv {
@@ -510,13 +634,35 @@ impl Driver {
                           })
     }
 
-    fn do_stats(&self) -> Resp {
+    fn do_stats(&self, stats: Stats) -> Resp {
+        match stats.group {
+            None => self.do_stats_general(),
+            Some(ref group) if group == "namespaces" => {
+                self.do_stats_namespaces()
+            }
+            Some(ref group) if group == "sizes" => {
+                self.do_stats_sizes(self.cache.get_item_sizes())
+            }
+            Some(ref group) if group == "key_sizes" => {
+                self.do_stats_sizes(self.cache.get_key_sizes())
+            }
+            Some(ref group) if group == "hotkeys" => self.do_stats_hotkeys(),
+            Some(ref group) if group == "commands" => self.do_stats_commands(),
+            Some(_) => Resp::Error,
+        }
+    }
+
+    fn do_stats_general(&self) -> Resp {
         let storage = self.cache.get_stats();
 
         let pid = get_pid().to_string();
-        let uptime = ((time_now() - self.time_start) as u64).to_string();
-        let time = (time_now() as u64).to_string();
+        let now = self.cache.get_clock().now();
+        let uptime = ((now - self.time_start) as u64).to_string();
+        let time = (now as u64).to_string();
         let version = get_version_string();
+        let (rusage_user, rusage_system) = get_rusage();
+        let rusage_user = format!("{:.6}", rusage_user);
+        let rusage_system = format!("{:.6}", rusage_system);
         let cmd_get = self.stats.cmd_get.to_string();
         let cmd_set = self.stats.cmd_set.to_string();
         let cmd_flush = self.stats.cmd_flush.to_string();
@@ -542,11 +688,18 @@ impl Driver {
         let total_items = storage.total_items.to_string();
         let evictions = storage.evictions.to_string();
         let reclaimed = storage.reclaimed.to_string();
+        let hash_bytes = self.cache.hash_bytes().to_string();
+        let rss = get_rss_bytes().unwrap_or(0).to_string();
+        let compress_ratio = format!("{:.2}", storage.compress_ratio());
+        let compress_time = format!("{:.6}", storage.compress_time);
+        let decompress_time = format!("{:.6}", storage.decompress_time);
 
         let st_pid = Stat::new("pid", pid);
         let st_uptime = Stat::new("uptime", uptime);
         let st_time = Stat::new("time", time);
         let st_version = Stat::new("version", version);
+        let st_rusage_user = Stat::new("rusage_user", rusage_user);
+        let st_rusage_system = Stat::new("rusage_system", rusage_system);
         let st_cmd_get = Stat::new("cmd_get", cmd_get);
         let st_cmd_set = Stat::new("cmd_set", cmd_set);
         let st_cmd_flush = Stat::new("cmd_flush", cmd_flush);
@@ -572,43 +725,199 @@ impl Driver {
         let st_total_items = Stat::new("total_items", total_items);
         let st_evictions = Stat::new("evictions", evictions);
         let st_reclaimed = Stat::new("reclaimed", reclaimed);
+        let st_hash_bytes = Stat::new("hash_bytes", hash_bytes);
+        let st_rss = Stat::new("rss", rss);
+        let st_compress_ratio = Stat::new("compress_ratio", compress_ratio);
+        let st_compress_time = Stat::new("compress_time", compress_time);
+        let st_decompress_time = Stat::new("decompress_time",
+                                           decompress_time);
+
+        let mut stats = vec![st_pid,
+                             st_uptime,
+                             st_time,
+                             st_version,
+                             st_rusage_user,
+                             st_rusage_system,
+                             st_cmd_get,
+                             st_cmd_set,
+                             st_cmd_flush,
+                             st_cmd_touch,
+                             st_get_hits,
+                             st_get_misses,
+                             st_delete_hits,
+                             st_delete_misses,
+                             st_incr_hits,
+                             st_incr_misses,
+                             st_decr_hits,
+                             st_decr_misses,
+                             st_cas_hits,
+                             st_cas_misses,
+                             st_cas_badval,
+                             st_touch_hits,
+                             st_touch_misses,
+                             st_bytes_read,
+                             st_bytes_written,
+                             st_limit_maxbytes,
+                             st_bytes,
+                             st_curr_items,
+                             st_total_items,
+                             st_evictions,
+                             st_reclaimed,
+                             st_hash_bytes,
+                             st_rss,
+                             st_compress_ratio,
+                             st_compress_time,
+                             st_decompress_time];
+
+        // The disk tier only reports when there is one
+        if let Some(ext) = self.cache.get_ext_stats() {
+            let ext_stats = vec![("extstore_hits", ext.hits),
+                                 ("extstore_misses", ext.misses),
+                                 ("extstore_bytes", ext.bytes),
+                                 ("extstore_items", ext.items),
+                                 ("extstore_index_bytes", ext.index_bytes),
+                                 ("extstore_bytes_written", ext.bytes_written),
+                                 ("extstore_compactions", ext.compactions),
+                                 ("extstore_evictions", ext.evictions)];
+
+            for (key, value) in ext_stats {
+                stats.push(Stat::new(key, value.to_string()));
+            }
+        }
 
-        Resp::Stats(vec![st_pid,
-                         st_uptime,
-                         st_time,
-                         st_version,
-                         st_cmd_get,
-                         st_cmd_set,
-                         st_cmd_flush,
-                         st_cmd_touch,
-                         st_get_hits,
-                         st_get_misses,
-                         st_delete_hits,
-                         st_delete_misses,
-                         st_incr_hits,
-                         st_incr_misses,
-                         st_decr_hits,
-                         st_decr_misses,
-                         st_cas_hits,
-                         st_cas_misses,
-                         st_cas_badval,
-                         st_touch_hits,
-                         st_touch_misses,
-                         st_bytes_read,
-                         st_bytes_written,
-                         st_limit_maxbytes,
-                         st_bytes,
-                         st_curr_items,
-                         st_total_items,
-                         st_evictions,
-                         st_reclaimed])
+        Resp::Stats(stats)
+    }
+
+    fn do_stats_namespaces(&self) -> Resp {
+        let namespaces = match self.cache.get_namespaces() {
+            Some(namespaces) => namespaces,
+            None => return Resp::Stats(vec![]),
+        };
+
+        let mut stats = vec![];
+        let mut push_stats = |prefix: String, ns: &NamespaceStats| {
+            // A quota of 0 means there is none
+            let ns_stats = vec![("bytes", ns.bytes),
+                                ("items", ns.items),
+                                ("quota", ns.quota.unwrap_or(0)),
+                                ("get_hits", ns.get_hits),
+                                ("get_misses", ns.get_misses),
+                                ("evictions", ns.evictions)];
+
+            for (key, value) in ns_stats {
+                let key = format!("{}:{}", prefix, key);
+                stats.push(Stat::new(&key, value.to_string()));
+            }
+        };
+
+        for (name, ns) in namespaces.iter_sorted() {
+            push_stats(format!("ns:{}", String::from_utf8_lossy(name)), ns);
+        }
+
+        // Any name could be a namespace, so other gets a prefix of its own
+        push_stats("ns_other".to_string(), namespaces.get_other());
+
+        Resp::Stats(stats)
+    }
+
+    // How often every kind of command ran, how it went and how long it took
+    // (in microseconds)
+    fn do_stats_commands(&self) -> Resp {
+        let mut stats = vec![];
+
+        for (name, command) in self.commands.iter() {
+            let key = |field: &str| format!("cmd:{}:{}", name, field);
+            let micros = |secs: Option<f64>| {
+                format!("{:.3}", secs.unwrap_or(0.0) * 1000000.0)
+            };
+
+            stats.push(Stat::new(&key("count"), command.count.to_string()));
+            for (outcome, count) in command.outcomes.iter() {
+                stats.push(Stat::new(&key(outcome), count.to_string()));
+            }
+
+            let latency = &command.latency;
+            stats.push(Stat::new(&key("avg_us"), micros(latency.average())));
+            stats.push(Stat::new(&key("p50_us"),
+                                 micros(latency.percentile(0.5))));
+            stats.push(Stat::new(&key("p99_us"),
+                                 micros(latency.percentile(0.99))));
+            stats.push(Stat::new(&key("max_us"), micros(latency.max())));
+        }
+
+        Resp::Stats(stats)
+    }
+
+    // The most requested keys and how often they were requested lately, most
+    // requested first
+    fn do_stats_hotkeys(&self) -> Resp {
+        let hotkeys = match self.hotkeys {
+            Some(ref hotkeys) => hotkeys,
+            None => return Resp::Stats(vec![]),
+        };
+
+        let stats = hotkeys.top()
+                           .into_iter()
+                           .map(|(key, count)| {
+                               let key = String::from_utf8_lossy(&key);
+                               Stat::new(&key, count.to_string())
+                           })
+                           .collect();
+
+        Resp::Stats(stats)
+    }
+
+    // How many items (or keys) of every size we store, in the format of
+    // memcached's "stats sizes": the size (rounded up to the bucket it falls
+    // into) and the count
+    fn do_stats_sizes(&self, sizes: &SizeHistogram) -> Resp {
+        let stats = sizes.iter()
+                         .map(|(size, count)| {
+                             Stat::new(&size.to_string(), count.to_string())
+                         })
+                         .collect();
+
+        Resp::Stats(stats)
+    }
+
+    pub fn do_tag(&mut self, tag: Tag) -> Resp {
+        let key = Key::new(tag.key);
+
+        // See if the key is set
+        let rv = self.cache.contains_key(&key);
+        maybe_reply_stmt!(!tag.noreply,
+                          match rv {
+                              Ok(true) => None,
+                              Ok(false) => Some(Resp::NotFound),
+                              Err(ref err) => Some(from_cache_err(err)),
+                          });
+
+        // Load the value
+        let rv = self.cache.remove(&key);
+        maybe_reply_stmt!(!tag.noreply,
+                          match rv {
+                              Ok(_) => None,
+                              Err(ref err) => Some(from_cache_err(err)),
+                          });
+
+        // Tag it and put it back
+        let mut value = rv.unwrap();
+        value.add_tag(tag.tag);
+
+        let rv = self.cache.set(key, value);
+
+        maybe_reply_expr!(!tag.noreply,
+                          match rv {
+                              Ok(_) => Resp::Tagged,
+                              Err(ref err) => from_cache_err(err),
+                          })
     }
 
     pub fn do_touch(&mut self, touch: Touch) -> Resp {
         // Update stats
         self.stats.cmd_touch += 1;
 
-        let key = Key::new(touch.key.into_bytes());
+        let key = Key::new(touch.key);
 
         // See if the key is set
         let rv = self.cache.contains_key(&key);
@@ -658,12 +967,49 @@ impl Driver {
     }
 
 
+    // Count the keys that are read and written
+    fn track_hotkeys(&mut self, cmd: &Cmd) {
+        let hotkeys = match self.hotkeys {
+            Some(ref mut hotkeys) => hotkeys,
+            None => return,
+        };
+        let now = self.cache.get_clock().now();
+
+        match *cmd {
+            Cmd::Get(ref get) => {
+                for key in &get.keys {
+                    hotkeys.add(key, now);
+                }
+            }
+            Cmd::Set(ref set) => hotkeys.add(&set.key, now),
+            _ => (),
+        }
+    }
+
     pub fn run(&mut self, cmd: Cmd) -> Resp {
+        self.track_hotkeys(&cmd);
+
+        let name = cmd.name();
+        let started = self.cache.get_clock().now();
+
+        let resp = self.execute(cmd);
+
+        let duration = self.cache.get_clock().now() - started;
+        self.commands.record(name, resp.outcome(), duration);
+
+        resp
+    }
+
+    fn execute(&mut self, cmd: Cmd) -> Resp {
         match cmd {
+            Cmd::CacheMemlimit(cache_memlimit) => {
+                self.do_cache_memlimit(cache_memlimit)
+            }
             Cmd::Delete(del) => self.do_delete(del),
             Cmd::FlushAll(flush_all) => self.do_flush_all(flush_all),
             Cmd::Get(get) => self.do_get(get),
             Cmd::Inc(inc) => self.do_inc(inc),
+            Cmd::Invalidate(invalidate) => self.do_invalidate(invalidate),
             Cmd::Quit => Resp::Empty,  // handled at transport level
             Cmd::Set(set) => {
                 match set.instr {
@@ -675,12 +1021,50 @@ impl Driver {
                     SetInstr::Cas => self.do_cas(set),
                 }
             }
-            Cmd::Stats => self.do_stats(),
+            Cmd::Slowlog(_) => Resp::Empty,  // handled at transport level
+            Cmd::Stats(stats) => self.do_stats(stats),
+            Cmd::Tag(tag) => self.do_tag(tag),
             Cmd::Touch(touch) => self.do_touch(touch),
             Cmd::Version => self.do_version(),
+            Cmd::Watch(_) => Resp::Ok,  // watchers are kept by the task
         }
     }
 
+    pub fn get_cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    pub fn get_hotkeys(&self) -> Option<&HotKeys> {
+        self.hotkeys.as_ref()
+    }
+
+    // Start or stop keeping what happens to the keys
+    pub fn set_watched(&mut self, watched: bool) {
+        self.watched = watched;
+        self.events.clear();
+        self.cache.set_watched(watched);
+    }
+
+    // What happened to the keys since the last time, including what the
+    // cache did by itself
+    pub fn take_events(&mut self) -> Vec<WatchEvent> {
+        let mut events = mem::replace(&mut self.events, vec![]);
+
+        for event in self.cache.take_events() {
+            let kind = match event.kind {
+                CacheEventKind::Evicted => WatchEventKind::Evict,
+                CacheEventKind::Expired => WatchEventKind::Expire,
+            };
+            let ttl = self.ttl(event.exptime);
+            events.push(WatchEvent::new(kind,
+                                        &event.key.item,
+                                        event.size,
+                                        ttl));
+        }
+
+        events
+    }
+
     pub fn update_transport_stats(&mut self, stats: TransportStats) {
         self.transport_stats = stats;
     }
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::mem;
+
+
+// Finds the most requested keys using the space saving algorithm: we count
+// at most capacity keys, and a key we aren't counting yet takes the place of
+// the one with the lowest count, carrying on from that count. Any key that
+// gets more than 1/capacity of the requests is sure to be among them.
+//
+// The counts are kept in a min-heap, indexed by key, so that both counting a
+// request and finding the lowest count take O(log capacity).
+//
+// Counts halve every half_life seconds so that keys that were hot a while ago
+// make way for the keys that are hot now.
+pub struct HotKeys {
+    capacity: usize, // Number of keys we count
+    half_life: f64, // in seconds, <=0 for counts that never decay
+    heap: Vec<(Vec<u8>, f64)>, // Keys and how often they were requested
+    positions: HashMap<Vec<u8>, usize>, // Key -> where it is in the heap
+    decayed_at: Option<f64>, // unixtime
+}
+
+impl HotKeys {
+    pub fn new(capacity: usize, half_life: f64) -> HotKeys {
+        HotKeys {
+            capacity: capacity,
+            decayed_at: None,
+            half_life: half_life,
+            heap: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    fn decay(&mut self, now: f64) {
+        let decayed_at = match self.decayed_at {
+            Some(decayed_at) => decayed_at,
+            None => {
+                self.decayed_at = Some(now);
+                return;
+            }
+        };
+
+        if self.half_life <= 0.0 {
+            return;
+        }
+
+        // Halve the counts once for every half life that has passed. They
+        // all shrink by the same factor, so the heap stays in order.
+        let halvings = ((now - decayed_at) / self.half_life).floor();
+        if halvings < 1.0 {
+            return;
+        }
+
+        let factor = 0.5f64.powf(halvings);
+        for entry in self.heap.iter_mut() {
+            entry.1 *= factor;
+        }
+
+        self.decayed_at = Some(decayed_at + halvings * self.half_life);
+    }
+
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        *self.positions.get_mut(&self.heap[i].0).unwrap() = i;
+        *self.positions.get_mut(&self.heap[j].0).unwrap() = j;
+    }
+
+    // Move an entry up the heap for as long as its parent counts more
+    fn sift_up(&mut self, mut pos: usize) {
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if self.heap[parent].1 <= self.heap[pos].1 {
+                break;
+            }
+
+            self.swap(parent, pos);
+            pos = parent;
+        }
+    }
+
+    // Move an entry down the heap for as long as one of its children counts
+    // less
+    fn sift_down(&mut self, mut pos: usize) {
+        loop {
+            let mut least = pos;
+            for child in &[2 * pos + 1, 2 * pos + 2] {
+                if *child < self.heap.len() &&
+                   self.heap[*child].1 < self.heap[least].1 {
+                    least = *child;
+                }
+            }
+            if least == pos {
+                break;
+            }
+
+            self.swap(pos, least);
+            pos = least;
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], now: f64) {
+        self.decay(now);
+
+        if let Some(pos) = self.positions.get(key).cloned() {
+            self.heap[pos].1 += 1.0;
+            self.sift_down(pos);
+            return;
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        // Still room to count another key
+        if self.heap.len() < self.capacity {
+            let pos = self.heap.len();
+            self.heap.push((key.to_vec(), 1.0));
+            self.positions.insert(key.to_vec(), pos);
+            self.sift_up(pos);
+            return;
+        }
+
+        // Take the place of the least requested key, at the top of the heap
+        let least_count = self.heap[0].1;
+        let least_key = mem::replace(&mut self.heap[0],
+                                     (key.to_vec(), least_count + 1.0))
+                            .0;
+        self.positions.remove(&least_key);
+        self.positions.insert(key.to_vec(), 0);
+        self.sift_down(0);
+    }
+
+    // The keys we count and their counts, most requested first
+    pub fn top(&self) -> Vec<(Vec<u8>, u64)> {
+        let mut top: Vec<(Vec<u8>, u64)> =
+            self.heap
+                .iter()
+                .map(|&(ref key, count)| (key.clone(), count as u64))
+                .collect();
+
+        top.sort_by(|&(ref key_a, count_a), &(ref key_b, count_b)| {
+            count_b.cmp(&count_a).then_with(|| key_a.cmp(key_b))
+        });
+
+        top
+    }
+}
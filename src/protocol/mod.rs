@@ -1,12 +1,18 @@
 // Declare sub modules
 pub mod cmd;
+pub mod command_stats;
 pub mod driver;
+pub mod hotkeys;
 pub mod util;
 
 // internal stuff
+#[cfg(test)]
 mod tests;  // needed to be part of the compilation unit in test mode
+#[cfg(test)]
 mod tests_bench;  // needed to be part of the compilation unit in test mode
 
 
 // Export our public api
+pub use self::command_stats::CommandStats;
 pub use self::driver::Driver;
+pub use self::hotkeys::HotKeys;
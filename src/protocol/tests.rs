@@ -1,12 +1,24 @@
+use std::mem;
+
+use linked_hash_map::LinkedHashMap;
+
+use common::blob::Blob;
 use common::consts::get_version_string;
+use platform::clock::Clock;
 use platform::process::get_pid;
-use platform::time::sleep_secs;
 use platform::time::time_now;
 use storage::Cache;
+use storage::ExtStore;
+use storage::Namespaces;
 use storage::Key as SKey;
 use storage::Value as SValue;
+use storage::entry_mem_size;
+use testlib::clock::FakeClock;
+use testlib::tempfile::TempPath;
 
 use super::Driver;
+use super::HotKeys;
+use super::cmd::CacheMemlimit;
 use super::cmd::Cmd;
 use super::cmd::Delete;
 use super::cmd::FlushAll;
@@ -14,59 +26,65 @@ use super::cmd::Get;
 use super::cmd::GetInstr;
 use super::cmd::Inc;
 use super::cmd::IncInstr;
+use super::cmd::Invalidate;
+use super::cmd::InvalidateInstr;
 use super::cmd::Resp;
 use super::cmd::Set;
 use super::cmd::SetInstr;
 use super::cmd::Stat;
+use super::cmd::Stats;
+use super::cmd::Tag;
 use super::cmd::Touch;
 use super::cmd::Value;
+use super::cmd::Watch;
+use super::cmd::WatchEventKind;
 
 
 // Add
 
 #[test]
 fn test_cmd_add() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
     // Add a new key
-    let set = Set::new(SetInstr::Add, "x", 4, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Add, b"x", 4, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Make sure it was added
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![8, 9], resp.get_first_value().unwrap().data);
     assert_eq!(4, resp.get_first_value().unwrap().flags);
 
     // Try using add to overwrite an existing key
-    let set = Set::new(SetInstr::Add, "x", 5, 0, vec![11], false);
+    let set = Set::new(SetInstr::Add, b"x", 5, 0, vec![11], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotStored);
 
     // Try using add to overwrite an existing key - noreply
-    let set = Set::new(SetInstr::Add, "x", 5, 0, vec![11], true);
+    let set = Set::new(SetInstr::Add, b"x", 5, 0, vec![11], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was not overwritten
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![8, 9], resp.get_first_value().unwrap().data);
     assert_eq!(4, resp.get_first_value().unwrap().flags);
 
     // Add with noreply
-    let set = Set::new(SetInstr::Add, "y", 5, 0, vec![11], true);
+    let set = Set::new(SetInstr::Add, b"y", 5, 0, vec![11], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was added
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "y"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"y"));
     let resp = driver.run(cmd);
     assert_eq!(vec![11], resp.get_first_value().unwrap().data);
     assert_eq!(5, resp.get_first_value().unwrap().flags);
@@ -77,114 +95,191 @@ fn test_cmd_add() {
 
 #[test]
 fn test_cmd_append() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
     // Try to append to an invalid key
-    let set = Set::new(SetInstr::Append, "x", 4, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Append, b"x", 4, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotStored);
 
     // Try to append to an invalid key - noreply
-    let set = Set::new(SetInstr::Append, "x", 4, 0, vec![8, 9], true);
+    let set = Set::new(SetInstr::Append, b"x", 4, 0, vec![8, 9], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key we can append to
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Append to it
-    let set = Set::new(SetInstr::Append, "x", 4, 0, vec![10], false);
+    let set = Set::new(SetInstr::Append, b"x", 4, 0, vec![10], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![8, 9, 10], resp.get_first_value().unwrap().data);
     assert_eq!(4, resp.get_first_value().unwrap().flags);
 
     // Append again, in noreply mode
-    let set = Set::new(SetInstr::Append, "x", 5, 0, vec![11], true);
+    let set = Set::new(SetInstr::Append, b"x", 5, 0, vec![11], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was updated again
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![8, 9, 10, 11], resp.get_first_value().unwrap().data);
     assert_eq!(5, resp.get_first_value().unwrap().flags);
 }
 
 
+// CacheMemlimit
+
+#[test]
+fn test_cmd_cache_memlimit() {
+    let cache = Cache::new(2 << 20);
+    let mut driver = Driver::new(cache);
+
+    // Fill the cache with ~1.5mb worth of items
+    for i in 0..15 {
+        let key = format!("x{}", i).into_bytes();
+        let set = Set::new(SetInstr::Set, &key, 0, 0, vec![1; 100000], false);
+        let resp = driver.run(Cmd::Set(set));
+        assert_eq!(resp, Resp::Stored);
+    }
+
+    // Lower the limit to 1mb
+    let cmd = Cmd::CacheMemlimit(CacheMemlimit::new(1, false));
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Ok);
+
+    // The oldest keys were evicted, the newest are still there
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x0"));
+    let resp = driver.run(cmd);
+    assert_eq!(0, resp.get_values().unwrap().len());
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x14"));
+    let resp = driver.run(cmd);
+    assert_eq!(1, resp.get_values().unwrap().len());
+
+    // The new limit is reported
+    let resp = driver.run(Cmd::Stats(Stats::new(None)));
+    let stat = resp.get_stats()
+                   .unwrap()
+                   .iter()
+                   .find(|stat| stat.key == "limit_maxbytes")
+                   .unwrap()
+                   .clone();
+    assert_eq!(stat, Stat::new("limit_maxbytes", (1 << 20).to_string()));
+
+    // Raise the limit again - noreply
+    let cmd = Cmd::CacheMemlimit(CacheMemlimit::new(4, true));
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Empty);
+
+    // A limit of zero is rejected
+    let cmd = Cmd::CacheMemlimit(CacheMemlimit::new(0, false));
+    let resp = driver.run(cmd);
+    assert_eq!(resp,
+               Resp::ClientError("bad command line format".to_string()));
+}
+
+#[test]
+fn test_cmd_cache_memlimit_overflow() {
+    let cache = Cache::new(2 << 20);
+    let mut driver = Driver::new(cache);
+
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![1; 100], false);
+    driver.run(Cmd::Set(set));
+
+    // A limit too large to count in bytes is rejected, it doesn't wrap
+    // around to a tiny one
+    let cmd = Cmd::CacheMemlimit(CacheMemlimit::new(1 << 44, false));
+    let resp = driver.run(cmd);
+    assert_eq!(resp,
+               Resp::ClientError("bad command line format".to_string()));
+
+    // The cache is as it was
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
+    let resp = driver.run(cmd);
+    assert_eq!(1, resp.get_values().unwrap().len());
+
+    // The largest limit we can count is fine
+    let cmd = Cmd::CacheMemlimit(CacheMemlimit::new((1 << 44) - 1, false));
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Ok);
+}
+
+
 // Cas
 
 #[test]
 fn test_cmd_cas() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
     // Try to append to an invalid key
-    let mut set = Set::new(SetInstr::Cas, "x", 4, 0, vec![8, 9], false);
+    let mut set = Set::new(SetInstr::Cas, b"x", 4, 0, vec![8, 9], false);
     set.with_cas_unique(5);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotFound);
 
     // Try to append to an invalid key - noreply
-    let mut set = Set::new(SetInstr::Cas, "x", 4, 0, vec![8, 9], true);
+    let mut set = Set::new(SetInstr::Cas, b"x", 4, 0, vec![8, 9], true);
     set.with_cas_unique(5);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key we can update
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Obtain cas value
-    let cmd = Cmd::Get(Get::one(GetInstr::Gets, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Gets, b"x"));
     let resp = driver.run(cmd);
     let cas_unique1 = resp.get_first_value().unwrap().cas_unique.unwrap();
 
     // Update it
-    let mut set = Set::new(SetInstr::Cas, "x", 4, 0, vec![10], false);
+    let mut set = Set::new(SetInstr::Cas, b"x", 4, 0, vec![10], false);
     set.with_cas_unique(cas_unique1);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Gets, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Gets, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![10], resp.get_first_value().unwrap().data);
     assert_eq!(4, resp.get_first_value().unwrap().flags);
     let cas_unique2 = resp.get_first_value().unwrap().cas_unique.unwrap();
 
     // Update it again - noreply
-    let mut set = Set::new(SetInstr::Cas, "x", 7, 0, vec![11], true);
+    let mut set = Set::new(SetInstr::Cas, b"x", 7, 0, vec![11], true);
     set.with_cas_unique(cas_unique2);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![11], resp.get_first_value().unwrap().data);
     assert_eq!(7, resp.get_first_value().unwrap().flags);
 
     // Try to update it with a stale cas token
-    let mut set = Set::new(SetInstr::Cas, "x", 4, 0, vec![10], false);
+    let mut set = Set::new(SetInstr::Cas, b"x", 4, 0, vec![10], false);
     set.with_cas_unique(cas_unique1);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
@@ -200,59 +295,59 @@ fn test_cmd_decr() {
     let mut driver = Driver::new(cache);
 
     // Try to decr an invalid key
-    let inc = Inc::new(IncInstr::Incr, "x", 4, false);
+    let inc = Inc::new(IncInstr::Incr, b"x", 4, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotFound);
 
     // Try to decr an invalid key - noreply
-    let inc = Inc::new(IncInstr::Decr, "x", 4, true);
+    let inc = Inc::new(IncInstr::Decr, b"x", 4, true);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key we can decr
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![b'2'], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![b'2'], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Decr it
-    let inc = Inc::new(IncInstr::Decr, "x", 1, false);
+    let inc = Inc::new(IncInstr::Decr, b"x", 1, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::IntValue(1));
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![b'1'], resp.get_first_value().unwrap().data);
 
     // Decr it again - noreply
-    let inc = Inc::new(IncInstr::Decr, "x", 1, true);
+    let inc = Inc::new(IncInstr::Decr, b"x", 1, true);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![b'0'], resp.get_first_value().unwrap().data);
 
     // Try to underflow it
-    let inc = Inc::new(IncInstr::Decr, "x", 1, false);
+    let inc = Inc::new(IncInstr::Decr, b"x", 1, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::IntValue(0));
 
     // Set a key we can't decr - would not fit in u64
-    let set = Set::new(SetInstr::Set, "y", 0, 0, vec![b'1'; 255], false);
+    let set = Set::new(SetInstr::Set, b"y", 0, 0, vec![b'1'; 255], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Try to decr it - fails
-    let inc = Inc::new(IncInstr::Decr, "y", 1, false);
+    let inc = Inc::new(IncInstr::Decr, b"y", 1, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::ClientError("Not a number".to_string()));
@@ -267,44 +362,44 @@ fn test_cmd_delete() {
     let mut driver = Driver::new(cache);
 
     // Try to delete a key that does not exist
-    let cmd = Cmd::Delete(Delete::new("z", false));
+    let cmd = Cmd::Delete(Delete::new(b"z", false));
     let resp = driver.run(cmd);
     assert_eq!(Resp::NotFound, resp);
 
     // Again, but now with noreply flag
-    let cmd = Cmd::Delete(Delete::new("z", true));
+    let cmd = Cmd::Delete(Delete::new(b"z", true));
     let resp = driver.run(cmd);
     assert_eq!(Resp::Empty, resp);
 
     // Set a key we can delete later
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // And another
-    let set = Set::new(SetInstr::Set, "y", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"y", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Delete the first one
-    let cmd = Cmd::Delete(Delete::new("x", false));
+    let cmd = Cmd::Delete(Delete::new(b"x", false));
     let resp = driver.run(cmd);
     assert_eq!(Resp::Deleted, resp);
 
     // Make sure it's gone
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(0, resp.get_values().unwrap().len());
 
     // Delete the second - with noreply
-    let cmd = Cmd::Delete(Delete::new("y", true));
+    let cmd = Cmd::Delete(Delete::new(b"y", true));
     let resp = driver.run(cmd);
     assert_eq!(Resp::Empty, resp);
 
     // Make sure it's gone
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "y"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"y"));
     let resp = driver.run(cmd);
     assert_eq!(0, resp.get_values().unwrap().len());
 }
@@ -314,10 +409,10 @@ fn test_cmd_delete() {
 
 #[test]
 fn test_flush_all() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
-    let key_name = "x";
+    let key_name = b"x";
     let blob = vec![1, 2, 3];
 
 
@@ -356,14 +451,97 @@ fn test_flush_all() {
 }
 
 
+#[test]
+fn test_flush_all_zero_delay() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let key_name = b"x";
+    let blob = vec![1, 2, 3];
+
+    // Set a key
+    let set = Set::new(SetInstr::Set, key_name, 15, 0, blob.clone(), false);
+    let cmd = Cmd::Set(set);
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Stored);
+
+    // Do a flush - a delay of 0 is the same as no delay
+    let cmd = Cmd::FlushAll(FlushAll::new(Some(0), false));
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Ok);
+
+    // The key is dead
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, key_name));
+    let resp = driver.run(cmd);
+    assert_eq!(0, resp.get_values().unwrap().len());
+}
+
+#[test]
+fn test_flush_all_delayed() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let key_name = b"x";
+    let blob = vec![1, 2, 3];
+
+    // Set a key
+    let set = Set::new(SetInstr::Set, key_name, 15, 0, blob.clone(), false);
+    let cmd = Cmd::Set(set);
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Stored);
+
+    // Schedule a flush
+    let cmd = Cmd::FlushAll(FlushAll::new(Some(100), false));
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Ok);
+
+    // The key is still there until the flush kicks in
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, key_name));
+    let resp = driver.run(cmd);
+    assert_eq!(1, resp.get_values().unwrap().len());
+}
+
+#[test]
+fn test_flush_all_replaces_pending() {
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
+    let mut driver = Driver::new(cache);
+
+    let set = |key: &[u8]| {
+        Cmd::Set(Set::new(SetInstr::Set, key, 0, 0, vec![1], false))
+    };
+    let get = |key: &[u8]| Cmd::Get(Get::one(GetInstr::Get, key));
+
+    // Schedule a flush in 60s, then flush right away instead
+    driver.run(set(b"x"));
+    driver.run(Cmd::FlushAll(FlushAll::new(Some(60), false)));
+    assert_eq!(1, driver.run(get(b"x")).get_values().unwrap().len());
+    driver.run(Cmd::FlushAll(FlushAll::new(Some(0), false)));
+    assert_eq!(0, driver.run(get(b"x")).get_values().unwrap().len());
+
+    // Then schedule one in 30s
+    driver.run(set(b"y"));
+    driver.run(Cmd::FlushAll(FlushAll::new(Some(30), false)));
+    clock.advance(29.0);
+    assert_eq!(1, driver.run(get(b"y")).get_values().unwrap().len());
+    clock.advance(2.0);
+    assert_eq!(0, driver.run(get(b"y")).get_values().unwrap().len());
+
+    // The one in 60s no longer happens
+    driver.run(set(b"z"));
+    clock.advance(30.0);
+    assert_eq!(1, driver.run(get(b"z")).get_values().unwrap().len());
+}
+
 // Get and Set
 
 #[test]
 fn test_cmd_set_and_get_a_key() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
-    let key_name = "x";
+    let key_name = b"x";
     let blob = vec![1, 2, 3];
 
     // Try to retrieve a key not set
@@ -384,18 +562,54 @@ fn test_cmd_set_and_get_a_key() {
     assert_eq!(blob, resp.get_first_value().unwrap().data);
 
     // Set a key with noreply flag
-    let set = Set::new(SetInstr::Set, "y", 15, 0, blob.clone(), true);
+    let set = Set::new(SetInstr::Set, b"y", 15, 0, blob.clone(), true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Retrieve it
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "y"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"y"));
     let resp = driver.run(cmd);
     assert_eq!(15, resp.get_first_value().unwrap().flags);
     assert_eq!(blob, resp.get_first_value().unwrap().data);
 }
 
+#[test]
+fn test_cmd_set_and_get_flags_32bit() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    for flags in &[65535, 65536, 1 << 31, u32::MAX] {
+        let set = Set::new(SetInstr::Set, b"x", *flags, 0, vec![1], false);
+        let resp = driver.run(Cmd::Set(set));
+        assert_eq!(resp, Resp::Stored);
+
+        let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
+        let resp = driver.run(cmd);
+        assert_eq!(*flags, resp.get_first_value().unwrap().flags);
+    }
+}
+
+#[test]
+fn test_cmd_set_and_get_non_utf8_key() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let key_name = b"\xff\xfe";
+    let blob = vec![1, 2, 3];
+
+    // Set a key that isn't valid utf8
+    let set = Set::new(SetInstr::Set, key_name, 15, 0, blob.clone(), false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    // Retrieve it, the key comes back as it went in
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, key_name));
+    let resp = driver.run(cmd);
+    assert_eq!(key_name.to_vec(), resp.get_first_value().unwrap().key);
+    assert_eq!(blob, resp.get_first_value().unwrap().data);
+}
+
 #[test]
 fn test_cmd_set_and_get_multiple_keys() {
     let cache = Cache::new(1024);
@@ -405,30 +619,116 @@ fn test_cmd_set_and_get_multiple_keys() {
     let val3 = vec![3];
 
     // Set two keys
-    let set = Set::new(SetInstr::Set, "a", 15, 0, val1.clone(), false);
+    let set = Set::new(SetInstr::Set, b"a", 15, 0, val1.clone(), false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
-    let set = Set::new(SetInstr::Set, "c", 17, 0, val3.clone(), false);
+    let set = Set::new(SetInstr::Set, b"c", 17, 0, val3.clone(), false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Try to retrieve three keys - get two
-    let keys = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+    let keys = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
     let cmd = Cmd::Get(Get::new(GetInstr::Get, keys));
     let resp = driver.run(cmd);
 
     let values = resp.get_values().unwrap();
-    let val1 = Value::new("a", 15, val1);
-    let val3 = Value::new("c", 17, val3);
+    let val1 = Value::new(b"a", 15, val1);
+    let val3 = Value::new(b"c", 17, val3);
     assert_eq!(2, values.len());
     assert_eq!(val1, values[0]);
     assert_eq!(val3, values[1]);
 }
 
 
+#[test]
+fn test_cmd_set_and_get_large_item() {
+    let mut cache = Cache::new(16 << 20);
+    cache.with_value_maxlen(4 << 20);
+    let mut driver = Driver::new(cache);
+
+    // A value much bigger than a single chunk
+    let blob: Vec<u8> = (0..(3 << 20)).map(|i| (i % 251) as u8).collect();
+
+    let mut set = Set::new(SetInstr::Set, b"x", 15, 0, vec![], false);
+    set.data = Blob::from_slice(&blob);
+    assert!(set.data.is_chunked());
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    // Retrieve it
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
+    let resp = driver.run(cmd);
+    assert_eq!(blob, resp.get_first_value().unwrap().data);
+
+    // Append and prepend work across chunk boundaries
+    let set = Set::new(SetInstr::Append, b"x", 15, 0, vec![1, 2], false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    let set = Set::new(SetInstr::Prepend, b"x", 15, 0, vec![3], false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    let mut expected = vec![3];
+    expected.extend_from_slice(&blob);
+    expected.extend_from_slice(&[1, 2]);
+
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
+    let resp = driver.run(cmd);
+    assert_eq!(expected, resp.get_first_value().unwrap().data);
+
+    // Values over the max item size are rejected
+    let mut set = Set::new(SetInstr::Set, b"y", 15, 0, vec![], false);
+    set.data = Blob::from_slice(&vec![0; (4 << 20) + 1]);
+    let resp = driver.run(Cmd::Set(set));
+    assert!(resp != Resp::Stored);
+}
+
+#[test]
+fn test_cmd_set_and_get_compressed() {
+    let mut cache = Cache::new(1 << 20);
+    cache.with_compress_threshold(64);
+    let mut driver = Driver::new(cache);
+
+    let mut blob = vec![];
+    for _ in 0..50 {
+        blob.extend_from_slice(b"{\"a\": 1}");
+    }
+
+    let set = Set::new(SetInstr::Set, b"x", 15, 0, blob.clone(), false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    // Append and prepend operate on the original data
+    let set = Set::new(SetInstr::Append, b"x", 15, 0, vec![1, 2], false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    let set = Set::new(SetInstr::Prepend, b"x", 15, 0, vec![3], false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::Stored);
+
+    let mut expected = vec![3];
+    expected.extend_from_slice(&blob);
+    expected.extend_from_slice(&[1, 2]);
+
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
+    let resp = driver.run(cmd);
+    assert_eq!(expected, resp.get_first_value().unwrap().data);
+
+    // The compression shows up in stats
+    let resp = driver.run(Cmd::Stats(Stats::new(None)));
+    let stats = resp.get_stats().unwrap();
+    let ratio = stats.iter()
+                     .find(|stat| stat.key == "compress_ratio")
+                     .unwrap();
+    assert!(ratio.value.parse::<f64>().unwrap() > 1.0);
+}
+
+
 // Gets
 
 #[test]
@@ -437,26 +737,26 @@ fn get_cmd_gets() {
     let mut driver = Driver::new(cache);
 
     // Set a key
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![b'1'], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![b'1'], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Retrieve it
-    let get = Get::one(GetInstr::Gets, "x");
+    let get = Get::one(GetInstr::Gets, b"x");
     let cmd = Cmd::Get(get);
     let gets_resp = driver.run(cmd);
     // cas_unique is present
     gets_resp.get_first_value().unwrap().cas_unique.unwrap();
 
     // Set the key again
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![b'2'], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![b'2'], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Retrieve it again - cas_unique should have changed
-    let get = Get::one(GetInstr::Gets, "x");
+    let get = Get::one(GetInstr::Gets, b"x");
     let cmd = Cmd::Get(get);
     let gets_resp2 = driver.run(cmd);
     // cas_unique has changed
@@ -473,110 +773,160 @@ fn test_cmd_incr() {
     let mut driver = Driver::new(cache);
 
     // Try to incr an invalid key
-    let inc = Inc::new(IncInstr::Incr, "x", 4, false);
+    let inc = Inc::new(IncInstr::Incr, b"x", 4, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotFound);
 
     // Try to incr an invalid key - noreply
-    let inc = Inc::new(IncInstr::Incr, "x", 4, true);
+    let inc = Inc::new(IncInstr::Incr, b"x", 4, true);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key we can incr
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![b'1'], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![b'1'], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Incr it
-    let inc = Inc::new(IncInstr::Incr, "x", 1, false);
+    let inc = Inc::new(IncInstr::Incr, b"x", 1, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::IntValue(2));
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![b'2'], resp.get_first_value().unwrap().data);
 
     // Incr it again - noreply
-    let inc = Inc::new(IncInstr::Incr, "x", 1, true);
+    let inc = Inc::new(IncInstr::Incr, b"x", 1, true);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![b'3'], resp.get_first_value().unwrap().data);
 
     // Overflow it
-    let inc = Inc::new(IncInstr::Incr, "x", 0xffffffffffffffff, false);
+    let inc = Inc::new(IncInstr::Incr, b"x", 0xffffffffffffffff, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::IntValue(2));
 
     // Set a key we can't incr - would not fit in u64
-    let set = Set::new(SetInstr::Set, "y", 0, 0, vec![b'1'; 255], false);
+    let set = Set::new(SetInstr::Set, b"y", 0, 0, vec![b'1'; 255], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Try to incr it - fails
-    let inc = Inc::new(IncInstr::Incr, "y", 1, false);
+    let inc = Inc::new(IncInstr::Incr, b"y", 1, false);
     let cmd = Cmd::Inc(inc);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::ClientError("Not a number".to_string()));
 }
 
 
+// Invalidate
+
+#[test]
+fn test_cmd_invalidate_prefix() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    for key in &[b"user:1:a", b"user:1:b", b"user:2:a"] {
+        let set = Set::new(SetInstr::Set, *key, 0, 0, vec![1], false);
+        driver.run(Cmd::Set(set));
+    }
+
+    let inv = Invalidate::new(InvalidateInstr::Prefix, b"user:1:", false);
+    let resp = driver.run(Cmd::Invalidate(inv));
+    assert_eq!(resp, Resp::Ok);
+
+    let keys = vec![b"user:1:a".to_vec(),
+                    b"user:1:b".to_vec(),
+                    b"user:2:a".to_vec()];
+    let resp = driver.run(Cmd::Get(Get::new(GetInstr::Get, keys)));
+    assert_eq!(1, resp.get_values().unwrap().len());
+    assert_eq!(b"user:2:a".to_vec(), resp.get_first_value().unwrap().key);
+
+    // noreply
+    let inv = Invalidate::new(InvalidateInstr::Prefix, b"user:2:", true);
+    let resp = driver.run(Cmd::Invalidate(inv));
+    assert_eq!(resp, Resp::Empty);
+    let resp = driver.run(Cmd::Get(Get::one(GetInstr::Get, b"user:2:a")));
+    assert_eq!(0, resp.get_values().unwrap().len());
+}
+
+#[test]
+fn test_cmd_invalidate_not_resurrected() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(set));
+    let inv = Invalidate::new(InvalidateInstr::Prefix, b"x", false);
+    driver.run(Cmd::Invalidate(inv));
+
+    // Commands that build on the existing item find nothing
+    let set = Set::new(SetInstr::Append, b"x", 0, 0, vec![2], false);
+    let resp = driver.run(Cmd::Set(set));
+    assert_eq!(resp, Resp::NotStored);
+    let resp = driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+    assert_eq!(0, resp.get_values().unwrap().len());
+}
+
+
 // Prepend
 
 #[test]
 fn test_cmd_prepend() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
     // Try to prepend to an invalid key
-    let set = Set::new(SetInstr::Prepend, "x", 4, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Prepend, b"x", 4, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotStored);
 
     // Try to prepend to an invalid key - noreply
-    let set = Set::new(SetInstr::Prepend, "x", 4, 0, vec![8, 9], true);
+    let set = Set::new(SetInstr::Prepend, b"x", 4, 0, vec![8, 9], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key we can prepend to
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Prepend to it
-    let set = Set::new(SetInstr::Prepend, "x", 4, 0, vec![10], false);
+    let set = Set::new(SetInstr::Prepend, b"x", 4, 0, vec![10], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![10, 8, 9], resp.get_first_value().unwrap().data);
     assert_eq!(4, resp.get_first_value().unwrap().flags);
 
     // Prepend again, in noreply mode
-    let set = Set::new(SetInstr::Prepend, "x", 5, 0, vec![11], true);
+    let set = Set::new(SetInstr::Prepend, b"x", 5, 0, vec![11], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was updated again
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![11, 10, 8, 9], resp.get_first_value().unwrap().data);
     assert_eq!(5, resp.get_first_value().unwrap().flags);
@@ -587,47 +937,47 @@ fn test_cmd_prepend() {
 
 #[test]
 fn test_cmd_replace() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
     // Try to replace an non-existent key
-    let set = Set::new(SetInstr::Replace, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Replace, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotStored);
 
     // Try to replace an non-existent key - noreply
-    let set = Set::new(SetInstr::Replace, "x", 0, 0, vec![8, 9], true);
+    let set = Set::new(SetInstr::Replace, b"x", 0, 0, vec![8, 9], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Replace a valid key
-    let set = Set::new(SetInstr::Replace, "x", 4, 0, vec![10], false);
+    let set = Set::new(SetInstr::Replace, b"x", 4, 0, vec![10], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![10], resp.get_first_value().unwrap().data);
     assert_eq!(4, resp.get_first_value().unwrap().flags);
 
     // Replace a valid key in noreply mode
-    let set = Set::new(SetInstr::Replace, "x", 6, 0, vec![11], true);
+    let set = Set::new(SetInstr::Replace, b"x", 6, 0, vec![11], true);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Make sure it was updated
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(vec![11], resp.get_first_value().unwrap().data);
     assert_eq!(6, resp.get_first_value().unwrap().flags);
@@ -638,33 +988,35 @@ fn test_cmd_replace() {
 
 #[test]
 fn test_cmd_stats() {
-    let cache = Cache::new(100);
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
     let mut driver = Driver::new(cache);
 
     // Set a key
-    let set = Set::new(SetInstr::Set, "x", 0, 0, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
     // Retrieve it
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     driver.run(cmd);
 
     // Run stats
-    let cmd = Cmd::Stats;
+    let cmd = Cmd::Stats(Stats::new(None));
     let resp = driver.run(cmd);
 
     // We need to know the bytecount, so figure out how much space the item we
     // stored needs
     let skey = SKey::new(vec![1]);
-    let svalue = SValue::new(vec![1, 2]);
-    let item_size = skey.mem_size() as u64 + svalue.mem_size() as u64;
+    let svalue = SValue::new(Blob::new(vec![1, 2]));
+    let item_size = entry_mem_size(&skey, &svalue);
 
     let st_pid = Stat::new("pid", get_pid().to_string());
     let st_bytes = Stat::new("bytes", item_size.to_string());
     let st_uptime = Stat::new("uptime", "0".to_string());
-    let st_time = Stat::new("time", (time_now() as u64).to_string());
+    let st_time = Stat::new("time", (clock.now() as u64).to_string());
     let st_version = Stat::new("version", get_version_string());
     let st_cmd_get = Stat::new("cmd_get", "1".to_string());
     let st_cmd_set = Stat::new("cmd_set", "1".to_string());
@@ -685,18 +1037,43 @@ fn test_cmd_stats() {
     let st_touch_misses = Stat::new("touch_misses", "0".to_string());
     let st_bytes_read = Stat::new("bytes_read", "0".to_string());
     let st_bytes_written = Stat::new("bytes_written", "0".to_string());
-    let st_limit_maxbytes = Stat::new("limit_maxbytes", "100".to_string());
+    let st_limit_maxbytes = Stat::new("limit_maxbytes", "1024".to_string());
     let st_curr_items = Stat::new("curr_items", "1".to_string());
     let st_total_items = Stat::new("total_items", "1".to_string());
     let st_evictions = Stat::new("evictions", "0".to_string());
     let st_reclaimed = Stat::new("reclaimed", "0".to_string());
-
+    let st_compress_ratio = Stat::new("compress_ratio", "0.00".to_string());
+    let st_compress_time = Stat::new("compress_time", "0.000000".to_string());
+    let st_decompress_time = Stat::new("decompress_time",
+                                       "0.000000".to_string());
+
+    // The hash table has grown to hold the one item, which costs a hash and
+    // two links per slot
+    let mut table = LinkedHashMap::new();
+    table.insert(skey, svalue);
+    let hash_bytes = table.capacity() * 3 * mem::size_of::<usize>();
+    let st_hash_bytes = Stat::new("hash_bytes", hash_bytes.to_string());
+
+    // These depend on the process so we can't predict them exactly (they are
+    // checked against the work done in test_cmd_stats_process)
     let stats = resp.get_stats().unwrap();
+    let find_stat = |key: &str| {
+        stats.iter().find(|stat| stat.key == key).unwrap().clone()
+    };
+    let st_rusage_user = find_stat("rusage_user");
+    let st_rusage_system = find_stat("rusage_system");
+    let st_rss = find_stat("rss");
+    st_rusage_user.value.parse::<f64>().unwrap();
+    st_rusage_system.value.parse::<f64>().unwrap();
+    assert!(st_rss.value.parse::<u64>().unwrap() >= item_size);
+
     assert_eq!(*stats,
                (vec![st_pid,
                      st_uptime,
                      st_time,
                      st_version,
+                     st_rusage_user,
+                     st_rusage_system,
                      st_cmd_get,
                      st_cmd_set,
                      st_cmd_flush,
@@ -721,65 +1098,483 @@ fn test_cmd_stats() {
                      st_curr_items,
                      st_total_items,
                      st_evictions,
-                     st_reclaimed]));
+                     st_reclaimed,
+                     st_hash_bytes,
+                     st_rss,
+                     st_compress_ratio,
+                     st_compress_time,
+                     st_decompress_time]));
+}
+
+#[test]
+fn test_cmd_stats_process() {
+    let mut cache = Cache::new(8 << 20);
+    cache.with_value_maxlen(4 << 20);
+    let mut driver = Driver::new(cache);
+    let find_stat = |resp: &Resp, key: &str| {
+        let stats = resp.get_stats().unwrap();
+        stats.iter().find(|stat| stat.key == key).unwrap().value.clone()
+    };
+
+    let before = driver.run(Cmd::Stats(Stats::new(None)));
+
+    // Spend some cpu time
+    let started_at = time_now();
+    let mut spins = 0u64;
+    while time_now() - started_at < 0.05 {
+        spins = spins.wrapping_add(1);
+    }
+    assert!(spins > 0);
+
+    // Store 4mb, which has to be resident
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![1; 4 << 20], false);
+    assert_eq!(driver.run(Cmd::Set(set)), Resp::Stored);
+
+    let after = driver.run(Cmd::Stats(Stats::new(None)));
+
+    let rusage_user = |resp: &Resp| {
+        find_stat(resp, "rusage_user").parse::<f64>().unwrap()
+    };
+    assert!(rusage_user(&after) > rusage_user(&before));
+
+    let bytes = find_stat(&after, "bytes").parse::<u64>().unwrap();
+    let rss = find_stat(&after, "rss").parse::<u64>().unwrap();
+    assert!(bytes > 4 << 20);
+    assert!(rss >= bytes);
+}
+
+#[test]
+fn test_cmd_stats_extstore() {
+    let path = TempPath::new("emcache-test-ext");
+    let mut extstore = ExtStore::new(path.as_path(), 1 << 20).unwrap();
+    extstore.with_item_min(1);
+
+    // Room for two items
+    let skey = SKey::new(vec![b'a']);
+    let svalue = SValue::new(Blob::new(vec![1; 60]));
+    let mut cache = Cache::new(entry_mem_size(&skey, &svalue) * 2);
+    cache.with_extstore(extstore);
+    let mut driver = Driver::new(cache);
+
+    // Push the first key out to disk
+    for key in &[b"a", b"b", b"c"] {
+        let set = Set::new(SetInstr::Set, *key, 0, 0, vec![1; 60], false);
+        driver.run(Cmd::Set(set));
+    }
+
+    // It's still there
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"a"));
+    let resp = driver.run(cmd);
+    assert_eq!(vec![1; 60], resp.get_first_value().unwrap().data);
+
+    let resp = driver.run(Cmd::Stats(Stats::new(None)));
+    let stats = resp.get_stats().unwrap();
+    let find_stat = |key: &str| {
+        stats.iter().find(|stat| stat.key == key).unwrap().value.clone()
+    };
+    assert_eq!(find_stat("extstore_hits"), "1");
+    assert_eq!(find_stat("extstore_misses"), "0");
+    assert_eq!(find_stat("extstore_items"), "1");
+    assert_eq!(find_stat("extstore_bytes"), "60");
+}
+
+#[test]
+fn test_cmd_stats_namespaces() {
+    let mut namespaces = Namespaces::new(b':');
+    namespaces.with_quota(b"teama", 1 << 20);
+    let mut cache = Cache::new(1 << 20);
+    cache.with_namespaces(namespaces);
+    let mut driver = Driver::new(cache);
+
+    let set = Set::new(SetInstr::Set, b"teama:x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(set));
+    let set = Set::new(SetInstr::Set, b"teamb:x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(set));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"teama:x")));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"teamb:y")));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"teamc:x")));
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("namespaces"))));
+    let stats = resp.get_stats().unwrap();
+    let keys: Vec<&str> = stats.iter().map(|stat| &stat.key[..]).collect();
+    assert_eq!(keys,
+               vec!["ns:teama:bytes",
+                    "ns:teama:items",
+                    "ns:teama:quota",
+                    "ns:teama:get_hits",
+                    "ns:teama:get_misses",
+                    "ns:teama:evictions",
+                    "ns:teamb:bytes",
+                    "ns:teamb:items",
+                    "ns:teamb:quota",
+                    "ns:teamb:get_hits",
+                    "ns:teamb:get_misses",
+                    "ns:teamb:evictions",
+                    "ns_other:bytes",
+                    "ns_other:items",
+                    "ns_other:quota",
+                    "ns_other:get_hits",
+                    "ns_other:get_misses",
+                    "ns_other:evictions"]);
+
+    let find_stat = |key: &str| {
+        stats.iter().find(|stat| stat.key == key).unwrap().value.clone()
+    };
+    assert_eq!(find_stat("ns:teama:items"), "1");
+    assert_eq!(find_stat("ns:teama:quota"), (1 << 20).to_string());
+    assert_eq!(find_stat("ns:teama:get_hits"), "1");
+    assert_eq!(find_stat("ns:teamb:quota"), "0");
+    assert_eq!(find_stat("ns:teamb:get_misses"), "1");
+    // Nothing was stored in teamc
+    assert_eq!(find_stat("ns_other:get_misses"), "1");
+}
+
+#[test]
+fn test_cmd_stats_namespaces_disabled() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("namespaces"))));
+    assert_eq!(resp, Resp::Stats(vec![]));
+}
+
+#[test]
+fn test_cmd_stats_sizes() {
+    let cache = Cache::new(1 << 20);
+    let mut driver = Driver::new(cache);
+
+    let set = Set::new(SetInstr::Set, b"a", 0, 0, vec![1; 10], false);
+    driver.run(Cmd::Set(set));
+    let set = Set::new(SetInstr::Set, b"b", 0, 0, vec![1; 10], false);
+    driver.run(Cmd::Set(set));
+    let set = Set::new(SetInstr::Set, b"c", 0, 0, vec![1; 1000], false);
+    driver.run(Cmd::Set(set));
+
+    // Item sizes include what we spend on keeping the item
+    let skey = SKey::new(vec![b'a']);
+    let small = entry_mem_size(&skey, &SValue::new(Blob::new(vec![1; 10])));
+    let large = entry_mem_size(&skey, &SValue::new(Blob::new(vec![1; 1000])));
+    let bucket = |size: u64| (size + 31) / 32 * 32;
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("sizes"))));
+    assert_eq!(resp,
+               Resp::Stats(vec![Stat::new(&bucket(small).to_string(),
+                                          "2".to_string()),
+                                Stat::new(&bucket(large).to_string(),
+                                          "1".to_string())]));
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("key_sizes"))));
+    assert_eq!(resp, Resp::Stats(vec![Stat::new("32", "3".to_string())]));
+}
+
+#[test]
+fn test_cmd_stats_commands() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(set));
+    let add = Set::new(SetInstr::Add, b"x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(add));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"y")));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"y")));
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("commands"))));
+    let stats = resp.get_stats().unwrap();
+    let keys: Vec<&str> = stats.iter().map(|stat| &stat.key[..]).collect();
+    assert_eq!(keys,
+               vec!["cmd:add:count",
+                    "cmd:add:not_stored",
+                    "cmd:add:avg_us",
+                    "cmd:add:p50_us",
+                    "cmd:add:p99_us",
+                    "cmd:add:max_us",
+                    "cmd:get:count",
+                    "cmd:get:hit",
+                    "cmd:get:miss",
+                    "cmd:get:avg_us",
+                    "cmd:get:p50_us",
+                    "cmd:get:p99_us",
+                    "cmd:get:max_us",
+                    "cmd:set:count",
+                    "cmd:set:stored",
+                    "cmd:set:avg_us",
+                    "cmd:set:p50_us",
+                    "cmd:set:p99_us",
+                    "cmd:set:max_us"]);
+
+    let find_stat = |key: &str| {
+        stats.iter().find(|stat| stat.key == key).unwrap().value.clone()
+    };
+    assert_eq!(find_stat("cmd:get:count"), "3");
+    assert_eq!(find_stat("cmd:get:hit"), "1");
+    assert_eq!(find_stat("cmd:get:miss"), "2");
+    assert_eq!(find_stat("cmd:add:not_stored"), "1");
+
+    // The stats command itself now shows up too
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("commands"))));
+    let stats = resp.get_stats().unwrap();
+    assert!(stats.iter().any(|stat| stat.key == "cmd:stats:ok"));
+}
+
+#[test]
+fn test_cmd_stats_commands_latency() {
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
+    let mut driver = Driver::new(cache);
+
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("commands"))));
+    let stats = resp.get_stats().unwrap();
+    let find_stat = |key: &str| {
+        stats.iter().find(|stat| stat.key == key).unwrap().value.clone()
+    };
+    // No time passes on the fake clock
+    assert_eq!(find_stat("cmd:get:max_us"), "0.000");
+}
+
+#[test]
+fn test_cmd_names_and_outcomes() {
+    let cmd = Cmd::Get(Get::one(GetInstr::Gets, b"x"));
+    assert_eq!("gets", cmd.name());
+    let cmd = Cmd::Inc(Inc::new(IncInstr::Decr, b"x", 1, false));
+    assert_eq!("decr", cmd.name());
+    let cmd = Cmd::Set(Set::new(SetInstr::Cas, b"x", 0, 0, vec![], false));
+    assert_eq!("cas", cmd.name());
+    assert_eq!("flush", Cmd::FlushAll(FlushAll::new(None, false)).name());
+
+    assert_eq!("miss", Resp::Values(vec![]).outcome());
+    assert_eq!("miss", Resp::NotFound.outcome());
+    assert_eq!("hit", Resp::Deleted.outcome());
+    assert_eq!("error", Resp::ServerError("oops".to_string()).outcome());
+    assert_eq!("noreply", Resp::Empty.outcome());
+}
+
+#[test]
+fn test_cmd_keys() {
+    let keys: Vec<Vec<u8>> = vec![b"a".to_vec(), b"b".to_vec()];
+    let cmd = Cmd::Get(Get::new(GetInstr::Get, keys));
+    assert_eq!(vec![&b"a"[..], &b"b"[..]], cmd.keys());
+    let cmd = Cmd::Set(Set::new(SetInstr::Set, b"x", 0, 0, vec![], false));
+    assert_eq!(vec![&b"x"[..]], cmd.keys());
+    assert!(Cmd::FlushAll(FlushAll::new(None, false)).keys().is_empty());
+}
+
+#[test]
+fn test_hotkeys_replace_least_requested() {
+    let mut hotkeys = HotKeys::new(2, 0.0);
+
+    for key in &[b"a", b"a", b"b", b"a"] {
+        hotkeys.add(*key, 0.0);
+    }
+    assert_eq!(hotkeys.top(), vec![(b"a".to_vec(), 3), (b"b".to_vec(), 1)]);
+
+    // A new key takes the place of the least requested one, carrying on from
+    // its count
+    hotkeys.add(b"c", 0.0);
+    assert_eq!(hotkeys.len(), 2);
+    assert_eq!(hotkeys.top(), vec![(b"a".to_vec(), 3), (b"c".to_vec(), 2)]);
+}
+
+#[test]
+fn test_hotkeys_find_heavy_keys_in_long_tail() {
+    let mut hotkeys = HotKeys::new(10, 0.0);
+
+    // Every other request is for one of three keys, the rest are for keys
+    // that are only ever requested once
+    for i in 0..3000u32 {
+        match i % 2 {
+            0 => hotkeys.add(format!("hot{}", i % 3).as_bytes(), 0.0),
+            _ => hotkeys.add(format!("cold{}", i).as_bytes(), 0.0),
+        }
+    }
+
+    assert_eq!(hotkeys.len(), 10);
+    let top: Vec<Vec<u8>> = hotkeys.top()
+                                   .into_iter()
+                                   .take(3)
+                                   .map(|(key, _)| key)
+                                   .collect();
+    assert_eq!(top,
+               vec![b"hot0".to_vec(), b"hot1".to_vec(), b"hot2".to_vec()]);
+}
+
+#[test]
+fn test_hotkeys_decay() {
+    let mut hotkeys = HotKeys::new(2, 10.0);
+
+    for _ in 0..8 {
+        hotkeys.add(b"a", 0.0);
+    }
+
+    // One half life on, a's count halved
+    hotkeys.add(b"b", 10.0);
+    assert_eq!(hotkeys.top(), vec![(b"a".to_vec(), 4), (b"b".to_vec(), 1)]);
+
+    // Two more half lives on, b overtakes a
+    hotkeys.add(b"b", 35.0);
+    hotkeys.add(b"b", 35.0);
+    assert_eq!(hotkeys.top(), vec![(b"b".to_vec(), 2), (b"a".to_vec(), 1)]);
+}
+
+#[test]
+fn test_cmd_stats_hotkeys() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+    driver.with_hotkeys(HotKeys::new(10, 60.0));
+
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(set));
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+    let keys = vec![b"x".to_vec(), b"y".to_vec()];
+    driver.run(Cmd::Get(Get::new(GetInstr::Get, keys)));
+
+    // Other commands don't count
+    driver.run(Cmd::Delete(Delete::new(b"y", false)));
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("hotkeys"))));
+    assert_eq!(resp,
+               Resp::Stats(vec![Stat::new("x", "3".to_string()),
+                                Stat::new("y", "1".to_string())]));
+}
+
+#[test]
+fn test_cmd_stats_hotkeys_disabled() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("hotkeys"))));
+    assert_eq!(resp, Resp::Stats(vec![]));
+}
+
+#[test]
+fn test_cmd_stats_unknown_group() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let resp = driver.run(Cmd::Stats(Stats::new(Some("woops"))));
+    assert_eq!(resp, Resp::Error);
+}
+
+
+// Tag
+
+#[test]
+fn test_cmd_tag_and_invalidate_tag() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    // Try to tag a key not set
+    let resp = driver.run(Cmd::Tag(Tag::new(b"x", b"p15", false)));
+    assert_eq!(resp, Resp::NotFound);
+
+    for key in &[b"x", b"y", b"z"] {
+        let set = Set::new(SetInstr::Set, *key, 3, 0, vec![1], false);
+        driver.run(Cmd::Set(set));
+    }
+
+    // Tag two of them, one with noreply
+    let resp = driver.run(Cmd::Tag(Tag::new(b"x", b"p15", false)));
+    assert_eq!(resp, Resp::Tagged);
+    let resp = driver.run(Cmd::Tag(Tag::new(b"y", b"p15", true)));
+    assert_eq!(resp, Resp::Empty);
+
+    // Tagging doesn't change the item
+    let resp = driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+    assert_eq!(3, resp.get_first_value().unwrap().flags);
+    assert_eq!(vec![1], resp.get_first_value().unwrap().data);
+
+    let inv = Invalidate::new(InvalidateInstr::Tag, b"p15", false);
+    let resp = driver.run(Cmd::Invalidate(inv));
+    assert_eq!(resp, Resp::Ok);
+
+    let keys = vec![b"x".to_vec(), b"y".to_vec(), b"z".to_vec()];
+    let resp = driver.run(Cmd::Get(Get::new(GetInstr::Get, keys)));
+    assert_eq!(1, resp.get_values().unwrap().len());
+    assert_eq!(b"z".to_vec(), resp.get_first_value().unwrap().key);
+}
+
+#[test]
+fn test_cmd_set_drops_tags() {
+    let cache = Cache::new(1024);
+    let mut driver = Driver::new(cache);
+
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![1], false);
+    driver.run(Cmd::Set(set));
+    driver.run(Cmd::Tag(Tag::new(b"x", b"p15", false)));
+
+    // Storing the key again replaces the item, tags and all
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, vec![2], false);
+    driver.run(Cmd::Set(set));
+
+    let inv = Invalidate::new(InvalidateInstr::Tag, b"p15", false);
+    driver.run(Cmd::Invalidate(inv));
+
+    let resp = driver.run(Cmd::Get(Get::one(GetInstr::Get, b"x")));
+    assert_eq!(vec![2], resp.get_first_value().unwrap().data);
 }
 
 
 // Touch
 
-// this is a slow test that relies on sleeps
-#[ignore]
 #[test]
 fn test_cmd_touch() {
-    let cache = Cache::new(100);
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
     let mut driver = Driver::new(cache);
 
     // Try to touch an invalid key
-    let touch = Touch::new("x", 0, false);
+    let touch = Touch::new(b"x", 0, false);
     let cmd = Cmd::Touch(touch);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::NotFound);
 
     // Try to touch an invalid key - noreply
-    let touch = Touch::new("x", 0, true);
+    let touch = Touch::new(b"x", 0, true);
     let cmd = Cmd::Touch(touch);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
     // Set a key that expires in 3s
-    let set = Set::new(SetInstr::Set, "x", 0, 3, vec![8, 9], false);
+    let set = Set::new(SetInstr::Set, b"x", 0, 3, vec![8, 9], false);
     let cmd = Cmd::Set(set);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Stored);
 
-    // sleep 1.5 secs - not long enough to expire key
-    sleep_secs(1.5);
+    // wait 1.5 secs - not long enough to expire key
+    clock.advance(1.5);
 
     // Touch the key to keep it alive (set same exptime)
-    let touch = Touch::new("x", 3, false);
+    let touch = Touch::new(b"x", 3, false);
     let cmd = Cmd::Touch(touch);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Touched);
 
     // Touch it again - noreply
-    let touch = Touch::new("x", 3, true);
+    let touch = Touch::new(b"x", 3, true);
     let cmd = Cmd::Touch(touch);
     let resp = driver.run(cmd);
     assert_eq!(resp, Resp::Empty);
 
-    // sleep 1.5 secs - the key would have expired by now without being touched
-    sleep_secs(1.5);
+    // wait 1.5 secs - the key would have expired by now without being touched
+    clock.advance(1.5);
 
     // It's still there
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(1, resp.get_values().unwrap().len());
 
-    // sleep 2.5 secs - long enough to expire after the touch
-    sleep_secs(2.5);
+    // wait 2.5 secs - long enough to expire after the touch
+    clock.advance(2.5);
 
     // It's gone
-    let cmd = Cmd::Get(Get::one(GetInstr::Get, "x"));
+    let cmd = Cmd::Get(Get::one(GetInstr::Get, b"x"));
     let resp = driver.run(cmd);
     assert_eq!(0, resp.get_values().unwrap().len());
 }
@@ -789,7 +1584,7 @@ fn test_cmd_touch() {
 
 #[test]
 fn test_cmd_version() {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
     // Set a key
@@ -799,16 +1594,75 @@ fn test_cmd_version() {
 }
 
 
+// Watch
+
+#[test]
+fn test_cmd_watch_events() {
+    let item_size = entry_mem_size(&SKey::new(b"a".to_vec()),
+                                   &SValue::new(Blob::new(vec![1])));
+    let mut cache = Cache::new(item_size * 2);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
+    let mut driver = Driver::new(cache);
+
+    // Nothing is kept while nobody watches
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"a")));
+    assert!(driver.take_events().is_empty());
+
+    let resp = driver.run(Cmd::Watch(Watch::new(true, true, true)));
+    assert_eq!(resp, Resp::Ok);
+    driver.set_watched(true);
+
+    driver.run(Cmd::Set(Set::new(SetInstr::Set, b"a", 0, 60, vec![1], false)));
+    driver.run(Cmd::Set(Set::new(SetInstr::Set, b"b", 0, 0, vec![2], false)));
+    driver.run(Cmd::Get(Get::new(GetInstr::Get, vec![b"a".to_vec(),
+                                                     b"z".to_vec()])));
+    driver.run(Cmd::Delete(Delete::new(b"b", false)));
+    driver.run(Cmd::Delete(Delete::new(b"b", false)));
+
+    let events = driver.take_events();
+    let kinds: Vec<WatchEventKind> = events.iter()
+                                           .map(|event| event.kind)
+                                           .collect();
+    assert_eq!(vec![WatchEventKind::Set,
+                    WatchEventKind::Set,
+                    WatchEventKind::Hit,
+                    WatchEventKind::Miss,
+                    WatchEventKind::Delete],
+               kinds);
+    assert_eq!(b"a".to_vec(), events[0].key);
+    assert_eq!(1, events[0].size);
+    assert_eq!(Some(60), events[0].ttl);
+    assert_eq!(None, events[1].ttl);
+    assert_eq!(b"z".to_vec(), events[3].key);
+    assert_eq!(0, events[3].size);
+
+    // Evictions come from the cache
+    driver.run(Cmd::Set(Set::new(SetInstr::Set, b"c", 0, 0, vec![3], false)));
+    driver.run(Cmd::Set(Set::new(SetInstr::Set, b"d", 0, 0, vec![4], false)));
+    let events = driver.take_events();
+    assert_eq!(3, events.len());
+    assert_eq!(WatchEventKind::Evict, events[2].kind);
+    assert_eq!(b"a".to_vec(), events[2].key);
+    assert_eq!(Some(60), events[2].ttl);
+
+    // And stop once nobody watches
+    driver.set_watched(false);
+    driver.run(Cmd::Get(Get::one(GetInstr::Get, b"c")));
+    assert!(driver.take_events().is_empty());
+}
+
+
 // Item expiration cases
 
-// this is a slow test that relies on sleeps
-#[ignore]
 #[test]
 fn test_cmd_relative_exptime() {
-    let cache = Cache::new(100);
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
     let mut driver = Driver::new(cache);
 
-    let key_name = "x";
+    let key_name = b"x";
     let blob = vec![1, 2, 3];
 
     // Set a key with exptime of 1 second
@@ -822,8 +1676,8 @@ fn test_cmd_relative_exptime() {
     let resp = driver.run(cmd);
     assert_eq!(blob, resp.get_first_value().unwrap().data);
 
-    // sleep 1.5 secs - long enough to expire key
-    sleep_secs(1.5);
+    // wait 1.5 secs - long enough to expire key
+    clock.advance(1.5);
 
     // Retrieve the key again - it's gone
     let cmd = Cmd::Get(Get::one(GetInstr::Get, key_name));
@@ -831,16 +1685,16 @@ fn test_cmd_relative_exptime() {
     assert_eq!(0, resp.get_values().unwrap().len());
 }
 
-// this is a slow test that relies on sleeps
-#[ignore]
 #[test]
 fn test_cmd_absolute_exptime() {
-    let cache = Cache::new(100);
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
     let mut driver = Driver::new(cache);
 
-    let key_name = "x";
+    let key_name = b"x";
     let blob = vec![1, 2, 3];
-    let exp = time_now().round() as u32 + 1;
+    let exp = clock.now().round() as u32 + 1;
 
     // Set a key with exptime of 1 second
     let set = Set::new(SetInstr::Set, key_name, 0, exp, blob.clone(), false);
@@ -853,8 +1707,8 @@ fn test_cmd_absolute_exptime() {
     let resp = driver.run(cmd);
     assert_eq!(blob, resp.get_first_value().unwrap().data);
 
-    // sleep 2.5 secs - long enough to expire key
-    sleep_secs(2.5);
+    // wait 2.5 secs - long enough to expire key
+    clock.advance(2.5);
 
     // Retrieve the key again - it's gone
     let cmd = Cmd::Get(Get::one(GetInstr::Get, key_name));
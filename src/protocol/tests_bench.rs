@@ -13,10 +13,10 @@ use super::cmd::SetInstr;
 
 #[bench]
 fn bench_cmd_set_key(b: &mut Bencher) {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
-    let key_name = "x";
+    let key_name = b"x";
     let blob = vec![1, 2, 3];
 
     b.iter(|| {
@@ -35,10 +35,10 @@ fn bench_cmd_set_key(b: &mut Bencher) {
 
 #[bench]
 fn bench_cmd_get_key(b: &mut Bencher) {
-    let cache = Cache::new(100);
+    let cache = Cache::new(1024);
     let mut driver = Driver::new(cache);
 
-    let key_name = "x";
+    let key_name = b"x";
     let blob = vec![1, 2, 3];
 
     // Set a key
@@ -55,3 +55,26 @@ fn bench_cmd_get_key(b: &mut Bencher) {
         assert_eq!(blob, resp.get_first_value().unwrap().data);
     })
 }
+
+#[bench]
+fn bench_cmd_get_large_value(b: &mut Bencher) {
+    let cache = Cache::new(16 << 20);
+    let mut driver = Driver::new(cache);
+
+    let key_name = b"x";
+    let blob = vec![7; 500 * 1024];
+
+    // Set a key
+    let set = Set::new(SetInstr::Set, key_name, 15, 0, blob.clone(), false);
+    let cmd = Cmd::Set(set);
+    let resp = driver.run(cmd);
+    assert_eq!(resp, Resp::Stored);
+
+    b.bytes = blob.len() as u64;
+    b.iter(|| {
+        // Retrieve it
+        let cmd = Cmd::Get(Get::one(GetInstr::Get, key_name));
+        let resp = driver.run(cmd);
+        assert_eq!(15, resp.get_first_value().unwrap().flags);
+    })
+}
@@ -1,4 +1,3 @@
-use platform::time::time_now;
 use storage::CacheError;
 
 use super::cmd::Resp;
@@ -25,7 +24,9 @@ pub fn u64_to_bytes(num: &u64) -> Vec<u8> {
 }
 
 
-pub fn convert_exptime(exptime: u32) -> Option<f64> {
+// Turns an exptime from a client into a time on the clock, given what time it
+// is now
+pub fn convert_exptime(exptime: u32, now: f64) -> Option<f64> {
     // If exptime is greater than zero it means it's set, otherwise unset
     if exptime > 0 {
         let tm;
@@ -36,7 +37,7 @@ pub fn convert_exptime(exptime: u32) -> Option<f64> {
 
         } else {
             // Otherwise it's relative from now
-            tm = time_now() + exptime as f64;
+            tm = now + exptime as f64;
         }
 
         return Some(tm);
@@ -60,9 +61,6 @@ pub fn from_cache_err(err: &CacheError) -> Resp {
 
 #[cfg(test)]
 mod tests {
-    use platform::time::time_now;
-    use testlib::cmp::eq_f64;
-
     use super::bytes_to_u64;
     use super::convert_exptime;
     use super::u64_to_bytes;
@@ -95,16 +93,16 @@ mod tests {
 
     #[test]
     fn test_convert_exptime() {
-        assert_eq!(None, convert_exptime(0));
+        let now = 1_000_000_000.0;
+
+        assert_eq!(None, convert_exptime(0, now));
 
         // big enough to be a timestamp
         let val = (60 * 60 * 24 * 30) + 1;
-        assert_eq!(Some(val as f64), convert_exptime(val));
+        assert_eq!(Some(val as f64), convert_exptime(val, now));
 
         // not big enough to be a timestamp - it's a relative time
         let val = 5;
-        let expected = time_now() + val as f64;
-        let actual = convert_exptime(val).unwrap();
-        assert!(eq_f64(expected, actual, 0.01));
+        assert_eq!(Some(now + 5.0), convert_exptime(val, now));
     }
 }
@@ -1,13 +1,49 @@
+use std::mem;
+
 use linked_hash_map::LinkedHashMap;
 
+use common::consts::KEY_MAXLEN;
+use platform::clock::Clock;
+use platform::clock::SystemClock;
 use platform::time::time_now;
 
 use super::errors::CacheError;
+use super::events::CacheEvent;
+use super::events::CacheEventKind;
+use super::extstore::ExtStore;
+use super::extstore::ExtStoreStats;
+use super::invalidations::Invalidations;
 use super::key::Key;
+use super::namespaces::NamespaceStats;
+use super::namespaces::Namespaces;
+use super::policy::EvictionPolicy;
+use super::sizes::SizeHistogram;
 use super::typedefs::CacheResult;
 use super::value::Value;
 
 
+// Once we remember this many invalidated prefixes and tags we let go of the
+// older half of them
+const MAX_INVALIDATIONS: usize = 10000;
+
+// Under segmented LRU at most this share of the capacity goes to the items
+// that were read again after they were stored
+const PROTECTED_SHARE: f64 = 0.8;
+
+
+// Every entry in the LinkedHashMap costs more than just its key and value: the
+// list node carries prev/next pointers and the hash table slot holds a pointer
+// to the key, a pointer to the node and the hash.
+fn entry_overhead() -> usize {
+    5 * mem::size_of::<usize>()
+}
+
+// The number of bytes an entry occupies in the cache
+pub fn entry_mem_size(key: &Key, value: &Value) -> u64 {
+    (key.mem_size() + value.mem_size() + entry_overhead()) as u64
+}
+
+
 pub struct CacheStats {
     pub bytes: u64, // Bytes currently stored
     pub evictions: u64, // Number of items removed to make space for new items
@@ -17,6 +53,10 @@ pub struct CacheStats {
     pub delete_hits: u64,
     pub reclaimed: u64, // Number of times an entry was reclaimed to store a new entry
     pub total_items: u64, // Total items stored since server started
+    pub compress_raw_bytes: u64, // Bytes of values before they were compressed
+    pub compress_bytes: u64, // Bytes of the same values after compression
+    pub compress_time: f64, // Seconds spent compressing values
+    pub decompress_time: f64, // Seconds spent decompressing values
 }
 
 impl CacheStats {
@@ -30,17 +70,29 @@ impl CacheStats {
             get_misses: 0,
             reclaimed: 0,
             total_items: 0,
+            compress_raw_bytes: 0,
+            compress_bytes: 0,
+            compress_time: 0.0,
+            decompress_time: 0.0,
+        }
+    }
+
+    // How many times smaller the values we compressed became, 0 if we haven't
+    // compressed any
+    pub fn compress_ratio(&self) -> f64 {
+        if self.compress_bytes == 0 {
+            return 0.0;
         }
+
+        self.compress_raw_bytes as f64 / self.compress_bytes as f64
     }
 
     fn bytes_add(&mut self, key: &Key, value: &Value) {
-        self.bytes += key.mem_size() as u64;
-        self.bytes += value.mem_size() as u64;
+        self.bytes += entry_mem_size(key, value);
     }
 
     fn bytes_subtract(&mut self, key: &Key, value: &Value) {
-        self.bytes -= key.mem_size() as u64;
-        self.bytes -= value.mem_size() as u64;
+        self.bytes -= entry_mem_size(key, value);
     }
 }
 
@@ -48,28 +100,105 @@ impl CacheStats {
 pub struct Cache {
     pub capacity: u64, // in bytes
     storage: LinkedHashMap<Key, Value>,
+
+    // Under segmented LRU new items are stored here, and only move on to
+    // storage (the protected segment) once they are read again. Items are
+    // evicted from here first. Under LRU it stays empty.
+    policy: EvictionPolicy,
+    probation: LinkedHashMap<Key, Value>,
+    probation_bytes: u64, // Memory used by the items on probation
+
+    clock: Box<dyn Clock>, // Tells the time for everything that expires
     item_lifetime: f64, // in seconds, <0 for unlimited
-    global_exptime: f64, // unixtime, <0 for unset
+    flushed_before: f64, // unixtime, items accessed before are dead
+    pending_flush: Option<f64>, // unixtime of a delayed flush still to come
+    invalidations: Invalidations, // Invalidated key prefixes and tags
 
     key_maxlen: u64, // in bytes
     value_maxlen: u64, // in bytes
 
+    // Values larger than this are stored compressed (in bytes), None to
+    // never compress
+    compress_threshold: Option<u64>,
+
+    // Items are only moved to the LRU head on access if they were last moved
+    // longer ago than this (in seconds), so that hot items don't get moved
+    // on every single read
+    bump_interval: f64,
+
+    // Max number of items to evict in one go when we are over capacity after
+    // the capacity was lowered
+    shrink_batch_size: u64,
+
+    // Disk tier that items evicted from memory go to, if any
+    extstore: Option<ExtStore>,
+
+    // Per namespace quotas and stats, if keys are namespaced
+    namespaces: Option<Namespaces>,
+
+    // Evictions and expiries since they were last taken, if anyone is
+    // watching for them
+    events: Option<Vec<CacheEvent>>,
+
     stats: CacheStats,
+    key_sizes: SizeHistogram, // Lengths of the keys we store
+    item_sizes: SizeHistogram, // Memory used by the items we store
 }
 
 impl Cache {
     pub fn new(capacity: u64) -> Cache {
         Cache {
+            bump_interval: 0.0,
             capacity: capacity,
-            global_exptime: -1.0,
+            clock: Box::new(SystemClock::new()),
+            compress_threshold: None,
+            events: None,
+            extstore: None,
+            flushed_before: -1.0,
+            invalidations: Invalidations::new(),
             item_lifetime: -1.0,
-            key_maxlen: 250, // 250b
+            item_sizes: SizeHistogram::new(),
+            key_maxlen: KEY_MAXLEN as u64,
+            key_sizes: SizeHistogram::new(),
+            namespaces: None,
+            pending_flush: None,
+            policy: EvictionPolicy::Lru,
+            probation: LinkedHashMap::new(),
+            probation_bytes: 0,
+            shrink_batch_size: 100,
             stats: CacheStats::new(),
             value_maxlen: 1048576, // 1mb
             storage: LinkedHashMap::new(),
         }
     }
 
+    pub fn with_bump_interval(&mut self, bump_interval: f64) -> &mut Cache {
+        self.bump_interval = bump_interval;
+        self
+    }
+
+    pub fn with_clock(&mut self, clock: Box<dyn Clock>) -> &mut Cache {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_compress_threshold(&mut self,
+                                   compress_threshold: u64)
+                                   -> &mut Cache {
+        self.compress_threshold = Some(compress_threshold);
+        self
+    }
+
+    pub fn with_extstore(&mut self, extstore: ExtStore) -> &mut Cache {
+        self.extstore = Some(extstore);
+        self
+    }
+
+    pub fn with_namespaces(&mut self, namespaces: Namespaces) -> &mut Cache {
+        self.namespaces = Some(namespaces);
+        self
+    }
+
     pub fn with_item_lifetime(&mut self, item_lifetime: f64) -> &mut Cache {
         self.item_lifetime = item_lifetime;
         self
@@ -85,11 +214,138 @@ impl Cache {
         self
     }
 
+    pub fn with_policy(&mut self, policy: EvictionPolicy) -> &mut Cache {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_shrink_batch_size(&mut self,
+                                  shrink_batch_size: u64)
+                                  -> &mut Cache {
+        self.shrink_batch_size = shrink_batch_size;
+        self
+    }
+
+
+    pub fn get_clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    pub fn get_policy(&self) -> EvictionPolicy {
+        self.policy
+    }
 
     pub fn get_stats(&self) -> &CacheStats {
         &self.stats
     }
 
+    pub fn get_ext_stats(&self) -> Option<&ExtStoreStats> {
+        self.extstore.as_ref().map(|extstore| extstore.get_stats())
+    }
+
+    pub fn get_key_sizes(&self) -> &SizeHistogram {
+        &self.key_sizes
+    }
+
+    pub fn get_item_sizes(&self) -> &SizeHistogram {
+        &self.item_sizes
+    }
+
+    pub fn get_namespaces(&self) -> Option<&Namespaces> {
+        self.namespaces.as_ref()
+    }
+
+    // Bytes allocated for the hash table itself, whether in use or not, and
+    // for the index of the items on disk
+    pub fn hash_bytes(&self) -> u64 {
+        let slots = self.storage.capacity() + self.probation.capacity();
+        let index_bytes = self.get_ext_stats()
+                              .map_or(0, |stats| stats.index_bytes);
+        (slots * 3 * mem::size_of::<usize>()) as u64 + index_bytes
+    }
+
+
+    // Start or stop keeping the evictions and expiries
+    pub fn set_watched(&mut self, watched: bool) {
+        self.events = match watched {
+            true => Some(vec![]),
+            false => None,
+        };
+    }
+
+    pub fn take_events(&mut self) -> Vec<CacheEvent> {
+        match self.events {
+            Some(ref mut events) => mem::replace(events, vec![]),
+            None => vec![],
+        }
+    }
+
+    fn record_event(&mut self,
+                    kind: CacheEventKind,
+                    key: &Key,
+                    value: &Value) {
+        if let Some(ref mut events) = self.events {
+            events.push(CacheEvent::new(kind,
+                                        key.clone(),
+                                        value.len() as u64,
+                                        *value.get_exptime()));
+        }
+    }
+
+
+    fn namespace_mut(&mut self, key: &Key) -> Option<&mut NamespaceStats> {
+        match self.namespaces {
+            Some(ref mut namespaces) => namespaces.get_mut(key),
+            None => None,
+        }
+    }
+
+    fn bytes_add(&mut self, key: &Key, value: &Value) {
+        self.stats.bytes_add(key, value);
+        self.key_sizes.add(key.len() as u64);
+        self.item_sizes.add(entry_mem_size(key, value));
+
+        let ns = match self.namespaces {
+            Some(ref mut namespaces) => namespaces.get_or_insert(key),
+            None => None,
+        };
+        if let Some(ns) = ns {
+            ns.bytes += entry_mem_size(key, value);
+            ns.items += 1;
+        }
+        if let Some(ref mut namespaces) = self.namespaces {
+            namespaces.stored(key);
+        }
+    }
+
+    fn bytes_subtract(&mut self, key: &Key, value: &Value) {
+        self.stats.bytes_subtract(key, value);
+        self.key_sizes.remove(key.len() as u64);
+        self.item_sizes.remove(entry_mem_size(key, value));
+
+        if let Some(ns) = self.namespace_mut(key) {
+            ns.bytes -= entry_mem_size(key, value);
+            ns.items -= 1;
+        }
+        if let Some(ref mut namespaces) = self.namespaces {
+            namespaces.removed(key);
+        }
+    }
+
+    fn count_get(&mut self, key: &Key, hit: bool) {
+        match hit {
+            true => self.stats.get_hits += 1,
+            false => self.stats.get_misses += 1,
+        }
+
+        if let Some(ns) = self.namespace_mut(key) {
+            match hit {
+                true => ns.get_hits += 1,
+                false => ns.get_misses += 1,
+            }
+        }
+    }
+
 
     fn check_key_len(&self, key: &Key) -> bool {
         key.len() as u64 <= self.key_maxlen
@@ -100,40 +356,261 @@ impl Cache {
     }
 
 
-    fn evict_oldest(&mut self) -> CacheResult<(Key, Value)> {
-        let opt = self.storage.pop_back();
+    fn should_compress(&self, value: &Value) -> bool {
+        match self.compress_threshold {
+            Some(threshold) => value.len() as u64 > threshold,
+            None => false,
+        }
+    }
+
+    fn compress_value(&mut self, value: &mut Value) {
+        let raw_len = value.len() as u64;
+
+        let time_start = time_now();
+        let compressed = value.compress();
+        self.stats.compress_time += time_now() - time_start;
 
-        match opt {
-            Some((key, value)) => {
-                // Update stats
-                self.stats.bytes_subtract(&key, &value);
-                self.stats.evictions += 1;
+        // Only count the values we actually store compressed
+        if compressed {
+            self.stats.compress_raw_bytes += raw_len;
+            self.stats.compress_bytes += value.len() as u64;
+        }
+    }
 
-                Ok((key, value))
+    fn decompress_value(&mut self, value: &mut Value) {
+        if !value.is_compressed() {
+            return;
+        }
+
+        let time_start = time_now();
+        value.decompress();
+        self.stats.decompress_time += time_now() - time_start;
+    }
+
+
+    // Returns the number of bytes freed
+    fn evict(&mut self, key: Key, value: Value) -> u64 {
+        let item_size = entry_mem_size(&key, &value);
+
+        // Update stats
+        self.bytes_subtract(&key, &value);
+        self.stats.evictions += 1;
+        if let Some(ns) = self.namespace_mut(&key) {
+            ns.evictions += 1;
+        }
+        self.record_event(CacheEventKind::Evicted, &key, &value);
+
+        self.spill(key, value);
+
+        item_size
+    }
+
+    // Returns the number of bytes freed
+    fn evict_oldest(&mut self) -> CacheResult<u64> {
+        // The front of the map is the least recently used entry, and items on
+        // probation go first
+        let opt = match self.probation.pop_front() {
+            Some((key, value)) => {
+                self.probation_bytes -= entry_mem_size(&key, &value);
+                Some((key, value))
             }
+            None => self.storage.pop_front(),
+        };
+
+        match opt {
+            Some((key, value)) => Ok(self.evict(key, value)),
             None => Err(CacheError::EvictionFailed),
         }
     }
 
-    fn value_is_alive(&self, value: &Value) -> bool {
-        // If we have a global exptime set, then any item touched before it is
-        // dead
-        if self.global_exptime > 0.0 {
-            if *value.get_atime() < self.global_exptime {
-                return false;
+    // Evict the least recently used item of a namespace that is over its
+    // quota, counting the item we are about to store towards its namespace.
+    // Returns the number of bytes freed, None if nobody is over quota.
+    fn evict_over_quota(&mut self,
+                        incoming: &Key,
+                        item_size: u64)
+                        -> Option<u64> {
+        let victim = match self.namespaces {
+            Some(ref namespaces) => {
+                namespaces.oldest_over_quota(incoming, item_size).cloned()
             }
+            None => None,
+        };
+
+        victim.map(|key| {
+            let value = self.take_stored(&key).unwrap();
+            self.evict(key, value)
+        })
+    }
+
+    // Takes an item out of whichever segment it's in, leaving the stats as
+    // they are
+    fn take_stored(&mut self, key: &Key) -> Option<Value> {
+        if let Some(value) = self.storage.remove(key) {
+            return Some(value);
         }
 
-        // If the value has an exptime set, that determines lifetime
-        // regardless of item_lifetime in the cache
-        if *value.get_exptime() > 0.0 {
-            if self.global_exptime > 0.0 {
-                if *value.get_exptime() < self.global_exptime {
-                    return false;
+        let opt = self.probation.remove(key);
+        if let Some(ref value) = opt {
+            self.probation_bytes -= entry_mem_size(key, value);
+        }
+        opt
+    }
+
+    // Under segmented LRU an item that is read again moves from probation to
+    // the protected segment
+    fn protect(&mut self, key: &Key) {
+        let value = match self.probation.remove(key) {
+            Some(value) => value,
+            None => return,
+        };
+        self.probation_bytes -= entry_mem_size(key, &value);
+        self.storage.insert(key.clone(), value);
+
+        self.demote_excess();
+    }
+
+    // If the protected segment is too big its least recently used items go
+    // back on probation, as the most recently used ones there. The item just
+    // protected always stays.
+    fn demote_excess(&mut self) {
+        let limit = (self.capacity as f64 * PROTECTED_SHARE) as u64;
+        while self.stats.bytes - self.probation_bytes > limit &&
+              self.storage.len() > 1 {
+            let (key, value) = self.storage.pop_front().unwrap();
+            self.probation_bytes += entry_mem_size(&key, &value);
+            self.probation.insert(key, value);
+        }
+    }
+
+    // Takes out the least recently used item of either segment if it's dead
+    fn pop_dead_front(&mut self) -> Option<(Key, Value)> {
+        let dead = match self.probation.front() {
+            Some((key, value)) => !self.value_is_alive(key, value),
+            None => false,
+        };
+        if dead {
+            let (key, value) = self.probation.pop_front().unwrap();
+            self.probation_bytes -= entry_mem_size(&key, &value);
+            return Some((key, value));
+        }
+
+        let dead = match self.storage.front() {
+            Some((key, value)) => !self.value_is_alive(key, value),
+            None => false,
+        };
+        match dead {
+            true => self.storage.pop_front(),
+            false => None,
+        }
+    }
+
+    // Move an item evicted from memory to the disk tier, if we have one and
+    // the item is worth keeping
+    fn spill(&mut self, key: Key, value: Value) {
+        if !self.value_is_alive(&key, &value) {
+            return;
+        }
+
+        if let Some(ref mut extstore) = self.extstore {
+            if extstore.accepts(&value) {
+                // If the write fails the item is gone, same as it would be
+                // without a disk tier
+                let _ = extstore.set(key, value);
+            }
+        }
+    }
+
+    // Is the item on disk and still alive?
+    fn lookup_ext(&mut self, key: &Key) -> bool {
+        let alive = match self.extstore {
+            Some(ref extstore) => {
+                match extstore.get_header(key) {
+                    Some(header) => self.value_is_alive(key, header),
+                    None => return false,
                 }
             }
+            None => return false,
+        };
+
+        // Dead items are no use to anyone
+        if !alive {
+            self.extstore.as_mut().unwrap().discard(key);
+        }
+
+        alive
+    }
+
+    // Read an item back from disk
+    fn get_ext(&mut self, key: &Key) -> Option<Value> {
+        if !self.lookup_ext(key) {
+            if let Some(ref mut extstore) = self.extstore {
+                extstore.get_stats_mut().misses += 1;
+            }
+            return None;
+        }
+
+        let now = self.clock.now();
+        let extstore = self.extstore.as_mut().unwrap();
+        match extstore.get(key, now) {
+            Ok(Some(value)) => {
+                extstore.get_stats_mut().hits += 1;
+                Some(value)
+            }
+            _ => {
+                // We can't read it, so we don't have it
+                extstore.discard(key);
+                extstore.get_stats_mut().misses += 1;
+                None
+            }
+        }
+    }
+
+    // If we're over capacity (because the capacity was lowered) evict a batch
+    // of items from the LRU tail. Shrinking a large cache thus happens
+    // gradually, a batch per operation (reads included, so that a read-only
+    // workload gets back under the limit too), instead of stalling on one.
+    fn shrink_batch(&mut self) {
+        let mut evicted = 0;
+
+        while self.stats.bytes > self.capacity &&
+              evicted < self.shrink_batch_size {
+            if self.evict_oldest().is_err() {
+                break;
+            }
+
+            evicted += 1;
+        }
+    }
+
+    pub fn is_over_capacity(&self) -> bool {
+        self.stats.bytes > self.capacity
+    }
 
-            if *value.get_exptime() < time_now() {
+    pub fn set_capacity(&mut self, capacity: u64) {
+        self.capacity = capacity;
+
+        // If we're shrinking make a start on evicting right away
+        self.shrink_batch();
+    }
+
+
+    fn value_is_alive(&self, key: &Key, value: &Value) -> bool {
+        // Anything invalidated after it was stored is dead
+        if self.invalidations.is_invalidated(key, value) {
+            return false;
+        }
+
+        // Any item not accessed since the last delayed flush took effect is
+        // dead
+        if *value.get_atime() < self.flushed_before {
+            return false;
+        }
+
+        // If the value has an exptime set, that determines lifetime
+        // regardless of item_lifetime in the cache
+        if *value.get_exptime() > 0.0 {
+            if *value.get_exptime() < self.clock.now() {
                 return false;
             } else {
                 return true;
@@ -146,74 +623,198 @@ impl Cache {
         }
 
         // otherwise use lifetime to determine liveness
-        *value.get_atime() + self.item_lifetime > time_now()
+        *value.get_atime() + self.item_lifetime > self.clock.now()
     }
 
 
     pub fn contains_key(&mut self, key: &Key) -> CacheResult<bool> {
-        let result = self.get(key);
+        // Check key size
+        if !self.check_key_len(key) {
+            return Err(CacheError::KeyTooLong);
+        }
 
-        match result {
-            // We know how to interpret found and not found
-            Ok(_) => Ok(true),
-            Err(CacheError::KeyNotFound) => Ok(false),
+        self.shrink_batch();
+        self.reclaim_flushed();
 
-            // Some other error
-            Err(x) => Err(x),
+        let found = self.lookup(key).is_some() || self.lookup_ext(key);
+
+        // Update stats
+        self.count_get(key, found);
+
+        Ok(found)
+    }
+
+    // Makes every item stored so far dead right away. Like in memcached this
+    // also calls off a delayed flush that is still pending.
+    pub fn flush_all(&mut self) -> CacheResult<()> {
+        self.pending_flush = None;
+        self.invalidations.invalidate_all();
+        self.reclaim_flushed();
+
+        // Nothing on disk survives either
+        if let Some(ref mut extstore) = self.extstore {
+            extstore.discard_if(|_, _| true);
         }
+
+        Ok(())
     }
 
-    pub fn flush_all(&mut self, exptime: f64) -> CacheResult<()> {
-        self.global_exptime = exptime;
+    // Makes every item that is not accessed again before the deadline dead
+    // once it has passed, including items stored in the meantime. Like in
+    // memcached (where this is settings.oldest_live) a later flush replaces
+    // one that is still pending.
+    pub fn flush_all_at(&mut self, deadline: f64) -> CacheResult<()> {
+        self.pending_flush = Some(deadline);
+        self.reclaim_flushed();
         Ok(())
     }
 
-    pub fn get(&mut self, key: &Key) -> CacheResult<&Value> {
-        // Check key size
-        if !self.check_key_len(key) {
-            return Err(CacheError::KeyTooLong);
+    // Let the delayed flush take effect once its deadline has passed
+    fn apply_due_flush(&mut self) {
+        match self.pending_flush {
+            Some(deadline) if deadline <= self.clock.now() => {
+                self.flushed_before = self.flushed_before.max(deadline);
+                self.pending_flush = None;
+            }
+            _ => (),
         }
+    }
+
+    // A flush leaves the dead items at the LRU head, since everything that
+    // was accessed after it is closer to the tail. Drop a batch of them per
+    // operation so that their memory is given back without stalling on a
+    // large cache. (Under segmented LRU the items sent back on probation
+    // break that order, those are dropped once they are looked at or evicted
+    // instead.)
+    fn reclaim_flushed(&mut self) {
+        self.apply_due_flush();
+
+        let mut reclaimed = 0;
 
-        // Pop the value first
-        let opt = self.storage.remove(key);
+        while reclaimed < self.shrink_batch_size {
+            let (key, value) = match self.pop_dead_front() {
+                Some(item) => item,
+                None => break,
+            };
 
-        // We didn't find it
-        if opt.is_none() {
-            self.stats.get_misses += 1;
-            return Err(CacheError::KeyNotFound);
+            self.bytes_subtract(&key, &value);
+            self.record_event(CacheEventKind::Expired, &key, &value);
+
+            reclaimed += 1;
         }
+    }
 
-        // From here on we can assume we did find it
-        let mut value = opt.unwrap();
+    // Invalidates every key that starts with the prefix. This doesn't touch
+    // the items, they are found to be dead when they are next looked at.
+    pub fn invalidate_prefix(&mut self, prefix: &[u8]) -> CacheResult<()> {
+        self.invalidations.invalidate_prefix(prefix);
+        self.retire_invalidations();
+        Ok(())
+    }
 
-        // The value has been successfully removed - update stats
-        self.stats.bytes_subtract(key, &value);
+    // Invalidates every item tagged with the tag, lazily like
+    // invalidate_prefix
+    pub fn invalidate_tag(&mut self, tag: &[u8]) -> CacheResult<()> {
+        self.invalidations.invalidate_tag(tag);
+        self.retire_invalidations();
+        Ok(())
+    }
 
-        // Now check if the value is still alive
-        if !self.value_is_alive(&value) {
-            self.stats.get_misses += 1;
-            return Err(CacheError::KeyNotFound);
+    // We can't hold on to every invalidation forever, but finding the items
+    // one invalidated (so that we can forget about it) takes a scan of the
+    // whole cache. So once there are too many we let go of the older half of
+    // them along with every item stored before them, unless it was read since.
+    // A cache can always drop items, and most of those are invalid or cold.
+    // They are reclaimed lazily, like flushed items.
+    fn retire_invalidations(&mut self) {
+        if self.invalidations.len() >= MAX_INVALIDATIONS {
+            self.invalidations.retire_oldest(MAX_INVALIDATIONS / 2);
         }
 
-        // Update the value to mark that it's been accessed just now
-        value.touch();
+        self.reclaim_flushed();
+    }
+
+    // Returns a copy of the value, decompressed if need be
+    pub fn get(&mut self, key: &Key) -> CacheResult<Value> {
+        // Check key size
+        if !self.check_key_len(key) {
+            return Err(CacheError::KeyTooLong);
+        }
 
-        // We are going to re-instate the key - update stats
-        self.stats.bytes_add(key, &value);
-        self.stats.get_hits += 1;
+        self.shrink_batch();
+        self.reclaim_flushed();
 
-        // Now we reinsert the key to refresh it
-        self.storage.insert(key.clone(), value);
+        // Look in memory first, then on disk
+        let opt = self.lookup(key).cloned();
+        let opt = opt.or_else(|| self.get_ext(key));
 
-        // Load since we need to return it
-        let value = self.storage.get(key).unwrap();
+        match opt {
+            Some(mut value) => {
+                self.count_get(key, true);
+                self.decompress_value(&mut value);
 
-        // Return success
-        Ok(value)
+                Ok(value)
+            }
+            None => {
+                self.count_get(key, false);
+                Err(CacheError::KeyNotFound)
+            }
+        }
+    }
+
+    // Finds a live value in memory and marks it as accessed, but leaves it as
+    // it is stored
+    fn lookup(&mut self, key: &Key) -> Option<&Value> {
+        let now = self.clock.now();
+
+        let stored = self.storage.get(key).or_else(|| self.probation.get(key));
+        let (alive, bumped_recently) = match stored {
+            Some(value) => {
+                (self.value_is_alive(key, value),
+                 *value.get_atime() + self.bump_interval > now)
+            }
+            None => return None,
+        };
+
+        // Dead values are of no use, so drop them while we're here
+        if !alive {
+            let value = self.take_stored(key).unwrap();
+            self.bytes_subtract(key, &value);
+            self.record_event(CacheEventKind::Expired, key, &value);
+            return None;
+        }
+
+        // It holds up against every invalidation so far, so it's as good as
+        // stored just now. That way items that are read survive us letting go
+        // of old invalidations (see retire_invalidations).
+        let generation = self.invalidations.generation();
+
+        // Leave it where it is if it was moved not long ago. Its access time
+        // stays the same too, so the LRU stays in access time order.
+        if bumped_recently {
+            let probation = &mut self.probation;
+            let value = self.storage
+                            .get_mut(key)
+                            .or_else(move || probation.get_mut(key))
+                            .unwrap();
+            value.set_generation(generation);
+            return Some(value);
+        }
+
+        // Move it to the LRU head and mark that it's been accessed just now.
+        // This doesn't change its size, so the stats stay as they are.
+        self.protect(key);
+        if let Some(ref mut namespaces) = self.namespaces {
+            namespaces.touched(key);
+        }
+        let value = self.storage.get_refresh(key).unwrap();
+        value.touch(now);
+        value.set_generation(generation);
+        Some(value)
     }
 
     pub fn len(&self) -> usize {
-        self.storage.len()
+        self.storage.len() + self.probation.len()
     }
 
     pub fn remove(&mut self, key: &Key) -> CacheResult<Value> {
@@ -222,13 +823,40 @@ impl Cache {
             return Err(CacheError::KeyTooLong);
         }
 
-        let opt = self.storage.remove(key);
+        self.shrink_batch();
+        self.reclaim_flushed();
+
+        let mut opt = self.take_stored(key);
+
+        match opt {
+            Some(ref value) => {
+                self.bytes_subtract(key, value);
+            }
+            None => {
+                // Maybe it's on disk
+                if self.lookup_ext(key) {
+                    let extstore = self.extstore.as_mut().unwrap();
+                    opt = extstore.remove(key).unwrap_or(None);
+                }
+            }
+        }
+
+        // A dead value is as good as gone, we must not hand it out to be
+        // stored again
+        let alive = match opt {
+            Some(ref value) => self.value_is_alive(key, value),
+            None => false,
+        };
+        if !alive {
+            opt = None;
+        }
 
         match opt {
-            Some(value) => {
+            Some(mut value) => {
                 // Update stats
                 self.stats.delete_hits += 1;
-                self.stats.bytes_subtract(key, &value);
+
+                self.decompress_value(&mut value);
 
                 Ok((value))
             }
@@ -241,7 +869,7 @@ impl Cache {
         }
     }
 
-    pub fn set(&mut self, key: Key, mut value: Value) -> CacheResult<()> {
+    pub fn set(&mut self, mut key: Key, mut value: Value) -> CacheResult<()> {
         // Check key & value sizes
         if !self.check_key_len(&key) {
             return Err(CacheError::KeyTooLong);
@@ -250,66 +878,86 @@ impl Cache {
             return Err(CacheError::ValueTooLong);
         }
 
-        // Does this item even fit into our cache at all?
-        if key.mem_size() as u64 + value.mem_size() as u64 > self.capacity {
-            return Err(CacheError::CapacityExceeded);
+        // Make sure the key does not hold on to more memory than it needs
+        key.shrink_to_fit();
+
+        // Compress before we figure out how much space we need
+        if self.should_compress(&value) {
+            self.compress_value(&mut value);
         }
 
-        // Do we already store this key?
-        if self.storage.contains_key(&key) {
-            let mut plus_delta = 0u64;
-            {
-                // Load the existing value
-                let prev_value = self.storage.get(&key).unwrap();
+        // Keep working off any excess from a lowered capacity, and any items
+        // a flush left behind
+        self.shrink_batch();
+        self.reclaim_flushed();
 
-                // We're updating the key, possibly with a different size value
-                self.stats.bytes_subtract(&key, &prev_value);
+        // Does this item even fit into our cache at all?
+        let item_size = entry_mem_size(&key, &value);
+        if item_size > self.capacity {
+            return Err(CacheError::CapacityExceeded);
+        }
 
-                // Figure out how much more space we need to store the new value
-                if value.mem_size() > prev_value.mem_size() {
-                    plus_delta = value.mem_size() as u64 -
-                                 prev_value.mem_size() as u64;
-                }
+        // Do we already store this key? Then take out the previous value so
+        // that it doesn't count towards the space we need (and can't be
+        // evicted from under us)
+        let stored_before = match self.take_stored(&key) {
+            Some(prev_value) => {
+                self.bytes_subtract(&key, &prev_value);
+                true
             }
+            None => false,
+        };
 
-            // Would the new value exceed our capacity? Then we need to reclaim
-            loop {
-                if self.stats.bytes + key.mem_size() as u64 + plus_delta <=
-                   self.capacity {
-                    break;
-                }
-
-                try!(self.evict_oldest());
+        // An older version on disk would otherwise come back to haunt us
+        if let Some(ref mut extstore) = self.extstore {
+            extstore.discard(&key);
+        }
 
-                // Update stats
-                self.stats.reclaimed += 1;
+        // Do we have space for the new item? If we're still over capacity
+        // after a shrink we only evict as much as the new item needs and
+        // leave the rest of the excess to shrink_batch
+        let mut freed = 0;
+        loop {
+            if self.stats.bytes + item_size <= self.capacity ||
+               freed >= item_size {
+                break;
             }
 
-        } else {
-            // Do we have space for the new item?
-            loop {
-                if self.stats.bytes + key.mem_size() as u64 +
-                   value.mem_size() as u64 <=
-                   self.capacity {
-                    break;
-                }
-
-                try!(self.evict_oldest());
+            // Make over quota namespaces pay for the space first
+            freed += match self.evict_over_quota(&key, item_size) {
+                Some(item_size) => item_size,
+                None => try!(self.evict_oldest()),
+            };
 
-                // Update stats
-                self.stats.reclaimed += 1;
-            }
+            // Update stats
+            self.stats.reclaimed += 1;
         }
 
         // Update stats
-        self.stats.bytes_add(&key, &value);
+        self.bytes_add(&key, &value);
         self.stats.total_items += 1;
 
         // Update atime for value
-        value.touch();
+        value.touch(self.clock.now());
 
-        // Store the value
-        self.storage.insert(key, value);
+        // The value is newer than any invalidation so far
+        value.set_generation(self.invalidations.generation());
+
+        // Store the value. Under segmented LRU a new key goes on probation,
+        // while storing a key again counts as using it again.
+        match self.policy {
+            EvictionPolicy::SegmentedLru if !stored_before => {
+                self.probation_bytes += entry_mem_size(&key, &value);
+                self.probation.insert(key, value);
+            }
+            EvictionPolicy::SegmentedLru => {
+                self.storage.insert(key, value);
+                self.demote_excess();
+            }
+            EvictionPolicy::Lru => {
+                self.storage.insert(key, value);
+            }
+        }
 
         // Return success
         Ok(())
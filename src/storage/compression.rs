@@ -0,0 +1,73 @@
+use std::io::Read;
+
+use common::blob::Blob;
+use common::lz;
+
+
+// A compressed blob is a sequence of blocks, one per chunk of the original
+// blob, so that neither side ever needs more than a chunk's worth of
+// contiguous memory. Every block starts with a header of the raw and
+// compressed lengths (u32, little endian).
+
+fn write_u32(blob: &mut Blob, num: u32) {
+    let bytes = [num as u8,
+                 (num >> 8) as u8,
+                 (num >> 16) as u8,
+                 (num >> 24) as u8];
+    blob.extend_from_slice(&bytes);
+}
+
+fn parse_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 |
+    (bytes[3] as u32) << 24
+}
+
+
+pub fn compress_blob(blob: &Blob) -> Blob {
+    let mut compressed = Blob::empty();
+
+    for chunk in blob.chunks() {
+        let block = lz::compress(chunk);
+
+        write_u32(&mut compressed, chunk.len() as u32);
+        write_u32(&mut compressed, block.len() as u32);
+        compressed.extend_from_slice(&block);
+    }
+
+    // We account for every byte we hold on to
+    compressed.shrink_to_fit();
+    compressed
+}
+
+// Returns None if the blob is not a valid compressed blob
+pub fn decompress_blob(compressed: &Blob) -> Option<Blob> {
+    let mut blob = Blob::empty();
+    let mut reader = compressed.reader();
+
+    loop {
+        let mut header = [0; 8];
+        match reader.read(&mut header[..1]) {
+            Ok(0) => break, // No more blocks
+            Ok(_) => (),
+            Err(_) => return None,
+        }
+        if reader.read_exact(&mut header[1..]).is_err() {
+            return None;
+        }
+
+        let raw_len = parse_u32(&header[..4]) as usize;
+        let block_len = parse_u32(&header[4..]) as usize;
+
+        let mut block = vec![0; block_len];
+        if reader.read_exact(&mut block).is_err() {
+            return None;
+        }
+
+        match lz::decompress(&block, raw_len) {
+            Some(bytes) => blob.extend_from_slice(&bytes),
+            None => return None,
+        }
+    }
+
+    Some(blob)
+}
@@ -0,0 +1,34 @@
+use super::key::Key;
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CacheEventKind {
+    Evicted, // Removed to make space
+    Expired, // Found dead and dropped
+}
+
+
+// Something that happened to an item that the cache decided on by itself, as
+// opposed to what a client asked for
+#[derive(Debug, PartialEq, Clone)]
+pub struct CacheEvent {
+    pub kind: CacheEventKind,
+    pub key: Key,
+    pub size: u64, // Bytes of the value
+    pub exptime: f64, // unixtime, 0 if it didn't have one
+}
+
+impl CacheEvent {
+    pub fn new(kind: CacheEventKind,
+               key: Key,
+               size: u64,
+               exptime: f64)
+               -> CacheEvent {
+        CacheEvent {
+            kind: kind,
+            key: key,
+            size: size,
+            exptime: exptime,
+        }
+    }
+}
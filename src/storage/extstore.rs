@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+
+use common::blob::Blob;
+use common::blob::CHUNK_SIZE;
+
+use super::key::Key;
+use super::value::Value;
+
+
+// Written at the start of the file, so that we can tell our own files from
+// ones we must not overwrite
+const MAGIC: &'static [u8] = b"EMCACHE EXTSTORE\n";
+
+// A page that has less than this fraction of its bytes still in use is
+// compacted rather than thrown away when we need space
+const COMPACT_RATIO: f64 = 0.5;
+
+
+pub struct ExtStoreStats {
+    pub bytes: u64, // Bytes of live items on disk
+    pub bytes_written: u64, // Bytes written to disk since server started
+    pub compactions: u64, // Number of pages compacted
+    pub evictions: u64, // Items dropped from disk to make space
+    pub hits: u64, // Gets served from disk
+    pub items: u64, // Items currently on disk
+    pub misses: u64, // Gets that missed both memory and disk
+    pub index_bytes: u64, // Memory spent on keeping track of items on disk
+}
+
+impl ExtStoreStats {
+    pub fn new() -> ExtStoreStats {
+        ExtStoreStats {
+            bytes: 0,
+            bytes_written: 0,
+            compactions: 0,
+            evictions: 0,
+            hits: 0,
+            items: 0,
+            misses: 0,
+            index_bytes: 0,
+        }
+    }
+}
+
+
+// The disk file is split into pages. Items are appended to one page at a
+// time, and pages are reclaimed as a whole.
+struct Page {
+    live: u64, // Bytes of items still in use
+    written: u64, // Bytes appended so far
+    seq: u64, // When the page was opened, to find the oldest one
+    keys: HashSet<Key>, // Of the items still in use
+}
+
+impl Page {
+    fn new(seq: u64) -> Page {
+        Page {
+            live: 0,
+            written: 0,
+            seq: seq,
+            keys: HashSet::new(),
+        }
+    }
+}
+
+
+// What we keep in memory for an item on disk: its header (the value with the
+// item taken out) and where to find the item
+struct ExtItem {
+    header: Value,
+    page: usize,
+    offset: u64, // Within the page
+    len: u64,
+}
+
+// The memory an item on disk still takes up: its key and header, where to
+// find it and the hash, and its key again in the page it is in
+fn index_mem_size(key: &Key, item: &ExtItem) -> u64 {
+    let location = 3 * mem::size_of::<u64>() + mem::size_of::<usize>();
+    let page_key = key.mem_size() + mem::size_of::<u64>();
+    (key.mem_size() + item.header.mem_size() + location + page_key) as u64
+}
+
+
+// Holds items evicted from memory in an append-only file, similar to
+// memcached's extstore
+pub struct ExtStore {
+    file: File,
+    capacity: u64, // in bytes
+    page_size: u64, // in bytes
+    item_min: u64, // Smaller items aren't worth storing on disk, in bytes
+
+    pages: Vec<Page>,
+    free_pages: Vec<usize>,
+    write_page: Option<usize>, // The page we are appending to
+    page_seq: u64,
+
+    index: HashMap<Key, ExtItem>,
+
+    stats: ExtStoreStats,
+}
+
+impl ExtStore {
+    // Whatever an earlier run left in the file is of no use without its
+    // index, so the file is emptied. It has to be empty or one of ours to
+    // begin with, though: we don't clobber a file we were pointed at by
+    // mistake.
+    pub fn new(path: &Path, capacity: u64) -> io::Result<ExtStore> {
+        let mut file = try!(OpenOptions::new()
+                                .read(true)
+                                .write(true)
+                                .create(true)
+                                .open(path));
+
+        let mut magic = vec![];
+        try!((&mut file).take(MAGIC.len() as u64).read_to_end(&mut magic));
+        if !magic.is_empty() && magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                      format!("{} is not an extstore file",
+                                              path.display())));
+        }
+
+        try!(file.set_len(0));
+        try!(file.seek(SeekFrom::Start(0)));
+        try!(file.write_all(MAGIC));
+
+        Ok(ExtStore {
+            file: file,
+            capacity: capacity,
+            free_pages: vec![],
+            index: HashMap::new(),
+            item_min: 512, // 512b
+            page_seq: 0,
+            page_size: 8 << 20, // 8mb
+            pages: vec![],
+            stats: ExtStoreStats::new(),
+            write_page: None,
+        })
+    }
+
+    pub fn with_item_min(&mut self, item_min: u64) -> &mut ExtStore {
+        self.item_min = item_min;
+        self
+    }
+
+    pub fn with_page_size(&mut self, page_size: u64) -> &mut ExtStore {
+        self.page_size = page_size;
+        self
+    }
+
+
+    pub fn get_stats(&self) -> &ExtStoreStats {
+        &self.stats
+    }
+
+    pub fn get_stats_mut(&mut self) -> &mut ExtStoreStats {
+        &mut self.stats
+    }
+
+    fn max_pages(&self) -> usize {
+        ((self.capacity / self.page_size) as usize).max(1)
+    }
+
+
+    fn read_at(&mut self, pos: u64, len: u64) -> io::Result<Blob> {
+        try!(self.file.seek(SeekFrom::Start(pos)));
+
+        // Read it back chunk by chunk, just like it came in
+        let mut chunks = vec![];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk_len = remaining.min(CHUNK_SIZE as u64);
+            let mut chunk = vec![0; chunk_len as usize];
+            try!(self.file.read_exact(&mut chunk));
+            chunks.push(chunk);
+            remaining -= chunk_len;
+        }
+
+        Ok(Blob::from_chunks(chunks))
+    }
+
+    fn write_at(&mut self, pos: u64, blob: &Blob) -> io::Result<()> {
+        try!(self.file.seek(SeekFrom::Start(pos)));
+
+        for chunk in blob.chunks() {
+            try!(self.file.write_all(chunk));
+        }
+
+        self.stats.bytes_written += blob.len() as u64;
+        Ok(())
+    }
+
+    // Pages start right after the magic
+    fn page_pos(&self, page: usize, offset: u64) -> u64 {
+        MAGIC.len() as u64 + page as u64 * self.page_size + offset
+    }
+
+    fn read_item(&mut self, item: &ExtItem) -> io::Result<Blob> {
+        let pos = self.page_pos(item.page, item.offset);
+        self.read_at(pos, item.len)
+    }
+
+
+    // Forget about an item, giving its page back once nothing in it is used
+    fn drop_item(&mut self, key: &Key, item: &ExtItem) {
+        self.stats.bytes -= item.len;
+        self.stats.items -= 1;
+        self.stats.index_bytes -= index_mem_size(key, item);
+
+        self.pages[item.page].live -= item.len;
+        self.pages[item.page].keys.remove(key);
+        if self.pages[item.page].live == 0 &&
+           self.write_page != Some(item.page) {
+            self.free_page(item.page);
+        }
+    }
+
+    fn free_page(&mut self, page: usize) {
+        self.pages[page].written = 0;
+        self.free_pages.push(page);
+    }
+
+    fn open_page(&mut self, page: usize) {
+        self.page_seq += 1;
+        self.pages[page] = Page::new(self.page_seq);
+        self.write_page = Some(page);
+    }
+
+    fn keys_in_page(&self, page: usize) -> Vec<Key> {
+        self.pages[page].keys.iter().cloned().collect()
+    }
+
+    // Rewrite the live items of a page from the start of the page, which
+    // leaves the rest of it free for new items
+    fn compact_page(&mut self, page: usize) -> io::Result<()> {
+        let keys = self.keys_in_page(page);
+
+        let mut items = vec![];
+        for key in keys {
+            let item = self.index.remove(&key).unwrap();
+            let data = try!(self.read_item(&item));
+            self.drop_item(&key, &item);
+            items.push((key, item.header, data));
+        }
+
+        // The page may have been freed if it was empty, we'll take it anyway.
+        // What's in it is no younger than before, so it keeps its age.
+        let seq = self.pages[page].seq;
+        self.free_pages.retain(|free| *free != page);
+        self.open_page(page);
+        self.pages[page].seq = seq;
+
+        for (key, header, data) in items {
+            try!(self.append(key, header, data));
+        }
+
+        self.stats.compactions += 1;
+        Ok(())
+    }
+
+    // Throw away everything in a page
+    fn evict_page(&mut self, page: usize) {
+        for key in self.keys_in_page(page) {
+            let item = self.index.remove(&key).unwrap();
+            self.drop_item(&key, &item);
+            self.stats.evictions += 1;
+        }
+    }
+
+    // Find a page to append len bytes to
+    fn make_room(&mut self, len: u64) -> io::Result<()> {
+        // Does it fit in the current page?
+        if let Some(page) = self.write_page {
+            if self.pages[page].written + len <= self.page_size {
+                return Ok(());
+            }
+        }
+        self.write_page = None;
+
+        // Use a free page, or grow the file
+        if let Some(page) = self.free_pages.pop() {
+            self.open_page(page);
+            return Ok(());
+        }
+        if self.pages.len() < self.max_pages() {
+            self.pages.push(Page::new(0));
+            let page = self.pages.len() - 1;
+            self.open_page(page);
+            return Ok(());
+        }
+
+        // We're full: compact the emptiest page if it's mostly dead (and that
+        // makes enough room), otherwise make way by dropping the oldest page
+        let emptiest = (0..self.pages.len())
+                           .min_by_key(|page| self.pages[*page].live)
+                           .unwrap();
+        let live = self.pages[emptiest].live;
+        if (live as f64) < self.page_size as f64 * COMPACT_RATIO &&
+           live + len <= self.page_size {
+            return self.compact_page(emptiest);
+        }
+
+        let oldest = (0..self.pages.len())
+                         .min_by_key(|page| self.pages[*page].seq)
+                         .unwrap();
+        self.evict_page(oldest);
+        self.free_pages.retain(|free| *free != oldest);
+        self.open_page(oldest);
+
+        Ok(())
+    }
+
+    fn append(&mut self,
+              key: Key,
+              header: Value,
+              data: Blob)
+              -> io::Result<()> {
+        let len = data.len() as u64;
+        try!(self.make_room(len));
+
+        let page = self.write_page.unwrap();
+        let offset = self.pages[page].written;
+        let pos = self.page_pos(page, offset);
+        try!(self.write_at(pos, &data));
+
+        self.pages[page].written += len;
+        self.pages[page].live += len;
+        self.pages[page].keys.insert(key.clone());
+        self.stats.bytes += len;
+        self.stats.items += 1;
+
+        let item = ExtItem {
+            header: header,
+            page: page,
+            offset: offset,
+            len: len,
+        };
+        self.stats.index_bytes += index_mem_size(&key, &item);
+        self.index.insert(key, item);
+
+        Ok(())
+    }
+
+
+    pub fn accepts(&self, value: &Value) -> bool {
+        let len = value.len() as u64;
+        len >= self.item_min && len <= self.page_size
+    }
+
+    // The header of an item on disk, without reading the item itself
+    pub fn get_header(&self, key: &Key) -> Option<&Value> {
+        self.index.get(key).map(|item| &item.header)
+    }
+
+    pub fn get(&mut self,
+               key: &Key,
+               now: f64)
+               -> io::Result<Option<Value>> {
+        match self.index.get_mut(key) {
+            // Mark it as accessed just now
+            Some(item) => item.header.touch(now),
+            None => return Ok(None),
+        }
+
+        self.read(key)
+    }
+
+    // Reads an item back without marking it as accessed
+    fn read(&mut self, key: &Key) -> io::Result<Option<Value>> {
+        let (pos, len) = match self.index.get(key) {
+            Some(item) => (self.page_pos(item.page, item.offset), item.len),
+            None => return Ok(None),
+        };
+
+        let data = try!(self.read_at(pos, len));
+
+        let mut value = self.index.get(key).unwrap().header.clone();
+        value.restore_item(data);
+        Ok(Some(value))
+    }
+
+    // Forget about an item without reading it back
+    pub fn discard(&mut self, key: &Key) -> bool {
+        match self.index.remove(key) {
+            Some(item) => {
+                self.drop_item(key, &item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Forget about every item the predicate picks, given the key and header
+    pub fn discard_if<F>(&mut self, pred: F)
+        where F: Fn(&Key, &Value) -> bool
+    {
+        let keys: Vec<Key> = self.index
+                                 .iter()
+                                 .filter(|&(key, item)| {
+                                     pred(key, &item.header)
+                                 })
+                                 .map(|(key, _)| key.clone())
+                                 .collect();
+
+        for key in keys {
+            self.discard(&key);
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> io::Result<Option<Value>> {
+        let rv = self.read(key);
+        self.discard(key);
+        rv
+    }
+
+    // Stores a value evicted from memory
+    pub fn set(&mut self, key: Key, mut value: Value) -> io::Result<()> {
+        // Replace whatever we had for the key
+        self.discard(&key);
+
+        let data = value.take_item();
+        self.append(key, value, data)
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use super::key::Key;
+use super::value::Value;
+
+
+// Invalidates items by key prefix, by tag or all at once without touching the
+// items themselves. Every invalidation starts a new generation of the cache
+// and records it against the prefix or tag. Items are stamped with the
+// generation they were stored in, so an item is invalid if any of its prefixes
+// or tags was invalidated in a later generation than the item's own, or if
+// everything was.
+pub struct Invalidations {
+    everything: u64, // Generation all items were last invalidated in
+    generation: u64, // The current generation
+    prefixes: HashMap<Vec<u8>, u64>, // Generation a prefix was invalidated in
+    prefix_lens: BTreeSet<usize>, // So we only look up prefixes we have
+    tags: HashMap<Vec<u8>, u64>, // Generation a tag was invalidated in
+}
+
+impl Invalidations {
+    pub fn new() -> Invalidations {
+        Invalidations {
+            everything: 0,
+            generation: 0,
+            prefix_lens: BTreeSet::new(),
+            prefixes: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // The number of prefixes and tags we hold on to
+    pub fn len(&self) -> usize {
+        self.prefixes.len() + self.tags.len()
+    }
+
+    // Forget all but the newest prefix and tag invalidations. Everything
+    // stored before the ones we forget is invalidated instead, which covers
+    // whatever they invalidated.
+    pub fn retire_oldest(&mut self, keep: usize) {
+        let mut generations: Vec<u64> = self.prefixes
+                                            .values()
+                                            .chain(self.tags.values())
+                                            .cloned()
+                                            .collect();
+        if generations.len() <= keep {
+            return;
+        }
+
+        generations.sort();
+        let newest_retired = generations[generations.len() - keep - 1];
+        self.everything = self.everything.max(newest_retired);
+
+        self.prefixes.retain(|_, generation| *generation > newest_retired);
+        self.tags.retain(|_, generation| *generation > newest_retired);
+        self.prefix_lens = self.prefixes.keys().map(|p| p.len()).collect();
+    }
+
+
+    pub fn invalidate_all(&mut self) {
+        self.generation += 1;
+        self.everything = self.generation;
+    }
+
+    pub fn invalidate_prefix(&mut self, prefix: &[u8]) {
+        self.generation += 1;
+        self.prefixes.insert(prefix.to_vec(), self.generation);
+        self.prefix_lens.insert(prefix.len());
+    }
+
+    pub fn invalidate_tag(&mut self, tag: &[u8]) {
+        self.generation += 1;
+        self.tags.insert(tag.to_vec(), self.generation);
+    }
+
+    pub fn is_invalidated(&self, key: &Key, value: &Value) -> bool {
+        let stored_in = *value.get_generation();
+
+        if self.everything > stored_in {
+            return true;
+        }
+
+        for len in &self.prefix_lens {
+            if *len > key.len() {
+                break;
+            }
+
+            match self.prefixes.get(&key.item[..*len]) {
+                Some(generation) if *generation > stored_in => return true,
+                _ => (),
+            }
+        }
+
+        for tag in value.get_tags() {
+            match self.tags.get(tag) {
+                Some(generation) if *generation > stored_in => return true,
+                _ => (),
+            }
+        }
+
+        false
+    }
+}
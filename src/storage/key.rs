@@ -16,6 +16,12 @@ impl Key {
     }
 
     pub fn mem_size(&self) -> usize {
+        // Keys are immutable once stored and the cache trims them to size, so
+        // the length is also the allocated capacity
         mem::size_of::<Self>() + self.item.len()
     }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.item.shrink_to_fit();
+    }
 }
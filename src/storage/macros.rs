@@ -5,24 +5,20 @@
 macro_rules! key {
     ( $( $x:expr ),* ) => {
         {
-            let mut vec = Vec::new();
-            $(
-                vec.push($x);
-            )*
+            // vec! allocates exactly as much as it needs
+            let vec = vec![$( $x ),*];
             Key::new(vec)
         }
     };
 }
 
-// value!(1, 2, 3) => Value { item: Vec<u8> = [1, 2, 3] }
+// value!(1, 2, 3) => Value { item: Blob = [1, 2, 3] }
 macro_rules! value {
     ( $( $x:expr ),* ) => {
         {
-            let mut vec = Vec::new();
-            $(
-                vec.push($x);
-            )*
-            Value::new(vec)
+            // vec! allocates exactly as much as it needs
+            let vec = vec![$( $x ),*];
+            Value::new(Blob::new(vec))
         }
     };
 }
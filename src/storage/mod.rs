@@ -1,20 +1,38 @@
 // Declare sub modules
+#[cfg(test)]
 pub mod macros;  // must be listed first since macros are order dependent
 
 pub mod cache;
+pub mod compression;
 pub mod errors;
+pub mod events;
+pub mod extstore;
+pub mod invalidations;
 pub mod key;
+pub mod namespaces;
+pub mod policy;
+pub mod sizes;
 pub mod typedefs;
 pub mod value;
 
 // internal stuff
+#[cfg(test)]
 mod tests;  // needed to be part of the compilation unit in test mode
+#[cfg(test)]
 mod tests_bench;  // needed to be part of the compilation unit in test mode
 
 
 // Export our public api
 pub use self::cache::Cache;
+pub use self::cache::entry_mem_size;
 pub use self::errors::CacheError;
+pub use self::events::CacheEvent;
+pub use self::events::CacheEventKind;
+pub use self::extstore::ExtStore;
 pub use self::key::Key;
+pub use self::namespaces::NamespaceStats;
+pub use self::namespaces::Namespaces;
+pub use self::policy::EvictionPolicy;
+pub use self::sizes::SizeHistogram;
 pub use self::typedefs::CacheResult;
 pub use self::value::Value;
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use linked_hash_map::LinkedHashMap;
+
+use super::key::Key;
+
+
+// Beyond this many namespaces we stop keeping track of new ones (they are
+// counted as other from then on), so that clients that put random prefixes
+// on keys can't make us grow without bound
+const MAX_NAMESPACES: usize = 1024;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamespaceStats {
+    pub bytes: u64, // Bytes currently stored
+    pub evictions: u64, // Items removed to make space for new items
+    pub get_hits: u64,
+    pub get_misses: u64,
+    pub items: u64, // Items currently stored
+    pub quota: Option<u64>, // in bytes, None for no quota
+}
+
+impl NamespaceStats {
+    pub fn new(quota: Option<u64>) -> NamespaceStats {
+        NamespaceStats {
+            bytes: 0,
+            evictions: 0,
+            get_hits: 0,
+            get_misses: 0,
+            items: 0,
+            quota: quota,
+        }
+    }
+
+    // Would we be over quota with extra more bytes?
+    pub fn is_over_quota(&self, extra: u64) -> bool {
+        match self.quota {
+            Some(quota) => self.bytes + extra > quota,
+            None => false,
+        }
+    }
+}
+
+
+// Groups keys by the prefix before the delimiter, eg. "team1" for
+// "team1:user:15" with ':' as delimiter. Keys without the delimiter don't
+// belong to any namespace.
+//
+// We only start keeping track of a namespace once a key is stored in it (or
+// it is given a quota). Gets on namespaces we don't know, which is all a
+// client needs to come up with to make one, are counted as other.
+//
+// For namespaces with a quota we also keep their keys in LRU order, so that
+// one that goes over its quota can always evict its own oldest key, wherever
+// it sits in the cache.
+pub struct Namespaces {
+    delimiter: u8,
+    stats: HashMap<Vec<u8>, NamespaceStats>,
+    other: NamespaceStats, // Namespaces we don't keep track of
+    lrus: HashMap<Vec<u8>, LinkedHashMap<Key, ()>>, // Of quota namespaces
+}
+
+impl Namespaces {
+    pub fn new(delimiter: u8) -> Namespaces {
+        Namespaces {
+            delimiter: delimiter,
+            stats: HashMap::new(),
+            other: NamespaceStats::new(None),
+            lrus: HashMap::new(),
+        }
+    }
+
+    pub fn with_quota(&mut self, name: &[u8], quota: u64) -> &mut Namespaces {
+        self.stats.insert(name.to_vec(), NamespaceStats::new(Some(quota)));
+        self.lrus.insert(name.to_vec(), LinkedHashMap::new());
+        self
+    }
+
+
+    pub fn name_of<'a>(&self, key: &'a Key) -> Option<&'a [u8]> {
+        key.item
+           .iter()
+           .position(|byte| *byte == self.delimiter)
+           .map(|pos| &key.item[..pos])
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&NamespaceStats> {
+        match self.name_of(key) {
+            Some(name) => self.stats.get(name),
+            None => None,
+        }
+    }
+
+    pub fn get_other(&self) -> &NamespaceStats {
+        &self.other
+    }
+
+    // The stats of the key's namespace, or other if we don't keep track of
+    // it
+    pub fn get_mut(&mut self, key: &Key) -> Option<&mut NamespaceStats> {
+        let name = match self.name_of(key) {
+            Some(name) => name,
+            None => return None,
+        };
+
+        match self.stats.get_mut(name) {
+            Some(stats) => Some(stats),
+            None => Some(&mut self.other),
+        }
+    }
+
+    // Like get_mut, but starts keeping track of the namespace if we haven't
+    // stored anything in it before (and there is room for it)
+    pub fn get_or_insert(&mut self,
+                         key: &Key)
+                         -> Option<&mut NamespaceStats> {
+        let name = match self.name_of(key) {
+            Some(name) => name,
+            None => return None,
+        };
+
+        let is_new = !self.stats.contains_key(name);
+        if is_new && self.stats.len() < MAX_NAMESPACES {
+            self.stats.insert(name.to_vec(), NamespaceStats::new(None));
+        }
+
+        self.get_mut(key)
+    }
+
+    fn lru_mut(&mut self,
+               key: &Key)
+               -> Option<&mut LinkedHashMap<Key, ()>> {
+        match self.name_of(key) {
+            Some(name) => self.lrus.get_mut(name),
+            None => None,
+        }
+    }
+
+    // The key was stored, as the most recently used of its namespace
+    pub fn stored(&mut self, key: &Key) {
+        if let Some(lru) = self.lru_mut(key) {
+            lru.insert(key.clone(), ());
+        }
+    }
+
+    // The key was used again
+    pub fn touched(&mut self, key: &Key) {
+        if let Some(lru) = self.lru_mut(key) {
+            lru.get_refresh(key);
+        }
+    }
+
+    // The key is no longer stored
+    pub fn removed(&mut self, key: &Key) {
+        if let Some(lru) = self.lru_mut(key) {
+            lru.remove(key);
+        }
+    }
+
+    // The least recently used key of a namespace that is over its quota,
+    // counting extra more bytes towards the namespace of the incoming key.
+    // That namespace goes first.
+    pub fn oldest_over_quota(&self,
+                             incoming: &Key,
+                             extra: u64)
+                             -> Option<&Key> {
+        let incoming_name = self.name_of(incoming);
+        let over_quota = |name: &[u8]| {
+            let extra = match Some(name) == incoming_name {
+                true => extra,
+                false => 0,
+            };
+            self.stats[name].is_over_quota(extra)
+        };
+        let oldest = |name: &[u8]| {
+            self.lrus[name].front().map(|(key, _)| key)
+        };
+
+        if let Some(name) = incoming_name {
+            if self.lrus.contains_key(name) && over_quota(name) {
+                if let Some(key) = oldest(name) {
+                    return Some(key);
+                }
+            }
+        }
+
+        self.lrus
+            .keys()
+            .filter(|name| over_quota(name))
+            .filter_map(|name| oldest(name))
+            .next()
+    }
+
+    // Namespaces and their stats, ordered by name
+    pub fn iter_sorted(&self) -> Vec<(&Vec<u8>, &NamespaceStats)> {
+        let mut all: Vec<_> = self.stats.iter().collect();
+        all.sort_by(|a, b| a.0.cmp(b.0));
+        all
+    }
+}
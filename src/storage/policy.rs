@@ -0,0 +1,27 @@
+// How the cache picks the items to evict when it needs space
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EvictionPolicy {
+    // Evict the least recently used item
+    Lru,
+    // New items are kept apart from the ones that were read again since they
+    // were stored, and evicted first. A burst of keys that are read once
+    // thus can't push out the keys that are read all the time.
+    SegmentedLru,
+}
+
+impl EvictionPolicy {
+    pub fn from_name(name: &str) -> Option<EvictionPolicy> {
+        match name {
+            "lru" => Some(EvictionPolicy::Lru),
+            "slru" => Some(EvictionPolicy::SegmentedLru),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EvictionPolicy::Lru => "lru",
+            EvictionPolicy::SegmentedLru => "slru",
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+
+
+// Sizes are counted in buckets this many bytes wide, like memcached does for
+// "stats sizes"
+pub const SIZE_BUCKET: u64 = 32;
+
+
+// Keeps count of how many things (keys, items) of every size we store, with
+// sizes rounded up to the bucket they fall into
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeHistogram {
+    buckets: BTreeMap<u64, u64>, // Upper bound of the bucket -> count
+    count: u64, // Total over all buckets
+}
+
+impl SizeHistogram {
+    pub fn new() -> SizeHistogram {
+        SizeHistogram {
+            buckets: BTreeMap::new(),
+            count: 0,
+        }
+    }
+
+    fn bucket_of(size: u64) -> u64 {
+        (size + SIZE_BUCKET - 1) / SIZE_BUCKET * SIZE_BUCKET
+    }
+
+    pub fn add(&mut self, size: u64) {
+        *self.buckets.entry(SizeHistogram::bucket_of(size)).or_insert(0) += 1;
+        self.count += 1;
+    }
+
+    pub fn remove(&mut self, size: u64) {
+        let bucket = SizeHistogram::bucket_of(size);
+
+        let empty = match self.buckets.get_mut(&bucket) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => return,
+        };
+        if empty {
+            self.buckets.remove(&bucket);
+        }
+
+        self.count -= 1;
+    }
+
+
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    // Buckets that aren't empty and their counts, smallest first
+    pub fn iter(&self) -> btree_map::Iter<'_, u64, u64> {
+        self.buckets.iter()
+    }
+
+    // The bucket that the given fraction of sizes fall into or below, eg.
+    // 0.99 for the p99
+    pub fn percentile(&self, pct: f64) -> Option<u64> {
+        let rank = ((self.count as f64) * pct).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for (bucket, count) in self.buckets.iter() {
+            seen += *count;
+            if seen >= rank {
+                return Some(*bucket);
+            }
+        }
+
+        None
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.buckets.keys().next_back().cloned()
+    }
+}
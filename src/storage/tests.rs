@@ -1,10 +1,26 @@
-use platform::time::sleep_secs;
+use std::fs;
+use std::io;
+
+use common::blob::Blob;
+use common::blob::CHUNK_SIZE;
+use platform::clock::Clock;
 use platform::time::time_now;
+use testlib::clock::FakeClock;
+use testlib::tempfile::TempPath;
 
 use super::Cache;
 use super::CacheError;
+use super::CacheEventKind;
+use super::EvictionPolicy;
+use super::ExtStore;
 use super::Key;
+use super::Namespaces;
+use super::SizeHistogram;
 use super::Value;
+use super::compression::compress_blob;
+use super::compression::decompress_blob;
+use super::invalidations::Invalidations;
+use super::entry_mem_size;
 
 
 #[test]
@@ -12,7 +28,7 @@ fn test_cas_id() {
     let mut value = value!(1);
     assert_eq!(0, *value.get_cas_id());
 
-    value.set_item(vec![2]);
+    value.set_item(Blob::new(vec![2]));
     assert_eq!(1, *value.get_cas_id());
 
     value.set_flags(15);
@@ -22,7 +38,7 @@ fn test_cas_id() {
     assert_eq!(3, *value.get_cas_id());
 
     // Touch is never due to a client changing it, just us
-    value.touch();
+    value.touch(time_now());
     assert_eq!(3, *value.get_cas_id());
 }
 
@@ -52,7 +68,7 @@ fn test_set_one_key() {
     // Now fetch it
     {
         let value_found = cache.get(&key).unwrap();
-        assert_eq!(value, *value_found);
+        assert_eq!(value, value_found);
     }
 
     // Now remove it
@@ -82,7 +98,7 @@ fn test_key_not_found() {
 
 #[test]
 fn test_store_beyond_capacity_lru() {
-    let item_size = key!(1).mem_size() as u64 + value!(1).mem_size() as u64;
+    let item_size = entry_mem_size(&key!(1), &value!(1));
     let mut cache = Cache::new(item_size);
 
     // we've now reached capacity
@@ -129,7 +145,7 @@ fn test_store_beyond_capacity_lru() {
 #[test]
 fn test_multiple_evictions() {
     // Get a cache just big enough to store two items with short key/val
-    let item_size = key!(1).mem_size() as u64 + value!(1).mem_size() as u64;
+    let item_size = entry_mem_size(&key!(1), &value!(1));
     let mut cache = Cache::new(item_size * 2);
 
     // Set a key
@@ -151,6 +167,214 @@ fn test_multiple_evictions() {
     assert_eq!(cache.get_stats().evictions, 2);
 }
 
+#[test]
+fn test_evict_least_recently_used() {
+    // Get a cache just big enough to store two items with short key/val
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 2);
+
+    // Set two keys
+    cache.set(key!(1), value!(8)).unwrap();
+    cache.set(key!(2), value!(9)).unwrap();
+
+    // Access the first key so that the second becomes the oldest
+    cache.get(&key!(1)).unwrap();
+
+    // Set a third key, evicting the second
+    cache.set(key!(3), value!(7)).unwrap();
+    assert_eq!(cache.contains_key(&key!(1)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(2)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(3)).unwrap(), true);
+}
+
+#[test]
+fn test_watched_evictions_and_expiries() {
+    // Get a cache just big enough to store two items with short key/val
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 2);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
+
+    // Nothing is kept while nobody watches
+    cache.set(key!(1), value!(8)).unwrap();
+    cache.set(key!(2), value!(9)).unwrap();
+    cache.set(key!(3), value!(7)).unwrap();
+    assert!(cache.take_events().is_empty());
+
+    cache.set_watched(true);
+
+    // Evict the oldest key
+    cache.set(key!(4), value!(6)).unwrap();
+    let events = cache.take_events();
+    assert_eq!(1, events.len());
+    assert_eq!(CacheEventKind::Evicted, events[0].kind);
+    assert_eq!(key!(2), events[0].key);
+    assert_eq!(1, events[0].size);
+
+    // Find a key that expired
+    let mut value = value!(5);
+    value.set_exptime(clock.now() + 10.0);
+    cache.set(key!(5), value).unwrap();
+    cache.take_events();
+    clock.advance(20.0);
+    assert!(cache.get(&key!(5)).is_err());
+
+    let events = cache.take_events();
+    assert_eq!(1, events.len());
+    assert_eq!(CacheEventKind::Expired, events[0].kind);
+    assert_eq!(key!(5), events[0].key);
+    assert!(cache.take_events().is_empty());
+}
+
+#[test]
+fn test_lru_bump_interval() {
+    // Get a cache just big enough to store two items with short key/val
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 2);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
+    cache.with_bump_interval(60.0);
+
+    // Set two keys
+    cache.set(key!(1), value!(8)).unwrap();
+    clock.advance(1.0);
+    cache.set(key!(2), value!(9)).unwrap();
+
+    // Access the first key - it was stored too recently to be moved
+    cache.get(&key!(1)).unwrap();
+
+    // Set a third key, evicting the first
+    cache.set(key!(3), value!(7)).unwrap();
+    assert_eq!(cache.contains_key(&key!(1)).unwrap(), false);
+
+    // Once the interval has passed an access does move it
+    clock.advance(61.0);
+    cache.get(&key!(2)).unwrap();
+
+    // Set a fourth key, evicting the third
+    cache.set(key!(4), value!(6)).unwrap();
+    assert_eq!(cache.contains_key(&key!(2)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(3)).unwrap(), false);
+}
+
+#[test]
+fn test_segmented_lru_keeps_keys_read_again() {
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 4);
+    cache.with_policy(EvictionPolicy::SegmentedLru);
+
+    // Read the first key again, then store a run of keys never read again
+    cache.set(key!(1), value!(8)).unwrap();
+    cache.get(&key!(1)).unwrap();
+    for i in 2..6 {
+        cache.set(key!(i), value!(9)).unwrap();
+    }
+
+    // The oldest new key is evicted, not the least recently used one
+    assert_eq!(cache.len(), 4);
+    assert_eq!(cache.get_stats().evictions, 1);
+    assert_eq!(cache.contains_key(&key!(1)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(2)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(5)).unwrap(), true);
+}
+
+#[test]
+fn test_segmented_lru_demotes_protected_keys() {
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 4);
+    cache.with_policy(EvictionPolicy::SegmentedLru);
+
+    // Read all of them again, more than the protected segment can hold, so
+    // the first one goes back on probation
+    for i in 1..5 {
+        cache.set(key!(i), value!(8)).unwrap();
+    }
+    for i in 1..5 {
+        cache.get(&key!(i)).unwrap();
+    }
+
+    // Which makes it the first to go
+    cache.set(key!(5), value!(9)).unwrap();
+    assert_eq!(cache.len(), 4);
+    assert_eq!(cache.contains_key(&key!(1)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(2)).unwrap(), true);
+
+    // Removing keys works the same on either segment
+    cache.remove(&key!(2)).unwrap();
+    cache.remove(&key!(5)).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get_stats().bytes, item_size * 2);
+}
+
+#[test]
+fn test_set_capacity_shrink_in_batches() {
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 10);
+    cache.with_shrink_batch_size(3);
+
+    // Fill the cache
+    for i in 0..10 {
+        cache.set(key!(i), value!(i)).unwrap();
+    }
+    assert_eq!(cache.len(), 10);
+
+    // Shrink to half the size - the first batch is evicted right away
+    cache.set_capacity(item_size * 5);
+    assert_eq!(cache.capacity, item_size * 5);
+    assert_eq!(cache.len(), 7);
+    assert!(cache.is_over_capacity());
+
+    // A set works off the rest of the excess, on top of making room for
+    // itself
+    cache.set(key!(10), value!(10)).unwrap();
+    assert_eq!(cache.len(), 5);
+    assert!(!cache.is_over_capacity());
+
+    // The oldest items went first
+    for i in 0..6 {
+        assert_eq!(cache.contains_key(&key!(i)).unwrap(), false);
+    }
+    for i in 6..11 {
+        assert_eq!(cache.contains_key(&key!(i)).unwrap(), true);
+    }
+}
+
+#[test]
+fn test_set_capacity_shrink_on_reads() {
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 10);
+    cache.with_shrink_batch_size(3);
+
+    for i in 0..10 {
+        cache.set(key!(i), value!(i)).unwrap();
+    }
+
+    cache.set_capacity(item_size * 5);
+    assert_eq!(cache.len(), 7);
+
+    // Without any sets the excess is worked off by gets and deletes
+    cache.get(&key!(9)).unwrap();
+    assert_eq!(cache.len(), 5);
+    assert!(!cache.is_over_capacity());
+
+    cache.remove(&key!(9)).unwrap();
+    assert_eq!(cache.len(), 4);
+}
+
+#[test]
+fn test_set_capacity_grow() {
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size);
+
+    cache.set(key!(1), value!(1)).unwrap();
+
+    // Grow the cache - there is room for a second item now
+    cache.set_capacity(item_size * 2);
+    cache.set(key!(2), value!(2)).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get_stats().evictions, 0);
+}
+
 #[test]
 fn test_exceed_item_size_limits() {
     let mut cache = Cache::new(1024);
@@ -214,7 +438,7 @@ fn test_key_explicit_exptime() {
     let key = key!(1);
     let mut value = value!(9);
     // set exptime in the past
-    value.set_exptime(time_now() - 1.0);
+    value.set_exptime(cache.get_clock().now() - 1.0);
 
     // set a key
     let rv = cache.set(key.clone(), value);
@@ -225,12 +449,12 @@ fn test_key_explicit_exptime() {
     assert_eq!(rv.unwrap_err(), CacheError::KeyNotFound);
 }
 
-// this is a slow test that relies on sleeps
-#[ignore]
 #[test]
 fn test_key_kept_alive_on_access() {
     // our cache has a lifetime of 2 secs
     let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
     cache.with_item_lifetime(2.0);
 
     let key = key!(1);
@@ -239,58 +463,117 @@ fn test_key_kept_alive_on_access() {
     let rv = cache.set(key.clone(), value.clone());
     assert!(rv.is_ok());
 
-    // sleep 1.5 secs - not long enough to expire key
-    sleep_secs(1.5);
+    // wait 1.5 secs - not long enough to expire key
+    clock.advance(1.5);
 
     // access key - it's there
     assert!(cache.get(&key).is_ok());
 
-    // sleep 1 secs - not long enough to expire key
-    sleep_secs(1.0);
+    // wait 1 secs - not long enough to expire key
+    clock.advance(1.0);
 
     // access key - it's now been 2.5s since it was set, but it's been accessed
     // so we've kept it alive
     assert!(cache.get(&key).is_ok());
 
-    // sleep 2.5 secs - long enough to expire key
-    sleep_secs(2.5);
+    // wait 2.5 secs - long enough to expire key
+    clock.advance(2.5);
 
     // access key - it's gone
     assert!(cache.get(&key).is_err());
 }
 
-// this is a slow test that relies on sleeps
-#[ignore]
 #[test]
 fn test_flush_all() {
+    let mut cache = Cache::new(4096);
+
+    // fill up the cache
+    for i in 0..10 {
+        let rv = cache.set(key!(i), value!(i));
+        assert!(rv.is_ok());
+    }
+    assert_eq!(10, cache.len());
+
+    // make all items dead right away
+    cache.flush_all().unwrap();
+
+    // they're gone, and so is the memory they held on to
+    assert!(cache.get(&key!(1)).is_err());
+    assert_eq!(0, cache.len());
+    assert_eq!(0, cache.get_stats().bytes);
+
+    // set a new item that came after flush_all - it's alive
+    let rv = cache.set(key!(1), value!(2));
+    assert!(rv.is_ok());
+    assert_eq!(value!(2), cache.get(&key!(1)).unwrap());
+}
+
+#[test]
+fn test_flush_all_reclaims_in_batches() {
+    let mut cache = Cache::new(4096);
+    cache.with_shrink_batch_size(2);
+
+    for i in 0..10 {
+        let rv = cache.set(key!(i), value!(i));
+        assert!(rv.is_ok());
+    }
+
+    // the flush itself reclaims a batch
+    cache.flush_all().unwrap();
+    assert_eq!(8, cache.len());
+
+    // and so does every operation after it
+    assert!(cache.get(&key!(42)).is_err());
+    assert_eq!(6, cache.len());
+}
+
+#[test]
+fn test_flush_all_at_past_deadline() {
+    let mut cache = Cache::new(1024);
+
+    let rv = cache.set(key!(1), value!(1));
+    assert!(rv.is_ok());
+
+    // the item was accessed after the deadline, so it lives on
+    cache.flush_all_at(cache.get_clock().now() - 10.0).unwrap();
+    assert!(cache.get(&key!(1)).is_ok());
+}
+
+#[test]
+fn test_flush_all_at() {
     // our cache has a lifetime of 2 secs
     let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
     cache.with_item_lifetime(2.0);
 
     // this item lives for 3s
     let key1 = key!(1);
     let mut value1 = value!(9);
-    value1.set_exptime(time_now() + 3.0);
+    value1.set_exptime(clock.now() + 3.0);
     let rv = cache.set(key1.clone(), value1.clone());
     assert!(rv.is_ok());
 
-    // this item lives until cache lifetime
+    // make all items dead in one second
+    cache.flush_all_at(clock.now() + 1.0).unwrap();
+
+    // the flush hasn't happened yet
+    assert!(cache.get(&key1).is_ok());
+
+    // this item was set after flush_all but before it kicks in
     let key2 = key!(2);
     let value2 = value!(8);
     let rv = cache.set(key2.clone(), value2.clone());
     assert!(rv.is_ok());
 
-    // make all items dead in one second
-    cache.flush_all(time_now() + 1.0).unwrap();
-
-    // sleep until flush time kicks in
-    sleep_secs(1.5);
+    // wait until flush time kicks in
+    clock.advance(1.5);
 
     // access both keys - both have expired
     assert!(cache.get(&key1).is_err());
     assert!(cache.get(&key2).is_err());
 
-    // set a new item that came after flush_all
+    // set a new item that came after the flush kicked in
     let key3 = key!(3);
     let value3 = value!(7);
     let rv = cache.set(key3.clone(), value3.clone());
@@ -300,12 +583,627 @@ fn test_flush_all() {
     assert!(cache.get(&key3).is_ok());
 }
 
+#[test]
+fn test_flush_all_at_replaces_pending() {
+    let mut cache = Cache::new(1024);
+    let clock = FakeClock::new();
+    cache.with_clock(Box::new(clock.clone()));
+
+    // schedule a flush, then put it off
+    cache.flush_all_at(clock.now() + 1.0).unwrap();
+    cache.flush_all_at(clock.now() + 2.0).unwrap();
+
+    let rv = cache.set(key!(1), value!(1));
+    assert!(rv.is_ok());
+
+    // the first deadline passes without a flush
+    clock.advance(1.5);
+    assert!(cache.get(&key!(1)).is_ok());
+
+    // the second one flushes
+    clock.advance(1.0);
+    assert!(cache.get(&key!(1)).is_err());
+
+    // a flush right away calls off a pending one
+    cache.flush_all_at(clock.now() + 1.0).unwrap();
+    cache.flush_all().unwrap();
+    let rv = cache.set(key!(2), value!(2));
+    assert!(rv.is_ok());
+    clock.advance(1.5);
+    assert!(cache.get(&key!(2)).is_ok());
+}
+
+#[test]
+fn test_bytes_include_overallocation() {
+    let mut cache = Cache::new(1024);
+
+    // A value that has reserved much more space than it uses
+    let mut item = Vec::with_capacity(200);
+    item.push(1);
+    let value = Value::new(Blob::new(item));
+
+    // All of the reserved space is counted
+    let item_size = entry_mem_size(&key!(1), &value);
+    assert!(item_size > 200);
+    cache.set(key!(1), value).unwrap();
+    assert_eq!(cache.get_stats().bytes, item_size);
+}
+
+#[test]
+fn test_bytes_overwrite_key() {
+    let item_size = entry_mem_size(&key!(1), &value!(1, 2));
+    let mut cache = Cache::new(item_size);
+
+    // Set a key that fills the whole cache
+    cache.set(key!(1), value!(2, 3)).unwrap();
+    assert_eq!(cache.get_stats().bytes, item_size);
+
+    // Overwrite it with a value of the same size - nothing is evicted
+    cache.set(key!(1), value!(4, 5)).unwrap();
+    assert_eq!(cache.get_stats().bytes, item_size);
+    assert_eq!(cache.get_stats().evictions, 0);
+    assert_eq!(cache.get(&key!(1)).unwrap(), value!(4, 5));
+}
+
+#[test]
+fn test_size_histogram() {
+    let mut sizes = SizeHistogram::new();
+    assert_eq!(sizes.percentile(0.5), None);
+    assert_eq!(sizes.max(), None);
+
+    // Sizes are rounded up to their bucket
+    for size in &[1, 30, 32, 33, 100, 1000] {
+        sizes.add(*size);
+    }
+    let buckets: Vec<(u64, u64)> = sizes.iter()
+                                        .map(|(size, count)| (*size, *count))
+                                        .collect();
+    assert_eq!(buckets, vec![(32, 3), (64, 1), (128, 1), (1024, 1)]);
+    assert_eq!(sizes.len(), 6);
+
+    assert_eq!(sizes.percentile(0.0), Some(32));
+    assert_eq!(sizes.percentile(0.5), Some(32));
+    assert_eq!(sizes.percentile(0.6), Some(64));
+    assert_eq!(sizes.percentile(0.99), Some(1024));
+    assert_eq!(sizes.max(), Some(1024));
+
+    // Empty buckets go away
+    sizes.remove(1000);
+    sizes.remove(100);
+    assert_eq!(sizes.len(), 4);
+    assert_eq!(sizes.max(), Some(64));
+}
+
+#[test]
+fn test_sizes_follow_items() {
+    let item_size = entry_mem_size(&key!(1), &value!(1));
+    let mut cache = Cache::new(item_size * 2);
+
+    // Set two keys
+    cache.set(key!(1), value!(1)).unwrap();
+    cache.set(key!(2), value!(2)).unwrap();
+    assert_eq!(cache.get_key_sizes().len(), 2);
+    assert_eq!(cache.get_item_sizes().len(), 2);
+    assert_eq!(cache.get_key_sizes().max(), Some(32));
+
+    // Overwrite one
+    cache.set(key!(2), value!(3)).unwrap();
+    assert_eq!(cache.get_item_sizes().len(), 2);
+
+    // Set a third key, evicting the first
+    cache.set(key!(3), value!(3)).unwrap();
+    assert_eq!(cache.get_key_sizes().len(), 2);
+    assert_eq!(cache.get_item_sizes().len(), 2);
+
+    // Remove them all
+    cache.remove(&key!(2)).unwrap();
+    cache.remove(&key!(3)).unwrap();
+    assert_eq!(cache.get_key_sizes().len(), 0);
+    assert_eq!(cache.get_item_sizes().len(), 0);
+    assert_eq!(cache.get_item_sizes().max(), None);
+}
+
+#[test]
+fn test_hash_bytes() {
+    let mut cache = Cache::new(1024);
+    assert_eq!(cache.hash_bytes(), 0);
+
+    // The hash table is allocated on first insert
+    cache.set(key!(1), value!(2)).unwrap();
+    assert!(cache.hash_bytes() > 0);
+}
+
+#[test]
+fn test_compress_blob_roundtrip() {
+    // Several chunks of compressible data
+    let mut bytes = vec![];
+    while bytes.len() < CHUNK_SIZE * 2 {
+        bytes.extend_from_slice(b"{\"key\": \"value\"}, ");
+    }
+    let blob = Blob::from_slice(&bytes);
+
+    let compressed = compress_blob(&blob);
+    assert!(compressed.len() < blob.len() / 5);
+    assert_eq!(decompress_blob(&compressed).unwrap(), blob);
+
+    // Garbage is detected
+    assert!(decompress_blob(&Blob::new(vec![9, 0, 0, 0, 1, 0, 0, 0, 7]))
+                .is_none());
+}
+
+#[test]
+fn test_compress_above_threshold() {
+    let mut cache = Cache::new(1 << 20);
+    cache.with_compress_threshold(100);
+
+    let bytes = vec![b'a'; 1000];
+    let value = Value::new(Blob::new(bytes.clone()));
+    let raw_size = entry_mem_size(&key!(1), &value);
+    cache.set(key!(1), value).unwrap();
+
+    // Stored compressed and accounted for as such
+    assert!(cache.get_stats().bytes < raw_size);
+    assert_eq!(cache.get_stats().compress_raw_bytes, 1000);
+    assert!(cache.get_stats().compress_ratio() > 10.0);
+
+    // But handed back as it was
+    let value = cache.get(&key!(1)).unwrap();
+    assert!(!value.is_compressed());
+    assert_eq!(*value.get_item(), bytes);
+    let value = cache.remove(&key!(1)).unwrap();
+    assert_eq!(*value.get_item(), bytes);
+    assert_eq!(cache.get_stats().bytes, 0);
+
+    // Small values are left alone
+    cache.set(key!(2), value!(1, 2, 3)).unwrap();
+    assert_eq!(cache.get_stats().bytes,
+               entry_mem_size(&key!(2), &value!(1, 2, 3)));
+}
+
+#[test]
+fn test_compress_skips_incompressible() {
+    let mut cache = Cache::new(1 << 20);
+    cache.with_compress_threshold(100);
+
+    // No repetition to speak of
+    let bytes: Vec<u8> = (0..256).map(|i| i as u8).collect();
+    cache.set(key!(1), Value::new(Blob::new(bytes.clone()))).unwrap();
+
+    assert_eq!(cache.get_stats().compress_raw_bytes, 0);
+    assert_eq!(cache.get_stats().compress_ratio(), 0.0);
+    assert_eq!(*cache.get(&key!(1)).unwrap().get_item(), bytes);
+}
+
+fn value_of_size(size: usize, byte: u8) -> Value {
+    Value::new(Blob::new(vec![byte; size]))
+}
+
+#[test]
+fn test_ext_spill_and_read_back() {
+    let path = TempPath::new("emcache-test-ext");
+    let mut extstore = ExtStore::new(path.as_path(), 1 << 20).unwrap();
+    extstore.with_item_min(50);
+
+    let item_size = entry_mem_size(&key!(1), &value_of_size(100, 1));
+    let mut cache = Cache::new(item_size * 2);
+    cache.with_extstore(extstore);
+
+    // The first key is evicted to disk
+    cache.set(key!(1), value_of_size(100, 1)).unwrap();
+    cache.set(key!(2), value_of_size(100, 2)).unwrap();
+    cache.set(key!(3), value_of_size(100, 3)).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get_ext_stats().unwrap().items, 1);
+    assert_eq!(cache.get_ext_stats().unwrap().bytes, 100);
+
+    // But we can still get it
+    assert_eq!(cache.contains_key(&key!(1)).unwrap(), true);
+    let value = cache.get(&key!(1)).unwrap();
+    assert_eq!(value, value_of_size(100, 1));
+    assert_eq!(cache.get_ext_stats().unwrap().hits, 1);
+
+    // A key we've never seen misses both
+    cache.get(&key!(9)).unwrap_err();
+    assert_eq!(cache.get_ext_stats().unwrap().misses, 1);
+
+    // Small items are not worth putting on disk
+    cache.set(key!(4), value_of_size(10, 4)).unwrap();
+    cache.set(key!(5), value_of_size(100, 5)).unwrap();
+    cache.set(key!(6), value_of_size(100, 6)).unwrap();
+    assert_eq!(cache.get_ext_stats().unwrap().items, 3);
+    assert!(cache.get(&key!(4)).is_err());
+}
+
+#[test]
+fn test_ext_remove_and_overwrite() {
+    let path = TempPath::new("emcache-test-ext");
+    let mut extstore = ExtStore::new(path.as_path(), 1 << 20).unwrap();
+    extstore.with_item_min(50);
+
+    let item_size = entry_mem_size(&key!(1), &value_of_size(100, 1));
+    let mut cache = Cache::new(item_size);
+    cache.with_extstore(extstore);
+
+    // Evict the key to disk and remove it from there
+    cache.set(key!(1), value_of_size(100, 1)).unwrap();
+    cache.set(key!(2), value_of_size(100, 2)).unwrap();
+    assert_eq!(cache.remove(&key!(1)).unwrap(), value_of_size(100, 1));
+    assert_eq!(cache.get_ext_stats().unwrap().items, 0);
+    assert!(cache.remove(&key!(1)).is_err());
+
+    // Setting a key drops the copy on disk, so a stale version can't
+    // resurface once the new one is deleted
+    cache.set(key!(3), value_of_size(100, 3)).unwrap();
+    assert_eq!(cache.get_ext_stats().unwrap().items, 1); // key 2
+    cache.set(key!(2), value_of_size(100, 7)).unwrap();
+    cache.remove(&key!(2)).unwrap();
+    assert!(cache.get(&key!(2)).is_err());
+}
+
+#[test]
+fn test_ext_compact_mostly_dead_page() {
+    let path = TempPath::new("emcache-test-ext");
+    let mut extstore = ExtStore::new(path.as_path(), 2000).unwrap();
+    extstore.with_item_min(1).with_page_size(1000);
+
+    // Fill both pages
+    for i in 0..6 {
+        extstore.set(key!(i), value_of_size(300, i)).unwrap();
+    }
+
+    // Most of the first page is now dead
+    extstore.discard(&key!(0));
+    extstore.discard(&key!(1));
+
+    // So it gets compacted to make room
+    extstore.set(key!(6), value_of_size(300, 6)).unwrap();
+    assert_eq!(extstore.get_stats().compactions, 1);
+    assert_eq!(extstore.get_stats().evictions, 0);
+    assert_eq!(extstore.get_stats().items, 5);
+
+    for i in 2..7 {
+        let value = extstore.get(&key!(i), time_now()).unwrap().unwrap();
+        assert_eq!(value, value_of_size(300, i));
+    }
+}
+
+#[test]
+fn test_ext_evict_oldest_page() {
+    let path = TempPath::new("emcache-test-ext");
+    let mut extstore = ExtStore::new(path.as_path(), 2000).unwrap();
+    extstore.with_item_min(1).with_page_size(1000);
+
+    // Fill both pages
+    for i in 0..6 {
+        extstore.set(key!(i), value_of_size(300, i)).unwrap();
+    }
+
+    // Nothing is worth compacting, so the oldest page goes
+    extstore.set(key!(6), value_of_size(300, 6)).unwrap();
+    assert_eq!(extstore.get_stats().compactions, 0);
+    assert_eq!(extstore.get_stats().evictions, 3);
+    assert_eq!(extstore.get_stats().bytes, 1200);
+
+    for i in 0..3 {
+        assert!(extstore.get(&key!(i), time_now()).unwrap().is_none());
+    }
+    for i in 3..7 {
+        let value = extstore.get(&key!(i), time_now()).unwrap().unwrap();
+        assert_eq!(value, value_of_size(300, i));
+    }
+}
+
+#[test]
+fn test_ext_refuses_foreign_file() {
+    let path = TempPath::new("emcache-test-ext");
+    fs::write(path.as_path(), b"precious").unwrap();
+
+    // The file is left as it was
+    let err = ExtStore::new(path.as_path(), 1 << 20).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read(path.as_path()).unwrap(), b"precious");
+
+    // But one of our own is reused
+    fs::remove_file(path.as_path()).unwrap();
+    let mut extstore = ExtStore::new(path.as_path(), 1 << 20).unwrap();
+    extstore.with_item_min(1);
+    extstore.set(key!(1), value_of_size(300, 1)).unwrap();
+    drop(extstore);
+
+    let mut extstore = ExtStore::new(path.as_path(), 1 << 20).unwrap();
+    assert!(extstore.get(&key!(1), time_now()).unwrap().is_none());
+}
+
+#[test]
+fn test_ext_index_counts_as_hash_bytes() {
+    let path = TempPath::new("emcache-test-ext");
+    let mut extstore = ExtStore::new(path.as_path(), 1 << 20).unwrap();
+    extstore.with_item_min(50);
+
+    let item_size = entry_mem_size(&key!(1), &value_of_size(100, 1));
+    let mut cache = Cache::new(item_size);
+    cache.with_extstore(extstore);
+
+    cache.set(key!(1), value_of_size(100, 1)).unwrap();
+    let hash_bytes = cache.hash_bytes();
+
+    // The key and header of the item on disk stay in memory
+    cache.set(key!(2), value_of_size(100, 2)).unwrap();
+    let index_bytes = cache.get_ext_stats().unwrap().index_bytes;
+    assert!(index_bytes > 0);
+    assert_eq!(cache.hash_bytes(), hash_bytes + index_bytes);
+
+    cache.remove(&key!(1)).unwrap();
+    assert_eq!(cache.get_ext_stats().unwrap().index_bytes, 0);
+    assert_eq!(cache.hash_bytes(), hash_bytes);
+}
+
+#[test]
+fn test_invalidate_prefix() {
+    let mut cache = Cache::new(1024);
+
+    cache.set(key!(1, 2, 3), value!(1)).unwrap();
+    cache.set(key!(1, 2, 4), value!(1)).unwrap();
+    cache.set(key!(1, 3, 3), value!(1)).unwrap();
+
+    cache.invalidate_prefix(&[1, 2]).unwrap();
+    assert_eq!(cache.get(&key!(1, 2, 3)), Err(CacheError::KeyNotFound));
+    assert_eq!(cache.get(&key!(1, 2, 4)), Err(CacheError::KeyNotFound));
+    assert_eq!(cache.get(&key!(1, 3, 3)), Ok(value!(1)));
+
+    // Keys set after the invalidation are not affected
+    cache.set(key!(1, 2, 3), value!(2)).unwrap();
+    assert_eq!(cache.get(&key!(1, 2, 3)), Ok(value!(2)));
+
+    // A whole key is a prefix of itself
+    cache.invalidate_prefix(&[1, 2, 3]).unwrap();
+    assert_eq!(cache.get(&key!(1, 2, 3)), Err(CacheError::KeyNotFound));
+}
+
+#[test]
+fn test_invalidate_tag() {
+    let mut cache = Cache::new(1024);
+
+    let mut value = value!(1);
+    value.add_tag(vec![9]);
+    cache.set(key!(1), value).unwrap();
+    cache.set(key!(2), value!(2)).unwrap();
+
+    cache.invalidate_tag(&[9]).unwrap();
+    assert_eq!(cache.get(&key!(1)), Err(CacheError::KeyNotFound));
+    assert_eq!(cache.get(&key!(2)), Ok(value!(2)));
+
+    // An invalidated item can't be taken out to be stored again either
+    let mut value = value!(3);
+    value.add_tag(vec![9]);
+    cache.set(key!(3), value).unwrap();
+    cache.invalidate_tag(&[9]).unwrap();
+    assert_eq!(cache.remove(&key!(3)), Err(CacheError::KeyNotFound));
+}
+
+#[test]
+fn test_invalidate_retires_oldest_when_too_many() {
+    let mut cache = Cache::new(1024);
+
+    cache.set(key!(1, 1), value!(1)).unwrap();
+    cache.set(key!(2, 1), value!(2)).unwrap();
+    cache.set(key!(3, 1), value!(3)).unwrap();
+    cache.invalidate_prefix(&[1]).unwrap();
+
+    // The dead item was the oldest one, so it's reclaimed right away
+    assert_eq!(cache.len(), 2);
+
+    // Once we've remembered enough invalidations we let go of the oldest,
+    // and with them of the items that were stored before them: the dead one
+    // and the one nobody read since. The one that is read stays.
+    for i in 0..10000 {
+        let tag = format!("tag{}", i).into_bytes();
+        cache.invalidate_tag(&tag).unwrap();
+        cache.get(&key!(2, 1)).unwrap();
+    }
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get_stats().bytes,
+               entry_mem_size(&key!(2, 1), &value!(2)));
+    assert_eq!(cache.get(&key!(1, 1)), Err(CacheError::KeyNotFound));
+    assert_eq!(cache.get(&key!(3, 1)), Err(CacheError::KeyNotFound));
+    assert_eq!(cache.get(&key!(2, 1)), Ok(value!(2)));
+}
+
+#[test]
+fn test_invalidations_retire_oldest() {
+    let mut invalidations = Invalidations::new();
+
+    // One item stored before any of the invalidations, one half way
+    let older = value!(1);
+    invalidations.invalidate_prefix(&[1]);
+    invalidations.invalidate_tag(&[8]);
+    let mut newer = value!(1);
+    newer.add_tag(vec![9]);
+    newer.set_generation(invalidations.generation());
+    invalidations.invalidate_prefix(&[2]);
+    invalidations.invalidate_tag(&[9]);
+    assert!(!invalidations.is_invalidated(&key!(3), &older));
+
+    invalidations.retire_oldest(2);
+    assert_eq!(invalidations.len(), 2);
+
+    // Everything from before the retired ones is gone, the newer ones still
+    // apply as they did
+    assert!(invalidations.is_invalidated(&key!(3), &older));
+    assert!(invalidations.is_invalidated(&key!(3), &newer));
+    newer.clear_tags();
+    assert!(!invalidations.is_invalidated(&key!(3), &newer));
+    assert!(invalidations.is_invalidated(&key!(2, 1), &newer));
+}
+
+#[test]
+fn test_namespace_stats() {
+    let mut namespaces = Namespaces::new(b':');
+    namespaces.with_quota(b"a", 1000);
+    let mut cache = Cache::new(1024);
+    cache.with_namespaces(namespaces);
+
+    let item_size = entry_mem_size(&key!(b'a', b':', 1), &value!(1));
+
+    // Two namespaced keys and one without a namespace
+    cache.set(key!(b'a', b':', 1), value!(1)).unwrap();
+    cache.set(key!(b'b', b':', 1), value!(1)).unwrap();
+    cache.set(key!(1), value!(1)).unwrap();
+
+    cache.get(&key!(b'a', b':', 1)).unwrap();
+    cache.get(&key!(b'a', b':', 2)).unwrap_err();
+
+    {
+        let namespaces = cache.get_namespaces().unwrap();
+        assert_eq!(namespaces.iter_sorted().len(), 2);
+
+        let ns = namespaces.get(&key!(b'a', b':', 9)).unwrap();
+        assert_eq!(ns.bytes, item_size);
+        assert_eq!(ns.items, 1);
+        assert_eq!(ns.get_hits, 1);
+        assert_eq!(ns.get_misses, 1);
+        assert_eq!(ns.quota, Some(1000));
+
+        let ns = namespaces.get(&key!(b'b', b':', 9)).unwrap();
+        assert_eq!(ns.bytes, item_size);
+        assert_eq!(ns.items, 1);
+        assert_eq!(ns.quota, None);
+    }
+
+    // Removing the key gives the bytes back to its namespace
+    cache.remove(&key!(b'a', b':', 1)).unwrap();
+    let ns = cache.get_namespaces().unwrap().get(&key!(b'a', b':')).unwrap();
+    assert_eq!(ns.bytes, 0);
+    assert_eq!(ns.items, 0);
+}
+
+#[test]
+fn test_namespace_gets_dont_create_namespaces() {
+    let mut cache = Cache::new(1024);
+    cache.with_namespaces(Namespaces::new(b':'));
+
+    // Gets on any number of namespaces we've never stored anything in
+    for i in 0..2000u32 {
+        let key = Key::new(format!("{}:x", i).into_bytes());
+        cache.get(&key).unwrap_err();
+    }
+
+    {
+        let namespaces = cache.get_namespaces().unwrap();
+        assert_eq!(namespaces.iter_sorted().len(), 0);
+        assert_eq!(namespaces.get_other().get_misses, 2000);
+    }
+
+    // So there is still room for the ones that are used
+    cache.set(key!(b'a', b':', 1), value!(1)).unwrap();
+    cache.get(&key!(b'a', b':', 1)).unwrap();
+    let namespaces = cache.get_namespaces().unwrap();
+    assert_eq!(namespaces.iter_sorted().len(), 1);
+    assert_eq!(namespaces.get(&key!(b'a', b':')).unwrap().get_hits, 1);
+}
+
+#[test]
+fn test_namespace_evict_own_keys_over_quota() {
+    let item_size = entry_mem_size(&key!(b'a', b':', 1), &value!(1));
+
+    // Room for four items, but only two of them for namespace a
+    let mut namespaces = Namespaces::new(b':');
+    namespaces.with_quota(b"a", item_size * 2);
+    let mut cache = Cache::new(item_size * 4);
+    cache.with_namespaces(namespaces);
+
+    cache.set(key!(b'b', b':', 1), value!(1)).unwrap();
+    cache.set(key!(b'b', b':', 2), value!(1)).unwrap();
+    cache.set(key!(b'a', b':', 1), value!(1)).unwrap();
+    cache.set(key!(b'a', b':', 2), value!(1)).unwrap();
+
+    // A third key for a makes it evict its own oldest key rather than b's
+    cache.set(key!(b'a', b':', 3), value!(1)).unwrap();
+    {
+        let namespaces = cache.get_namespaces().unwrap();
+        let ns = namespaces.get(&key!(b'a', b':')).unwrap();
+        assert_eq!(ns.evictions, 1);
+        assert_eq!(ns.bytes, item_size * 2);
+        let ns = namespaces.get(&key!(b'b', b':')).unwrap();
+        assert_eq!(ns.evictions, 0);
+        assert_eq!(ns.items, 2);
+    }
+
+    // Nobody is over quota, so another namespace evicts the oldest key
+    cache.set(key!(b'c', b':', 1), value!(1)).unwrap();
+    assert_eq!(cache.contains_key(&key!(b'b', b':', 1)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(b'b', b':', 2)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 1)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 2)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 3)).unwrap(), true);
+}
+
+#[test]
+fn test_namespace_evict_own_keys_near_head() {
+    let item_size = entry_mem_size(&key!(b'a', b':', 1), &value!(1));
+
+    // Room for 200 items, but only 10 of them for namespace a
+    let mut namespaces = Namespaces::new(b':');
+    namespaces.with_quota(b"a", item_size * 10);
+    let mut cache = Cache::new(item_size * 200);
+    cache.with_namespaces(namespaces);
+
+    // b fills up most of the cache, then a bulk loads far over its quota
+    // while there is still room, so all of its keys are near the LRU head
+    for i in 0..150 {
+        cache.set(key!(b'b', b':', i), value!(1)).unwrap();
+    }
+    for i in 0..50 {
+        cache.set(key!(b'a', b':', i), value!(1)).unwrap();
+    }
+
+    // Once the cache is full a still pays for the space, oldest key first
+    cache.set(key!(b'c', b':', 1), value!(1)).unwrap();
+    cache.set(key!(b'c', b':', 2), value!(1)).unwrap();
+    {
+        let namespaces = cache.get_namespaces().unwrap();
+        let ns = namespaces.get(&key!(b'a', b':')).unwrap();
+        assert_eq!(ns.evictions, 2);
+        assert_eq!(ns.items, 48);
+        let ns = namespaces.get(&key!(b'b', b':')).unwrap();
+        assert_eq!(ns.evictions, 0);
+        assert_eq!(ns.items, 150);
+    }
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 0)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 1)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 2)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(b'b', b':', 0)).unwrap(), true);
+}
+
+#[test]
+fn test_namespace_evict_over_quota_first() {
+    let item_size = entry_mem_size(&key!(b'a', b':', 1), &value!(1));
+
+    // Quotas are soft: a can go over its quota while there is room
+    let mut namespaces = Namespaces::new(b':');
+    namespaces.with_quota(b"a", item_size);
+    let mut cache = Cache::new(item_size * 4);
+    cache.with_namespaces(namespaces);
+
+    cache.set(key!(b'b', b':', 1), value!(1)).unwrap();
+    cache.set(key!(b'a', b':', 1), value!(1)).unwrap();
+    cache.set(key!(b'a', b':', 2), value!(1)).unwrap();
+    cache.set(key!(b'b', b':', 2), value!(1)).unwrap();
+    assert_eq!(cache.len(), 4);
+
+    // But once space runs out a pays for it, not the older key of b
+    cache.set(key!(b'b', b':', 3), value!(1)).unwrap();
+    assert_eq!(cache.contains_key(&key!(b'b', b':', 1)).unwrap(), true);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 1)).unwrap(), false);
+    assert_eq!(cache.contains_key(&key!(b'a', b':', 2)).unwrap(), true);
+}
+
 #[test]
 fn test_metrics() {
     // NOTE: The most crucial metric is bytes, so make sure to test every data
     // path that affects it.
 
-    let item_size = key!(1).mem_size() as u64 + value!(1, 2).mem_size() as u64;
+    let item_size = entry_mem_size(&key!(1), &value!(1, 2));
     let mut cache = Cache::new(item_size);
     assert_eq!(cache.get_stats().bytes, 0);
     assert_eq!(cache.get_stats().total_items, 0);
@@ -372,7 +1270,7 @@ fn test_metrics() {
 
     // Create an expired value
     let mut value = value!(11, 12);
-    value.set_exptime(time_now() - 1.0);
+    value.set_exptime(cache.get_clock().now() - 1.0);
 
     // Set a key that expires immediately
     cache.set(key!(9), value).unwrap();
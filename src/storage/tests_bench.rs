@@ -1,5 +1,7 @@
 use test::Bencher;
 
+use common::blob::Blob;
+
 use super::Cache;
 use super::Key;
 use super::Value;
@@ -23,3 +25,45 @@ fn bench_get_key(b: &mut Bencher) {
         cache.get(&key!(1)).unwrap();
     })
 }
+
+// Fills the cache with more keys than fit in the cpu caches, so that moving
+// an item in the LRU is as expensive as it is in production
+fn fill_cache(cache: &mut Cache) -> Vec<Key> {
+    let keys: Vec<Key> = (0..100000)
+                             .map(|i| format!("key:{}", i).into_bytes())
+                             .map(Key::new)
+                             .collect();
+
+    for key in &keys {
+        cache.set(key.clone(), value!(9)).unwrap();
+    }
+
+    keys
+}
+
+#[bench]
+fn bench_get_many_keys(b: &mut Bencher) {
+    let mut cache = Cache::new(1 << 30);
+    let keys = fill_cache(&mut cache);
+
+    let mut i = 0;
+    b.iter(|| {
+        // step through the keys out of order
+        i = (i + 7919) % keys.len();
+        cache.get(&keys[i]).unwrap();
+    })
+}
+
+#[bench]
+fn bench_get_many_keys_bump_interval(b: &mut Bencher) {
+    let mut cache = Cache::new(1 << 30);
+    cache.with_bump_interval(60.0);
+    let keys = fill_cache(&mut cache);
+
+    let mut i = 0;
+    b.iter(|| {
+        // step through the keys out of order
+        i = (i + 7919) % keys.len();
+        cache.get(&keys[i]).unwrap();
+    })
+}
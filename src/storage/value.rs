@@ -1,18 +1,24 @@
 use std::mem;
 
-use platform::time::time_now;
+use common::blob::Blob;
+
+use super::compression::compress_blob;
+use super::compression::decompress_blob;
 
 
 #[derive(Debug, Clone)]
 pub struct Value {
     // Settable/gettable
-    item: Vec<u8>,
-    flags: u16, // chosen by the client
-    exptime: f64, // expiry time (unixtime), <0 for unset
+    item: Blob,
+    flags: u32, // chosen by the client
+    exptime: f64, // expiry time (clock time), <0 for unset
+    tags: Vec<Vec<u8>>, // for invalidating related items together
 
     // Managed internally
-    atime: f64, // last access time (unixtime)
+    atime: f64, // last access time (clock time)
     cas_id: u64, // Incremented every time the value is changed
+    compressed: bool, // Whether the item is stored in compressed form
+    generation: u64, // Cache generation the value was stored in
 }
 
 impl PartialEq for Value {
@@ -24,47 +30,68 @@ impl PartialEq for Value {
 }
 
 impl Value {
-    pub fn new(item: Vec<u8>) -> Value {
+    pub fn new(item: Blob) -> Value {
         Value {
             item: item,
             flags: 0,
             atime: -1.0,
             exptime: -1.0,
             cas_id: 0,
+            compressed: false,
+            generation: 0,
+            tags: vec![],
         }
     }
 
     pub fn empty() -> Value {
         Value {
-            item: vec![],
+            item: Blob::empty(),
             flags: 0,
             atime: -1.0,
             exptime: -1.0,
             cas_id: 0,
+            compressed: false,
+            generation: 0,
+            tags: vec![],
         }
     }
 
 
-    pub fn get_item_mut(&mut self) -> &mut Vec<u8> {
+    pub fn get_item_mut(&mut self) -> &mut Blob {
         self.bump_cas_id();
         &mut self.item
     }
 
-    pub fn get_item(&self) -> &Vec<u8> {
+    pub fn get_item(&self) -> &Blob {
         &self.item
     }
 
-    pub fn set_item(&mut self, item: Vec<u8>) -> &mut Self {
+    pub fn set_item(&mut self, item: Blob) -> &mut Self {
         self.bump_cas_id();
         self.item = item;
+        self.compressed = false;
         self
     }
 
-    pub fn get_flags(&self) -> &u16 {
+    pub fn into_item(self) -> Blob {
+        self.item
+    }
+
+    // Take the item out of the value, eg. to keep it elsewhere. This and
+    // restore_item don't change the value as far as the client is concerned.
+    pub fn take_item(&mut self) -> Blob {
+        mem::replace(&mut self.item, Blob::empty())
+    }
+
+    pub fn restore_item(&mut self, item: Blob) {
+        self.item = item;
+    }
+
+    pub fn get_flags(&self) -> &u32 {
         &self.flags
     }
 
-    pub fn set_flags(&mut self, flags: u16) -> &mut Self {
+    pub fn set_flags(&mut self, flags: u32) -> &mut Self {
         self.bump_cas_id();
         self.flags = flags;
         self
@@ -79,6 +106,31 @@ impl Value {
         self.exptime = exptime;
     }
 
+    pub fn get_tags(&self) -> &Vec<Vec<u8>> {
+        &self.tags
+    }
+
+    // Tags are not the client's data, so this doesn't bump the cas id
+    pub fn add_tag(&mut self, mut tag: Vec<u8>) {
+        if !self.tags.contains(&tag) {
+            tag.shrink_to_fit();
+            self.tags.push(tag);
+            self.tags.shrink_to_fit();
+        }
+    }
+
+    pub fn clear_tags(&mut self) {
+        self.tags = vec![];
+    }
+
+    pub fn get_generation(&self) -> &u64 {
+        &self.generation
+    }
+
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
     pub fn get_atime(&self) -> &f64 {
         &self.atime
     }
@@ -91,8 +143,41 @@ impl Value {
         self.cas_id += 1;
     }
 
-    pub fn touch(&mut self) {
-        self.atime = time_now();
+    pub fn touch(&mut self, now: f64) {
+        self.atime = now;
+    }
+
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    // Replaces the item with its compressed form, but only if that is
+    // smaller. Returns whether it was. The client can't tell the difference,
+    // so this doesn't count as a change to the value (cas id).
+    pub fn compress(&mut self) -> bool {
+        if self.compressed {
+            return true;
+        }
+
+        let compressed = compress_blob(&self.item);
+        if compressed.len() >= self.item.len() {
+            return false;
+        }
+
+        self.item = compressed;
+        self.compressed = true;
+        true
+    }
+
+    pub fn decompress(&mut self) {
+        if !self.compressed {
+            return;
+        }
+
+        // We compressed it ourselves, so this can't fail
+        self.item = decompress_blob(&self.item).unwrap();
+        self.compressed = false;
     }
 
 
@@ -101,6 +186,10 @@ impl Value {
     }
 
     pub fn mem_size(&self) -> usize {
-        mem::size_of::<Self>() + self.item.len()
+        // Count the allocated capacity, not just the bytes in use
+        let tags = self.tags.iter().fold(0, |acc, tag| acc + tag.capacity());
+        let tags = tags + self.tags.capacity() * mem::size_of::<Vec<u8>>();
+
+        mem::size_of::<Self>() + self.item.mem_size() + tags
     }
 }
@@ -1,9 +1,23 @@
 use std::str::FromStr;
 
+use common::consts::KEY_MAXLEN;
+
 use super::errors::TcpTransportError;
 use super::typedefs::TcpTransportResult;
 
 
+// Keys can be any bytes except whitespace and control characters
+pub fn as_key(bytes: Vec<u8>) -> TcpTransportResult<Vec<u8>> {
+    if bytes.is_empty() || bytes.len() > KEY_MAXLEN {
+        return Err(TcpTransportError::InvalidKey);
+    }
+
+    match bytes.iter().any(|byte| *byte <= b' ' || *byte == 0x7f) {
+        true => Err(TcpTransportError::InvalidKey),
+        false => Ok(bytes),
+    }
+}
+
 pub fn as_string(bytes: Vec<u8>) -> TcpTransportResult<String> {
     match String::from_utf8(bytes) {
         Ok(st) => Ok(st),
@@ -25,10 +39,33 @@ pub fn as_number<N: FromStr>(bytes: Vec<u8>) -> TcpTransportResult<N> {
 mod tests {
     use tcp_transport::TcpTransportError;
 
+    use super::as_key;
     use super::as_number;
     use super::as_string;
 
 
+    #[test]
+    fn test_as_key() {
+        // any bytes will do, they don't have to be utf8
+        let key = as_key(vec![b'a', 254, b'b']).unwrap();
+        assert_eq!(key, vec![b'a', 254, b'b']);
+
+        // up to 250 of them
+        as_key(vec![b'a'; 250]).unwrap();
+        let err = as_key(vec![b'a'; 251]).unwrap_err();
+        assert_eq!(err, TcpTransportError::InvalidKey);
+
+        // but no whitespace or control characters
+        for byte in &[b' ', b'\t', b'\r', b'\n', 0, 0x7f] {
+            let err = as_key(vec![b'a', *byte]).unwrap_err();
+            assert_eq!(err, TcpTransportError::InvalidKey);
+        }
+
+        // and not empty
+        let err = as_key(vec![]).unwrap_err();
+        assert_eq!(err, TcpTransportError::InvalidKey);
+    }
+
     #[test]
     fn test_as_string() {
         // bytestring is utf8
@@ -2,9 +2,11 @@
 pub enum TcpTransportError {
     CommandParseError,
     InvalidCmd,
+    InvalidKey,
     LineReadError,
     NumberParseError,
     StreamReadError,
     StreamWriteError,
     Utf8Error,
+    ValueTooLarge,
 }
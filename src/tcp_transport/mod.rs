@@ -6,7 +6,9 @@ pub mod transport;
 pub mod typedefs;
 
 // internal stuff
+#[cfg(test)]
 mod tests;  // needed to be part of the compilation unit in test mode
+#[cfg(test)]
 mod tests_bench;  // needed to be part of the compilation unit in test mode
 
 
@@ -1,3 +1,6 @@
+use common::blob::Blob;
+use common::blob::CHUNK_SIZE;
+use protocol::cmd::CacheMemlimit;
 use protocol::cmd::Cmd;
 use protocol::cmd::Delete;
 use protocol::cmd::FlushAll;
@@ -5,12 +8,21 @@ use protocol::cmd::Get;
 use protocol::cmd::GetInstr;
 use protocol::cmd::Inc;
 use protocol::cmd::IncInstr;
+use protocol::cmd::Invalidate;
+use protocol::cmd::InvalidateInstr;
 use protocol::cmd::Resp;
 use protocol::cmd::Set;
 use protocol::cmd::SetInstr;
+use protocol::cmd::Slowlog;
+use protocol::cmd::SlowlogInstr;
 use protocol::cmd::Stat;
+use protocol::cmd::Stats;
+use protocol::cmd::Tag;
 use protocol::cmd::Touch;
 use protocol::cmd::Value;
+use protocol::cmd::Watch;
+use protocol::cmd::WatchEvent;
+use protocol::cmd::WatchEventKind;
 use testlib::test_stream::TestStream;
 
 use super::TcpTransport;
@@ -81,6 +93,27 @@ fn test_read_bytes_many() {
     assert_eq!(bytes, vec![97; 1 << 20]);
 }
 
+#[test]
+fn test_read_blob_exact_chunked() {
+    let ts = TestStream::new(vec![97; CHUNK_SIZE * 2 + 1]);
+    let mut transport = TcpTransport::new(ts);
+
+    let len = (CHUNK_SIZE * 2 + 1) as u64;
+    let blob = transport.read_blob_exact(len).unwrap();
+    assert_eq!(blob.chunks().count(), 3);
+    assert_eq!(blob, vec![97; CHUNK_SIZE * 2 + 1]);
+}
+
+#[test]
+fn test_read_blob_exact_too_few() {
+    let ts = TestStream::new(vec![97; CHUNK_SIZE + 1]);
+    let mut transport = TcpTransport::new(ts);
+
+    let len = (CHUNK_SIZE * 2) as u64;
+    let blob = transport.read_blob_exact(len).unwrap();
+    assert_eq!(blob, vec![97; CHUNK_SIZE + 1]);
+}
+
 
 #[test]
 fn test_read_word_in_line_one_char() {
@@ -189,7 +222,7 @@ fn test_read_cmd_add() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let exp = Set::new(SetInstr::Add, "x", 15, 0, vec![97, 98, 99], false);
+    let exp = Set::new(SetInstr::Add, b"x", 15, 0, vec![97, 98, 99], false);
     assert_eq!(cmd, Cmd::Set(exp));
 }
 
@@ -203,11 +236,44 @@ fn test_read_cmd_append() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let exp = Set::new(SetInstr::Append, "x", 15, 0, vec![97, 98, 99], false);
+    let exp = Set::new(SetInstr::Append, b"x", 15, 0, vec![97, 98, 99], false);
     assert_eq!(cmd, Cmd::Set(exp));
 }
 
 
+// Command parsing: CacheMemlimit
+
+#[test]
+fn test_read_cmd_cache_memlimit() {
+    let cmd_str = b"cache_memlimit 128\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::CacheMemlimit(CacheMemlimit::new(128, false)));
+}
+
+#[test]
+fn test_read_cmd_cache_memlimit_noreply() {
+    let cmd_str = b"cache_memlimit 128 noreply\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::CacheMemlimit(CacheMemlimit::new(128, true)));
+}
+
+#[test]
+fn test_read_cmd_cache_memlimit_malformed() {
+    let cmd_str = b"cache_memlimit lots\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::NumberParseError);
+}
+
+
 // Command parsing: Cas
 
 #[test]
@@ -217,7 +283,12 @@ fn test_read_cmd_cas() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let mut exp = Set::new(SetInstr::Cas, "x", 15, 0, vec![97, 98, 99], false);
+    let mut exp = Set::new(SetInstr::Cas,
+                           b"x",
+                           15,
+                           0,
+                           vec![97, 98, 99],
+                           false);
     exp.with_cas_unique(44);
     assert_eq!(cmd, Cmd::Set(exp));
 }
@@ -229,7 +300,7 @@ fn test_read_cmd_cas_noreply() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let mut exp = Set::new(SetInstr::Cas, "x", 15, 0, vec![97, 98, 99], true);
+    let mut exp = Set::new(SetInstr::Cas, b"x", 15, 0, vec![97, 98, 99], true);
     exp.with_cas_unique(44);
     assert_eq!(cmd, Cmd::Set(exp));
 }
@@ -244,7 +315,7 @@ fn test_read_cmd_decr() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Decr, "x", 5, false)));
+    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Decr, b"x", 5, false)));
 }
 
 #[test]
@@ -254,7 +325,7 @@ fn test_read_cmd_decr_noreply() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Decr, "x", 5, true)));
+    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Decr, b"x", 5, true)));
 }
 
 
@@ -267,7 +338,7 @@ fn test_read_cmd_delete() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Delete(Delete::new("x", false)));
+    assert_eq!(cmd, Cmd::Delete(Delete::new(b"x", false)));
 }
 
 #[test]
@@ -277,7 +348,7 @@ fn test_read_cmd_delete_noreply() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Delete(Delete::new("x", true)));
+    assert_eq!(cmd, Cmd::Delete(Delete::new(b"x", true)));
 }
 
 
@@ -293,6 +364,69 @@ fn test_read_cmd_flush_all() {
     assert_eq!(cmd, Cmd::FlushAll(FlushAll::new(None, false)));
 }
 
+#[test]
+fn test_read_cmd_flush_all_delay() {
+    let cmd_str = b"flush_all 10\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::FlushAll(FlushAll::new(Some(10), false)));
+}
+
+#[test]
+fn test_read_cmd_flush_all_noreply() {
+    let cmd_str = b"flush_all noreply\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::FlushAll(FlushAll::new(None, true)));
+}
+
+#[test]
+fn test_read_cmd_flush_all_delay_noreply() {
+    let cmd_str = b"flush_all 0 noreply\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::FlushAll(FlushAll::new(Some(0), true)));
+}
+
+#[test]
+fn test_read_cmd_flush_all_consumes_line() {
+    let cmd_str = b"flush_all 10\r\nversion\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::FlushAll(FlushAll::new(Some(10), false)));
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Version);
+}
+
+#[test]
+fn test_read_cmd_flush_all_invalid() {
+    let cmd_str = b"flush_all soon\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::NumberParseError);
+}
+
+#[test]
+fn test_read_cmd_flush_all_too_many_args() {
+    let cmd_str = b"flush_all 10 20 noreply\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::CommandParseError);
+}
+
 
 // Command parsing: Get
 
@@ -303,7 +437,7 @@ fn test_read_cmd_get_one_key() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Get, "x")));
+    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Get, b"x")));
 }
 
 #[test]
@@ -313,18 +447,40 @@ fn test_read_cmd_get_two_keys() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let keys = vec!["x".to_string(), "y".to_string()];
+    let keys = vec![b"x".to_vec(), b"y".to_vec()];
     assert_eq!(cmd, Cmd::Get(Get::new(GetInstr::Get, keys)));
 }
 
 #[test]
 fn test_read_cmd_get_non_utf8() {
-    let cmd_bytes = b"get \xfe\r\n".to_vec();
+    let cmd_bytes = b"get \xfe\xff\r\n".to_vec();
     let ts = TestStream::new(cmd_bytes);
     let mut transport = TcpTransport::new(ts);
 
-    let err = transport.read_cmd().unwrap_err();
-    assert_eq!(err, TcpTransportError::Utf8Error);
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Get, b"\xfe\xff")));
+}
+
+#[test]
+fn test_read_cmd_get_invalid_key() {
+    fn try_cmd(cmd: &[u8]) {
+        let ts = TestStream::new(cmd.to_vec());
+        let mut transport = TcpTransport::new(ts);
+
+        let err = transport.read_cmd().unwrap_err();
+        assert_eq!(err, TcpTransportError::InvalidKey);
+    }
+
+    // Control characters
+    try_cmd(b"get a\tb\r\n");
+    try_cmd(b"get a\x00b\r\n");
+    try_cmd(b"get x a\x7fb\r\n");
+
+    // Too long
+    let mut cmd = b"get ".to_vec();
+    cmd.extend_from_slice(&vec![b'a'; 251]);
+    cmd.extend_from_slice(b"\r\n");
+    try_cmd(&cmd);
 }
 
 #[test]
@@ -354,7 +510,7 @@ fn test_read_cmd_gets_one_key() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Gets, "x")));
+    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Gets, b"x")));
 }
 
 
@@ -367,7 +523,7 @@ fn test_read_cmd_incr() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Incr, "x", 5, false)));
+    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Incr, b"x", 5, false)));
 }
 
 #[test]
@@ -377,7 +533,48 @@ fn test_read_cmd_incr_noreply() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Incr, "x", 5, true)));
+    assert_eq!(cmd, Cmd::Inc(Inc::new(IncInstr::Incr, b"x", 5, true)));
+}
+
+
+// Command parsing: Invalidate
+
+#[test]
+fn test_read_cmd_invalidate_prefix() {
+    let cmd_str = b"invalidate prefix user:15:\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    let exp = Invalidate::new(InvalidateInstr::Prefix, b"user:15:", false);
+    assert_eq!(cmd, Cmd::Invalidate(exp));
+}
+
+#[test]
+fn test_read_cmd_invalidate_tag_noreply() {
+    let cmd_str = b"invalidate tag product15 noreply\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    let exp = Invalidate::new(InvalidateInstr::Tag, b"product15", true);
+    assert_eq!(cmd, Cmd::Invalidate(exp));
+}
+
+#[test]
+fn test_read_cmd_invalidate_malformed() {
+    fn try_cmd(cmd: &[u8]) {
+        let ts = TestStream::new(cmd.to_vec());
+        let mut transport = TcpTransport::new(ts);
+
+        transport.read_cmd().unwrap_err();
+    }
+
+    try_cmd(b"invalidate\r\n");
+    try_cmd(b"invalidate prefix\r\n");
+    try_cmd(b"invalidate suffix x\r\n");
+    try_cmd(b"invalidate tag x noreply more\r\n");
+    try_cmd(b"invalidate tag a\tb\r\n");
 }
 
 
@@ -403,7 +600,12 @@ fn test_read_cmd_prepend() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let exp = Set::new(SetInstr::Prepend, "x", 15, 0, vec![97, 98, 99], false);
+    let exp = Set::new(SetInstr::Prepend,
+                       b"x",
+                       15,
+                       0,
+                       vec![97, 98, 99],
+                       false);
     assert_eq!(cmd, Cmd::Set(exp));
 }
 
@@ -417,7 +619,12 @@ fn test_read_cmd_replace() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let exp = Set::new(SetInstr::Replace, "x", 15, 0, vec![97, 98, 99], false);
+    let exp = Set::new(SetInstr::Replace,
+                       b"x",
+                       15,
+                       0,
+                       vec![97, 98, 99],
+                       false);
     assert_eq!(cmd, Cmd::Set(exp));
 }
 
@@ -431,10 +638,38 @@ fn test_read_cmd_set_ok() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let exp = Set::new(SetInstr::Set, "x", 15, 0, vec![97, 98, 99], false);
+    let exp = Set::new(SetInstr::Set, b"x", 15, 0, vec![97, 98, 99], false);
     assert_eq!(cmd, Cmd::Set(exp));
 }
 
+#[test]
+fn test_read_cmd_set_flags_32bit() {
+    fn try_flags(flags: u32) {
+        let cmd_str = format!("set x {} 0 1 \r\na\r\n", flags).into_bytes();
+        let ts = TestStream::new(cmd_str);
+        let mut transport = TcpTransport::new(ts);
+
+        let cmd = transport.read_cmd().unwrap();
+        let exp = Set::new(SetInstr::Set, b"x", flags, 0, vec![97], false);
+        assert_eq!(cmd, Cmd::Set(exp));
+    }
+
+    try_flags(65535);
+    try_flags(65536);
+    try_flags(1 << 31);
+    try_flags(u32::MAX);
+}
+
+#[test]
+fn test_read_cmd_set_flags_too_large() {
+    let cmd_str = b"set x 4294967296 0 1 \r\na\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::NumberParseError);
+}
+
 #[test]
 fn test_read_cmd_set_noreply_ok() {
     let cmd_str = b"set x 15 0 3 noreply\r\nabc\r\n".to_vec();
@@ -442,10 +677,85 @@ fn test_read_cmd_set_noreply_ok() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let exp = Set::new(SetInstr::Set, "x", 15, 0, vec![97, 98, 99], true);
+    let exp = Set::new(SetInstr::Set, b"x", 15, 0, vec![97, 98, 99], true);
+    assert_eq!(cmd, Cmd::Set(exp));
+}
+
+#[test]
+fn test_read_cmd_set_large() {
+    let mut cmd_str = b"set x 15 0 2097152 \r\n".to_vec();
+    cmd_str.extend_from_slice(&vec![97; 2 << 20]);
+    cmd_str.extend_from_slice(b"\r\n");
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    let exp = Set::new(SetInstr::Set, b"x", 15, 0, vec![97; 2 << 20], false);
+    assert_eq!(cmd, Cmd::Set(exp));
+}
+
+#[test]
+fn test_read_cmd_set_non_utf8() {
+    let cmd_str = b"set \xff\xfe 0 0 1 \r\na\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    let exp = Set::new(SetInstr::Set, b"\xff\xfe", 0, 0, vec![97], false);
     assert_eq!(cmd, Cmd::Set(exp));
 }
 
+#[test]
+fn test_read_cmd_set_invalid_key() {
+    let mut cmd_str = b"set ".to_vec();
+    cmd_str.extend_from_slice(&vec![b'a'; 251]);
+    cmd_str.extend_from_slice(b" 0 0 3 \r\nabc\r\nget x\r\n");
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::InvalidKey);
+
+    // The whole command was consumed so we can read the next one
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Get, b"x")));
+}
+
+#[test]
+fn test_read_cmd_set_too_large() {
+    let mut cmd_str = b"set x 0 0 2000 \r\n".to_vec();
+    cmd_str.extend_from_slice(&vec![b'a'; 2000]);
+    cmd_str.extend_from_slice(b"\r\nget x\r\n");
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+    transport.with_value_maxlen(1000);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::ValueTooLarge);
+
+    // The value is dropped on the way to the next command
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Get(Get::one(GetInstr::Get, b"x")));
+    assert_eq!(transport.get_stats_clone().bytes_read, 2026);
+}
+
+#[test]
+fn test_read_cmd_set_too_large_never_sent() {
+    // The client claims a value far larger than it sends
+    let cmd_str = b"set x 0 0 1000000000000 \r\nabc\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+    transport.with_value_maxlen(1000);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::ValueTooLarge);
+
+    // We only ever read what it sent
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::StreamReadError);
+    assert_eq!(transport.get_stats_clone().bytes_read, 31);
+}
+
 #[test]
 fn test_read_cmd_set_under_size() {
     let cmd_str = b"set x 0 0 2 \r\nabc\r\n".to_vec();
@@ -495,6 +805,67 @@ fn test_read_cmd_set_malformed() {
 }
 
 
+// Command parsing: Slowlog
+
+#[test]
+fn test_read_cmd_slowlog_get() {
+    let cmd_str = b"slowlog get\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Slowlog(Slowlog::new(SlowlogInstr::Get, None)));
+}
+
+#[test]
+fn test_read_cmd_slowlog_get_count() {
+    let cmd_str = b"slowlog get 5\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Slowlog(Slowlog::new(SlowlogInstr::Get, Some(5))));
+}
+
+#[test]
+fn test_read_cmd_slowlog_reset() {
+    let cmd_str = b"slowlog reset\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Slowlog(Slowlog::new(SlowlogInstr::Reset, None)));
+}
+
+#[test]
+fn test_read_cmd_slowlog_malformed() {
+    fn try_cmd(cmd: &[u8]) {
+        let mut cmd_str = cmd.to_vec();
+        cmd_str.extend_from_slice(b"\r\n");
+        let ts = TestStream::new(cmd_str);
+        let mut transport = TcpTransport::new(ts);
+
+        let err = transport.read_cmd().unwrap_err();
+        assert_eq!(err, TcpTransportError::CommandParseError);
+    }
+
+    try_cmd(b"slowlog");
+    try_cmd(b"slowlog len");
+    try_cmd(b"slowlog get 5 more");
+    try_cmd(b"slowlog reset 5");
+}
+
+#[test]
+fn test_read_cmd_slowlog_bad_count() {
+    let cmd_str = b"slowlog get many\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::NumberParseError);
+}
+
+
 // Command parsing: Stats
 
 #[test]
@@ -504,7 +875,70 @@ fn test_read_cmd_stats() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    assert_eq!(cmd, Cmd::Stats);
+    assert_eq!(cmd, Cmd::Stats(Stats::new(None)));
+}
+
+#[test]
+fn test_read_cmd_stats_group() {
+    let cmd_str = b"stats namespaces\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Stats(Stats::new(Some("namespaces"))));
+}
+
+#[test]
+fn test_read_cmd_stats_trailing_space() {
+    let cmd_str = b"stats \r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Stats(Stats::new(None)));
+}
+
+#[test]
+fn test_read_cmd_stats_malformed() {
+    let cmd_str = b"stats namespaces more\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::CommandParseError);
+}
+
+
+// Command parsing: Tag
+
+#[test]
+fn test_read_cmd_tag() {
+    let cmd_str = b"tag x product15\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Tag(Tag::new(b"x", b"product15", false)));
+}
+
+#[test]
+fn test_read_cmd_tag_noreply() {
+    let cmd_str = b"tag x product15 noreply\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Tag(Tag::new(b"x", b"product15", true)));
+}
+
+#[test]
+fn test_read_cmd_tag_invalid() {
+    let cmd_str = b"tag x a\x7fb\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::InvalidKey);
 }
 
 
@@ -517,7 +951,7 @@ fn test_read_cmd_touch() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let touch = Touch::new("x", 0, false);
+    let touch = Touch::new(b"x", 0, false);
     assert_eq!(cmd, Cmd::Touch(touch));
 }
 
@@ -528,7 +962,7 @@ fn test_read_cmd_touch_noreply() {
     let mut transport = TcpTransport::new(ts);
 
     let cmd = transport.read_cmd().unwrap();
-    let touch = Touch::new("x", 0, true);
+    let touch = Touch::new(b"x", 0, true);
     assert_eq!(cmd, Cmd::Touch(touch));
 }
 
@@ -546,6 +980,39 @@ fn test_read_cmd_version() {
 }
 
 
+// Command parsing: Watch
+
+#[test]
+fn test_read_cmd_watch() {
+    let cmd_str = b"watch\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Watch(Watch::new(true, true, true)));
+}
+
+#[test]
+fn test_read_cmd_watch_kinds() {
+    let cmd_str = b"watch fetchers evictions\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let cmd = transport.read_cmd().unwrap();
+    assert_eq!(cmd, Cmd::Watch(Watch::new(true, false, true)));
+}
+
+#[test]
+fn test_read_cmd_watch_malformed() {
+    let cmd_str = b"watch fetchers everything\r\n".to_vec();
+    let ts = TestStream::new(cmd_str);
+    let mut transport = TcpTransport::new(ts);
+
+    let err = transport.read_cmd().unwrap_err();
+    assert_eq!(err, TcpTransportError::CommandParseError);
+}
+
+
 // Response writing: ClientError
 
 #[test]
@@ -602,6 +1069,28 @@ fn test_write_resp_error() {
 }
 
 
+// Response writing: Events
+
+#[test]
+fn test_write_resp_events() {
+    let ts = TestStream::new(vec![]);
+    let mut transport = TcpTransport::new(ts);
+
+    let mut hit = WatchEvent::new(WatchEventKind::Hit, b"x", 5, Some(60));
+    hit.time = 1.5;
+    hit.conn = 3;
+    let mut miss = WatchEvent::new(WatchEventKind::Miss, b"y", 0, None);
+    miss.time = 2.0;
+    miss.conn = 4;
+
+    let resp = Resp::Events(vec![hit, miss]);
+    transport.write_resp(&resp).unwrap();
+    let expected = b"ts=1.500000 conn=3 event=hit key=x size=5 ttl=60\r\n\
+                     ts=2.000000 conn=4 event=miss key=y size=0 ttl=-1\r\n";
+    assert_eq!(transport.get_stream().outgoing, expected.to_vec());
+}
+
+
 // Response writing: Exists
 
 #[test]
@@ -715,6 +1204,20 @@ fn test_write_resp_stored() {
 }
 
 
+// Response writing: Tagged
+
+#[test]
+fn test_write_resp_tagged() {
+    let ts = TestStream::new(vec![]);
+    let mut transport = TcpTransport::new(ts);
+
+    let resp = Resp::Tagged;
+    transport.write_resp(&resp).unwrap();
+    let expected = b"TAGGED\r\n";
+    assert_eq!(transport.get_stream().outgoing, expected.to_vec());
+}
+
+
 // Response writing: Touched
 
 #[test]
@@ -736,20 +1239,44 @@ fn test_write_resp_value_one() {
     let ts = TestStream::new(vec![]);
     let mut transport = TcpTransport::new(ts);
 
-    let val1 = Value::new("x", 15, b"abc".to_vec());
+    let val1 = Value::new(b"x", 15, b"abc".to_vec());
     let resp = Resp::Values(vec![val1]);
     transport.write_resp(&resp).unwrap();
     let expected = b"VALUE x 15 3\r\nabc\r\nEND\r\n";
     assert_eq!(transport.get_stream().outgoing, expected.to_vec());
 }
 
+#[test]
+fn test_write_resp_value_flags_32bit() {
+    let ts = TestStream::new(vec![]);
+    let mut transport = TcpTransport::new(ts);
+
+    let val1 = Value::new(b"x", u32::MAX, b"a".to_vec());
+    let resp = Resp::Values(vec![val1]);
+    transport.write_resp(&resp).unwrap();
+    let expected = b"VALUE x 4294967295 1\r\na\r\nEND\r\n";
+    assert_eq!(transport.get_stream().outgoing, expected.to_vec());
+}
+
+#[test]
+fn test_write_resp_value_non_utf8() {
+    let ts = TestStream::new(vec![]);
+    let mut transport = TcpTransport::new(ts);
+
+    let val1 = Value::new(b"\xff\xfe", 0, b"a".to_vec());
+    let resp = Resp::Values(vec![val1]);
+    transport.write_resp(&resp).unwrap();
+    let expected = b"VALUE \xff\xfe 0 1\r\na\r\nEND\r\n";
+    assert_eq!(transport.get_stream().outgoing, expected.to_vec());
+}
+
 #[test]
 fn test_write_resp_value_two() {
     let ts = TestStream::new(vec![]);
     let mut transport = TcpTransport::new(ts);
 
-    let val1 = Value::new("x", 15, b"abc".to_vec());
-    let val2 = Value::new("y", 17, b"def".to_vec());
+    let val1 = Value::new(b"x", 15, b"abc".to_vec());
+    let val2 = Value::new(b"y", 17, b"def".to_vec());
     let resp = Resp::Values(vec![val1, val2]);
     transport.write_resp(&resp).unwrap();
     let expected = b"VALUE x 15 3\r\nabc\r\nVALUE y 17 3\r\ndef\r\nEND\r\n";
@@ -761,7 +1288,7 @@ fn test_write_resp_value_one_cas() {
     let ts = TestStream::new(vec![]);
     let mut transport = TcpTransport::new(ts);
 
-    let mut val1 = Value::new("x", 15, b"abc".to_vec());
+    let mut val1 = Value::new(b"x", 15, b"abc".to_vec());
     val1.with_cas_unique(45);
     let resp = Resp::Values(vec![val1]);
     transport.write_resp(&resp).unwrap();
@@ -769,6 +1296,44 @@ fn test_write_resp_value_one_cas() {
     assert_eq!(transport.get_stream().outgoing, expected.to_vec());
 }
 
+#[test]
+fn test_write_resp_value_chunked() {
+    let ts = TestStream::new(vec![]);
+    let mut transport = TcpTransport::new(ts);
+
+    let mut val1 = Value::new(b"x", 15, vec![]);
+    val1.data = Blob::from_slice(&vec![97; CHUNK_SIZE + 1]);
+    let resp = Resp::Values(vec![val1]);
+    transport.write_resp(&resp).unwrap();
+
+    let header = format!("VALUE x 15 {}\r\n", CHUNK_SIZE + 1);
+    let mut expected = header.into_bytes();
+    expected.extend_from_slice(&vec![97; CHUNK_SIZE + 1]);
+    expected.extend_from_slice(b"\r\nEND\r\n");
+    assert_eq!(transport.get_stream().outgoing, expected);
+}
+
+#[test]
+fn test_write_resp_value_large_after_small() {
+    let ts = TestStream::new(vec![]);
+    let mut transport = TcpTransport::new(ts);
+
+    // The large value bypasses the write buffer, but still goes out after
+    // what was written before it
+    let val1 = Value::new(b"x", 15, b"abc".to_vec());
+    let val2 = Value::new(b"y", 17, vec![98; 100000]);
+    let resp = Resp::Values(vec![val1, val2]);
+    transport.write_resp(&resp).unwrap();
+
+    let mut expected = b"VALUE x 15 3\r\nabc\r\n".to_vec();
+    expected.extend_from_slice(b"VALUE y 17 100000\r\n");
+    expected.extend_from_slice(&vec![98; 100000]);
+    expected.extend_from_slice(b"\r\nEND\r\n");
+    assert_eq!(transport.get_stream().outgoing, expected);
+    assert_eq!(transport.get_stats_clone().bytes_written,
+               expected.len() as u64);
+}
+
 
 // Response writing: Version
 
@@ -1,3 +1,7 @@
+use std::io;
+use std::io::IoSlice;
+use std::io::Read;
+use std::io::Write;
 use test::Bencher;
 
 use protocol::cmd::Resp;
@@ -7,6 +11,31 @@ use testlib::test_stream::TestStream;
 use super::TcpTransport;
 
 
+// A stream that throws away whatever is written to it, like a socket with a
+// client on the other end that keeps up with anything we send
+struct NullStream;
+
+impl Read for NullStream {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for NullStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        Ok(bufs.iter().fold(0, |acc, buf| acc + buf.len()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
 // Reading
 
 #[bench]
@@ -42,8 +71,21 @@ fn bench_transport_write_resp_value(b: &mut Bencher) {
     let mut transport = TcpTransport::new(ts);
 
     b.iter(|| {
-        let val = Value::new("x", 15, "abc".to_string().into_bytes());
+        let val = Value::new(b"x", 15, "abc".to_string().into_bytes());
         let resp = Resp::Values(vec![val]);
         transport.write_resp(&resp).unwrap();
     })
 }
+
+#[bench]
+fn bench_transport_write_resp_large_value(b: &mut Bencher) {
+    let mut transport = TcpTransport::new(NullStream);
+
+    let val = Value::new(b"x", 15, vec![7; 500 * 1024]);
+    let resp = Resp::Values(vec![val]);
+
+    b.bytes = 500 * 1024;
+    b.iter(|| {
+        transport.write_resp(&resp).unwrap();
+    })
+}
@@ -1,8 +1,14 @@
+use std::io;
+use std::io::BufRead;
+use std::io::IoSlice;
 use std::io::Read;
 use std::io::Write;
 
 use bufstream::BufStream;
 
+use common::blob::Blob;
+use common::blob::CHUNK_SIZE;
+use protocol::cmd::CacheMemlimit;
 use protocol::cmd::Cmd;
 use protocol::cmd::Delete;
 use protocol::cmd::FlushAll;
@@ -10,11 +16,19 @@ use protocol::cmd::Get;
 use protocol::cmd::GetInstr;
 use protocol::cmd::Inc;
 use protocol::cmd::IncInstr;
+use protocol::cmd::Invalidate;
+use protocol::cmd::InvalidateInstr;
 use protocol::cmd::Resp;
 use protocol::cmd::Set;
 use protocol::cmd::SetInstr;
+use protocol::cmd::Slowlog;
+use protocol::cmd::SlowlogInstr;
+use protocol::cmd::Stats;
+use protocol::cmd::Tag;
 use protocol::cmd::Touch;
+use protocol::cmd::Watch;
 
+use super::conversions::as_key;
 use super::conversions::as_number;
 use super::conversions::as_string;
 use super::errors::TcpTransportError;
@@ -22,6 +36,11 @@ use super::stats::TransportStats;
 use super::typedefs::TcpTransportResult;
 
 
+// Values at least this big (in bytes) are written to the stream straight from
+// where they are stored, instead of being copied into the write buffer first
+const VECTORED_WRITE_MIN: usize = 16 * 1024; // 16kb
+
+
 // return_err_if!(end_of_line, TcpTransportError::StreamReadError) =>
 //
 // if end_of_line {
@@ -38,9 +57,53 @@ macro_rules! return_err_if {
 }
 
 
+// Writes all the buffers in as few writes as the stream allows, picking up
+// where it left off when a write only gets part of them out
+fn write_all_vectored<W: Write>(stream: &mut W,
+                                mut bufs: Vec<&[u8]>)
+                                -> io::Result<usize> {
+    let mut total = 0;
+
+    while !bufs.is_empty() {
+        let written = {
+            let slices: Vec<IoSlice> = bufs.iter()
+                                           .map(|buf| IoSlice::new(buf))
+                                           .collect();
+            match stream.write_vectored(&slices) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(written) => written,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                Err(e) => return Err(e),
+            }
+        };
+        total += written;
+
+        // Drop whatever made it out
+        let mut left = written;
+        while left > 0 && left >= bufs[0].len() {
+            left -= bufs[0].len();
+            bufs.remove(0);
+        }
+        if left > 0 {
+            bufs[0] = &bufs[0][left..];
+        }
+    }
+
+    Ok(total)
+}
+
+
 pub struct TcpTransport<T: Read + Write> {
     stream: BufStream<T>,
     stats: TransportStats,
+
+    // Values larger than this (in bytes) are turned down before we read
+    // them, None for no limit
+    value_maxlen: Option<u64>,
+
+    // Bytes of a value we turned down that are still to be read and dropped
+    // before the next command
+    swallow: u64,
 }
 
 impl<T: Read + Write> TcpTransport<T> {
@@ -48,9 +111,18 @@ impl<T: Read + Write> TcpTransport<T> {
         TcpTransport {
             stats: TransportStats::new(),
             stream: BufStream::new(stream),
+            swallow: 0,
+            value_maxlen: None,
         }
     }
 
+    pub fn with_value_maxlen(&mut self,
+                             value_maxlen: u64)
+                             -> &mut TcpTransport<T> {
+        self.value_maxlen = Some(value_maxlen);
+        self
+    }
+
 
     pub fn get_stats_clone(&self) -> TransportStats {
         self.stats.clone()
@@ -62,6 +134,15 @@ impl<T: Read + Write> TcpTransport<T> {
 
     // Basic bytes manipulation and reading from the stream
 
+    // Blocks until there is something to read, so that the time a client
+    // sits idle isn't counted as time spent reading its next command
+    pub fn wait_for_cmd(&mut self) -> TcpTransportResult<()> {
+        match self.stream.fill_buf() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(TcpTransportError::StreamReadError),
+        }
+    }
+
     pub fn read_bytes_exact(&mut self,
                             len: u64)
                             -> TcpTransportResult<Vec<u8>> {
@@ -116,6 +197,53 @@ impl<T: Read + Write> TcpTransport<T> {
         Ok(bytes)
     }
 
+    // Reads and drops what is left of a value we turned down, without ever
+    // holding more of it than the read buffer
+    pub fn swallow_rejected(&mut self) -> TcpTransportResult<()> {
+        while self.swallow > 0 {
+            let bytes_cnt = match self.stream.fill_buf() {
+                Ok(buf) if !buf.is_empty() => {
+                    (buf.len() as u64).min(self.swallow) as usize
+                }
+                _ => return Err(TcpTransportError::StreamReadError),
+            };
+            self.stream.consume(bytes_cnt);
+
+            // Update stats
+            self.stats.bytes_read += bytes_cnt as u64;
+
+            self.swallow -= bytes_cnt as u64;
+        }
+
+        Ok(())
+    }
+
+    // Like read_bytes_exact, but large values are read chunk by chunk so we
+    // never need one huge allocation
+    pub fn read_blob_exact(&mut self, len: u64) -> TcpTransportResult<Blob> {
+        let mut chunks = vec![];
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let chunk_len = remaining.min(CHUNK_SIZE as u64);
+            let chunk = try!(self.read_bytes_exact(chunk_len));
+            let chunk_cnt = chunk.len() as u64;
+
+            if chunk_cnt > 0 {
+                chunks.push(chunk);
+            }
+
+            // The stream ran dry, return what we have
+            if chunk_cnt < chunk_len {
+                break;
+            }
+
+            remaining -= chunk_cnt;
+        }
+
+        Ok(Blob::from_chunks(chunks))
+    }
+
     pub fn read_word_in_line(&mut self) -> TcpTransportResult<(Vec<u8>, bool)> {
         let mut word = vec![];
         let mut byte = [0; 1];
@@ -208,12 +336,14 @@ impl<T: Read + Write> TcpTransport<T> {
     pub fn write_bytes(&mut self,
                        bytes: &Vec<u8>)
                        -> TcpTransportResult<usize> {
-        match self.stream.write(bytes) {
-            Ok(cnt_written) => {
+        // Large values may not be written in one go, so keep writing until
+        // it's all out
+        match self.stream.write_all(bytes) {
+            Ok(_) => {
                 // Update stats
-                self.stats.bytes_written += cnt_written as u64;
+                self.stats.bytes_written += bytes.len() as u64;
 
-                Ok(cnt_written)
+                Ok(bytes.len())
             }
             Err(_) => Err(TcpTransportError::StreamWriteError),
         }
@@ -224,15 +354,74 @@ impl<T: Read + Write> TcpTransport<T> {
         Ok(try!(self.write_bytes(&bytes)))
     }
 
+    // Writes a data block followed by a newline. Large blocks bypass the
+    // write buffer and are written with vectored writes, so their bytes are
+    // never copied on the way out.
+    pub fn write_blob_line(&mut self,
+                           blob: &Blob)
+                           -> TcpTransportResult<usize> {
+        if blob.len() < VECTORED_WRITE_MIN {
+            let mut written = 0;
+            for chunk in blob.chunks() {
+                written += try!(self.write_bytes(chunk));
+            }
+            written += try!(self.write_string("\r\n"));
+            return Ok(written);
+        }
+
+        // Whatever is in the buffer has to go out first
+        try!(self.flush_writes());
+
+        let mut bufs: Vec<&[u8]> = blob.chunks()
+                                       .map(|chunk| &chunk[..])
+                                       .collect();
+        bufs.push(b"\r\n");
+
+        match write_all_vectored(self.stream.get_mut(), bufs) {
+            Ok(written) => {
+                // Update stats
+                self.stats.bytes_written += written as u64;
+
+                Ok(written)
+            }
+            Err(_) => Err(TcpTransportError::StreamWriteError),
+        }
+    }
+
 
     // Parse individual commands
 
+    pub fn parse_cmd_cache_memlimit(&mut self) -> TcpTransportResult<Cmd> {
+        // parse the memlimit
+        let (memlimit_num, end_of_line) = {
+            let (memlimit, end_of_line) = try!(self.read_word_in_line());
+            (try!(as_number::<u64>(memlimit)), end_of_line)
+        };
+
+        // parse noreply (optional)
+        let noreply_flag = match end_of_line {
+            true => false,
+            false => {
+                let (noreply, end_of_line) = try!(self.read_word_in_line());
+                return_err_if!(!end_of_line,
+                               TcpTransportError::CommandParseError);
+                let noreply_str = try!(as_string(noreply));
+                noreply_str == "noreply"
+            }
+        };
+
+        Ok(Cmd::CacheMemlimit(CacheMemlimit {
+            memlimit: memlimit_num,
+            noreply: noreply_flag,
+        }))
+    }
+
     pub fn parse_cmd_delete(&mut self) -> TcpTransportResult<Cmd> {
-        // parse the key
-        let key_str = {
+        // parse the key, it's checked once we've read the whole command
+        let key_bytes = {
             let (key, end_of_line) = try!(self.read_word_in_line());
             return_err_if!(end_of_line, TcpTransportError::CommandParseError);
-            try!(as_string(key))
+            key
         };
 
         // parse noreply
@@ -247,19 +436,43 @@ impl<T: Read + Write> TcpTransport<T> {
         };
 
         Ok(Cmd::Delete(Delete {
-            key: key_str,
+            key: try!(as_key(key_bytes)),
             noreply: noreply_flag,
         }))
     }
 
-    pub fn parse_cmd_flush_all(&mut self) -> TcpTransportResult<Cmd> {
-        // consume the line
-        // try!(self.read_line_as_words());
+    pub fn parse_cmd_flush_all(&mut self,
+                               end_of_line: bool)
+                               -> TcpTransportResult<Cmd> {
+        // A plain "flush_all" ends right after the keyword
+        if end_of_line {
+            return Ok(Cmd::FlushAll(FlushAll {
+                exptime: None,
+                noreply: false,
+            }));
+        }
+
+        // parse the delay and noreply (both optional)
+        let mut words = try!(self.read_line_as_words());
+        return_err_if!(words.len() > 2, TcpTransportError::CommandParseError);
+
+        let noreply_flag = match words.last() {
+            Some(word) => word == b"noreply",
+            None => false,
+        };
+        if noreply_flag {
+            words.pop();
+        }
+        return_err_if!(words.len() > 1, TcpTransportError::CommandParseError);
+
+        let exptime = match words.pop() {
+            Some(word) => Some(try!(as_number::<u32>(word))),
+            None => None,
+        };
 
-        // TODO hardcoded - need better parser primitives
         Ok(Cmd::FlushAll(FlushAll {
-            exptime: None,
-            noreply: false,
+            exptime: exptime,
+            noreply: noreply_flag,
         }))
     }
 
@@ -270,14 +483,16 @@ impl<T: Read + Write> TcpTransport<T> {
 
         loop {
             let (key, end_of_line) = try!(self.read_word_in_line());
-            let key_str = try!(as_string(key));
-            keys.push(key_str);
+            keys.push(key);
 
             if end_of_line {
                 break;
             }
         }
 
+        // Check the keys once we've read the whole line
+        let keys = try!(keys.into_iter().map(as_key).collect());
+
         Ok(Cmd::Get(Get {
             instr: instr,
             keys: keys,
@@ -287,11 +502,11 @@ impl<T: Read + Write> TcpTransport<T> {
     pub fn parse_cmd_inc(&mut self,
                          instr: IncInstr)
                          -> TcpTransportResult<Cmd> {
-        // parse the key
-        let key_str = {
+        // parse the key, it's checked once we've read the whole command
+        let key_bytes = {
             let (key, end_of_line) = try!(self.read_word_in_line());
             return_err_if!(end_of_line, TcpTransportError::CommandParseError);
-            try!(as_string(key))
+            key
         };
 
         // parse the delta
@@ -315,27 +530,62 @@ impl<T: Read + Write> TcpTransport<T> {
         // We got all the values we expected and there is nothing left
         return Ok(Cmd::Inc(Inc {
             instr: instr,
-            key: key_str,
+            key: try!(as_key(key_bytes)),
             delta: delta_num,
             noreply: noreply_flag,
         }));
     }
 
+    pub fn parse_cmd_invalidate(&mut self) -> TcpTransportResult<Cmd> {
+        // parse what to invalidate by
+        let instr = {
+            let (instr, end_of_line) = try!(self.read_word_in_line());
+            return_err_if!(end_of_line, TcpTransportError::CommandParseError);
+            match &try!(as_string(instr))[..] {
+                "prefix" => InvalidateInstr::Prefix,
+                "tag" => InvalidateInstr::Tag,
+                _ => return Err(TcpTransportError::CommandParseError),
+            }
+        };
+
+        // parse the prefix or tag, it's checked once we've read the whole
+        // command
+        let (target_bytes, end_of_line) = try!(self.read_word_in_line());
+
+        // parse noreply (optional)
+        let noreply_flag = match end_of_line {
+            true => false,
+            false => {
+                let (noreply, end_of_line) = try!(self.read_word_in_line());
+                return_err_if!(!end_of_line,
+                               TcpTransportError::CommandParseError);
+                let noreply_str = try!(as_string(noreply));
+                noreply_str == "noreply"
+            }
+        };
+
+        Ok(Cmd::Invalidate(Invalidate {
+            instr: instr,
+            target: try!(as_key(target_bytes)),
+            noreply: noreply_flag,
+        }))
+    }
+
     pub fn parse_cmd_set(&mut self,
                          instr: SetInstr)
                          -> TcpTransportResult<Cmd> {
-        // parse the key
-        let key_str = {
+        // parse the key, it's checked once we've read the whole command
+        let key_bytes = {
             let (key, end_of_line) = try!(self.read_word_in_line());
             return_err_if!(end_of_line, TcpTransportError::CommandParseError);
-            try!(as_string(key))
+            key
         };
 
         // parse the flags
         let flags_num = {
             let (flags, end_of_line) = try!(self.read_word_in_line());
             return_err_if!(end_of_line, TcpTransportError::CommandParseError);
-            try!(as_number::<u16>(flags))
+            try!(as_number::<u32>(flags))
         };
 
         // parse the exptime
@@ -374,8 +624,17 @@ impl<T: Read + Write> TcpTransport<T> {
             }
         };
 
+        // Don't even read a value we wouldn't store, just drop it (and its
+        // line terminator) on the way to the next command
+        if let Some(value_maxlen) = self.value_maxlen {
+            if bytelen_num > value_maxlen {
+                self.swallow = bytelen_num.saturating_add(2);
+                return Err(TcpTransportError::ValueTooLarge);
+            }
+        }
+
         // We now know the byte length, so read the value
-        let value = try!(self.read_bytes_exact(bytelen_num));
+        let value = try!(self.read_blob_exact(bytelen_num));
 
         // The value is the wrong size
         if value.len() as u64 != bytelen_num {
@@ -391,7 +650,7 @@ impl<T: Read + Write> TcpTransport<T> {
         // We got all the values we expected and there is nothing left
         return Ok(Cmd::Set(Set {
             instr: instr,
-            key: key_str,
+            key: try!(as_key(key_bytes)),
             flags: flags_num,
             exptime: exptime_num,
             data: value,
@@ -400,12 +659,91 @@ impl<T: Read + Write> TcpTransport<T> {
         }));
     }
 
+    pub fn parse_cmd_slowlog(&mut self,
+                             end_of_line: bool)
+                             -> TcpTransportResult<Cmd> {
+        // The instruction is not optional
+        return_err_if!(end_of_line, TcpTransportError::CommandParseError);
+
+        let mut words = try!(self.read_line_as_words()).into_iter();
+        let instr = match words.next() {
+            Some(ref word) if word == b"get" => SlowlogInstr::Get,
+            Some(ref word) if word == b"reset" => SlowlogInstr::Reset,
+            _ => return Err(TcpTransportError::CommandParseError),
+        };
+
+        // parse the count (optional, only for get)
+        let count = match (&instr, words.next()) {
+            (&SlowlogInstr::Get, Some(word)) => {
+                Some(try!(as_number::<u64>(word)))
+            }
+            (_, Some(_)) => return Err(TcpTransportError::CommandParseError),
+            (_, None) => None,
+        };
+        return_err_if!(words.next().is_some(),
+                       TcpTransportError::CommandParseError);
+
+        Ok(Cmd::Slowlog(Slowlog {
+            instr: instr,
+            count: count,
+        }))
+    }
+
+    pub fn parse_cmd_stats(&mut self,
+                           end_of_line: bool)
+                           -> TcpTransportResult<Cmd> {
+        // A plain "stats" ends right after the keyword
+        if end_of_line {
+            return Ok(Cmd::Stats(Stats { group: None }));
+        }
+
+        // parse the group (optional)
+        let mut words = try!(self.read_line_as_words());
+        return_err_if!(words.len() > 1, TcpTransportError::CommandParseError);
+
+        let group = match words.pop() {
+            Some(word) => Some(try!(as_string(word))),
+            None => None,
+        };
+
+        Ok(Cmd::Stats(Stats { group: group }))
+    }
+
+    pub fn parse_cmd_tag(&mut self) -> TcpTransportResult<Cmd> {
+        // parse the key and the tag, they are checked once we've read the
+        // whole command
+        let key_bytes = {
+            let (key, end_of_line) = try!(self.read_word_in_line());
+            return_err_if!(end_of_line, TcpTransportError::CommandParseError);
+            key
+        };
+        let (tag_bytes, end_of_line) = try!(self.read_word_in_line());
+
+        // parse noreply (optional)
+        let noreply_flag = match end_of_line {
+            true => false,
+            false => {
+                let (noreply, end_of_line) = try!(self.read_word_in_line());
+                return_err_if!(!end_of_line,
+                               TcpTransportError::CommandParseError);
+                let noreply_str = try!(as_string(noreply));
+                noreply_str == "noreply"
+            }
+        };
+
+        Ok(Cmd::Tag(Tag {
+            key: try!(as_key(key_bytes)),
+            tag: try!(as_key(tag_bytes)),
+            noreply: noreply_flag,
+        }))
+    }
+
     pub fn parse_cmd_touch(&mut self) -> TcpTransportResult<Cmd> {
-        // parse the key
-        let key_str = {
+        // parse the key, it's checked once we've read the whole command
+        let key_bytes = {
             let (key, end_of_line) = try!(self.read_word_in_line());
             return_err_if!(end_of_line, TcpTransportError::CommandParseError);
-            try!(as_string(key))
+            key
         };
 
         // parse the exptime
@@ -428,18 +766,43 @@ impl<T: Read + Write> TcpTransport<T> {
 
         // We got all the values we expected and there is nothing left
         return Ok(Cmd::Touch(Touch {
-            key: key_str,
+            key: try!(as_key(key_bytes)),
             exptime: exptime_num,
             noreply: noreply_flag,
         }));
     }
 
+    pub fn parse_cmd_watch(&mut self,
+                           end_of_line: bool)
+                           -> TcpTransportResult<Cmd> {
+        // A plain "watch" watches everything
+        if end_of_line {
+            return Ok(Cmd::Watch(Watch::new(true, true, true)));
+        }
+
+        // parse the kinds of events to watch
+        let mut watch = Watch::new(false, false, false);
+        for word in try!(self.read_line_as_words()) {
+            match &word[..] {
+                b"fetchers" => watch.fetchers = true,
+                b"mutations" => watch.mutations = true,
+                b"evictions" => watch.evictions = true,
+                _ => return Err(TcpTransportError::CommandParseError),
+            }
+        }
+
+        Ok(Cmd::Watch(watch))
+    }
+
     // High level functions
 
     pub fn read_cmd(&mut self) -> TcpTransportResult<Cmd> {
-        let keyword_str = {
-            let (word, _) = try!(self.read_word_in_line());
-            try!(as_string(word))
+        // Get past a value we turned down first
+        try!(self.swallow_rejected());
+
+        let (keyword_str, end_of_line) = {
+            let (word, end_of_line) = try!(self.read_word_in_line());
+            (try!(as_string(word)), end_of_line)
         };
 
         // TODO replace if's with something nicer
@@ -461,6 +824,10 @@ impl<T: Read + Write> TcpTransport<T> {
             return self.parse_cmd_set(SetInstr::Prepend);
         } else if keyword_str == "touch" {
             return self.parse_cmd_touch();
+        } else if keyword_str == "tag" {
+            return self.parse_cmd_tag();
+        } else if keyword_str == "invalidate" {
+            return self.parse_cmd_invalidate();
         } else if keyword_str == "incr" {
             return self.parse_cmd_inc(IncInstr::Incr);
         } else if keyword_str == "decr" {
@@ -468,11 +835,17 @@ impl<T: Read + Write> TcpTransport<T> {
         } else if keyword_str == "delete" {
             return self.parse_cmd_delete();
         } else if keyword_str == "flush_all" {
-            return self.parse_cmd_flush_all();
+            return self.parse_cmd_flush_all(end_of_line);
+        } else if keyword_str == "cache_memlimit" {
+            return self.parse_cmd_cache_memlimit();
+        } else if keyword_str == "slowlog" {
+            return self.parse_cmd_slowlog(end_of_line);
         } else if keyword_str == "stats" {
-            return Ok(Cmd::Stats);
+            return self.parse_cmd_stats(end_of_line);
         } else if keyword_str == "version" {
             return Ok(Cmd::Version);
+        } else if keyword_str == "watch" {
+            return self.parse_cmd_watch(end_of_line);
         } else if keyword_str == "quit" {
             return Ok(Cmd::Quit);
         }
@@ -494,6 +867,24 @@ impl<T: Read + Write> TcpTransport<T> {
             Resp::Error => {
                 try!(self.write_string("ERROR\r\n"));
             }
+            Resp::Events(ref events) => {
+                for event in events {
+                    let ttl = match event.ttl {
+                        Some(ttl) => ttl.to_string(),
+                        None => "-1".to_string(),
+                    };
+
+                    try!(self.write_string(&format!("ts={:.6} conn={} \
+                                                     event={} key=",
+                                                    event.time,
+                                                    event.conn,
+                                                    event.kind.name())));
+                    try!(self.write_bytes(&event.key));
+                    try!(self.write_string(&format!(" size={} ttl={}\r\n",
+                                                    event.size,
+                                                    ttl)));
+                }
+            }
             Resp::Exists => {
                 try!(self.write_string("EXISTS\r\n"));
             }
@@ -528,13 +919,16 @@ impl<T: Read + Write> TcpTransport<T> {
             Resp::Stored => {
                 try!(self.write_string("STORED\r\n"));
             }
+            Resp::Tagged => {
+                try!(self.write_string("TAGGED\r\n"));
+            }
             Resp::Touched => {
                 try!(self.write_string("TOUCHED\r\n"));
             }
             Resp::Values(ref values) => {
                 for value in values {
                     try!(self.write_string("VALUE ")); // keyword
-                    try!(self.write_string(&value.key)); // key
+                    try!(self.write_bytes(&value.key)); // key
                     try!(self.write_string(" ")); // space
                     try!(self.write_string(&value.flags.to_string())); // flags
                     try!(self.write_string(" ")); // space
@@ -546,8 +940,7 @@ impl<T: Read + Write> TcpTransport<T> {
                                                      .to_string())); // flags
                     }
                     try!(self.write_string(&"\r\n".to_string())); // newline
-                    try!(self.write_bytes(&value.data)); // data block
-                    try!(self.write_string(&"\r\n".to_string())); // newline
+                    try!(self.write_blob_line(&value.data)); // data block
                 }
                 try!(self.write_string(&"END\r\n".to_string())); // END + newline
             }
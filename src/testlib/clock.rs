@@ -0,0 +1,33 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use platform::clock::Clock;
+
+
+// Where a fake clock starts out, far enough from the epoch for absolute
+// exptimes to be told apart from relative ones
+const FAKE_CLOCK_START: f64 = 1_000_000_000.0;
+
+
+// A clock that only moves when a test moves it. Clones share the time, so a
+// test can hand one to the cache and keep one to move it with.
+#[derive(Clone)]
+pub struct FakeClock {
+    now: Rc<Cell<f64>>,
+}
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock { now: Rc::new(Cell::new(FAKE_CLOCK_START)) }
+    }
+
+    pub fn advance(&self, secs: f64) {
+        self.now.set(self.now.get() + secs);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> f64 {
+        self.now.get()
+    }
+}
@@ -1,4 +1,6 @@
 // Declare sub modules
+pub mod clock;
 pub mod cmp;
 pub mod datagen;
+pub mod tempfile;
 pub mod test_stream;
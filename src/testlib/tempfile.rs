@@ -0,0 +1,37 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use platform::process::get_pid;
+
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+
+// A path in the temp dir that is unique to the test using it. The file (if
+// one was created) is removed when it goes out of scope.
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    pub fn new(prefix: &str) -> TempPath {
+        let cnt = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let name = format!("{}-{}-{}", prefix, get_pid(), cnt);
+
+        TempPath { path: env::temp_dir().join(name) }
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,329 @@
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+
+use bufstream::BufStream;
+
+use protocol::cmd::Cmd;
+use protocol::cmd::GetInstr;
+use protocol::cmd::IncInstr;
+use protocol::cmd::InvalidateInstr;
+use protocol::cmd::Resp;
+use protocol::cmd::SetInstr;
+use protocol::cmd::Stat;
+use protocol::cmd::Value;
+
+
+fn invalid_data(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+fn noreply_word(noreply: bool) -> &'static str {
+    match noreply {
+        true => "noreply",
+        false => "",
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>(word: Option<&str>) -> io::Result<T> {
+    match word.map(|word| word.parse::<T>()) {
+        Some(Ok(num)) => Ok(num),
+        _ => Err(invalid_data(format!("expected a number: {:?}", word))),
+    }
+}
+
+
+// Sends commands to a server in the text protocol and reads back the
+// responses, as the same Resp the driver would have given. Commands that
+// turn the connection into something else (quit, watch) are not supported.
+pub struct Client<T: Read + Write> {
+    stream: BufStream<T>,
+}
+
+impl<T: Read + Write> Client<T> {
+    pub fn new(stream: T) -> Client<T> {
+        Client { stream: BufStream::new(stream) }
+    }
+
+    pub fn get_stream(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    fn write_str(&mut self, string: &str) -> io::Result<()> {
+        self.stream.write_all(string.as_bytes())
+    }
+
+    fn write_cmd(&mut self, cmd: &Cmd) -> io::Result<()> {
+        // There is always a word for noreply, even if it's empty, like other
+        // clients send it
+        match *cmd {
+            Cmd::CacheMemlimit(ref cache_memlimit) => {
+                let noreply = noreply_word(cache_memlimit.noreply);
+                try!(self.write_str(&format!("cache_memlimit {} {}\r\n",
+                                             cache_memlimit.memlimit,
+                                             noreply)));
+            }
+            Cmd::Delete(ref delete) => {
+                try!(self.write_str("delete "));
+                try!(self.stream.write_all(&delete.key));
+                try!(self.write_str(&format!(" {}\r\n",
+                                             noreply_word(delete.noreply))));
+            }
+            Cmd::FlushAll(ref flush_all) => {
+                let exptime = match flush_all.exptime {
+                    Some(exptime) => exptime.to_string(),
+                    None => "".to_string(),
+                };
+                let noreply = noreply_word(flush_all.noreply);
+                try!(self.write_str(&format!("flush_all {} {}\r\n",
+                                             exptime,
+                                             noreply)));
+            }
+            Cmd::Get(ref get) => {
+                try!(self.write_str(match get.instr {
+                    GetInstr::Get => "get",
+                    GetInstr::Gets => "gets",
+                }));
+                for key in &get.keys {
+                    try!(self.write_str(" "));
+                    try!(self.stream.write_all(key));
+                }
+                try!(self.write_str("\r\n"));
+            }
+            Cmd::Inc(ref inc) => {
+                try!(self.write_str(match inc.instr {
+                    IncInstr::Incr => "incr ",
+                    IncInstr::Decr => "decr ",
+                }));
+                try!(self.stream.write_all(&inc.key));
+                try!(self.write_str(&format!(" {} {}\r\n",
+                                             inc.delta,
+                                             noreply_word(inc.noreply))));
+            }
+            Cmd::Invalidate(ref invalidate) => {
+                try!(self.write_str(match invalidate.instr {
+                    InvalidateInstr::Prefix => "invalidate prefix ",
+                    InvalidateInstr::Tag => "invalidate tag ",
+                }));
+                try!(self.stream.write_all(&invalidate.target));
+                let noreply = noreply_word(invalidate.noreply);
+                try!(self.write_str(&format!(" {}\r\n", noreply)));
+            }
+            Cmd::Set(ref set) => {
+                try!(self.write_str(match set.instr {
+                    SetInstr::Set => "set ",
+                    SetInstr::Add => "add ",
+                    SetInstr::Replace => "replace ",
+                    SetInstr::Append => "append ",
+                    SetInstr::Prepend => "prepend ",
+                    SetInstr::Cas => "cas ",
+                }));
+                try!(self.stream.write_all(&set.key));
+                try!(self.write_str(&format!(" {} {} {}",
+                                             set.flags,
+                                             set.exptime,
+                                             set.data.len())));
+                if let Some(cas_unique) = set.cas_unique {
+                    try!(self.write_str(&format!(" {}", cas_unique)));
+                }
+                try!(self.write_str(&format!(" {}\r\n",
+                                             noreply_word(set.noreply))));
+                for chunk in set.data.chunks() {
+                    try!(self.stream.write_all(chunk));
+                }
+                try!(self.write_str("\r\n"));
+            }
+            Cmd::Stats(ref stats) => {
+                match stats.group {
+                    Some(ref group) => {
+                        try!(self.write_str(&format!("stats {}\r\n", group)))
+                    }
+                    None => try!(self.write_str("stats\r\n")),
+                }
+            }
+            Cmd::Tag(ref tag) => {
+                try!(self.write_str("tag "));
+                try!(self.stream.write_all(&tag.key));
+                try!(self.write_str(" "));
+                try!(self.stream.write_all(&tag.tag));
+                try!(self.write_str(&format!(" {}\r\n",
+                                             noreply_word(tag.noreply))));
+            }
+            Cmd::Touch(ref touch) => {
+                try!(self.write_str("touch "));
+                try!(self.stream.write_all(&touch.key));
+                try!(self.write_str(&format!(" {} {}\r\n",
+                                             touch.exptime,
+                                             noreply_word(touch.noreply))));
+            }
+            Cmd::Version => try!(self.write_str("version\r\n")),
+            Cmd::Quit | Cmd::Slowlog(_) | Cmd::Watch(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "command is not supported"));
+            }
+        }
+
+        self.stream.flush()
+    }
+
+    // A line of the response, without the line terminator
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = vec![];
+        try!(self.stream.read_until(b'\n', &mut line));
+        if !line.ends_with(b"\r\n") {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "connection closed"));
+        }
+
+        let len = line.len() - 2;
+        line.truncate(len);
+        Ok(line)
+    }
+
+    fn read_values(&mut self) -> io::Result<Resp> {
+        let mut values = vec![];
+
+        loop {
+            let line = try!(self.read_line());
+            if line == b"END" {
+                return Ok(Resp::Values(values));
+            }
+
+            let header = String::from_utf8_lossy(&line).into_owned();
+            let mut words = header.split(' ');
+            if words.next() != Some("VALUE") {
+                return Err(invalid_data(format!("expected a value: {}",
+                                                header)));
+            }
+            let key = words.next().unwrap_or("").as_bytes().to_vec();
+            let flags = try!(parse_number::<u32>(words.next()));
+            let len = try!(parse_number::<usize>(words.next()));
+            let cas_unique = match words.next() {
+                Some(word) => Some(try!(parse_number::<u64>(Some(word)))),
+                None => None,
+            };
+
+            // The data block is followed by a line terminator of its own
+            let mut data = vec![0; len + 2];
+            try!(self.stream.read_exact(&mut data));
+            data.truncate(len);
+
+            let mut value = Value::new(&key, flags, data);
+            if let Some(cas_unique) = cas_unique {
+                value.with_cas_unique(cas_unique);
+            }
+            values.push(value);
+        }
+    }
+
+    fn read_stats(&mut self) -> io::Result<Resp> {
+        let mut stats = vec![];
+
+        loop {
+            let line = try!(self.read_line());
+            if line == b"END" {
+                return Ok(Resp::Stats(stats));
+            }
+
+            let line = String::from_utf8_lossy(&line).into_owned();
+            let mut words = line.splitn(3, ' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some("STAT"), Some(key), value) => {
+                    stats.push(Stat::new(key,
+                                         value.unwrap_or("").to_string()));
+                }
+                _ => {
+                    return Err(invalid_data(format!("expected a stat: {}",
+                                                    line)))
+                }
+            }
+        }
+    }
+
+    fn read_status(&mut self) -> io::Result<Resp> {
+        let line = try!(self.read_line());
+        let line = String::from_utf8_lossy(&line).into_owned();
+
+        let resp = match &line[..] {
+            "DELETED" => Resp::Deleted,
+            "END" => Resp::Values(vec![]),
+            "ERROR" => Resp::Error,
+            "EXISTS" => Resp::Exists,
+            "NOT_FOUND" => Resp::NotFound,
+            "NOT_STORED" => Resp::NotStored,
+            "OK" => Resp::Ok,
+            "STORED" => Resp::Stored,
+            "TAGGED" => Resp::Tagged,
+            "TOUCHED" => Resp::Touched,
+            _ if line.starts_with("CLIENT_ERROR ") => {
+                Resp::ClientError(line["CLIENT_ERROR ".len()..].to_string())
+            }
+            _ if line.starts_with("SERVER_ERROR ") => {
+                Resp::ServerError(line["SERVER_ERROR ".len()..].to_string())
+            }
+            _ if line.starts_with("VERSION ") => {
+                Resp::Version(line["VERSION ".len()..].to_string())
+            }
+            _ => {
+                match line.parse::<u64>() {
+                    Ok(num) => Resp::IntValue(num),
+                    Err(_) => {
+                        return Err(invalid_data(format!("unknown \
+                                                         response: {}",
+                                                        line)))
+                    }
+                }
+            }
+        };
+
+        Ok(resp)
+    }
+
+    fn read_resp(&mut self, cmd: &Cmd) -> io::Result<Resp> {
+        match *cmd {
+            Cmd::Get(_) => {
+                // An error comes instead of the values
+                match try!(self.peek_error()) {
+                    true => self.read_status(),
+                    false => self.read_values(),
+                }
+            }
+            Cmd::Stats(_) => {
+                match try!(self.peek_error()) {
+                    true => self.read_status(),
+                    false => self.read_stats(),
+                }
+            }
+            _ => self.read_status(),
+        }
+    }
+
+    // Does the response start with an error rather than what we asked for?
+    fn peek_error(&mut self) -> io::Result<bool> {
+        let buf = try!(self.stream.fill_buf());
+        Ok(buf.starts_with(b"ERROR") || buf.starts_with(b"CLIENT_ERROR") ||
+           buf.starts_with(b"SERVER_ERROR"))
+    }
+
+    pub fn run(&mut self, cmd: &Cmd) -> io::Result<Resp> {
+        try!(self.write_cmd(cmd));
+
+        let noreply = match *cmd {
+            Cmd::CacheMemlimit(ref cache_memlimit) => cache_memlimit.noreply,
+            Cmd::Delete(ref delete) => delete.noreply,
+            Cmd::FlushAll(ref flush_all) => flush_all.noreply,
+            Cmd::Inc(ref inc) => inc.noreply,
+            Cmd::Invalidate(ref invalidate) => invalidate.noreply,
+            Cmd::Set(ref set) => set.noreply,
+            Cmd::Tag(ref tag) => tag.noreply,
+            Cmd::Touch(ref touch) => touch.noreply,
+            _ => false,
+        };
+        if noreply {
+            return Ok(Resp::Empty);
+        }
+
+        self.read_resp(cmd)
+    }
+}
@@ -0,0 +1,377 @@
+use std::io;
+use std::io::Read;
+
+use common::blob::Blob;
+use options::MAX_ITEM_SIZE;
+use protocol::cmd::CacheMemlimit;
+use protocol::cmd::Cmd;
+use protocol::cmd::Delete;
+use protocol::cmd::FlushAll;
+use protocol::cmd::Get;
+use protocol::cmd::GetInstr;
+use protocol::cmd::Inc;
+use protocol::cmd::IncInstr;
+use protocol::cmd::Invalidate;
+use protocol::cmd::InvalidateInstr;
+use protocol::cmd::Set;
+use protocol::cmd::SetInstr;
+use protocol::cmd::Slowlog;
+use protocol::cmd::SlowlogInstr;
+use protocol::cmd::Stats;
+use protocol::cmd::Tag;
+use protocol::cmd::Touch;
+use protocol::cmd::Watch;
+
+
+// A trace starts with the magic bytes, the version of the format and the
+// flags, followed by the unixtime it started at (in microseconds)
+pub const MAGIC: &'static [u8] = b"EMTRACE";
+pub const VERSION: u8 = 1;
+
+// The data of sets is in the trace, not just its size
+pub const FLAG_VALUES: u8 = 1;
+
+
+// Every command starts with one of these
+const OP_CACHE_MEMLIMIT: u8 = 0;
+const OP_DELETE: u8 = 1;
+const OP_FLUSH_ALL: u8 = 2;
+const OP_GET: u8 = 3;
+const OP_INC: u8 = 4;
+const OP_INVALIDATE: u8 = 5;
+const OP_QUIT: u8 = 6;
+const OP_SET: u8 = 7;
+const OP_SLOWLOG: u8 = 8;
+const OP_STATS: u8 = 9;
+const OP_TAG: u8 = 10;
+const OP_TOUCH: u8 = 11;
+const OP_VERSION: u8 = 12;
+const OP_WATCH: u8 = 13;
+
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+
+// Numbers are written 7 bits at a time, least significant first, with the
+// high bit set on every byte but the last. Small numbers (which most sizes,
+// flags and time deltas are) take a byte or two.
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn read_varint<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = try!(read_u8(input));
+        if shift > 63 {
+            return Err(invalid_data("varint too long"));
+        }
+
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+pub fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    try!(input.read_exact(&mut buf));
+    Ok(buf[0])
+}
+
+// None is written as 0, anything else shifted up by one
+fn write_opt(out: &mut Vec<u8>, value: Option<u64>) {
+    write_varint(out, value.map_or(0, |value| value + 1));
+}
+
+fn read_opt<R: Read>(input: &mut R) -> io::Result<Option<u64>> {
+    let value = try!(read_varint(input));
+    Ok(value.checked_sub(1))
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn read_bytes<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let len = try!(read_varint(input));
+    read_exact_vec(input, len)
+}
+
+fn read_exact_vec<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    try!(input.take(len).read_to_end(&mut bytes));
+    if (bytes.len() as u64) < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                  "trace ends in the middle of a record"));
+    }
+
+    Ok(bytes)
+}
+
+fn write_bool(out: &mut Vec<u8>, value: bool) {
+    out.push(value as u8);
+}
+
+fn read_bool<R: Read>(input: &mut R) -> io::Result<bool> {
+    match try!(read_u8(input)) {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(invalid_data("bad bool")),
+    }
+}
+
+
+// Appends the command to the buffer. Without values only the size of the
+// data of a set is kept.
+pub fn encode_cmd(out: &mut Vec<u8>, cmd: &Cmd, with_values: bool) {
+    match *cmd {
+        Cmd::CacheMemlimit(ref cache_memlimit) => {
+            out.push(OP_CACHE_MEMLIMIT);
+            write_varint(out, cache_memlimit.memlimit);
+            write_bool(out, cache_memlimit.noreply);
+        }
+        Cmd::Delete(ref delete) => {
+            out.push(OP_DELETE);
+            write_bytes(out, &delete.key);
+            write_bool(out, delete.noreply);
+        }
+        Cmd::FlushAll(ref flush_all) => {
+            out.push(OP_FLUSH_ALL);
+            write_opt(out, flush_all.exptime.map(|exptime| exptime as u64));
+            write_bool(out, flush_all.noreply);
+        }
+        Cmd::Get(ref get) => {
+            out.push(OP_GET);
+            out.push(match get.instr {
+                GetInstr::Get => 0,
+                GetInstr::Gets => 1,
+            });
+            write_varint(out, get.keys.len() as u64);
+            for key in &get.keys {
+                write_bytes(out, key);
+            }
+        }
+        Cmd::Inc(ref inc) => {
+            out.push(OP_INC);
+            out.push(match inc.instr {
+                IncInstr::Incr => 0,
+                IncInstr::Decr => 1,
+            });
+            write_bytes(out, &inc.key);
+            write_varint(out, inc.delta);
+            write_bool(out, inc.noreply);
+        }
+        Cmd::Invalidate(ref invalidate) => {
+            out.push(OP_INVALIDATE);
+            out.push(match invalidate.instr {
+                InvalidateInstr::Prefix => 0,
+                InvalidateInstr::Tag => 1,
+            });
+            write_bytes(out, &invalidate.target);
+            write_bool(out, invalidate.noreply);
+        }
+        Cmd::Quit => out.push(OP_QUIT),
+        Cmd::Set(ref set) => {
+            out.push(OP_SET);
+            out.push(match set.instr {
+                SetInstr::Set => 0,
+                SetInstr::Add => 1,
+                SetInstr::Replace => 2,
+                SetInstr::Append => 3,
+                SetInstr::Prepend => 4,
+                SetInstr::Cas => 5,
+            });
+            write_bytes(out, &set.key);
+            write_varint(out, set.flags as u64);
+            write_varint(out, set.exptime as u64);
+            write_varint(out, set.data.len() as u64);
+            if with_values {
+                for chunk in set.data.chunks() {
+                    out.extend_from_slice(chunk);
+                }
+            }
+            write_opt(out, set.cas_unique);
+            write_bool(out, set.noreply);
+        }
+        Cmd::Slowlog(ref slowlog) => {
+            out.push(OP_SLOWLOG);
+            out.push(match slowlog.instr {
+                SlowlogInstr::Get => 0,
+                SlowlogInstr::Reset => 1,
+            });
+            write_opt(out, slowlog.count);
+        }
+        Cmd::Stats(ref stats) => {
+            out.push(OP_STATS);
+            write_bool(out, stats.group.is_some());
+            if let Some(ref group) = stats.group {
+                write_bytes(out, group.as_bytes());
+            }
+        }
+        Cmd::Tag(ref tag) => {
+            out.push(OP_TAG);
+            write_bytes(out, &tag.key);
+            write_bytes(out, &tag.tag);
+            write_bool(out, tag.noreply);
+        }
+        Cmd::Touch(ref touch) => {
+            out.push(OP_TOUCH);
+            write_bytes(out, &touch.key);
+            write_varint(out, touch.exptime as u64);
+            write_bool(out, touch.noreply);
+        }
+        Cmd::Version => out.push(OP_VERSION),
+        Cmd::Watch(ref watch) => {
+            out.push(OP_WATCH);
+            write_bool(out, watch.fetchers);
+            write_bool(out, watch.mutations);
+            write_bool(out, watch.evictions);
+        }
+    }
+}
+
+// Reads back a command written by encode_cmd. Without values the data of a
+// set is made up of zeroes, as many as there were.
+pub fn decode_cmd<R: Read>(input: &mut R,
+                           with_values: bool)
+                           -> io::Result<Cmd> {
+    let cmd = match try!(read_u8(input)) {
+        OP_CACHE_MEMLIMIT => {
+            let memlimit = try!(read_varint(input));
+            Cmd::CacheMemlimit(CacheMemlimit::new(memlimit,
+                                                  try!(read_bool(input))))
+        }
+        OP_DELETE => {
+            let key = try!(read_bytes(input));
+            Cmd::Delete(Delete::new(&key, try!(read_bool(input))))
+        }
+        OP_FLUSH_ALL => {
+            let exptime = try!(read_opt(input)).map(|exptime| exptime as u32);
+            Cmd::FlushAll(FlushAll::new(exptime, try!(read_bool(input))))
+        }
+        OP_GET => {
+            let instr = match try!(read_u8(input)) {
+                0 => GetInstr::Get,
+                1 => GetInstr::Gets,
+                _ => return Err(invalid_data("bad get instruction")),
+            };
+            let count = try!(read_varint(input));
+            let mut keys = vec![];
+            for _ in 0..count {
+                keys.push(try!(read_bytes(input)));
+            }
+            Cmd::Get(Get::new(instr, keys))
+        }
+        OP_INC => {
+            let instr = match try!(read_u8(input)) {
+                0 => IncInstr::Incr,
+                1 => IncInstr::Decr,
+                _ => return Err(invalid_data("bad inc instruction")),
+            };
+            let key = try!(read_bytes(input));
+            let delta = try!(read_varint(input));
+            Cmd::Inc(Inc::new(instr, &key, delta, try!(read_bool(input))))
+        }
+        OP_INVALIDATE => {
+            let instr = match try!(read_u8(input)) {
+                0 => InvalidateInstr::Prefix,
+                1 => InvalidateInstr::Tag,
+                _ => return Err(invalid_data("bad invalidate instruction")),
+            };
+            let target = try!(read_bytes(input));
+            Cmd::Invalidate(Invalidate::new(instr,
+                                            &target,
+                                            try!(read_bool(input))))
+        }
+        OP_QUIT => Cmd::Quit,
+        OP_SET => {
+            let instr = match try!(read_u8(input)) {
+                0 => SetInstr::Set,
+                1 => SetInstr::Add,
+                2 => SetInstr::Replace,
+                3 => SetInstr::Append,
+                4 => SetInstr::Prepend,
+                5 => SetInstr::Cas,
+                _ => return Err(invalid_data("bad set instruction")),
+            };
+            let key = try!(read_bytes(input));
+            let flags = try!(read_varint(input)) as u32;
+            let exptime = try!(read_varint(input)) as u32;
+            let len = try!(read_varint(input));
+            // Without the values nothing backs the size, so a broken one
+            // must not make us allocate whatever it says
+            if len > MAX_ITEM_SIZE {
+                return Err(invalid_data("set data too large"));
+            }
+            let data = match with_values {
+                true => try!(read_exact_vec(input, len)),
+                false => vec![0; len as usize],
+            };
+            let cas_unique = try!(read_opt(input));
+            let noreply = try!(read_bool(input));
+
+            Cmd::Set(Set {
+                instr: instr,
+                key: key,
+                flags: flags,
+                exptime: exptime,
+                data: Blob::from_slice(&data),
+                cas_unique: cas_unique,
+                noreply: noreply,
+            })
+        }
+        OP_SLOWLOG => {
+            let instr = match try!(read_u8(input)) {
+                0 => SlowlogInstr::Get,
+                1 => SlowlogInstr::Reset,
+                _ => return Err(invalid_data("bad slowlog instruction")),
+            };
+            Cmd::Slowlog(Slowlog::new(instr, try!(read_opt(input))))
+        }
+        OP_STATS => {
+            let group = match try!(read_bool(input)) {
+                true => {
+                    let group = try!(read_bytes(input));
+                    match String::from_utf8(group) {
+                        Ok(group) => Some(group),
+                        Err(_) => return Err(invalid_data("bad stats group")),
+                    }
+                }
+                false => None,
+            };
+            Cmd::Stats(Stats { group: group })
+        }
+        OP_TAG => {
+            let key = try!(read_bytes(input));
+            let tag = try!(read_bytes(input));
+            Cmd::Tag(Tag::new(&key, &tag, try!(read_bool(input))))
+        }
+        OP_TOUCH => {
+            let key = try!(read_bytes(input));
+            let exptime = try!(read_varint(input)) as u32;
+            Cmd::Touch(Touch::new(&key, exptime, try!(read_bool(input))))
+        }
+        OP_VERSION => Cmd::Version,
+        OP_WATCH => {
+            let fetchers = try!(read_bool(input));
+            let mutations = try!(read_bool(input));
+            let evictions = try!(read_bool(input));
+            Cmd::Watch(Watch::new(fetchers, mutations, evictions))
+        }
+        _ => return Err(invalid_data("unknown command")),
+    };
+
+    Ok(cmd)
+}
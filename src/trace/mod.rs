@@ -0,0 +1,22 @@
+// Declare sub modules
+pub mod client;
+pub mod codec;
+pub mod reader;
+pub mod record;
+pub mod replay;
+pub mod simulate;
+pub mod writer;
+
+// internal stuff
+#[cfg(test)]
+mod tests;  // needed to be part of the compilation unit in test mode
+
+
+// Export our public api
+pub use self::client::Client;
+pub use self::reader::TraceReader;
+pub use self::record::TraceRecord;
+pub use self::replay::ReplayStats;
+pub use self::replay::replay;
+pub use self::simulate::simulate;
+pub use self::writer::TraceWriter;
@@ -0,0 +1,90 @@
+use std::io;
+use std::io::Read;
+
+use super::TraceRecord;
+use super::codec::FLAG_VALUES;
+use super::codec::MAGIC;
+use super::codec::VERSION;
+use super::codec::decode_cmd;
+use super::codec::read_u8;
+use super::codec::read_varint;
+
+
+fn from_micros(time: u64) -> f64 {
+    time as f64 / 1_000_000.0
+}
+
+
+// Reads back the commands of a trace written by a TraceWriter, in order
+pub struct TraceReader<R: Read> {
+    input: R,
+    with_values: bool, // Are the values of sets in the trace?
+    start: f64, // unixtime when the trace was started
+    last_time: u64, // microseconds, of the command before
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+        // Anything too short to have the magic bytes is not a trace either
+        let mut magic = vec![0; MAGIC.len()];
+        if input.read_exact(&mut magic).is_err() || magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "not a trace"));
+        }
+        if try!(read_u8(&mut input)) != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "unsupported trace version"));
+        }
+        let flags = try!(read_u8(&mut input));
+        let start = try!(read_varint(&mut input));
+
+        Ok(TraceReader {
+            input: input,
+            with_values: flags & FLAG_VALUES != 0,
+            start: from_micros(start),
+            last_time: start,
+        })
+    }
+
+    pub fn has_values(&self) -> bool {
+        self.with_values
+    }
+
+    pub fn get_start(&self) -> f64 {
+        self.start
+    }
+
+    // The next command, None at the end of the trace
+    pub fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        // A trace may end after any whole record, but not inside one
+        let mut first = [0; 1];
+        loop {
+            match self.input.read(&mut first) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut rest = (&first[..]).chain(&mut self.input);
+        let delta = try!(read_varint(&mut rest));
+        let conn = try!(read_varint(&mut self.input));
+        let cmd = try!(decode_cmd(&mut self.input, self.with_values));
+
+        self.last_time += delta;
+        Ok(Some(TraceRecord::new(from_micros(self.last_time), conn, cmd)))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<io::Result<TraceRecord>> {
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use protocol::cmd::Cmd;
+
+
+// A command as it came in
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceRecord {
+    pub time: f64, // unixtime when it was read, to the microsecond
+    pub conn: u64, // The connection it came in on
+    pub cmd: Cmd,
+}
+
+impl TraceRecord {
+    pub fn new(time: f64, conn: u64, cmd: Cmd) -> TraceRecord {
+        TraceRecord {
+            time: time,
+            conn: conn,
+            cmd: cmd,
+        }
+    }
+}
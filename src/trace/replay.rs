@@ -0,0 +1,154 @@
+use std::io;
+
+use metrics::Histogram;
+use platform::time::sleep_secs;
+use platform::time::time_now;
+use protocol::cmd::Cmd;
+use protocol::cmd::Resp;
+
+use super::TraceRecord;
+
+
+// How a replay went
+pub struct ReplayStats {
+    pub cmds: u64, // Commands that were run
+    pub skipped: u64, // Commands that can't be replayed (quit, watch...)
+    pub errors: u64, // Commands that got an error back
+    pub get_hits: u64, // Keys that were found, over all gets
+    pub get_misses: u64, // Keys that were not found, over all gets
+    pub latencies: Histogram, // Of every command, in seconds
+    pub elapsed: f64, // How long the replay took in seconds
+}
+
+impl ReplayStats {
+    pub fn new() -> ReplayStats {
+        ReplayStats {
+            cmds: 0,
+            skipped: 0,
+            errors: 0,
+            get_hits: 0,
+            get_misses: 0,
+            latencies: Histogram::new(),
+            elapsed: 0.0,
+        }
+    }
+
+    // Between 0 and 1, None if there were no gets
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.get_hits + self.get_misses;
+        match total {
+            0 => None,
+            _ => Some(self.get_hits as f64 / total as f64),
+        }
+    }
+
+    // The number of keys is given for gets
+    fn record(&mut self, get_keys: Option<usize>, resp: &Resp, latency: f64) {
+        self.cmds += 1;
+        self.latencies.add(latency);
+
+        match *resp {
+            Resp::Error | Resp::ClientError(_) | Resp::ServerError(_) => {
+                self.errors += 1;
+            }
+            _ => (),
+        }
+
+        if let Some(keys) = get_keys {
+            let hits = resp.get_values().map_or(0, |values| values.len());
+            self.get_hits += hits as u64;
+            self.get_misses += keys.saturating_sub(hits) as u64;
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let micros = |value: Option<f64>| value.unwrap_or(0.0) * 1_000_000.0;
+        let hit_rate = match self.hit_rate() {
+            Some(rate) => format!("{:.2}%", rate * 100.0),
+            None => "-".to_string(),
+        };
+        let throughput = match self.elapsed > 0.0 {
+            true => self.cmds as f64 / self.elapsed,
+            false => 0.0,
+        };
+
+        let mut lines = vec![];
+        lines.push(format!("commands:  {} ({} skipped, {} errors)",
+                           self.cmds,
+                           self.skipped,
+                           self.errors));
+        lines.push(format!("gets:      {} hits, {} misses, hit rate {}",
+                           self.get_hits,
+                           self.get_misses,
+                           hit_rate));
+        lines.push(format!("latency:   avg {:.1}us, p50 {:.1}us, p99 {:.1}us, \
+                            max {:.1}us",
+                           micros(self.latencies.average()),
+                           micros(self.latencies.percentile(0.5)),
+                           micros(self.latencies.percentile(0.99)),
+                           micros(self.latencies.max())));
+        lines.push(format!("elapsed:   {:.3}s ({:.0} commands/s)",
+                           self.elapsed,
+                           throughput));
+
+        lines.join("\n")
+    }
+}
+
+
+// Runs the commands of a trace one after the other, at the pace they
+// originally came in divided by the speed (2.0 is twice as fast). With a
+// speed of 0 they are run as fast as they can be.
+//
+// A trace that was cut off (eg. because the server was killed while
+// recording) is replayed up to the last whole command.
+pub fn replay<I, F>(records: I,
+                    speed: f64,
+                    mut run: F)
+                    -> io::Result<ReplayStats>
+    where I: Iterator<Item = io::Result<TraceRecord>>,
+          F: FnMut(Cmd) -> io::Result<Resp>
+{
+    let mut stats = ReplayStats::new();
+    let started_at = time_now();
+    let mut first_time = None;
+
+    for record in records {
+        let record = match record {
+            Ok(record) => record,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+
+        match record.cmd {
+            Cmd::Quit | Cmd::Slowlog(_) | Cmd::Watch(_) => {
+                stats.skipped += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        // Wait until it's time for the command
+        let first_time = *first_time.get_or_insert(record.time);
+        if speed > 0.0 {
+            let due_at = started_at + (record.time - first_time) / speed;
+            let now = time_now();
+            if due_at > now {
+                sleep_secs(due_at - now);
+            }
+        }
+
+        let get_keys = match record.cmd {
+            Cmd::Get(ref get) => Some(get.keys.len()),
+            _ => None,
+        };
+        let sent_at = time_now();
+        let resp = try!(run(record.cmd));
+        stats.record(get_keys, &resp, time_now() - sent_at);
+    }
+
+    stats.elapsed = time_now() - started_at;
+    Ok(stats)
+}
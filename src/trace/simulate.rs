@@ -0,0 +1,127 @@
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io;
+use std::rc::Rc;
+
+use platform::clock::Clock;
+use protocol::Driver;
+use protocol::cmd::Cmd;
+use storage::Cache;
+use storage::EvictionPolicy;
+
+use super::ReplayStats;
+use super::TraceRecord;
+use super::replay;
+
+
+// Keys are sampled by their hash, with this many possible outcomes
+const SAMPLE_SCALE: u64 = 1_000_000;
+
+
+// Tells the time of the command being simulated, so that items expire as
+// they would have when the trace was recorded
+#[derive(Clone)]
+struct TraceClock {
+    now: Rc<Cell<f64>>,
+}
+
+impl TraceClock {
+    fn new() -> TraceClock {
+        TraceClock { now: Rc::new(Cell::new(0.0)) }
+    }
+
+    fn set(&self, now: f64) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for TraceClock {
+    fn now(&self) -> f64 {
+        self.now.get()
+    }
+}
+
+
+// Whether the key is in a sample of the given share of all keys. A key is
+// either always in it or never, so that the sample sees every command on the
+// keys it has.
+pub fn is_sampled(key: &[u8], rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+
+    // The hasher has fixed keys, so the sample is the same on every run
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() % SAMPLE_SCALE < (rate * SAMPLE_SCALE as f64) as u64
+}
+
+// The command as far as it concerns the keys in the sample, None if it
+// doesn't touch any of them or has no bearing on what is in the cache
+fn sample_cmd(cmd: Cmd, rate: f64) -> Option<Cmd> {
+    match cmd {
+        Cmd::Get(mut get) => {
+            get.keys.retain(|key| is_sampled(key, rate));
+            match get.keys.is_empty() {
+                true => None,
+                false => Some(Cmd::Get(get)),
+            }
+        }
+        Cmd::Delete(_) | Cmd::Inc(_) | Cmd::Set(_) | Cmd::Tag(_) |
+        Cmd::Touch(_) => {
+            match cmd.keys().iter().all(|key| is_sampled(key, rate)) {
+                true => Some(cmd),
+                false => None,
+            }
+        }
+        Cmd::FlushAll(_) | Cmd::Invalidate(_) => Some(cmd),
+        // The rest doesn't change what is in the cache, except for
+        // cache_memlimit which would change the capacity we are simulating
+        _ => None,
+    }
+}
+
+fn sample_record(record: io::Result<TraceRecord>,
+                 rate: f64)
+                 -> Option<io::Result<TraceRecord>> {
+    match record {
+        Ok(record) => {
+            let (time, conn) = (record.time, record.conn);
+            sample_cmd(record.cmd, rate)
+                .map(|cmd| Ok(TraceRecord::new(time, conn, cmd)))
+        }
+        Err(err) => Some(Err(err)),
+    }
+}
+
+
+// Runs a trace through a cache of the given capacity (in bytes) and policy,
+// the same one the server uses, as fast as it can. With a sample rate below
+// 1 only that share of the keys is run, through a cache that is scaled down
+// by as much, which gives close to the same hit rate for a fraction of the
+// memory and time.
+pub fn simulate<I>(records: I,
+                   policy: EvictionPolicy,
+                   capacity: u64,
+                   rate: f64)
+                   -> io::Result<ReplayStats>
+    where I: Iterator<Item = io::Result<TraceRecord>>
+{
+    let clock = TraceClock::new();
+    let mut cache = Cache::new((capacity as f64 * rate.min(1.0)) as u64);
+    cache.with_policy(policy);
+    cache.with_clock(Box::new(clock.clone()));
+    let mut driver = Driver::new(cache);
+
+    // Move time on before each command runs
+    let records = records.filter_map(|record| sample_record(record, rate))
+                         .inspect(|record| {
+                             if let Ok(ref record) = *record {
+                                 clock.set(record.time);
+                             }
+                         });
+
+    replay(records, 0.0, |cmd| Ok(driver.run(cmd)))
+}
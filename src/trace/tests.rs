@@ -0,0 +1,344 @@
+use std::io;
+
+use protocol::Driver;
+use protocol::cmd::CacheMemlimit;
+use protocol::cmd::Cmd;
+use protocol::cmd::Delete;
+use protocol::cmd::FlushAll;
+use protocol::cmd::Get;
+use protocol::cmd::GetInstr;
+use protocol::cmd::Inc;
+use protocol::cmd::IncInstr;
+use protocol::cmd::Invalidate;
+use protocol::cmd::InvalidateInstr;
+use protocol::cmd::Resp;
+use protocol::cmd::Set;
+use protocol::cmd::SetInstr;
+use protocol::cmd::Slowlog;
+use protocol::cmd::SlowlogInstr;
+use protocol::cmd::Stat;
+use protocol::cmd::Stats;
+use protocol::cmd::Tag;
+use protocol::cmd::Touch;
+use protocol::cmd::Value;
+use protocol::cmd::Watch;
+use common::blob::Blob;
+use storage::Cache;
+use storage::EvictionPolicy;
+use storage::Key;
+use storage::Value as SValue;
+use storage::entry_mem_size;
+use testlib::test_stream::TestStream;
+
+use super::Client;
+use super::TraceReader;
+use super::TraceRecord;
+use super::TraceWriter;
+use super::codec::decode_cmd;
+use super::codec::encode_cmd;
+use super::codec::write_varint;
+use super::replay;
+use super::simulate;
+use super::simulate::is_sampled;
+
+
+fn all_cmds() -> Vec<Cmd> {
+    let mut cas = Set::new(SetInstr::Cas, b"x", 3, 60, vec![1, 2], true);
+    cas.with_cas_unique(1 << 40);
+
+    vec![Cmd::CacheMemlimit(CacheMemlimit::new(128, false)),
+         Cmd::Delete(Delete::new(b"x", true)),
+         Cmd::FlushAll(FlushAll::new(Some(10), false)),
+         Cmd::FlushAll(FlushAll::new(None, true)),
+         Cmd::Get(Get::new(GetInstr::Gets,
+                           vec![b"x".to_vec(), b"y".to_vec()])),
+         Cmd::Inc(Inc::new(IncInstr::Decr, b"x", 300, false)),
+         Cmd::Invalidate(Invalidate::new(InvalidateInstr::Tag, b"t", false)),
+         Cmd::Quit,
+         Cmd::Set(Set::new(SetInstr::Append, b"x", 0, 0, vec![7; 300], false)),
+         Cmd::Set(cas),
+         Cmd::Slowlog(Slowlog::new(SlowlogInstr::Get, Some(5))),
+         Cmd::Stats(Stats::new(Some("slabs"))),
+         Cmd::Stats(Stats::new(None)),
+         Cmd::Tag(Tag::new(b"x", b"t", true)),
+         Cmd::Touch(Touch::new(b"x", 1 << 20, false)),
+         Cmd::Version,
+         Cmd::Watch(Watch::new(true, false, true))]
+}
+
+fn write_trace(records: &[TraceRecord], with_values: bool) -> Vec<u8> {
+    let mut writer = TraceWriter::new(vec![], with_values, 1000.0).unwrap();
+    for record in records {
+        writer.write(record.time, record.conn, &record.cmd).unwrap();
+    }
+    writer.into_inner()
+}
+
+
+// Encoding commands
+
+#[test]
+fn test_codec_round_trip() {
+    for cmd in all_cmds() {
+        let mut buf = vec![];
+        encode_cmd(&mut buf, &cmd, true);
+
+        let decoded = decode_cmd(&mut &buf[..], true).unwrap();
+        assert_eq!(cmd, decoded);
+    }
+}
+
+#[test]
+fn test_codec_without_values() {
+    let cmd = Cmd::Set(Set::new(SetInstr::Set, b"x", 5, 0, vec![9; 70], true));
+    let mut buf = vec![];
+    encode_cmd(&mut buf, &cmd, false);
+
+    // Only the size of the data is kept
+    assert!(buf.len() < 10);
+    let decoded = decode_cmd(&mut &buf[..], false).unwrap();
+    let expected = Set::new(SetInstr::Set, b"x", 5, 0, vec![0; 70], true);
+    assert_eq!(Cmd::Set(expected), decoded);
+}
+
+#[test]
+fn test_codec_data_too_large() {
+    let cmd = Cmd::Set(Set::new(SetInstr::Set, b"x", 5, 0, vec![9; 70], true));
+    let mut buf = vec![];
+    encode_cmd(&mut buf, &cmd, false);
+
+    // Make the size far larger than any item can be
+    let mut broken = buf[..buf.len() - 3].to_vec();
+    write_varint(&mut broken, 1 << 60);
+    broken.extend_from_slice(&buf[buf.len() - 2..]);
+    let err = decode_cmd(&mut &broken[..], false).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn test_codec_unknown_op() {
+    let err = decode_cmd(&mut &[99u8][..], true).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+
+// Writing and reading traces
+
+#[test]
+fn test_trace_round_trip() {
+    let records: Vec<TraceRecord> = all_cmds()
+                                        .into_iter()
+                                        .enumerate()
+                                        .map(|(i, cmd)| {
+                                            TraceRecord::new(1000.5 +
+                                                             i as f64,
+                                                             i as u64 % 3,
+                                                             cmd)
+                                        })
+                                        .collect();
+    let trace = write_trace(&records, true);
+
+    let reader = TraceReader::new(&trace[..]).unwrap();
+    assert!(reader.has_values());
+    assert_eq!(1000.0, reader.get_start());
+
+    let read: Vec<TraceRecord> = reader.map(|rec| rec.unwrap()).collect();
+    assert_eq!(records, read);
+}
+
+#[test]
+fn test_trace_time_never_goes_back() {
+    let get = Cmd::Get(Get::one(GetInstr::Get, b"x"));
+    let records = vec![TraceRecord::new(1002.0, 1, get.clone()),
+                       TraceRecord::new(1001.0, 2, get.clone())];
+    let trace = write_trace(&records, false);
+
+    let mut reader = TraceReader::new(&trace[..]).unwrap();
+    assert!(!reader.has_values());
+    assert_eq!(1002.0, reader.read_record().unwrap().unwrap().time);
+    assert_eq!(1002.0, reader.read_record().unwrap().unwrap().time);
+    assert!(reader.read_record().unwrap().is_none());
+}
+
+#[test]
+fn test_trace_cut_off() {
+    let get = Cmd::Get(Get::one(GetInstr::Get, b"abc"));
+    let records = vec![TraceRecord::new(1000.0, 1, get.clone()),
+                       TraceRecord::new(1000.0, 1, get.clone())];
+    let mut trace = write_trace(&records, true);
+    let len = trace.len() - 2;
+    trace.truncate(len);
+
+    // The whole record is read, the one that was cut off is an error
+    let mut reader = TraceReader::new(&trace[..]).unwrap();
+    assert_eq!(get, reader.read_record().unwrap().unwrap().cmd);
+    let err = reader.read_record().unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+#[test]
+fn test_trace_not_a_trace() {
+    let err = TraceReader::new(&b"VALUE x 0 1\r\n"[..]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+    // Too short to tell
+    let err = TraceReader::new(&b"END\r\n"[..]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+
+// Client
+
+#[test]
+fn test_client_set_and_get() {
+    let ts = TestStream::new(b"STORED\r\nVALUE x 3 2 9\r\nab\r\nEND\r\n"
+                                 .to_vec());
+    let mut client = Client::new(ts);
+
+    let set = Set::new(SetInstr::Set, b"x", 3, 0, b"ab".to_vec(), false);
+    assert_eq!(Resp::Stored, client.run(&Cmd::Set(set)).unwrap());
+
+    let get = Get::one(GetInstr::Gets, b"x");
+    let mut value = Value::new(b"x", 3, b"ab".to_vec());
+    value.with_cas_unique(9);
+    assert_eq!(Resp::Values(vec![value]),
+               client.run(&Cmd::Get(get)).unwrap());
+
+    assert_eq!(b"set x 3 0 2 \r\nab\r\ngets x\r\n".to_vec(),
+               client.get_stream().outgoing);
+}
+
+#[test]
+fn test_client_noreply_and_errors() {
+    let ts = TestStream::new(b"ERROR\r\nSTAT pid 12\r\nEND\r\n".to_vec());
+    let mut client = Client::new(ts);
+
+    // Nothing to read back after a noreply
+    let delete = Delete::new(b"x", true);
+    assert_eq!(Resp::Empty, client.run(&Cmd::Delete(delete)).unwrap());
+
+    let get = Get::one(GetInstr::Get, b"x");
+    assert_eq!(Resp::Error, client.run(&Cmd::Get(get)).unwrap());
+
+    let stats = Stats::new(None);
+    assert_eq!(Resp::Stats(vec![Stat::new("pid", "12".to_string())]),
+               client.run(&Cmd::Stats(stats)).unwrap());
+
+    let err = client.run(&Cmd::Quit).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
+
+
+// Replay
+
+#[test]
+fn test_replay_into_driver() {
+    let set = Set::new(SetInstr::Set, b"x", 0, 0, b"ab".to_vec(), false);
+    let get = Get::new(GetInstr::Get, vec![b"x".to_vec(), b"y".to_vec()]);
+    let records = vec![TraceRecord::new(1000.0, 1, Cmd::Set(set)),
+                       TraceRecord::new(1000.0, 2, Cmd::Get(get)),
+                       TraceRecord::new(1000.0, 1, Cmd::Quit)];
+    let mut trace = write_trace(&records, true);
+    trace.push(0x80); // cut off in the middle of the next record
+
+    let mut driver = Driver::new(Cache::new(1 << 20));
+    let reader = TraceReader::new(&trace[..]).unwrap();
+    let stats = replay(reader, 0.0, |cmd| Ok(driver.run(cmd))).unwrap();
+
+    assert_eq!(2, stats.cmds);
+    assert_eq!(1, stats.skipped);
+    assert_eq!(0, stats.errors);
+    assert_eq!(1, stats.get_hits);
+    assert_eq!(1, stats.get_misses);
+    assert_eq!(Some(0.5), stats.hit_rate());
+    assert_eq!(2, stats.latencies.len());
+}
+
+#[test]
+fn test_replay_paced() {
+    let version = Cmd::Version;
+    let records = vec![TraceRecord::new(1000.0, 1, version.clone()),
+                       TraceRecord::new(1000.2, 1, version.clone())];
+    let trace = write_trace(&records, true);
+
+    // Twice as fast as it was recorded
+    let reader = TraceReader::new(&trace[..]).unwrap();
+    let stats = replay(reader, 2.0, |_| Ok(Resp::Ok)).unwrap();
+    assert_eq!(2, stats.cmds);
+    assert!(stats.elapsed >= 0.09);
+    assert!(stats.elapsed < 0.2);
+}
+
+
+// Simulation
+
+// A few keys that are read all the time, with runs of keys that are never
+// read again stored in between
+fn hot_and_scan_trace(rounds: usize) -> Vec<u8> {
+    let mut records = vec![];
+    let mut time = 1000.0;
+    let mut record = |cmd: Cmd| {
+        time += 0.001;
+        records.push(TraceRecord::new(time, 1, cmd));
+    };
+
+    let hot_keys: Vec<Vec<u8>> = (0..4)
+                                     .map(|i| format!("hot{}", i).into_bytes())
+                                     .collect();
+    for key in &hot_keys {
+        record(Cmd::Set(Set::new(SetInstr::Set, key, 0, 0, vec![], false)));
+    }
+
+    for round in 0..rounds {
+        for key in &hot_keys {
+            record(Cmd::Get(Get::one(GetInstr::Get, key)));
+        }
+        for i in 0..12 {
+            let key = format!("scan{}", round * 12 + i).into_bytes();
+            let set = Set::new(SetInstr::Set, &key, 0, 0, vec![], false);
+            record(Cmd::Set(set));
+        }
+    }
+
+    write_trace(&records, false)
+}
+
+#[test]
+fn test_sample_keys() {
+    let keys: Vec<Vec<u8>> = (0..1000)
+                                 .map(|i| format!("key{}", i).into_bytes())
+                                 .collect();
+
+    assert!(keys.iter().all(|key| is_sampled(key, 1.0)));
+
+    // About as many as asked for, and the same ones every time
+    let sampled: Vec<&Vec<u8>> = keys.iter()
+                                     .filter(|key| is_sampled(key, 0.1))
+                                     .collect();
+    assert!(sampled.len() > 50 && sampled.len() < 150);
+    assert!(sampled.iter().all(|key| is_sampled(key, 0.1)));
+}
+
+#[test]
+fn test_simulate_policies() {
+    let trace = hot_and_scan_trace(50);
+    let item_size = entry_mem_size(&Key::new(b"scan100".to_vec()),
+                                   &SValue::new(Blob::empty()));
+
+    let run = |policy: EvictionPolicy, items: u64| {
+        let reader = TraceReader::new(&trace[..]).unwrap();
+        simulate(reader, policy, item_size * items, 1.0).unwrap()
+    };
+
+    // The scan pushes the hot keys out of a small LRU, but not out of a
+    // segmented one
+    let lru = run(EvictionPolicy::Lru, 10);
+    let slru = run(EvictionPolicy::SegmentedLru, 10);
+    assert_eq!(200, lru.get_hits + lru.get_misses);
+    assert!(lru.hit_rate().unwrap() < 0.1);
+    assert_eq!(0, slru.get_misses);
+
+    // Nothing is lost with room for everything
+    let lru = run(EvictionPolicy::Lru, 1000);
+    assert_eq!(0, lru.get_misses);
+}
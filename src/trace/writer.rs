@@ -0,0 +1,87 @@
+use std::io;
+use std::io::Write;
+
+use protocol::cmd::Cmd;
+
+use super::codec::FLAG_VALUES;
+use super::codec::MAGIC;
+use super::codec::VERSION;
+use super::codec::encode_cmd;
+use super::codec::write_varint;
+
+
+// How often to flush what was written (in seconds), so that the trace on disk
+// is never far behind
+const FLUSH_INTERVAL: f64 = 1.0;
+
+
+pub fn to_micros(time: f64) -> u64 {
+    (time * 1_000_000.0).max(0.0) as u64
+}
+
+
+// Writes commands to a trace. The time of a command is kept as the time since
+// the one before, so that it takes a byte or two.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    with_values: bool, // Keep the data of sets, not just its size
+    last_time: u64, // microseconds, of the command before
+    flushed_at: f64, // unixtime when we last flushed
+    buf: Vec<u8>, // Where a command is encoded before it's written
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W,
+               with_values: bool,
+               start: f64)
+               -> io::Result<TraceWriter<W>> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(match with_values {
+            true => FLAG_VALUES,
+            false => 0,
+        });
+        write_varint(&mut header, to_micros(start));
+        try!(out.write_all(&header));
+
+        Ok(TraceWriter {
+            out: out,
+            with_values: with_values,
+            last_time: to_micros(start),
+            flushed_at: start,
+            buf: vec![],
+        })
+    }
+
+    pub fn write(&mut self,
+                 time: f64,
+                 conn: u64,
+                 cmd: &Cmd)
+                 -> io::Result<()> {
+        // Connections take turns writing, so a command can come in a little
+        // after one that was read later. We don't go back in time.
+        let time_us = to_micros(time).max(self.last_time);
+
+        self.buf.clear();
+        write_varint(&mut self.buf, time_us - self.last_time);
+        write_varint(&mut self.buf, conn);
+        encode_cmd(&mut self.buf, cmd, self.with_values);
+        try!(self.out.write_all(&self.buf));
+        self.last_time = time_us;
+
+        if time - self.flushed_at >= FLUSH_INTERVAL {
+            try!(self.out.flush());
+            self.flushed_at = time;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}